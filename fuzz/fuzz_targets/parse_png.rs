@@ -0,0 +1,12 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use pngme::png::Png;
+
+// Parsing must never panic, and whatever parses must survive a round trip
+fuzz_target!(|data: &[u8]| {
+    if let Ok(png) = Png::try_from(data) {
+        let reparsed = Png::try_from(png.as_bytes().as_slice()).expect("serialized PNGs parse");
+        assert!(reparsed == png);
+    }
+});
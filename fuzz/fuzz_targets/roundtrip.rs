@@ -0,0 +1,11 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use pngme::png::Png;
+
+// Any PNG built from valid chunks is read back unchanged
+fuzz_target!(|png: Png| {
+    let bytes = png.as_bytes();
+    let parsed = Png::try_from(bytes.as_slice()).expect("serialized PNGs parse");
+    assert!(parsed == png);
+});
@@ -0,0 +1,28 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use pngme::stream::{PngStreamParser, StreamEvent};
+
+// The first byte decides how the rest of the input is split up, so the
+// parser sees every kind of piece boundary
+fuzz_target!(|data: &[u8]| {
+    let Some((&piece_size, input)) = data.split_first() else {
+        return;
+    };
+    let mut parser = PngStreamParser::new();
+    let mut remaining = 0u64;
+    for piece in input.chunks(piece_size.max(1) as usize) {
+        let Ok(events) = parser.push(piece) else {
+            return;
+        };
+        for event in events {
+            match event {
+                StreamEvent::ChunkStart { length, .. } => remaining = length as u64,
+                StreamEvent::ChunkData(data) => remaining -= data.len() as u64,
+                StreamEvent::ChunkEnd { .. } => assert_eq!(remaining, 0),
+                _ => {}
+            }
+        }
+    }
+    let _ = parser.finish();
+});
@@ -0,0 +1,1205 @@
+use crate::{
+    audit::BEFORE_PLTE,
+    chunk::Chunk,
+    chunk_type::ChunkType,
+    color::RenderingIntent,
+    ihdr::{ColorType, ImageHeader},
+    png::Png,
+    text,
+};
+use std::{
+    fmt::{self, Display, Write},
+    str::FromStr,
+};
+
+/// How many `sPLT` entries are rendered before the rest are summarized
+const SHOWN_PALETTE_ENTRIES: usize = 8;
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum AncillaryError {
+    #[error("the {chunk_type} chunk has {actual} bytes of data instead of {expected}")]
+    Length {
+        chunk_type: &'static str,
+        expected: usize,
+        actual: usize,
+    },
+
+    #[error("the {chunk_type} chunk is malformed: {reason}")]
+    Malformed {
+        chunk_type: &'static str,
+        reason: String,
+    },
+}
+
+/// The background color from a `bKGD` chunk, in the color type of the image
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Background {
+    Gray(u16),
+    Rgb(u16, u16, u16),
+    PaletteIndex(u8),
+}
+
+/// An entry of a suggested palette
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PaletteEntry {
+    pub red: u16,
+    pub green: u16,
+    pub blue: u16,
+    pub alpha: u16,
+    /// How often the color appears relative to the other entries
+    pub frequency: u16,
+}
+
+/// A suggested palette from an `sPLT` chunk, for showing the image on
+/// displays with few colors
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SuggestedPalette {
+    pub name: String,
+    /// 8 or 16 bits per sample
+    pub sample_depth: u8,
+    pub entries: Vec<PaletteEntry>,
+}
+
+/// The unit of a `pHYs` chunk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PixelUnit {
+    /// Only the aspect ratio is known
+    Unknown,
+    Meter,
+}
+
+/// The unit of an `oFFs` chunk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OffsetUnit {
+    Pixel,
+    Micrometer,
+}
+
+/// The unit of an `sCAL` chunk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScaleUnit {
+    Meter,
+    Radian,
+}
+
+/// How a `pCAL` chunk maps sample values to physical values
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Equation {
+    /// `p0 + p1 * x / (x1 - x0)`
+    Linear,
+    /// `p0 + p1 * e^(p2 * x / (x1 - x0))`
+    Exponential,
+    /// `p0 + p1 * p3^(p2 * x / (x1 - x0))`
+    ArbitraryExponential,
+    /// `p0 + p1 * sinh(p2 * (x - p3) / (x1 - x0))`
+    Hyperbolic,
+}
+
+impl Equation {
+    fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            0 => Some(Self::Linear),
+            1 => Some(Self::Exponential),
+            2 => Some(Self::ArbitraryExponential),
+            3 => Some(Self::Hyperbolic),
+            _ => None,
+        }
+    }
+
+    /// The number of parameters the equation takes
+    pub fn parameters(self) -> usize {
+        match self {
+            Self::Linear => 2,
+            Self::Exponential => 3,
+            Self::ArbitraryExponential | Self::Hyperbolic => 4,
+        }
+    }
+}
+
+/// A name on the command line which isn't one of the known values
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+#[error("unknown {kind} '{value}', expected {expected}")]
+pub struct UnknownValueError {
+    kind: &'static str,
+    value: String,
+    expected: &'static str,
+}
+
+impl FromStr for OffsetUnit {
+    type Err = UnknownValueError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pixel" => Ok(Self::Pixel),
+            "micrometer" => Ok(Self::Micrometer),
+            other => Err(UnknownValueError {
+                kind: "unit",
+                value: other.to_owned(),
+                expected: "pixel or micrometer",
+            }),
+        }
+    }
+}
+
+impl FromStr for ScaleUnit {
+    type Err = UnknownValueError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "meter" => Ok(Self::Meter),
+            "radian" => Ok(Self::Radian),
+            other => Err(UnknownValueError {
+                kind: "unit",
+                value: other.to_owned(),
+                expected: "meter or radian",
+            }),
+        }
+    }
+}
+
+impl FromStr for Equation {
+    type Err = UnknownValueError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "linear" => Ok(Self::Linear),
+            "exponential" => Ok(Self::Exponential),
+            "arbitrary-exponential" => Ok(Self::ArbitraryExponential),
+            "hyperbolic" => Ok(Self::Hyperbolic),
+            other => Err(UnknownValueError {
+                kind: "equation",
+                value: other.to_owned(),
+                expected: "linear, exponential, arbitrary-exponential or hyperbolic",
+            }),
+        }
+    }
+}
+
+impl Display for Equation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Linear => "linear",
+            Self::Exponential => "exponential",
+            Self::ArbitraryExponential => "arbitrary-exponential",
+            Self::Hyperbolic => "hyperbolic",
+        })
+    }
+}
+
+/// A mapping from sample values to physical values, from a `pCAL` chunk
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Calibration {
+    /// What is measured, following the keyword rules of text chunks
+    pub name: String,
+    /// The sample values mapped to the range of the equation
+    pub x0: i32,
+    pub x1: i32,
+    pub equation: Equation,
+    /// The physical unit, e.g. m or degrees Celsius
+    pub unit: String,
+    /// The parameters of the equation as floating point strings, kept as
+    /// written so they survive a round trip unchanged
+    pub parameters: Vec<String>,
+}
+
+/// A standard ancillary chunk with its contents decoded
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Ancillary {
+    /// `sBIT`: how many bits of each channel were significant in the
+    /// original image, in channel order (red, green and blue for palette
+    /// images)
+    SignificantBits(Vec<(&'static str, u8)>),
+
+    /// `bKGD`: the color to show the image on
+    Background(Background),
+
+    /// `hIST`: approximately how often each palette entry is used
+    Histogram(Vec<u16>),
+
+    /// `sPLT`: a suggested reduced palette
+    SuggestedPalette(SuggestedPalette),
+
+    /// `gAMA`: the gamma times 100000
+    Gamma(u32),
+
+    /// `sRGB`: the image is in the sRGB color space
+    Srgb(RenderingIntent),
+
+    /// `pHYs`: the pixels per unit in both directions
+    PhysicalSize { x: u32, y: u32, unit: PixelUnit },
+
+    /// `tIME`: the last modification, in UTC
+    Time {
+        year: u16,
+        month: u8,
+        day: u8,
+        hour: u8,
+        minute: u8,
+        second: u8,
+    },
+
+    /// `oFFs`: the position of the image on a page or in a larger image
+    Offset { x: i32, y: i32, unit: OffsetUnit },
+
+    /// `sCAL`: the physical size of a pixel, as positive floating point
+    /// strings like `0.5` or `2.5E-3`
+    Scale {
+        width: String,
+        height: String,
+        unit: ScaleUnit,
+    },
+
+    /// `pCAL`: what the sample values measure
+    Calibration(Calibration),
+}
+
+/// The names of the channels `sBIT` and `bKGD` have values for
+fn channel_names(color_type: ColorType) -> &'static [&'static str] {
+    match color_type {
+        ColorType::Grayscale => &["gray"],
+        ColorType::GrayscaleAlpha => &["gray", "alpha"],
+        ColorType::Rgb | ColorType::Indexed => &["red", "green", "blue"],
+        ColorType::Rgba => &["red", "green", "blue", "alpha"],
+    }
+}
+
+fn check_length(
+    chunk_type: &'static str,
+    data: &[u8],
+    expected: usize,
+) -> Result<(), AncillaryError> {
+    match data.len() == expected {
+        true => Ok(()),
+        false => Err(AncillaryError::Length {
+            chunk_type,
+            expected,
+            actual: data.len(),
+        }),
+    }
+}
+
+fn malformed(chunk_type: &'static str, reason: impl Into<String>) -> AncillaryError {
+    AncillaryError::Malformed {
+        chunk_type,
+        reason: reason.into(),
+    }
+}
+
+fn u16_at(data: &[u8], offset: usize) -> u16 {
+    u16::from_be_bytes([data[offset], data[offset + 1]])
+}
+
+fn u32_at(data: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+fn i32_at(data: &[u8], offset: usize) -> i32 {
+    i32::from_be_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+fn latin1(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| *b as char).collect()
+}
+
+fn encode_latin1(chunk_type: &'static str, text: &str) -> Result<Vec<u8>, AncillaryError> {
+    text.chars()
+        .map(|c| u8::try_from(c).ok().filter(|b| *b != 0))
+        .collect::<Option<_>>()
+        .ok_or_else(|| malformed(chunk_type, format!("'{}' isn't Latin-1 text", text)))
+}
+
+/// Whether `s` is a floating point string as `sCAL` and `pCAL` store them:
+/// an optional sign, digits with an optional decimal point, and an optional
+/// exponent
+fn is_float_string(s: &str) -> bool {
+    let s = s.strip_prefix(['+', '-']).unwrap_or(s);
+    let (mantissa, exponent) = match s.find(['e', 'E']) {
+        Some(position) => (&s[..position], Some(&s[position + 1..])),
+        None => (s, None),
+    };
+    let (integer, fraction) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+    let digits = |part: &str| part.bytes().all(|b| b.is_ascii_digit());
+    let valid_mantissa =
+        !(integer.is_empty() && fraction.is_empty()) && digits(integer) && digits(fraction);
+    let valid_exponent = exponent.is_none_or(|exponent| {
+        let exponent = exponent.strip_prefix(['+', '-']).unwrap_or(exponent);
+        !exponent.is_empty() && digits(exponent)
+    });
+    valid_mantissa && valid_exponent
+}
+
+fn check_float(
+    chunk_type: &'static str,
+    value: &str,
+    positive: bool,
+) -> Result<(), AncillaryError> {
+    if !is_float_string(value) {
+        return Err(malformed(
+            chunk_type,
+            format!("'{}' isn't a floating point number", value),
+        ));
+    }
+    if positive && !value.parse::<f64>().is_ok_and(|value| value > 0.0) {
+        return Err(malformed(
+            chunk_type,
+            format!("{} isn't greater than zero", value),
+        ));
+    }
+    Ok(())
+}
+
+fn check_calibration(calibration: &Calibration) -> Result<(), AncillaryError> {
+    text::validate_keyword(&calibration.name)
+        .map_err(|err| malformed("pCAL", format!("invalid name, {}", err)))?;
+    if calibration.x0 == calibration.x1 {
+        return Err(malformed("pCAL", "the sample range is empty"));
+    }
+    if calibration.parameters.len() != calibration.equation.parameters() {
+        return Err(malformed(
+            "pCAL",
+            format!(
+                "the {} equation takes {} parameters, got {}",
+                calibration.equation,
+                calibration.equation.parameters(),
+                calibration.parameters.len()
+            ),
+        ));
+    }
+    calibration
+        .parameters
+        .iter()
+        .try_for_each(|parameter| check_float("pCAL", parameter, false))
+}
+
+impl Ancillary {
+    /// Decodes a standard ancillary chunk. `header` is needed for the chunks
+    /// whose layout depends on the color type. Returns `None` for other
+    /// chunk types.
+    pub fn decode(chunk: &Chunk, header: &ImageHeader) -> Option<Result<Self, AncillaryError>> {
+        let data = chunk.data();
+        Some(match &chunk.chunk_type().bytes() {
+            b"sBIT" => Self::significant_bits(data, header),
+            b"bKGD" => Self::background(data, header),
+            b"hIST" => Self::histogram(data),
+            b"sPLT" => Self::suggested_palette(data),
+            b"gAMA" => check_length("gAMA", data, 4).map(|_| Self::Gamma(u32_at(data, 0))),
+            b"sRGB" => check_length("sRGB", data, 1).and_then(|_| {
+                RenderingIntent::from_byte(data[0])
+                    .map(Self::Srgb)
+                    .ok_or_else(|| {
+                        malformed("sRGB", format!("unknown rendering intent {}", data[0]))
+                    })
+            }),
+            b"pHYs" => Self::physical_size(data),
+            b"tIME" => Self::time(data),
+            b"oFFs" => Self::offset(data),
+            b"sCAL" => Self::scale(data),
+            b"pCAL" => Self::calibration(data),
+            _ => return None,
+        })
+    }
+
+    fn significant_bits(data: &[u8], header: &ImageHeader) -> Result<Self, AncillaryError> {
+        let names = channel_names(header.color_type);
+        check_length("sBIT", data, names.len())?;
+        // Palette entries always have 8 bits per sample
+        let depth = match header.color_type {
+            ColorType::Indexed => 8,
+            _ => header.bit_depth,
+        };
+        if let Some(bits) = data.iter().find(|&&bits| !(1..=depth).contains(&bits)) {
+            return Err(malformed(
+                "sBIT",
+                format!("{} significant bits for {}-bit samples", bits, depth),
+            ));
+        }
+        Ok(Self::SignificantBits(
+            names.iter().copied().zip(data.iter().copied()).collect(),
+        ))
+    }
+
+    fn background(data: &[u8], header: &ImageHeader) -> Result<Self, AncillaryError> {
+        let background = match header.color_type {
+            ColorType::Grayscale | ColorType::GrayscaleAlpha => {
+                check_length("bKGD", data, 2)?;
+                Background::Gray(u16_at(data, 0))
+            }
+            ColorType::Rgb | ColorType::Rgba => {
+                check_length("bKGD", data, 6)?;
+                Background::Rgb(u16_at(data, 0), u16_at(data, 2), u16_at(data, 4))
+            }
+            ColorType::Indexed => {
+                check_length("bKGD", data, 1)?;
+                Background::PaletteIndex(data[0])
+            }
+        };
+        Ok(Self::Background(background))
+    }
+
+    fn histogram(data: &[u8]) -> Result<Self, AncillaryError> {
+        if !data.len().is_multiple_of(2) {
+            return Err(malformed("hIST", "the data isn't a list of 16-bit values"));
+        }
+        Ok(Self::Histogram(
+            data.chunks_exact(2)
+                .map(|value| u16::from_be_bytes([value[0], value[1]]))
+                .collect(),
+        ))
+    }
+
+    fn suggested_palette(data: &[u8]) -> Result<Self, AncillaryError> {
+        let end = data
+            .iter()
+            .position(|b| *b == 0)
+            .ok_or_else(|| malformed("sPLT", "the palette name isn't null-terminated"))?;
+        let name = data[..end].iter().map(|b| *b as char).collect();
+        let (sample_depth, entries) = match &data[end + 1..] {
+            [depth, entries @ ..] => (*depth, entries),
+            [] => return Err(malformed("sPLT", "the sample depth is missing")),
+        };
+
+        let entry_size = match sample_depth {
+            8 => 6,
+            16 => 10,
+            depth => return Err(malformed("sPLT", format!("unknown sample depth {}", depth))),
+        };
+        if !entries.len().is_multiple_of(entry_size) {
+            return Err(malformed(
+                "sPLT",
+                format!("the entries don't fit into {}-byte entries", entry_size),
+            ));
+        }
+        let entries = entries
+            .chunks_exact(entry_size)
+            .map(|entry| match sample_depth {
+                8 => PaletteEntry {
+                    red: entry[0].into(),
+                    green: entry[1].into(),
+                    blue: entry[2].into(),
+                    alpha: entry[3].into(),
+                    frequency: u16_at(entry, 4),
+                },
+                _ => PaletteEntry {
+                    red: u16_at(entry, 0),
+                    green: u16_at(entry, 2),
+                    blue: u16_at(entry, 4),
+                    alpha: u16_at(entry, 6),
+                    frequency: u16_at(entry, 8),
+                },
+            })
+            .collect();
+
+        Ok(Self::SuggestedPalette(SuggestedPalette {
+            name,
+            sample_depth,
+            entries,
+        }))
+    }
+
+    fn physical_size(data: &[u8]) -> Result<Self, AncillaryError> {
+        check_length("pHYs", data, 9)?;
+        let unit = match data[8] {
+            0 => PixelUnit::Unknown,
+            1 => PixelUnit::Meter,
+            unit => return Err(malformed("pHYs", format!("unknown unit {}", unit))),
+        };
+        Ok(Self::PhysicalSize {
+            x: u32_at(data, 0),
+            y: u32_at(data, 4),
+            unit,
+        })
+    }
+
+    fn time(data: &[u8]) -> Result<Self, AncillaryError> {
+        check_length("tIME", data, 7)?;
+        let [month, day, hour, minute, second] = data[2..7].try_into().unwrap();
+        // Up to 60 seconds for leap seconds
+        if !(1..=12).contains(&month)
+            || !(1..=31).contains(&day)
+            || hour > 23
+            || minute > 59
+            || second > 60
+        {
+            return Err(malformed("tIME", "the date or time is out of range"));
+        }
+        Ok(Self::Time {
+            year: u16_at(data, 0),
+            month,
+            day,
+            hour,
+            minute,
+            second,
+        })
+    }
+
+    fn offset(data: &[u8]) -> Result<Self, AncillaryError> {
+        check_length("oFFs", data, 9)?;
+        let unit = match data[8] {
+            0 => OffsetUnit::Pixel,
+            1 => OffsetUnit::Micrometer,
+            unit => return Err(malformed("oFFs", format!("unknown unit {}", unit))),
+        };
+        Ok(Self::Offset {
+            x: i32_at(data, 0),
+            y: i32_at(data, 4),
+            unit,
+        })
+    }
+
+    fn scale(data: &[u8]) -> Result<Self, AncillaryError> {
+        let (unit, values) = data
+            .split_first()
+            .ok_or_else(|| malformed("sCAL", "the chunk is empty"))?;
+        let unit = match unit {
+            1 => ScaleUnit::Meter,
+            2 => ScaleUnit::Radian,
+            unit => return Err(malformed("sCAL", format!("unknown unit {}", unit))),
+        };
+        let fields: Vec<&[u8]> = values.split(|b| *b == 0).collect();
+        let [width, height] = fields[..] else {
+            return Err(malformed(
+                "sCAL",
+                "expected a width and a height separated by a null byte",
+            ));
+        };
+        let (width, height) = (latin1(width), latin1(height));
+        check_float("sCAL", &width, true)?;
+        check_float("sCAL", &height, true)?;
+        Ok(Self::Scale {
+            width,
+            height,
+            unit,
+        })
+    }
+
+    fn calibration(data: &[u8]) -> Result<Self, AncillaryError> {
+        let end = data
+            .iter()
+            .position(|b| *b == 0)
+            .ok_or_else(|| malformed("pCAL", "the name isn't null-terminated"))?;
+        let rest = &data[end + 1..];
+        if rest.len() < 10 {
+            return Err(malformed("pCAL", "the chunk ends before the equation"));
+        }
+        let equation = Equation::from_byte(rest[8])
+            .ok_or_else(|| malformed("pCAL", format!("unknown equation type {}", rest[8])))?;
+        let declared = usize::from(rest[9]);
+        let mut fields = rest[10..].split(|b| *b == 0).map(latin1);
+        let unit = fields
+            .next()
+            .ok_or_else(|| malformed("pCAL", "the unit is missing"))?;
+        let parameters: Vec<String> = fields.collect();
+        if parameters.len() != declared {
+            return Err(malformed(
+                "pCAL",
+                format!(
+                    "{} parameters declared, {} found",
+                    declared,
+                    parameters.len()
+                ),
+            ));
+        }
+
+        let calibration = Calibration {
+            name: latin1(&data[..end]),
+            x0: i32_at(rest, 0),
+            x1: i32_at(rest, 4),
+            equation,
+            unit,
+            parameters,
+        };
+        check_calibration(&calibration)?;
+        Ok(Self::Calibration(calibration))
+    }
+
+    /// The type of the chunk holding this
+    pub fn chunk_type(&self) -> &'static str {
+        match self {
+            Self::SignificantBits(_) => "sBIT",
+            Self::Background(_) => "bKGD",
+            Self::Histogram(_) => "hIST",
+            Self::SuggestedPalette(_) => "sPLT",
+            Self::Gamma(_) => "gAMA",
+            Self::Srgb(_) => "sRGB",
+            Self::PhysicalSize { .. } => "pHYs",
+            Self::Time { .. } => "tIME",
+            Self::Offset { .. } => "oFFs",
+            Self::Scale { .. } => "sCAL",
+            Self::Calibration(_) => "pCAL",
+        }
+    }
+
+    /// Encodes the chunk. Fails if a value can't be stored, e.g. a scale
+    /// which isn't a positive floating point string.
+    pub fn to_chunk(&self) -> Result<Chunk, AncillaryError> {
+        let mut data = Vec::new();
+        match self {
+            Self::SignificantBits(channels) => data.extend(channels.iter().map(|(_, bits)| bits)),
+            Self::Background(Background::Gray(gray)) => data.extend(gray.to_be_bytes()),
+            Self::Background(Background::Rgb(red, green, blue)) => {
+                for sample in [red, green, blue] {
+                    data.extend(sample.to_be_bytes());
+                }
+            }
+            Self::Background(Background::PaletteIndex(index)) => data.push(*index),
+            Self::Histogram(frequencies) => data.extend(
+                frequencies
+                    .iter()
+                    .flat_map(|frequency| frequency.to_be_bytes()),
+            ),
+            Self::SuggestedPalette(palette) => {
+                data.extend(encode_latin1("sPLT", &palette.name)?);
+                data.extend([0, palette.sample_depth]);
+                for entry in &palette.entries {
+                    let samples = [entry.red, entry.green, entry.blue, entry.alpha];
+                    match palette.sample_depth {
+                        8 => {
+                            for sample in samples {
+                                data.push(u8::try_from(sample).map_err(|_| {
+                                    malformed("sPLT", format!("{} doesn't fit into 8 bits", sample))
+                                })?);
+                            }
+                        }
+                        16 => data.extend(samples.iter().flat_map(|sample| sample.to_be_bytes())),
+                        depth => {
+                            return Err(malformed(
+                                "sPLT",
+                                format!("unknown sample depth {}", depth),
+                            ))
+                        }
+                    }
+                    data.extend(entry.frequency.to_be_bytes());
+                }
+            }
+            Self::Gamma(gamma) => data.extend(gamma.to_be_bytes()),
+            Self::Srgb(intent) => data.push(intent.to_byte()),
+            Self::PhysicalSize { x, y, unit } => {
+                data.extend(x.to_be_bytes());
+                data.extend(y.to_be_bytes());
+                data.push(match unit {
+                    PixelUnit::Unknown => 0,
+                    PixelUnit::Meter => 1,
+                });
+            }
+            Self::Time {
+                year,
+                month,
+                day,
+                hour,
+                minute,
+                second,
+            } => {
+                data.extend(year.to_be_bytes());
+                data.extend([*month, *day, *hour, *minute, *second]);
+                // Checks the ranges
+                Self::time(&data)?;
+            }
+            Self::Offset { x, y, unit } => {
+                data.extend(x.to_be_bytes());
+                data.extend(y.to_be_bytes());
+                data.push(match unit {
+                    OffsetUnit::Pixel => 0,
+                    OffsetUnit::Micrometer => 1,
+                });
+            }
+            Self::Scale {
+                width,
+                height,
+                unit,
+            } => {
+                check_float("sCAL", width, true)?;
+                check_float("sCAL", height, true)?;
+                data.push(match unit {
+                    ScaleUnit::Meter => 1,
+                    ScaleUnit::Radian => 2,
+                });
+                data.extend_from_slice(width.as_bytes());
+                data.push(0);
+                data.extend_from_slice(height.as_bytes());
+            }
+            Self::Calibration(calibration) => {
+                check_calibration(calibration)?;
+                data.extend(encode_latin1("pCAL", &calibration.name)?);
+                data.push(0);
+                data.extend(calibration.x0.to_be_bytes());
+                data.extend(calibration.x1.to_be_bytes());
+                // The parameter count fits, it was checked against the equation
+                data.extend([
+                    calibration.equation as u8,
+                    calibration.parameters.len() as u8,
+                ]);
+                data.extend(encode_latin1("pCAL", &calibration.unit)?);
+                for parameter in &calibration.parameters {
+                    data.push(0);
+                    data.extend_from_slice(parameter.as_bytes());
+                }
+            }
+        }
+
+        let chunk_type = ChunkType::from_str(self.chunk_type()).expect("the chunk types are valid");
+        Ok(Chunk::new(chunk_type, data))
+    }
+
+    /// Whether storing this replaces `chunk`: chunks of the same type, and
+    /// for `sPLT` only the palette with the same name
+    fn replaces(&self, chunk: &Chunk) -> bool {
+        if chunk.chunk_type().to_string() != self.chunk_type() {
+            return false;
+        }
+        match self {
+            Self::SuggestedPalette(palette) => {
+                Self::suggested_palette(chunk.data()).is_ok_and(|existing| {
+                    matches!(existing, Self::SuggestedPalette(existing) if existing.name == palette.name)
+                })
+            }
+            _ => true,
+        }
+    }
+}
+
+/// Stores `ancillary` in the PNG, replacing the chunk it [replaces]. The new
+/// chunk takes the place of the old one, or goes where the spec wants it:
+/// right after `IHDR` for the chunks which must precede `PLTE`, before
+/// `IEND` for `tIME` and before the first `IDAT` for the rest.
+///
+/// [replaces]: Ancillary::replaces
+pub fn store(png: &mut Png, ancillary: &Ancillary) -> Result<(), AncillaryError> {
+    let chunk = ancillary.to_chunk()?;
+    let position = |png: &Png, bytes: &[u8; 4]| {
+        png.chunks()
+            .iter()
+            .position(|chunk| &chunk.chunk_type().bytes() == bytes)
+    };
+
+    if let Some(existing) = png
+        .chunks()
+        .iter()
+        .position(|chunk| ancillary.replaces(chunk))
+    {
+        png.retain(|chunk| !ancillary.replaces(chunk));
+        png.insert_chunk(existing, chunk);
+    } else if &chunk.chunk_type().bytes() == b"tIME" {
+        png.insert_before_end(chunk);
+    } else if BEFORE_PLTE.contains(&&chunk.chunk_type().bytes()) {
+        let start = position(png, b"IHDR").map_or(0, |index| index + 1);
+        png.insert_chunk(start, chunk);
+    } else {
+        match position(png, b"IDAT") {
+            Some(index) => png.insert_chunk(index, chunk),
+            None => png.insert_before_end(chunk),
+        }
+    }
+    Ok(())
+}
+
+impl Display for Ancillary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::SignificantBits(channels) => {
+                f.write_str("significant bits: ")?;
+                let channels: Vec<String> = channels
+                    .iter()
+                    .map(|(name, bits)| format!("{} {}", name, bits))
+                    .collect();
+                f.write_str(&channels.join(", "))
+            }
+            Self::Background(Background::Gray(gray)) => write!(f, "background: gray {}", gray),
+            Self::Background(Background::Rgb(red, green, blue)) => {
+                write!(f, "background: red {}, green {}, blue {}", red, green, blue)
+            }
+            Self::Background(Background::PaletteIndex(index)) => {
+                write!(f, "background: palette entry {}", index)
+            }
+            Self::Histogram(frequencies) => {
+                let unused = frequencies
+                    .iter()
+                    .filter(|frequency| **frequency == 0)
+                    .count();
+                write!(
+                    f,
+                    "histogram of {} palette entries ({} unused): ",
+                    frequencies.len(),
+                    unused
+                )?;
+                let frequencies: Vec<String> = frequencies.iter().map(u16::to_string).collect();
+                f.write_str(&frequencies.join(", "))
+            }
+            Self::SuggestedPalette(palette) => {
+                write!(
+                    f,
+                    "suggested palette \"{}\": {} entries with {}-bit samples",
+                    palette.name,
+                    palette.entries.len(),
+                    palette.sample_depth
+                )?;
+                let width = if palette.sample_depth == 8 { 2 } else { 4 };
+                for entry in palette.entries.iter().take(SHOWN_PALETTE_ENTRIES) {
+                    let mut color = String::new();
+                    for sample in [entry.red, entry.green, entry.blue, entry.alpha] {
+                        write!(color, "{:0width$x}", sample, width = width)?;
+                    }
+                    write!(f, ", #{} x{}", color, entry.frequency)?;
+                }
+                match palette.entries.len().checked_sub(SHOWN_PALETTE_ENTRIES) {
+                    Some(rest) if rest > 0 => write!(f, ", ... ({} more)", rest),
+                    _ => Ok(()),
+                }
+            }
+            Self::Gamma(gamma) => write!(f, "gamma {:.5}", *gamma as f64 / 100000.0),
+            Self::Srgb(intent) => write!(f, "sRGB color space, {} rendering intent", intent),
+            Self::PhysicalSize {
+                x,
+                y,
+                unit: PixelUnit::Meter,
+            } => {
+                write!(f, "{}x{} pixels per meter", x, y)?;
+                if x == y {
+                    write!(f, " ({:.0} dpi)", *x as f64 * 0.0254)?;
+                }
+                Ok(())
+            }
+            Self::PhysicalSize { x, y, .. } => write!(f, "pixel aspect ratio {}:{}", x, y),
+            Self::Time {
+                year,
+                month,
+                day,
+                hour,
+                minute,
+                second,
+            } => write!(
+                f,
+                "last modified {:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+                year, month, day, hour, minute, second
+            ),
+            Self::Offset { x, y, unit } => write!(
+                f,
+                "offset {}, {} {}",
+                x,
+                y,
+                match unit {
+                    OffsetUnit::Pixel => "pixels",
+                    OffsetUnit::Micrometer => "micrometers",
+                }
+            ),
+            Self::Scale {
+                width,
+                height,
+                unit,
+            } => write!(
+                f,
+                "pixel size {} x {} {}",
+                width,
+                height,
+                match unit {
+                    ScaleUnit::Meter => "meters",
+                    ScaleUnit::Radian => "radians",
+                }
+            ),
+            Self::Calibration(calibration) => write!(
+                f,
+                "calibration \"{}\": samples {} to {}, {} equation with parameters {}, unit \"{}\"",
+                calibration.name,
+                calibration.x0,
+                calibration.x1,
+                calibration.equation,
+                calibration.parameters.join(", "),
+                calibration.unit
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{chunk_type::ChunkType, png::tests::PNG_FILE, png::Png};
+    use std::str::FromStr;
+
+    fn header(color_type: ColorType, bit_depth: u8) -> ImageHeader {
+        ImageHeader {
+            width: 1,
+            height: 1,
+            bit_depth,
+            color_type,
+            interlaced: false,
+        }
+    }
+
+    fn decode(
+        chunk_type: &str,
+        data: &[u8],
+        header: &ImageHeader,
+    ) -> Result<Ancillary, AncillaryError> {
+        let chunk = Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data.to_vec());
+        Ancillary::decode(&chunk, header).unwrap()
+    }
+
+    #[test]
+    fn test_significant_bits() {
+        let rgb = header(ColorType::Rgb, 8);
+        let sbit = decode("sBIT", &[5, 6, 5], &rgb).unwrap();
+        assert_eq!(sbit.to_string(), "significant bits: red 5, green 6, blue 5");
+
+        let gray = header(ColorType::GrayscaleAlpha, 16);
+        assert_eq!(
+            decode("sBIT", &[12, 1], &gray).unwrap().to_string(),
+            "significant bits: gray 12, alpha 1"
+        );
+        assert_eq!(
+            decode("sBIT", &[5, 6], &rgb),
+            Err(AncillaryError::Length {
+                chunk_type: "sBIT",
+                expected: 3,
+                actual: 2
+            })
+        );
+        assert!(decode("sBIT", &[9, 8, 8], &rgb).is_err());
+        assert!(decode("sBIT", &[8, 8, 8], &header(ColorType::Indexed, 2)).is_ok());
+    }
+
+    #[test]
+    fn test_background() {
+        assert_eq!(
+            decode("bKGD", &[0, 255, 0, 128, 0, 0], &header(ColorType::Rgba, 8)),
+            Ok(Ancillary::Background(Background::Rgb(255, 128, 0)))
+        );
+        assert_eq!(
+            decode("bKGD", &[3], &header(ColorType::Indexed, 8))
+                .unwrap()
+                .to_string(),
+            "background: palette entry 3"
+        );
+        assert_eq!(
+            decode("bKGD", &[1, 0], &header(ColorType::Grayscale, 16)),
+            Ok(Ancillary::Background(Background::Gray(256)))
+        );
+        assert!(decode("bKGD", &[1, 0], &header(ColorType::Rgb, 8)).is_err());
+    }
+
+    #[test]
+    fn test_histogram() {
+        let indexed = header(ColorType::Indexed, 8);
+        assert_eq!(
+            decode("hIST", &[0, 10, 0, 0, 1, 0], &indexed)
+                .unwrap()
+                .to_string(),
+            "histogram of 3 palette entries (1 unused): 10, 0, 256"
+        );
+        assert!(decode("hIST", &[0, 10, 0], &indexed).is_err());
+    }
+
+    #[test]
+    fn test_suggested_palette() {
+        let rgb = header(ColorType::Rgb, 8);
+        let mut data = b"web\0\x08".to_vec();
+        for i in 0..10u8 {
+            data.extend_from_slice(&[i, 0, 255, 255, 0, i]);
+        }
+        let Ancillary::SuggestedPalette(palette) = decode("sPLT", &data, &rgb).unwrap() else {
+            panic!("expected a suggested palette");
+        };
+        assert_eq!(palette.name, "web");
+        assert_eq!(palette.entries.len(), 10);
+        assert_eq!(
+            palette.entries[1],
+            PaletteEntry {
+                red: 1,
+                green: 0,
+                blue: 255,
+                alpha: 255,
+                frequency: 1
+            }
+        );
+        let rendered = Ancillary::SuggestedPalette(palette).to_string();
+        assert!(rendered.starts_with(
+            "suggested palette \"web\": 10 entries with 8-bit samples, #0000ffff x0, #0100ffff x1"
+        ));
+        assert!(rendered.ends_with(", ... (2 more)"));
+
+        let wide = b"wide\0\x10\x01\x00\x00\x00\xff\xff\xff\xff\x00\x02";
+        let Ancillary::SuggestedPalette(palette) = decode("sPLT", wide, &rgb).unwrap() else {
+            panic!("expected a suggested palette");
+        };
+        assert_eq!(palette.entries[0].red, 256);
+        assert_eq!(palette.entries[0].frequency, 2);
+
+        assert!(decode("sPLT", b"web", &rgb).is_err());
+        assert!(decode("sPLT", b"web\0\x04", &rgb).is_err());
+        assert!(decode("sPLT", b"web\0\x08\x00\x00", &rgb).is_err());
+    }
+
+    #[test]
+    fn test_dice() {
+        let png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let header = png.image_header().unwrap();
+        let decoded: Vec<String> = png
+            .chunks()
+            .iter()
+            .filter_map(|chunk| Ancillary::decode(chunk, &header))
+            .map(|decoded| decoded.unwrap().to_string())
+            .collect();
+        assert_eq!(
+            decoded,
+            [
+                "sRGB color space, perceptual rendering intent",
+                "gamma 0.45455",
+                "3778x3778 pixels per meter (96 dpi)",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_time() {
+        let rgb = header(ColorType::Rgb, 8);
+        assert_eq!(
+            decode("tIME", &[0x07, 0xe8, 2, 29, 23, 59, 60], &rgb)
+                .unwrap()
+                .to_string(),
+            "last modified 2024-02-29 23:59:60 UTC"
+        );
+        assert!(decode("tIME", &[0x07, 0xe8, 13, 1, 0, 0, 0], &rgb).is_err());
+        assert_eq!(
+            decode("pHYs", &[0, 0, 0, 1, 0, 0, 0, 2, 0], &rgb)
+                .unwrap()
+                .to_string(),
+            "pixel aspect ratio 1:2"
+        );
+    }
+
+    fn calibration(equation: Equation, parameters: &[&str]) -> Ancillary {
+        Ancillary::Calibration(Calibration {
+            name: "elevation".to_owned(),
+            x0: 0,
+            x1: 65535,
+            equation,
+            unit: "m".to_owned(),
+            parameters: parameters.iter().map(|p| p.to_string()).collect(),
+        })
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let rgb = header(ColorType::Rgb, 8);
+        let values = [
+            Ancillary::SignificantBits(vec![("red", 5), ("green", 6), ("blue", 5)]),
+            Ancillary::Background(Background::Rgb(1, 2, 3)),
+            Ancillary::Histogram(vec![3, 0, 1000]),
+            Ancillary::SuggestedPalette(SuggestedPalette {
+                name: "wide".to_owned(),
+                sample_depth: 16,
+                entries: vec![PaletteEntry {
+                    red: 1000,
+                    green: 0,
+                    blue: 65535,
+                    alpha: 1,
+                    frequency: 7,
+                }],
+            }),
+            Ancillary::Gamma(45455),
+            Ancillary::Srgb(RenderingIntent::Saturation),
+            Ancillary::PhysicalSize {
+                x: 1,
+                y: 2,
+                unit: PixelUnit::Unknown,
+            },
+            Ancillary::Offset {
+                x: -120,
+                y: 45,
+                unit: OffsetUnit::Micrometer,
+            },
+            Ancillary::Scale {
+                width: "0.5".to_owned(),
+                height: "2.5E-3".to_owned(),
+                unit: ScaleUnit::Meter,
+            },
+            calibration(Equation::Linear, &["-10.5", "8848"]),
+            calibration(Equation::Hyperbolic, &["0", "1", "2e2", "+3."]),
+        ];
+        for value in values {
+            let chunk = value.to_chunk().unwrap();
+            assert_eq!(chunk.chunk_type().to_string(), value.chunk_type());
+            assert_eq!(Ancillary::decode(&chunk, &rgb), Some(Ok(value)));
+        }
+    }
+
+    #[test]
+    fn test_extensions() {
+        let rgb = header(ColorType::Rgb, 8);
+        assert_eq!(
+            decode("sCAL", b"\x021.5\x000.25", &rgb)
+                .unwrap()
+                .to_string(),
+            "pixel size 1.5 x 0.25 radians"
+        );
+        assert_eq!(
+            calibration(Equation::Linear, &["0", "8848"]).to_string(),
+            "calibration \"elevation\": samples 0 to 65535, linear equation with parameters 0, 8848, unit \"m\""
+        );
+        assert_eq!(
+            decode("oFFs", &[255, 255, 255, 255, 0, 0, 0, 2, 0], &rgb)
+                .unwrap()
+                .to_string(),
+            "offset -1, 2 pixels"
+        );
+
+        for (width, height) in [
+            ("0", "1"),
+            ("-1", "1"),
+            ("1", "abc"),
+            ("1", ""),
+            ("1e", "1"),
+        ] {
+            let scale = Ancillary::Scale {
+                width: width.to_owned(),
+                height: height.to_owned(),
+                unit: ScaleUnit::Meter,
+            };
+            assert!(scale.to_chunk().is_err(), "{} x {}", width, height);
+        }
+        assert!(decode("sCAL", b"\x011.5", &rgb).is_err());
+        assert!(decode("sCAL", b"\x031\x001", &rgb).is_err());
+
+        assert!(calibration(Equation::Exponential, &["1", "2"])
+            .to_chunk()
+            .is_err());
+        assert!(calibration(Equation::Linear, &["1", "x"])
+            .to_chunk()
+            .is_err());
+        let mut data = calibration(Equation::Linear, &["1", "2"])
+            .to_chunk()
+            .unwrap()
+            .data()
+            .to_vec();
+        // The declared number of parameters
+        data[19] = 3;
+        assert!(decode("pCAL", &data, &rgb).is_err());
+    }
+
+    #[test]
+    fn test_store() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let scale = |width: &str| Ancillary::Scale {
+            width: width.to_owned(),
+            height: "1".to_owned(),
+            unit: ScaleUnit::Meter,
+        };
+        store(&mut png, &scale("2")).unwrap();
+        store(&mut png, &Ancillary::SignificantBits(vec![("red", 8); 4])).unwrap();
+        store(&mut png, &scale("3")).unwrap();
+        let types: Vec<String> = png
+            .chunks()
+            .iter()
+            .map(|chunk| chunk.chunk_type().to_string())
+            .collect();
+        assert_eq!(
+            types,
+            ["IHDR", "sBIT", "sRGB", "gAMA", "pHYs", "sCAL", "IDAT", "RuSt", "IEND"]
+        );
+        let header = png.image_header().unwrap();
+        assert_eq!(
+            Ancillary::decode(&png.chunks()[5], &header),
+            Some(Ok(scale("3")))
+        );
+        assert!(store(&mut png, &scale("0")).is_err());
+    }
+}
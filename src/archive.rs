@@ -0,0 +1,333 @@
+use flate2::{read::GzDecoder, Compression, GzBuilder};
+use std::io::{Cursor, Read, Write};
+use zip::{write::SimpleFileOptions, ZipArchive, ZipWriter};
+
+/// A layer of packing around an image
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Layer {
+    Gzip { file_name: Option<Vec<u8>> },
+    Zip { archive: Vec<u8>, entry: String },
+    Tar { archive: Vec<u8>, entry: String },
+}
+
+/// Remembers how an image was packed (e.g. in a `.tar.gz`), so it can be
+/// packed the same way again after it was modified
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Carrier {
+    layers: Vec<Layer>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ArchiveError {
+    #[error("the archive contains several files, choose one with --entry: {}", .0.join(", "))]
+    AmbiguousEntry(Vec<String>),
+
+    #[error("the archive has no file {0}")]
+    MissingEntry(String),
+
+    #[error("--entry {0} was given, but the file is not a zip or tar archive")]
+    NotAnArchive(String),
+
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+
+    #[error(transparent)]
+    Zip(#[from] zip::result::ZipError),
+}
+
+fn is_gzip(bytes: &[u8]) -> bool {
+    bytes.starts_with(&[0x1f, 0x8b])
+}
+
+fn is_zip(bytes: &[u8]) -> bool {
+    bytes.starts_with(b"PK\x03\x04")
+}
+
+/// POSIX and GNU tar headers carry a magic value at offset 257
+fn is_tar(bytes: &[u8]) -> bool {
+    bytes.get(257..262) == Some(b"ustar")
+}
+
+/// Picks the file to operate on: the given entry, or the only file there is
+fn choose_entry(names: Vec<String>, entry: Option<&str>) -> Result<String, ArchiveError> {
+    match entry {
+        Some(entry) if names.iter().any(|name| name == entry) => Ok(entry.to_owned()),
+        Some(entry) => Err(ArchiveError::MissingEntry(entry.to_owned())),
+        None if names.len() == 1 => Ok(names.into_iter().next().unwrap()),
+        None => Err(ArchiveError::AmbiguousEntry(names)),
+    }
+}
+
+impl Carrier {
+    /// Unpacks gzip, zip and tar layers (in any combination) from `bytes`.
+    /// `entry` selects the file in the outermost archive, it may be left out
+    /// for archives with a single file.
+    pub fn unpack(
+        mut bytes: Vec<u8>,
+        mut entry: Option<&str>,
+    ) -> Result<(Self, Vec<u8>), ArchiveError> {
+        let mut layers = Vec::new();
+
+        loop {
+            let (layer, inner) = if is_gzip(&bytes) {
+                let mut decoder = GzDecoder::new(&bytes[..]);
+                let mut inner = Vec::new();
+                decoder.read_to_end(&mut inner)?;
+                let file_name = decoder
+                    .header()
+                    .and_then(|header| header.filename())
+                    .map(<[u8]>::to_vec);
+                (Layer::Gzip { file_name }, inner)
+            } else if is_zip(&bytes) {
+                let (name, inner) = unzip(&bytes, entry.take())?;
+                (
+                    Layer::Zip {
+                        archive: bytes,
+                        entry: name,
+                    },
+                    inner,
+                )
+            } else if is_tar(&bytes) {
+                let (name, inner) = untar(&bytes, entry.take())?;
+                (
+                    Layer::Tar {
+                        archive: bytes,
+                        entry: name,
+                    },
+                    inner,
+                )
+            } else {
+                break;
+            };
+            layers.push(layer);
+            bytes = inner;
+        }
+
+        match entry {
+            Some(entry) => Err(ArchiveError::NotAnArchive(entry.to_owned())),
+            None => Ok((Self { layers }, bytes)),
+        }
+    }
+
+    /// Whether the image was stored as is
+    pub fn is_plain(&self) -> bool {
+        self.layers.is_empty()
+    }
+
+    /// Packs a (modified) image the way the original one was packed. Other
+    /// files in archives are kept unchanged.
+    pub fn pack(&self, mut bytes: Vec<u8>) -> Result<Vec<u8>, ArchiveError> {
+        for layer in self.layers.iter().rev() {
+            bytes = match layer {
+                Layer::Gzip { file_name } => {
+                    let mut builder = GzBuilder::new();
+                    if let Some(file_name) = file_name {
+                        builder = builder.filename(file_name.clone());
+                    }
+                    let mut encoder = builder.write(Vec::new(), Compression::default());
+                    encoder.write_all(&bytes)?;
+                    encoder.finish()?
+                }
+                Layer::Zip { archive, entry } => rezip(archive, entry, &bytes)?,
+                Layer::Tar { archive, entry } => retar(archive, entry, &bytes)?,
+            };
+        }
+        Ok(bytes)
+    }
+}
+
+fn unzip(bytes: &[u8], entry: Option<&str>) -> Result<(String, Vec<u8>), ArchiveError> {
+    let mut archive = ZipArchive::new(Cursor::new(bytes))?;
+    let mut names = Vec::new();
+    for index in 0..archive.len() {
+        let file = archive.by_index_raw(index)?;
+        if !file.is_dir() {
+            names.push(file.name()?.into_owned());
+        }
+    }
+    let name = choose_entry(names, entry)?;
+
+    let mut data = Vec::new();
+    archive.by_name(&name)?.read_to_end(&mut data)?;
+    Ok((name, data))
+}
+
+/// Rebuilds a zip archive with new contents for `entry`, compressed the same
+/// way as before
+fn rezip(archive: &[u8], entry: &str, data: &[u8]) -> Result<Vec<u8>, ArchiveError> {
+    let mut archive = ZipArchive::new(Cursor::new(archive))?;
+    let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+
+    for index in 0..archive.len() {
+        let file = archive.by_index_raw(index)?;
+        if file.name()? != entry {
+            writer.raw_copy_file(file)?;
+            continue;
+        }
+
+        let mut options = SimpleFileOptions::default().compression_method(file.compression());
+        if let Some(modified) = file.last_modified() {
+            options = options.last_modified_time(modified);
+        }
+        if let Some(mode) = file.unix_mode() {
+            options = options.unix_permissions(mode);
+        }
+        drop(file);
+        writer.start_file(entry, options)?;
+        writer.write_all(data)?;
+    }
+
+    Ok(writer.finish()?.into_inner())
+}
+
+fn untar(bytes: &[u8], entry: Option<&str>) -> Result<(String, Vec<u8>), ArchiveError> {
+    let mut files = Vec::new();
+    for file in tar::Archive::new(bytes).entries()? {
+        let mut file = file?;
+        if file.header().entry_type().is_file() {
+            let name = file.path()?.to_string_lossy().into_owned();
+            let mut data = Vec::new();
+            file.read_to_end(&mut data)?;
+            files.push((name, data));
+        }
+    }
+
+    let name = choose_entry(files.iter().map(|(name, _)| name.clone()).collect(), entry)?;
+    let data = files
+        .into_iter()
+        .find_map(|(file, data)| (file == name).then_some(data))
+        .unwrap();
+    Ok((name, data))
+}
+
+/// Rebuilds a tar archive with new contents for `entry`, keeping the
+/// metadata of all files
+fn retar(archive: &[u8], entry: &str, data: &[u8]) -> Result<Vec<u8>, ArchiveError> {
+    let mut builder = tar::Builder::new(Vec::new());
+
+    for file in tar::Archive::new(archive).entries()? {
+        let mut file = file?;
+        let mut header = file.header().clone();
+        let path = file.path()?.into_owned();
+
+        let contents = if header.entry_type().is_file() && path.to_string_lossy() == entry {
+            data.to_vec()
+        } else {
+            let mut contents = Vec::new();
+            file.read_to_end(&mut contents)?;
+            contents
+        };
+        header.set_size(contents.len() as u64);
+        builder.append_data(&mut header, &path, &contents[..])?;
+    }
+
+    Ok(builder.into_inner()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::png::tests::PNG_FILE;
+    use flate2::read::GzDecoder;
+
+    fn gzip(bytes: &[u8]) -> Vec<u8> {
+        Carrier {
+            layers: vec![Layer::Gzip { file_name: None }],
+        }
+        .pack(bytes.to_vec())
+        .unwrap()
+    }
+
+    fn zip_archive(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, data) in files {
+            writer
+                .start_file(*name, SimpleFileOptions::default())
+                .unwrap();
+            writer.write_all(data).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    fn tar_archive(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        for (name, data) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            builder.append_data(&mut header, name, *data).unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    #[test]
+    fn test_plain() {
+        let (carrier, bytes) = Carrier::unpack(PNG_FILE.to_vec(), None).unwrap();
+        assert!(carrier.is_plain());
+        assert_eq!(bytes, PNG_FILE);
+        assert!(matches!(
+            Carrier::unpack(PNG_FILE.to_vec(), Some("a.png")),
+            Err(ArchiveError::NotAnArchive(_))
+        ));
+    }
+
+    #[test]
+    fn test_gzip() {
+        let (carrier, bytes) = Carrier::unpack(gzip(&PNG_FILE), None).unwrap();
+        assert_eq!(bytes, PNG_FILE);
+
+        let packed = carrier.pack(b"changed".to_vec()).unwrap();
+        let mut unpacked = Vec::new();
+        GzDecoder::new(&packed[..])
+            .read_to_end(&mut unpacked)
+            .unwrap();
+        assert_eq!(unpacked, b"changed");
+    }
+
+    #[test]
+    fn test_zip() {
+        let archive = zip_archive(&[("readme.txt", b"hello"), ("img/dice.png", &PNG_FILE)]);
+        assert!(matches!(
+            Carrier::unpack(archive.clone(), None),
+            Err(ArchiveError::AmbiguousEntry(names)) if names == ["readme.txt", "img/dice.png"]
+        ));
+        assert!(matches!(
+            Carrier::unpack(archive.clone(), Some("dice.png")),
+            Err(ArchiveError::MissingEntry(_))
+        ));
+
+        let (carrier, bytes) = Carrier::unpack(archive, Some("img/dice.png")).unwrap();
+        assert_eq!(bytes, PNG_FILE);
+
+        let packed = carrier.pack(b"changed".to_vec()).unwrap();
+        let (_, bytes) = Carrier::unpack(packed.clone(), Some("img/dice.png")).unwrap();
+        assert_eq!(bytes, b"changed");
+        let (_, bytes) = Carrier::unpack(packed, Some("readme.txt")).unwrap();
+        assert_eq!(bytes, b"hello");
+    }
+
+    #[test]
+    fn test_tar_gz() {
+        let archive = gzip(&tar_archive(&[
+            ("dice.png", &PNG_FILE),
+            ("readme.txt", b"hello"),
+        ]));
+
+        let (carrier, bytes) = Carrier::unpack(archive, Some("dice.png")).unwrap();
+        assert_eq!(bytes, PNG_FILE);
+
+        let packed = carrier.pack(b"changed".to_vec()).unwrap();
+        assert!(is_gzip(&packed));
+        let (_, bytes) = Carrier::unpack(packed.clone(), Some("dice.png")).unwrap();
+        assert_eq!(bytes, b"changed");
+        let (_, bytes) = Carrier::unpack(packed, Some("readme.txt")).unwrap();
+        assert_eq!(bytes, b"hello");
+    }
+
+    #[test]
+    fn test_single_file_archive() {
+        let archive = tar_archive(&[("dice.png", &PNG_FILE)]);
+        let (_, bytes) = Carrier::unpack(archive, None).unwrap();
+        assert_eq!(bytes, PNG_FILE);
+    }
+}
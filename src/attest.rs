@@ -0,0 +1,380 @@
+use crate::{chunk::Chunk, chunk_type::ChunkType, history::HistoryEntry, payload, png::Png};
+use ed25519_dalek::{Signature, Signer};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+pub use ed25519_dalek::{SigningKey, VerifyingKey};
+
+/// The chunk type attestations are stored in (private, ancillary, safe to
+/// copy). Every attestation has its own chunk.
+pub const ATTESTATION_CHUNK: &[u8; 4] = b"pmAt";
+
+/// Signed along with every record, so the signatures can't be taken for
+/// those of another protocol using the same key
+const CONTEXT: &[u8] = b"pngme attestation v1\0";
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum AttestError {
+    #[error("the key file must hold a 32-byte Ed25519 secret key as 64 hex digits")]
+    InvalidKey,
+
+    #[error("'{0}' isn't an Ed25519 public key of 64 hex digits")]
+    InvalidPublicKey(String),
+
+    #[error("the file has no attestations")]
+    Empty,
+
+    #[error("attestation {index} is invalid: {reason}")]
+    Invalid { index: usize, reason: String },
+
+    #[error("the signature of attestation {0} is invalid")]
+    BadSignature(usize),
+
+    #[error("attestation {0} was signed by {1}, which isn't an authorized key")]
+    Unauthorized(usize, String),
+
+    #[error("the attestations don't form a chain, some of them were removed or replaced")]
+    BrokenChain,
+
+    #[error("the file was changed after the last attestation")]
+    Modified,
+}
+
+/// A chunk an edit added or removed, identified by the SHA-256 of its data
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AttestedChunk {
+    pub chunk_type: String,
+    #[serde(with = "serde_bytes")]
+    pub sha256: Vec<u8>,
+}
+
+/// What an attestation vouches for: an edit by pngme and the file it left
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EditRecord {
+    pub operation: String,
+    /// Seconds since the unix epoch
+    pub timestamp: i64,
+    pub added: Vec<AttestedChunk>,
+    pub removed: Vec<AttestedChunk>,
+    /// The SHA-256 of the chunks after the edit, see [`content_sha256`]
+    #[serde(with = "serde_bytes")]
+    pub content_sha256: Vec<u8>,
+    /// The SHA-256 of the chunk data of the previous attestation, None for
+    /// the first one
+    #[serde(with = "serde_bytes")]
+    pub previous_sha256: Option<Vec<u8>>,
+}
+
+/// The data of an attestation chunk, stored as CBOR
+#[derive(Debug, Serialize, Deserialize)]
+struct Signed {
+    /// The CBOR of the [`EditRecord`]
+    #[serde(with = "serde_bytes")]
+    record: Vec<u8>,
+    #[serde(with = "serde_bytes")]
+    public_key: Vec<u8>,
+    #[serde(with = "serde_bytes")]
+    signature: Vec<u8>,
+}
+
+/// A record whose signature has been checked
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Attestation {
+    pub record: EditRecord,
+    pub public_key: VerifyingKey,
+}
+
+fn is_attestation(chunk: &Chunk) -> bool {
+    &chunk.chunk_type().bytes() == ATTESTATION_CHUNK
+}
+
+fn sha256(data: &[u8]) -> Vec<u8> {
+    Sha256::digest(data).to_vec()
+}
+
+fn parse_hex_key(s: &str) -> Option<[u8; 32]> {
+    let s = s.trim();
+    if s.len() != 64 || !s.is_ascii() {
+        return None;
+    }
+    let mut key = [0; 32];
+    for (byte, pair) in key.iter_mut().zip(s.as_bytes().chunks(2)) {
+        *byte = u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok()?;
+    }
+    Some(key)
+}
+
+/// Creates a new secret key from the randomness of the operating system
+pub fn generate_key() -> Result<SigningKey, getrandom::Error> {
+    let mut secret = [0; 32];
+    getrandom::fill(&mut secret)?;
+    Ok(SigningKey::from_bytes(&secret))
+}
+
+/// The contents of a key file for `key`: its secret key as hex digits
+pub fn key_file(key: &SigningKey) -> String {
+    format!("{}\n", payload::hex(key.as_bytes()))
+}
+
+/// Reads a secret key from the contents of a key file
+pub fn signing_key(key_file: &[u8]) -> Result<SigningKey, AttestError> {
+    let secret = std::str::from_utf8(key_file)
+        .ok()
+        .and_then(parse_hex_key)
+        .ok_or(AttestError::InvalidKey)?;
+    Ok(SigningKey::from_bytes(&secret))
+}
+
+/// Parses a public key written as hex digits, e.g. as printed by
+/// [`public_key_hex`]
+pub fn parse_public_key(s: &str) -> Result<VerifyingKey, AttestError> {
+    parse_hex_key(s)
+        .and_then(|key| VerifyingKey::from_bytes(&key).ok())
+        .ok_or_else(|| AttestError::InvalidPublicKey(s.to_owned()))
+}
+
+pub fn public_key_hex(key: &VerifyingKey) -> String {
+    payload::hex(key.as_bytes())
+}
+
+/// The SHA-256 of the types and data of every chunk but the attestations,
+/// in order
+pub fn content_sha256(png: &Png) -> Vec<u8> {
+    let mut hasher = Sha256::new();
+    for chunk in png.chunks().iter().filter(|chunk| !is_attestation(chunk)) {
+        hasher.update(chunk.length().to_be_bytes());
+        hasher.update(chunk.chunk_type().bytes());
+        hasher.update(chunk.data());
+    }
+    hasher.finalize().to_vec()
+}
+
+fn parse(index: usize, chunk: &Chunk) -> Result<Attestation, AttestError> {
+    let invalid = |reason: String| AttestError::Invalid { index, reason };
+    let signed: Signed =
+        ciborium::de::from_reader(chunk.data()).map_err(|err| invalid(err.to_string()))?;
+    let public_key = <[u8; 32]>::try_from(&signed.public_key[..])
+        .ok()
+        .and_then(|key| VerifyingKey::from_bytes(&key).ok())
+        .ok_or_else(|| invalid("the public key is invalid".to_owned()))?;
+    let signature = Signature::from_slice(&signed.signature)
+        .map_err(|_| invalid("the signature is malformed".to_owned()))?;
+    let message = [CONTEXT, &signed.record].concat();
+    public_key
+        .verify_strict(&message, &signature)
+        .map_err(|_| AttestError::BadSignature(index))?;
+    let record =
+        ciborium::de::from_reader(&signed.record[..]).map_err(|err| invalid(err.to_string()))?;
+    Ok(Attestation { record, public_key })
+}
+
+/// The attestations of `png` with valid signatures, oldest first, and the
+/// SHA-256 of the last one's chunk data. They are ordered by how they link
+/// to each other, as normalizing the file may move them around.
+fn chain(png: &Png) -> Result<(Vec<Attestation>, Option<Vec<u8>>), AttestError> {
+    let mut unlinked = Vec::new();
+    for (index, chunk) in png
+        .chunks()
+        .iter()
+        .filter(|chunk| is_attestation(chunk))
+        .enumerate()
+    {
+        unlinked.push((parse(index + 1, chunk)?, sha256(chunk.data())));
+    }
+    let mut chain = Vec::with_capacity(unlinked.len());
+    let mut previous = None;
+    while !unlinked.is_empty() {
+        let next = unlinked
+            .iter()
+            .position(|(attestation, _)| attestation.record.previous_sha256 == previous)
+            .ok_or(AttestError::BrokenChain)?;
+        let (attestation, hash) = unlinked.remove(next);
+        chain.push(attestation);
+        previous = Some(hash);
+    }
+    Ok((chain, previous))
+}
+
+/// Appends an attestation signed with `key` for `operation`, which turned
+/// `before` into `png`, linking it to the attestations already present
+pub fn attest(
+    png: &mut Png,
+    before: &Png,
+    operation: &str,
+    timestamp: i64,
+    key: &SigningKey,
+) -> Result<(), AttestError> {
+    let (_, previous_sha256) = chain(png)?;
+    let (added, removed) = match HistoryEntry::diff(operation, timestamp, before, png) {
+        Some(diff) => (
+            diff.added
+                .into_iter()
+                .map(|chunk| AttestedChunk {
+                    chunk_type: chunk.chunk_type,
+                    sha256: chunk.sha256,
+                })
+                .collect(),
+            diff.removed
+                .into_iter()
+                .map(|chunk| AttestedChunk {
+                    chunk_type: chunk.chunk_type,
+                    sha256: chunk.sha256,
+                })
+                .collect(),
+        ),
+        None => (Vec::new(), Vec::new()),
+    };
+    let record = EditRecord {
+        operation: operation.to_owned(),
+        timestamp,
+        added,
+        removed,
+        content_sha256: content_sha256(png),
+        previous_sha256,
+    };
+
+    let mut record_bytes = Vec::new();
+    ciborium::ser::into_writer(&record, &mut record_bytes).expect("writing to a Vec never fails");
+    let signature = key.sign(&[CONTEXT, &record_bytes].concat());
+    let signed = Signed {
+        record: record_bytes,
+        public_key: key.verifying_key().to_bytes().to_vec(),
+        signature: signature.to_bytes().to_vec(),
+    };
+    let mut data = Vec::new();
+    ciborium::ser::into_writer(&signed, &mut data).expect("writing to a Vec never fails");
+    png.insert_before_end(Chunk::new(
+        ChunkType::try_from(*ATTESTATION_CHUNK).unwrap(),
+        data,
+    ));
+    Ok(())
+}
+
+/// Checks that every attestation of `png` is signed by one of the
+/// `authorized` keys, that none of them were removed or replaced, and that
+/// the file hasn't changed since the last one. Returns the attestations,
+/// oldest first.
+pub fn verify(png: &Png, authorized: &[VerifyingKey]) -> Result<Vec<Attestation>, AttestError> {
+    let (chain, _) = chain(png)?;
+    let last = chain.last().ok_or(AttestError::Empty)?;
+    for (index, attestation) in chain.iter().enumerate() {
+        if !authorized.contains(&attestation.public_key) {
+            return Err(AttestError::Unauthorized(
+                index + 1,
+                public_key_hex(&attestation.public_key),
+            ));
+        }
+    }
+    if last.record.content_sha256 != content_sha256(png) {
+        return Err(AttestError::Modified);
+    }
+    Ok(chain)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::png::tests::PNG_FILE;
+    use std::str::FromStr;
+
+    fn key(seed: u8) -> SigningKey {
+        SigningKey::from_bytes(&[seed; 32])
+    }
+
+    fn strip(png: &Png) -> Png {
+        let mut stripped = png.clone();
+        stripped.retain(|chunk| chunk.chunk_type().is_critical() || is_attestation(chunk));
+        stripped
+    }
+
+    #[test]
+    fn test_attest_verify() {
+        let original = Png::try_from(&PNG_FILE[..]).unwrap();
+        let mut png = strip(&original);
+        attest(&mut png, &original, "strip", 1_700_000_000, &key(1)).unwrap();
+        let authorized = [key(1).verifying_key()];
+        let chain = verify(&png, &authorized).unwrap();
+        assert_eq!(chain.len(), 1);
+        assert_eq!(chain[0].record.operation, "strip");
+        assert_eq!(chain[0].record.removed.len(), 3);
+        assert!(chain[0].record.added.is_empty());
+
+        // A second edit links to the first
+        let before = png.clone();
+        png.insert_before_end(Chunk::new(
+            ChunkType::from_str("tEXt").unwrap(),
+            b"a\0b".to_vec(),
+        ));
+        attest(&mut png, &before, "text set", 1_700_000_001, &key(1)).unwrap();
+        let chain = verify(&png, &authorized).unwrap();
+        assert_eq!(chain.len(), 2);
+        assert_eq!(chain[1].record.added[0].chunk_type, "tEXt");
+
+        assert_eq!(
+            verify(&png, &[key(2).verifying_key()]),
+            Err(AttestError::Unauthorized(
+                1,
+                public_key_hex(&key(1).verifying_key())
+            ))
+        );
+        let mut modified = png.clone();
+        modified.insert_before_end(Chunk::new(ChunkType::from_str("zzZz").unwrap(), vec![]));
+        assert_eq!(verify(&modified, &authorized), Err(AttestError::Modified));
+        assert_eq!(verify(&original, &authorized), Err(AttestError::Empty));
+    }
+
+    #[test]
+    fn test_tampering() {
+        let original = Png::try_from(&PNG_FILE[..]).unwrap();
+        let mut png = strip(&original);
+        attest(&mut png, &original, "strip", 0, &key(1)).unwrap();
+        let before = png.clone();
+        attest(&mut png, &before, "normalize", 1, &key(1)).unwrap();
+        let authorized = [key(1).verifying_key()];
+
+        // Dropping the first attestation breaks the chain
+        let mut dropped = png.clone();
+        let first = dropped.chunks().iter().position(is_attestation).unwrap();
+        let mut index = 0;
+        dropped.retain(|_| {
+            index += 1;
+            index - 1 != first
+        });
+        assert_eq!(verify(&dropped, &authorized), Err(AttestError::BrokenChain));
+
+        // Changing a signed record invalidates its signature
+        let mut forged = png.clone();
+        let position = forged.chunks().iter().rposition(is_attestation).unwrap();
+        let mut signed: Signed = ciborium::de::from_reader(forged[position].data()).unwrap();
+        let mut record: EditRecord = ciborium::de::from_reader(&signed.record[..]).unwrap();
+        record.operation = "nothing".to_owned();
+        signed.record.clear();
+        ciborium::ser::into_writer(&record, &mut signed.record).unwrap();
+        let mut data = Vec::new();
+        ciborium::ser::into_writer(&signed, &mut data).unwrap();
+        forged
+            .remove_chunk(&ChunkType::try_from(*ATTESTATION_CHUNK).unwrap())
+            .unwrap();
+        forged.insert_before_end(Chunk::new(
+            ChunkType::try_from(*ATTESTATION_CHUNK).unwrap(),
+            data,
+        ));
+        assert!(matches!(
+            verify(&forged, &authorized),
+            Err(AttestError::BadSignature(_))
+        ));
+    }
+
+    #[test]
+    fn test_keys() {
+        let key = generate_key().unwrap();
+        assert_eq!(signing_key(key_file(&key).as_bytes()), Ok(key.clone()));
+        let public = public_key_hex(&key.verifying_key());
+        assert_eq!(parse_public_key(&public), Ok(key.verifying_key()));
+        assert_eq!(signing_key(b"not a key"), Err(AttestError::InvalidKey));
+        assert_eq!(
+            parse_public_key("abc"),
+            Err(AttestError::InvalidPublicKey("abc".to_owned()))
+        );
+    }
+}
@@ -0,0 +1,104 @@
+use std::{fs, io, path::Path};
+
+/// What [`carry_over`] copies from the file a command read to the file it
+/// wrote
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Preserve {
+    /// Extended attributes, e.g. Finder tags or labels of asset management
+    /// systems
+    pub xattrs: bool,
+    /// The owner, group and permissions
+    pub owner: bool,
+}
+
+impl Default for Preserve {
+    fn default() -> Self {
+        Self {
+            xattrs: true,
+            owner: true,
+        }
+    }
+}
+
+/// Copies the extended attributes, owner and permissions of `from` to `to`,
+/// as far as `preserve` asks for them. Attributes the file system of `to`
+/// doesn't support are left out, as is the owner if only the superuser may
+/// give files away; the group is still copied then if possible.
+pub fn carry_over<P: AsRef<Path>, Q: AsRef<Path>>(
+    from: P,
+    to: Q,
+    preserve: Preserve,
+) -> io::Result<()> {
+    let (from, to) = (from.as_ref(), to.as_ref());
+    if preserve.xattrs && xattr::SUPPORTED_PLATFORM {
+        for name in xattr::list_deref(from)? {
+            let Some(value) = xattr::get_deref(from, &name)? else {
+                continue;
+            };
+            match xattr::set_deref(to, &name, &value) {
+                Err(err) if is_unsupported(&err) => {}
+                result => result?,
+            }
+        }
+    }
+    if preserve.owner {
+        let metadata = fs::metadata(from)?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            if std::os::unix::fs::chown(to, Some(metadata.uid()), Some(metadata.gid())).is_err() {
+                match std::os::unix::fs::chown(to, None, Some(metadata.gid())) {
+                    Err(err) if err.kind() == io::ErrorKind::PermissionDenied => {}
+                    result => result?,
+                }
+            }
+        }
+        fs::set_permissions(to, metadata.permissions())?;
+    }
+    Ok(())
+}
+
+/// Whether setting an attribute failed because the file system or its
+/// namespace (e.g. `security.*` for other users) doesn't allow it
+fn is_unsupported(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::Unsupported | io::ErrorKind::PermissionDenied
+    )
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn test_carry_over() {
+        let dir = std::env::temp_dir();
+        let from = dir.join(format!("pngme-attributes-from-{}", std::process::id()));
+        let to = dir.join(format!("pngme-attributes-to-{}", std::process::id()));
+        fs::write(&from, b"from").unwrap();
+        fs::write(&to, b"to").unwrap();
+        fs::set_permissions(&from, fs::Permissions::from_mode(0o640)).unwrap();
+        // Not every file system the tests run on has extended attributes
+        let xattrs = xattr::set(&from, "user.pngme.label", b"approved").is_ok();
+
+        let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+        let label = |path: &Path| xattr::get(path, "user.pngme.label").unwrap_or_default();
+        let none = Preserve {
+            xattrs: false,
+            owner: false,
+        };
+        carry_over(&from, &to, none).unwrap();
+        assert_ne!(mode(&to), 0o640);
+        assert_eq!(label(&to), None);
+
+        carry_over(&from, &to, Preserve::default()).unwrap();
+        assert_eq!(mode(&to), 0o640);
+        if xattrs {
+            assert_eq!(label(&to).as_deref(), Some(&b"approved"[..]));
+        }
+        fs::remove_file(&from).unwrap();
+        fs::remove_file(&to).unwrap();
+    }
+}
@@ -0,0 +1,626 @@
+use crate::{
+    cancel::CancellationToken,
+    chunk_type::{ChunkType, Multiplicity},
+    date, detect,
+    payload::Envelope,
+    png::{Png, PngParseError},
+    stream::{PngStreamParser, StreamEvent},
+};
+use std::{collections::HashMap, fmt::Display, str::FromStr};
+
+/// Text chunks larger than this are reported by [`Rule::OversizedText`]
+pub const MAX_TEXT_LENGTH: u32 = 64 * 1024;
+
+/// Chunk types which must appear before both `PLTE` and the first `IDAT`
+pub(crate) const BEFORE_PLTE: &[&[u8; 4]] = &[b"cHRM", b"gAMA", b"iCCP", b"sBIT", b"sRGB"];
+
+/// Chunk types which must appear after `PLTE` and before the first `IDAT`
+pub(crate) const AFTER_PLTE: &[&[u8; 4]] = &[b"bKGD", b"hIST", b"tRNS"];
+
+/// Chunk types which must appear before the first `IDAT`
+pub(crate) const BEFORE_IDAT: &[&[u8; 4]] = &[b"pHYs", b"sPLT", b"oFFs", b"pCAL", b"sCAL"];
+
+pub(crate) const TEXT_CHUNKS: &[&[u8; 4]] = &[b"tEXt", b"zTXt", b"iTXt"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Info => "info",
+            Self::Warning => "warning",
+            Self::Error => "error",
+        })
+    }
+}
+
+/// A check performed by [`audit`] or [`verify`](crate::verify::verify).
+/// Every rule has a stable id and name which can be used to refer to it,
+/// e.g. on the command line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Rule {
+    /// A chunk which may only appear once appears multiple times
+    DuplicateChunk,
+
+    /// A text chunk is larger than [`MAX_TEXT_LENGTH`]
+    OversizedText,
+
+    /// Neither `sRGB` nor `iCCP` declare the color space of the image
+    MissingColorSpace,
+
+    /// There is data after the `IEND` chunk
+    TrailingData,
+
+    /// A payload written by `encode` has passed its expiry date
+    ExpiredPayload,
+
+    /// A chunk is not in the position required by the specification
+    ChunkOrder,
+
+    /// The file doesn't start with the PNG signature
+    InvalidSignature,
+
+    /// The stored crc of a chunk doesn't match its contents
+    InvalidChecksum,
+
+    /// A chunk type contains bytes other than ASCII letters
+    InvalidChunkType,
+
+    /// The file ends in the middle of a chunk
+    TruncatedChunk,
+
+    /// The reserved bit of a chunk type is set
+    ReservedBit,
+
+    /// There is no `IEND` chunk
+    MissingEnd,
+
+    /// The file no longer matches the hash in its C2PA manifest
+    ProvenanceMismatch,
+
+    /// The color management chunks are invalid or contradict each other
+    ColorConflict,
+
+    /// The ancillary chunks hold more data than the verification profile
+    /// allows
+    OversizedAncillary,
+
+    /// A chunk required by the verification profile is missing
+    MissingRequiredChunk,
+
+    /// A sealed payload doesn't match its SHA-256
+    PayloadChecksum,
+}
+
+impl Rule {
+    pub const ALL: [Rule; 17] = [
+        Rule::DuplicateChunk,
+        Rule::OversizedText,
+        Rule::MissingColorSpace,
+        Rule::TrailingData,
+        Rule::ChunkOrder,
+        Rule::ExpiredPayload,
+        Rule::InvalidSignature,
+        Rule::InvalidChecksum,
+        Rule::InvalidChunkType,
+        Rule::TruncatedChunk,
+        Rule::ReservedBit,
+        Rule::MissingEnd,
+        Rule::ProvenanceMismatch,
+        Rule::ColorConflict,
+        Rule::OversizedAncillary,
+        Rule::MissingRequiredChunk,
+        Rule::PayloadChecksum,
+    ];
+
+    pub fn id(&self) -> &'static str {
+        match self {
+            Self::DuplicateChunk => "A001",
+            Self::OversizedText => "A002",
+            Self::MissingColorSpace => "A003",
+            Self::TrailingData => "A004",
+            Self::ChunkOrder => "A005",
+            Self::ExpiredPayload => "A006",
+            Self::InvalidSignature => "V001",
+            Self::InvalidChecksum => "V002",
+            Self::InvalidChunkType => "V003",
+            Self::TruncatedChunk => "V004",
+            Self::ReservedBit => "V005",
+            Self::MissingEnd => "V006",
+            Self::ProvenanceMismatch => "V007",
+            Self::ColorConflict => "V008",
+            Self::OversizedAncillary => "P001",
+            Self::MissingRequiredChunk => "P002",
+            Self::PayloadChecksum => "P003",
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::DuplicateChunk => "duplicate-chunk",
+            Self::OversizedText => "oversized-text",
+            Self::MissingColorSpace => "missing-color-space",
+            Self::TrailingData => "trailing-data",
+            Self::ChunkOrder => "chunk-order",
+            Self::ExpiredPayload => "expired-payload",
+            Self::InvalidSignature => "invalid-signature",
+            Self::InvalidChecksum => "invalid-checksum",
+            Self::InvalidChunkType => "invalid-chunk-type",
+            Self::TruncatedChunk => "truncated-chunk",
+            Self::ReservedBit => "reserved-bit",
+            Self::MissingEnd => "missing-end",
+            Self::ProvenanceMismatch => "provenance-mismatch",
+            Self::ColorConflict => "color-conflict",
+            Self::OversizedAncillary => "oversized-ancillary",
+            Self::MissingRequiredChunk => "missing-required-chunk",
+            Self::PayloadChecksum => "payload-checksum",
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            Self::DuplicateChunk => "A chunk that may only appear once appears repeatedly",
+            Self::OversizedText => "A text chunk is larger than 64 KiB",
+            Self::MissingColorSpace => "Neither sRGB nor iCCP is present",
+            Self::TrailingData => "There is data after the IEND chunk",
+            Self::ChunkOrder => "A chunk is not where the specification requires it",
+            Self::ExpiredPayload => "An embedded payload has passed its expiry date",
+            Self::InvalidSignature => "The file doesn't start with the PNG signature",
+            Self::InvalidChecksum => "The crc of a chunk doesn't match its contents",
+            Self::InvalidChunkType => "A chunk type contains bytes other than ASCII letters",
+            Self::TruncatedChunk => "The file ends in the middle of a chunk",
+            Self::ReservedBit => "The reserved bit of a chunk type is set",
+            Self::MissingEnd => "There is no IEND chunk",
+            Self::ProvenanceMismatch => "The file doesn't match the hash in its C2PA manifest",
+            Self::ColorConflict => {
+                "The color management chunks are invalid or contradict each other"
+            }
+            Self::OversizedAncillary => "The ancillary chunks hold more data than allowed",
+            Self::MissingRequiredChunk => "A chunk required by the profile is missing",
+            Self::PayloadChecksum => "A sealed payload doesn't match its SHA-256",
+        }
+    }
+
+    pub fn severity(&self) -> Severity {
+        match self {
+            Self::DuplicateChunk
+            | Self::ChunkOrder
+            | Self::InvalidSignature
+            | Self::InvalidChecksum
+            | Self::InvalidChunkType
+            | Self::TruncatedChunk
+            | Self::MissingEnd
+            | Self::ProvenanceMismatch
+            | Self::OversizedAncillary
+            | Self::MissingRequiredChunk
+            | Self::PayloadChecksum => Severity::Error,
+            Self::OversizedText
+            | Self::TrailingData
+            | Self::ReservedBit
+            | Self::ExpiredPayload
+            | Self::ColorConflict => Severity::Warning,
+            Self::MissingColorSpace => Severity::Info,
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+#[error("unknown rule '{0}'")]
+pub struct UnknownRuleError(String);
+
+/// Accepts either the id or the name of a rule, e.g. `A004` or `trailing-data`
+impl FromStr for Rule {
+    type Err = UnknownRuleError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|rule| rule.id().eq_ignore_ascii_case(s) || rule.name() == s)
+            .ok_or_else(|| UnknownRuleError(s.to_owned()))
+    }
+}
+
+/// The part of a file a [`Finding`] is about
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Location {
+    /// Index of the chunk, if the location is a chunk
+    pub chunk: Option<usize>,
+    /// Offset in bytes from the start of the file
+    pub offset: usize,
+    /// Length in bytes
+    pub length: usize,
+}
+
+/// A violation of a [`Rule`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finding {
+    pub rule: Rule,
+    /// Where the violation was found, if it can be pinned down
+    pub location: Option<Location>,
+    pub message: String,
+}
+
+/// A chunk as seen by the audit, with only the start of its data
+struct ChunkInfo {
+    chunk_type: ChunkType,
+    length: u32,
+    location: Location,
+    /// Up to [`Envelope::PEEK_LEN`] bytes of data
+    head: Vec<u8>,
+}
+
+impl Display for Finding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}[{} {}]: {}",
+            self.rule.severity(),
+            self.rule.id(),
+            self.rule.name(),
+            self.message
+        )
+    }
+}
+
+/// Checks a PNG file against all [`Rule`]s. Fails if the file can't be
+/// parsed at all.
+pub fn audit(bytes: &[u8], cancel: &CancellationToken) -> Result<Vec<Finding>, PngParseError> {
+    let mut parser = PngStreamParser::with_cancellation(cancel.clone());
+    let mut chunks: Vec<ChunkInfo> = Vec::new();
+    let mut offset = 8;
+    let mut trailing = Vec::new();
+
+    // The stream parser only sees the signature, which isn't enough to
+    // recognize every other format
+    if !Png::is_png(bytes) {
+        return Err(PngParseError::from_signature(bytes));
+    }
+    for event in parser.push(bytes)? {
+        match event {
+            StreamEvent::ChunkStart { length, chunk_type } => {
+                let location = Location {
+                    chunk: Some(chunks.len()),
+                    offset,
+                    length: length as usize + 12,
+                };
+                offset += location.length;
+                chunks.push(ChunkInfo {
+                    chunk_type,
+                    length,
+                    location,
+                    head: Vec::new(),
+                });
+            }
+            StreamEvent::ChunkData(data) => {
+                if let Some(chunk) = chunks.last_mut() {
+                    let missing = Envelope::PEEK_LEN.saturating_sub(chunk.head.len());
+                    chunk
+                        .head
+                        .extend_from_slice(&data[..missing.min(data.len())]);
+                }
+            }
+            StreamEvent::TrailingData(data) => trailing.extend_from_slice(data),
+            _ => {}
+        }
+    }
+    parser.finish()?;
+
+    let mut findings = Vec::new();
+    check_duplicates(&chunks, &mut findings);
+    check_text_sizes(&chunks, &mut findings);
+    check_color_space(&chunks, &mut findings);
+    check_order(&chunks, &mut findings);
+    // `encode` appends its chunks after IEND, so their payloads are found in
+    // the trailing data
+    let appended = appended_chunks(&trailing, chunks.len(), offset);
+    check_expiry(chunks.iter().chain(&appended), date::now(), &mut findings);
+
+    if !trailing.is_empty() {
+        findings.push(Finding {
+            rule: Rule::TrailingData,
+            location: Some(Location {
+                chunk: None,
+                offset,
+                length: trailing.len(),
+            }),
+            message: match detect::appended_signature(&trailing) {
+                Some(what) => format!(
+                    "found {} bytes after the IEND chunk, they look like {}",
+                    trailing.len(),
+                    what
+                ),
+                None => format!("found {} bytes after the IEND chunk", trailing.len()),
+            },
+        });
+    }
+
+    Ok(findings)
+}
+
+pub(crate) fn is_one_of(chunk_type: &ChunkType, types: &[&[u8; 4]]) -> bool {
+    types.contains(&&chunk_type.bytes())
+}
+
+fn check_duplicates(chunks: &[ChunkInfo], findings: &mut Vec<Finding>) {
+    let mut seen: HashMap<[u8; 4], usize> = HashMap::new();
+    for (index, ChunkInfo { chunk_type, .. }) in chunks.iter().enumerate() {
+        let Some(info) = chunk_type.registered_info() else {
+            continue;
+        };
+        if info.multiplicity != Multiplicity::Single {
+            continue;
+        }
+        if let Some(first) = seen.insert(chunk_type.bytes(), index) {
+            findings.push(Finding {
+                rule: Rule::DuplicateChunk,
+                location: Some(chunks[index].location),
+                message: format!(
+                    "chunk {} is a second {} chunk ({}, first one is chunk {}), only one is allowed",
+                    index, chunk_type, info.name, first
+                ),
+            });
+        }
+    }
+}
+
+fn check_text_sizes(chunks: &[ChunkInfo], findings: &mut Vec<Finding>) {
+    for (index, chunk) in chunks.iter().enumerate() {
+        let ChunkInfo {
+            chunk_type, length, ..
+        } = chunk;
+        if is_one_of(chunk_type, TEXT_CHUNKS) && *length > MAX_TEXT_LENGTH {
+            findings.push(Finding {
+                rule: Rule::OversizedText,
+                location: Some(chunk.location),
+                message: format!(
+                    "{} chunk {} holds {} bytes, more than the recommended {}",
+                    chunk_type, index, length, MAX_TEXT_LENGTH
+                ),
+            });
+        }
+    }
+}
+
+fn check_color_space(chunks: &[ChunkInfo], findings: &mut Vec<Finding>) {
+    if !chunks
+        .iter()
+        .any(|chunk| is_one_of(&chunk.chunk_type, &[b"sRGB", b"iCCP"]))
+    {
+        findings.push(Finding {
+            rule: Rule::MissingColorSpace,
+            location: None,
+            message: "neither an sRGB nor an iCCP chunk declares the color space".to_owned(),
+        });
+    }
+}
+
+/// Reads the well-formed chunks at the start of the data after IEND, which
+/// start at chunk `index` and byte `offset`
+fn appended_chunks(trailing: &[u8], mut index: usize, mut offset: usize) -> Vec<ChunkInfo> {
+    let mut chunks = Vec::new();
+    let mut rest = trailing;
+    while rest.len() >= 12 {
+        let length = u32::from_be_bytes(rest[..4].try_into().unwrap());
+        let Ok(chunk_type) = ChunkType::try_from(<[u8; 4]>::try_from(&rest[4..8]).unwrap()) else {
+            break;
+        };
+        if rest.len() - 12 < length as usize {
+            break;
+        }
+        let data = &rest[8..8 + length as usize];
+
+        chunks.push(ChunkInfo {
+            chunk_type,
+            length,
+            location: Location {
+                chunk: Some(index),
+                offset,
+                length: length as usize + 12,
+            },
+            head: data[..data.len().min(Envelope::PEEK_LEN)].to_vec(),
+        });
+        index += 1;
+        offset += length as usize + 12;
+        rest = &rest[12 + length as usize..];
+    }
+    chunks
+}
+
+fn check_expiry<'a>(
+    chunks: impl Iterator<Item = &'a ChunkInfo>,
+    now: i64,
+    findings: &mut Vec<Finding>,
+) {
+    for chunk in chunks {
+        let index = chunk.location.chunk.unwrap_or_default();
+        let Some(expires) = Envelope::peek_expiry(&chunk.head) else {
+            continue;
+        };
+        if expires <= now {
+            findings.push(Finding {
+                rule: Rule::ExpiredPayload,
+                location: Some(chunk.location),
+                message: format!(
+                    "{} chunk {} holds a payload which expired on {}",
+                    chunk.chunk_type,
+                    index,
+                    date::format_utc(expires)
+                ),
+            });
+        }
+    }
+}
+
+fn check_order(chunks: &[ChunkInfo], findings: &mut Vec<Finding>) {
+    let position = |name: &[u8; 4]| {
+        chunks
+            .iter()
+            .position(|chunk| &chunk.chunk_type.bytes() == name)
+    };
+    let first_idat = position(b"IDAT");
+    let plte = position(b"PLTE");
+
+    let mut misplaced = |index: usize, chunk_type: &ChunkType, requirement: &str| {
+        findings.push(Finding {
+            rule: Rule::ChunkOrder,
+            location: Some(chunks[index].location),
+            message: format!("{} chunk {} {}", chunk_type, index, requirement),
+        });
+    };
+
+    let last = chunks.len().saturating_sub(1);
+    let mut idat_ended = false;
+    for (index, ChunkInfo { chunk_type, .. }) in chunks.iter().enumerate() {
+        let after_idat = first_idat.is_some_and(|idat| index > idat);
+        let bytes = &chunk_type.bytes();
+
+        if bytes == b"IHDR" && index != 0 {
+            misplaced(index, chunk_type, "must be the first chunk");
+        } else if bytes == b"IEND" && index != last {
+            misplaced(index, chunk_type, "must be the last chunk");
+        } else if bytes == b"IDAT" {
+            if idat_ended {
+                misplaced(
+                    index,
+                    chunk_type,
+                    "must directly follow the other IDAT chunks",
+                );
+            }
+        } else if bytes == b"PLTE" && after_idat {
+            misplaced(index, chunk_type, "must come before the first IDAT chunk");
+        } else if is_one_of(chunk_type, BEFORE_PLTE)
+            && (after_idat || plte.is_some_and(|plte| index > plte))
+        {
+            misplaced(index, chunk_type, "must come before PLTE and IDAT");
+        } else if is_one_of(chunk_type, AFTER_PLTE)
+            && (after_idat || plte.is_some_and(|plte| index < plte))
+        {
+            misplaced(index, chunk_type, "must come after PLTE and before IDAT");
+        } else if is_one_of(chunk_type, BEFORE_IDAT) && after_idat {
+            misplaced(index, chunk_type, "must come before the first IDAT chunk");
+        }
+
+        if first_idat.is_some_and(|idat| index >= idat) && bytes != b"IDAT" {
+            idat_ended = true;
+        }
+    }
+
+    if position(b"IHDR").is_none() {
+        findings.push(Finding {
+            rule: Rule::ChunkOrder,
+            location: None,
+            message: "the IHDR chunk is missing".to_owned(),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use crate::png::{tests::PNG_FILE, Png};
+
+    fn png_with(types: &[&str], trailing: &[u8]) -> Vec<u8> {
+        let chunks = types
+            .iter()
+            .map(|name| Chunk::new(ChunkType::from_str(name).unwrap(), vec![]))
+            .collect();
+        let mut bytes = Png::from_chunks(chunks).as_bytes();
+        bytes.extend_from_slice(trailing);
+        bytes
+    }
+
+    fn rules(bytes: &[u8]) -> Vec<Rule> {
+        audit(bytes, &CancellationToken::new())
+            .unwrap()
+            .into_iter()
+            .map(|finding| finding.rule)
+            .collect()
+    }
+
+    #[test]
+    fn test_clean_file() {
+        assert!(rules(&PNG_FILE).is_empty());
+    }
+
+    #[test]
+    fn test_duplicate_chunk() {
+        let bytes = png_with(
+            &[
+                "IHDR", "sRGB", "gAMA", "gAMA", "IDAT", "tEXt", "tEXt", "IEND",
+            ],
+            &[],
+        );
+        assert_eq!(rules(&bytes), vec![Rule::DuplicateChunk]);
+    }
+
+    #[test]
+    fn test_missing_color_space_and_trailing_data() {
+        let bytes = png_with(&["IHDR", "IDAT", "IEND"], b"hidden");
+        assert_eq!(
+            rules(&bytes),
+            vec![Rule::MissingColorSpace, Rule::TrailingData]
+        );
+    }
+
+    #[test]
+    fn test_chunk_order() {
+        let bytes = png_with(
+            &["IHDR", "PLTE", "sRGB", "IDAT", "pHYs", "IDAT", "IEND"],
+            &[],
+        );
+        let findings = audit(&bytes, &CancellationToken::new()).unwrap();
+        let misplaced: Vec<Option<usize>> = findings
+            .iter()
+            .filter(|finding| finding.rule == Rule::ChunkOrder)
+            .map(|finding| finding.location.and_then(|location| location.chunk))
+            .collect();
+        assert_eq!(misplaced, vec![Some(2), Some(4), Some(5)]);
+    }
+
+    #[test]
+    fn test_expired_payload() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let sealed = |expires| Envelope::seal(b"secret".to_vec()).with_expiry(expires);
+        png.insert_before_end(Chunk::new(
+            ChunkType::from_str("ruSt").unwrap(),
+            sealed(0).as_bytes(),
+        ));
+        png.insert_before_end(Chunk::new(
+            ChunkType::from_str("ruSt").unwrap(),
+            sealed(i64::MAX).as_bytes(),
+        ));
+
+        let findings = audit(&png.as_bytes(), &CancellationToken::new()).unwrap();
+        assert_eq!(findings.len(), 1);
+        assert_eq!(findings[0].rule, Rule::ExpiredPayload);
+        assert!(findings[0]
+            .message
+            .ends_with("expired on 1970-01-01T00:00:00Z"));
+    }
+
+    #[test]
+    fn test_expired_payload_after_end() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let sealed = Envelope::seal(b"secret".to_vec()).with_expiry(0);
+        png.append_chunk(Chunk::new(
+            ChunkType::from_str("ruSt").unwrap(),
+            sealed.as_bytes(),
+        ));
+
+        let rules = rules(&png.as_bytes());
+        assert_eq!(rules, vec![Rule::ExpiredPayload, Rule::TrailingData]);
+    }
+
+    #[test]
+    fn test_rule_from_str() {
+        assert_eq!(Rule::from_str("A004"), Ok(Rule::TrailingData));
+        assert_eq!(Rule::from_str("chunk-order"), Ok(Rule::ChunkOrder));
+        assert!(Rule::from_str("A999").is_err());
+    }
+}
@@ -0,0 +1,153 @@
+use crate::{date, history::HistoryEntry, payload, png::Png};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::{
+    fs::OpenOptions,
+    io::{self, Write},
+    path::Path,
+};
+
+/// A chunk an operation added or removed, identified by the SHA-256 of its
+/// data
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LoggedChunk {
+    pub chunk_type: String,
+    pub sha256: String,
+}
+
+/// One line of the audit log: an operation which modified a file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LogEntry {
+    /// RFC 3339 UTC
+    pub timestamp: String,
+    pub operation: String,
+    /// The file written
+    pub file: String,
+    /// The file read, if the operation wrote its result to another file
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub input: Option<String>,
+    /// The SHA-256 of the file before the operation, or of the input
+    pub before_sha256: String,
+    pub after_sha256: String,
+    /// Empty for files which aren't PNGs
+    pub added: Vec<LoggedChunk>,
+    pub removed: Vec<LoggedChunk>,
+}
+
+fn sha256(data: &[u8]) -> String {
+    payload::hex(&Sha256::digest(data))
+}
+
+impl LogEntry {
+    /// An entry for `operation` turning the file `before` into `after`,
+    /// which is written to `file`
+    pub fn new(operation: &str, timestamp: i64, file: &Path, before: &[u8], after: &[u8]) -> Self {
+        Self {
+            timestamp: date::format_utc(timestamp),
+            operation: operation.to_owned(),
+            file: file.display().to_string(),
+            input: None,
+            before_sha256: sha256(before),
+            after_sha256: sha256(after),
+            added: Vec::new(),
+            removed: Vec::new(),
+        }
+    }
+
+    /// Records which chunks differ between the PNGs, leaving out the
+    /// history chunks
+    pub fn with_changes(mut self, before: &Png, after: &Png) -> Self {
+        let Some(diff) = HistoryEntry::diff(&self.operation, 0, before, after) else {
+            return self;
+        };
+        self.added = diff
+            .added
+            .into_iter()
+            .map(|chunk| LoggedChunk {
+                chunk_type: chunk.chunk_type,
+                sha256: payload::hex(&chunk.sha256),
+            })
+            .collect();
+        self.removed = diff
+            .removed
+            .into_iter()
+            .map(|chunk| LoggedChunk {
+                chunk_type: chunk.chunk_type,
+                sha256: payload::hex(&chunk.sha256),
+            })
+            .collect();
+        self
+    }
+}
+
+/// Appends `entry` to the log at `path` as a line of JSON, creating the log
+/// if needed. The line is written at once, so processes appending to the
+/// same log don't interleave their entries.
+pub fn append<P: AsRef<Path>>(path: P, entry: &LogEntry) -> io::Result<()> {
+    let mut line = serde_json::to_vec(entry)?;
+    line.push(b'\n');
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?
+        .write_all(&line)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{chunk::Chunk, chunk_type::ChunkType, png::tests::PNG_FILE};
+    use std::str::FromStr;
+
+    #[test]
+    fn test_entry() {
+        let before = Png::try_from(&PNG_FILE[..]).unwrap();
+        let mut after = before.clone();
+        after
+            .remove_chunk(&ChunkType::from_str("gAMA").unwrap())
+            .unwrap();
+        after.insert_before_end(Chunk::new(
+            ChunkType::from_str("ruSt").unwrap(),
+            b"hi".to_vec(),
+        ));
+
+        let entry = LogEntry::new(
+            "encode",
+            1_700_000_000,
+            Path::new("dice.png"),
+            &PNG_FILE,
+            &after.as_bytes(),
+        )
+        .with_changes(&before, &after);
+        assert_eq!(entry.timestamp, "2023-11-14T22:13:20Z");
+        assert_eq!(entry.before_sha256, sha256(&PNG_FILE));
+        assert_eq!(
+            entry.added,
+            [LoggedChunk {
+                chunk_type: "ruSt".to_owned(),
+                sha256: "8f434346648f6b96df89dda901c5176b10a6d83961dd3c1ac88b59b2dc327aa4"
+                    .to_owned()
+            }]
+        );
+        assert_eq!(entry.removed.len(), 1);
+        assert_eq!(entry.removed[0].chunk_type, "gAMA");
+
+        let json = serde_json::to_string(&entry).unwrap();
+        assert!(json.starts_with(
+            r#"{"timestamp":"2023-11-14T22:13:20Z","operation":"encode","file":"dice.png","before_sha256":"#
+        ));
+    }
+
+    #[test]
+    fn test_append() {
+        let path =
+            std::env::temp_dir().join(format!("pngme-audit-log-{}.jsonl", std::process::id()));
+        let entry = LogEntry::new("remove", 0, Path::new("a.png"), b"a", b"b");
+        append(&path, &entry).unwrap();
+        append(&path, &entry).unwrap();
+        let log = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(log.lines().count(), 2);
+        assert!(log.ends_with("\"added\":[],\"removed\":[]}\n"));
+    }
+}
@@ -0,0 +1,223 @@
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Display},
+    path::Path,
+};
+
+/// What happened to a single file of a batch
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "outcome", content = "reason", rename_all = "lowercase")]
+pub enum Outcome {
+    /// The command succeeded and the file changed
+    Modified,
+    /// The command succeeded without changing the file
+    Unchanged,
+    /// The file wasn't processed, e.g. because it isn't a PNG
+    Skipped(String),
+    /// The command failed
+    Failed(String),
+}
+
+impl Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Modified => f.write_str("modified"),
+            Self::Unchanged => f.write_str("unchanged"),
+            Self::Skipped(reason) => write!(f, "skipped: {}", reason),
+            Self::Failed(reason) => write!(f, "failed: {}", reason),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FileOutcome {
+    pub path: String,
+    #[serde(flatten)]
+    pub outcome: Outcome,
+}
+
+impl FileOutcome {
+    pub fn new(path: &Path, outcome: Outcome) -> Self {
+        Self {
+            path: path.to_string_lossy().into_owned(),
+            outcome,
+        }
+    }
+}
+
+/// Puts results which arrive in any order back into the order of their
+/// inputs, holding each back until the results before it have arrived
+#[derive(Debug)]
+pub struct InputOrder<T> {
+    next: usize,
+    pending: BTreeMap<usize, T>,
+}
+
+impl<T> InputOrder<T> {
+    pub fn new() -> Self {
+        Self {
+            next: 0,
+            pending: BTreeMap::new(),
+        }
+    }
+
+    /// Adds the result for input `index`, returning the results which are
+    /// now next in order
+    pub fn push(&mut self, index: usize, result: T) -> Vec<T> {
+        self.pending.insert(index, result);
+        let mut ready = Vec::new();
+        while let Some(result) = self.pending.remove(&self.next) {
+            ready.push(result);
+            self.next += 1;
+        }
+        ready
+    }
+}
+
+impl<T> Default for InputOrder<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The number of files per kind of [`Outcome`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct Summary {
+    /// Files the command succeeded on, modified or not
+    pub processed: usize,
+    pub modified: usize,
+    pub skipped: usize,
+    pub failed: usize,
+}
+
+impl Summary {
+    pub fn new(outcomes: &[FileOutcome]) -> Self {
+        let mut summary = Self::default();
+        for file in outcomes {
+            match file.outcome {
+                Outcome::Modified => {
+                    summary.processed += 1;
+                    summary.modified += 1;
+                }
+                Outcome::Unchanged => summary.processed += 1,
+                Outcome::Skipped(_) => summary.skipped += 1,
+                Outcome::Failed(_) => summary.failed += 1,
+            }
+        }
+        summary
+    }
+}
+
+/// Renders the outcome of every file as a table, followed by the totals
+pub fn render_table(outcomes: &[FileOutcome]) -> String {
+    let summary = Summary::new(outcomes);
+    let width = outcomes
+        .iter()
+        .map(|file| file.path.chars().count())
+        .max()
+        .unwrap_or(0);
+
+    let mut table = String::new();
+    for file in outcomes {
+        table += &format!("{:width$}  {}\n", file.path, file.outcome, width = width);
+    }
+    table += &format!(
+        "Processed {}, modified {}, skipped {}, failed {}\n",
+        summary.processed, summary.modified, summary.skipped, summary.failed
+    );
+    table
+}
+
+#[derive(Serialize)]
+struct Report<'a> {
+    summary: Summary,
+    files: &'a [FileOutcome],
+}
+
+/// Renders the totals and the outcome of every file as JSON, for dashboards
+pub fn render_json(outcomes: &[FileOutcome]) -> String {
+    let report = Report {
+        summary: Summary::new(outcomes),
+        files: outcomes,
+    };
+    serde_json::to_string_pretty(&report).expect("the report serializes to JSON")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    fn outcomes() -> Vec<FileOutcome> {
+        vec![
+            FileOutcome {
+                path: "a.png".to_owned(),
+                outcome: Outcome::Modified,
+            },
+            FileOutcome {
+                path: "b.png".to_owned(),
+                outcome: Outcome::Unchanged,
+            },
+            FileOutcome {
+                path: "notes.txt".to_owned(),
+                outcome: Outcome::Skipped("not a PNG".to_owned()),
+            },
+            FileOutcome {
+                path: "c.png".to_owned(),
+                outcome: Outcome::Failed("not a valid PNG".to_owned()),
+            },
+        ]
+    }
+
+    #[test]
+    fn test_input_order() {
+        let mut order = InputOrder::new();
+        assert!(order.push(2, 'c').is_empty());
+        assert!(order.push(1, 'b').is_empty());
+        assert_eq!(order.push(0, 'a'), ['a', 'b', 'c']);
+        assert_eq!(order.push(3, 'd'), ['d']);
+    }
+
+    #[test]
+    fn test_summary() {
+        assert_eq!(
+            Summary::new(&outcomes()),
+            Summary {
+                processed: 2,
+                modified: 1,
+                skipped: 1,
+                failed: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn test_render_table() {
+        assert_eq!(
+            render_table(&outcomes()),
+            "a.png      modified\n\
+             b.png      unchanged\n\
+             notes.txt  skipped: not a PNG\n\
+             c.png      failed: not a valid PNG\n\
+             Processed 2, modified 1, skipped 1, failed 1\n"
+        );
+    }
+
+    #[test]
+    fn test_render_json() {
+        let report: Value = serde_json::from_str(&render_json(&outcomes())).unwrap();
+        assert_eq!(
+            report["summary"],
+            json!({"processed": 2, "modified": 1, "skipped": 1, "failed": 1})
+        );
+        assert_eq!(
+            report["files"][0],
+            json!({"path": "a.png", "outcome": "modified"})
+        );
+        assert_eq!(
+            report["files"][3],
+            json!({"path": "c.png", "outcome": "failed", "reason": "not a valid PNG"})
+        );
+    }
+}
@@ -1,31 +1,235 @@
 use crate::chunk_type::{ChunkType, ChunkTypeParseError};
-use crc::{Crc, CRC_32_ISO_HDLC};
-use std::fmt::Display;
+use alloc::{
+    borrow::ToOwned,
+    string::{FromUtf8Error, String},
+    sync::Arc,
+    vec::Vec,
+};
+use core::{
+    fmt::{Debug, Display},
+    ops::Range,
+    sync::atomic::{AtomicBool, AtomicU32, Ordering},
+};
+use crc::{Crc, Digest, CRC_32_ISO_HDLC};
+
+/// The largest length a chunk may have, 2^31 - 1 bytes
+pub const MAX_CHUNK_LENGTH: u32 = (1 << 31) - 1;
+
+/// The CRC-32 algorithm used for chunk checksums (ISO 3309 / ITU-T V.42)
+static CHUNK_CRC: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+
+/// Computes the CRC of a chunk piece by piece, so the data can be checked or
+/// written while it is copied through instead of being buffered
+#[derive(Clone)]
+pub struct CrcHasher(Digest<'static, u32>);
+
+impl CrcHasher {
+    /// Starts the CRC of a chunk of `chunk_type`, which is covered by it
+    pub fn new(chunk_type: &ChunkType) -> Self {
+        Self::from_type_bytes(&chunk_type.bytes())
+    }
+
+    /// Like [`CrcHasher::new`], but for type bytes which may not form a
+    /// valid [`ChunkType`], e.g. when checking a broken file
+    pub fn from_type_bytes(type_bytes: &[u8; 4]) -> Self {
+        let mut digest = CHUNK_CRC.digest();
+        digest.update(type_bytes);
+        Self(digest)
+    }
+
+    /// Adds the next piece of the chunk's data
+    pub fn update(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
 
-#[derive(Debug)]
+    pub fn finalize(self) -> u32 {
+        self.0.finalize()
+    }
+}
+
+/// A chunk of a PNG file. Chunks are `Send + Sync`, and the data of chunks
+/// parsed with [`Chunk::from_shared`] isn't copied when they are cloned.
+#[derive(Debug, Clone)]
 pub struct Chunk {
     length: u32,
     chunk_type: ChunkType,
-    data: Vec<u8>,
-    crc: u32,
+    data: Data,
+    /// Computed on first use, as building a chunk shouldn't cost a pass over
+    /// its data
+    crc: LazyCrc,
+}
+
+// Parsed files are shared between threads, see `Png::parse_shared`
+const _: () = {
+    const fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Chunk>();
+};
+
+/// Where the data of a chunk is kept
+#[derive(Clone)]
+enum Data {
+    Owned(Vec<u8>),
+    /// A range of a buffer shared with other chunks, usually the whole file
+    Shared(Arc<[u8]>, Range<usize>),
+}
+
+impl Data {
+    fn as_slice(&self) -> &[u8] {
+        match self {
+            Self::Owned(data) => data,
+            Self::Shared(bytes, range) => &bytes[range.clone()],
+        }
+    }
+}
+
+/// Only shows the data, not the rest of a shared buffer
+impl Debug for Data {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        self.as_slice().fmt(f)
+    }
+}
+
+/// A crc computed on first use. Unlike a `OnceCell`, it can be shared between
+/// threads, which at worst compute the same value twice.
+#[derive(Default)]
+struct LazyCrc {
+    known: AtomicBool,
+    value: AtomicU32,
+}
+
+impl LazyCrc {
+    fn get(&self) -> Option<u32> {
+        self.known
+            .load(Ordering::Acquire)
+            .then(|| self.value.load(Ordering::Relaxed))
+    }
+
+    fn get_or_init<F: FnOnce() -> u32>(&self, f: F) -> u32 {
+        if let Some(crc) = self.get() {
+            return crc;
+        }
+        let crc = f();
+        self.value.store(crc, Ordering::Relaxed);
+        self.known.store(true, Ordering::Release);
+        crc
+    }
+}
+
+impl Clone for LazyCrc {
+    fn clone(&self) -> Self {
+        let clone = Self::default();
+        if let Some(crc) = self.get() {
+            clone.get_or_init(|| crc);
+        }
+        clone
+    }
+}
+
+impl Debug for LazyCrc {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        self.get().fmt(f)
+    }
+}
+
+/// The value of the length field for `length` bytes of data
+fn length_field(length: usize) -> Result<u32, ChunkParseError> {
+    u32::try_from(length)
+        .ok()
+        .filter(|length| *length <= MAX_CHUNK_LENGTH)
+        .ok_or(ChunkParseError::DataTooLong(length as u64))
 }
 
 impl Chunk {
+    /// Creates a chunk holding `data`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `data` is longer than [`MAX_CHUNK_LENGTH`], use
+    /// [`Chunk::try_new`] for data of unknown length.
     pub fn new(chunk_type: ChunkType, data: Vec<u8>) -> Self {
-        let crc = Crc::<u32>::new(&CRC_32_ISO_HDLC);
-        let crc_bytes: Vec<u8> = chunk_type
-            .bytes()
-            .iter()
-            .chain(data.iter())
-            .cloned()
-            .collect();
+        match Self::try_new(chunk_type, data) {
+            Ok(chunk) => chunk,
+            Err(err) => panic!("{}", err),
+        }
+    }
+
+    /// Creates a chunk holding `data`, unless it is longer than
+    /// [`MAX_CHUNK_LENGTH`]
+    pub fn try_new(chunk_type: ChunkType, data: Vec<u8>) -> Result<Self, ChunkParseError> {
+        Ok(Self {
+            length: length_field(data.len())?,
+            crc: LazyCrc::default(),
+            chunk_type,
+            data: Data::Owned(data),
+        })
+    }
+
+    /// Parses the serialized chunk at `range` of `bytes`, keeping its data
+    /// in `bytes` instead of copying it
+    pub fn from_shared(bytes: Arc<[u8]>, range: Range<usize>) -> Result<Self, ChunkParseError> {
+        let value = bytes
+            .get(range.clone())
+            .ok_or(ChunkParseError::Incomplete)?;
+        let (chunk_type, data) = Self::parse_header(value)?;
+        let data = range.start + data.start..range.start + data.end;
+        let crc = Self::stored_crc(value);
+        Self::checked(chunk_type, Data::Shared(bytes, data), crc)
+    }
+
+    /// Checks the length field and type of the serialized chunk `value`,
+    /// returning the type and where the data is
+    fn parse_header(value: &[u8]) -> Result<(ChunkType, Range<usize>), ChunkParseError> {
+        // length (4) + type (4) + data (0) + crc (4) => 12
+        // 12 is the smallest chunk that can exist. By checking the length
+        // beforehand we can ensure that there will be no panics.
+        if value.len() < 12 {
+            return Err(ChunkParseError::Incomplete);
+        }
+
+        let length = u32::from_be_bytes(value[..4].try_into().unwrap());
+        if length > MAX_CHUNK_LENGTH {
+            return Err(ChunkParseError::LengthOutOfRange(length));
+        }
+
+        // The size of all fields except `data` are 12 bytes in total.
+        let expected = value.len() as u64 - 12;
+        if length as u64 != expected {
+            return Err(ChunkParseError::InvalidLengthField {
+                expected,
+                found: length,
+            });
+        }
+
+        let chunk_type: [u8; 4] = value[4..8].try_into().unwrap();
+        let chunk_type = ChunkType::try_from(chunk_type)?;
+        Ok((chunk_type, 8..value.len() - 4))
+    }
+
+    /// The crc field of the serialized chunk `value`
+    fn stored_crc(value: &[u8]) -> u32 {
+        u32::from_be_bytes(value[value.len() - 4..].try_into().unwrap())
+    }
 
-        Self {
-            length: data.len() as u32,
+    /// Creates a chunk with parsed data of a valid length, unless `crc` is
+    /// wrong for it
+    fn checked(chunk_type: ChunkType, data: Data, crc: u32) -> Result<Self, ChunkParseError> {
+        let chunk = Self {
+            length: data.as_slice().len() as u32,
             chunk_type,
             data,
-            crc: crc.checksum(&crc_bytes),
+            crc: LazyCrc::default(),
+        };
+        if crc != chunk.crc() {
+            return Err(ChunkParseError::InvalidChecksum);
         }
+        Ok(chunk)
+    }
+
+    /// The CRC of a chunk with `chunk_type` and `data`, without creating it
+    pub fn crc_of(chunk_type: &ChunkType, data: &[u8]) -> u32 {
+        let mut hasher = CrcHasher::new(chunk_type);
+        hasher.update(data);
+        hasher.finalize()
     }
 
     pub fn length(&self) -> u32 {
@@ -37,15 +241,32 @@ impl Chunk {
     }
 
     pub fn data(&self) -> &[u8] {
-        &self.data
+        self.data.as_slice()
     }
 
+    /// The checksum over the type and data, computed when first asked for
     pub fn crc(&self) -> u32 {
         self.crc
+            .get_or_init(|| Self::crc_of(&self.chunk_type, self.data()))
     }
 
-    pub fn data_as_string(&self) -> Result<String, std::string::FromUtf8Error> {
-        String::from_utf8(self.data.clone())
+    /// Replaces the data, updating the length and crc. The chunk is left
+    /// unchanged if `data` is longer than [`MAX_CHUNK_LENGTH`].
+    pub fn set_data(&mut self, data: Vec<u8>) -> Result<(), ChunkParseError> {
+        self.length = length_field(data.len())?;
+        self.crc = LazyCrc::default();
+        self.data = Data::Owned(data);
+        Ok(())
+    }
+
+    /// Replaces the data with what `f` makes of it, see [`Chunk::set_data`]
+    pub fn map_data<F: FnOnce(&[u8]) -> Vec<u8>>(&mut self, f: F) -> Result<(), ChunkParseError> {
+        let data = f(self.data());
+        self.set_data(data)
+    }
+
+    pub fn data_as_string(&self) -> Result<String, FromUtf8Error> {
+        String::from_utf8(self.data().to_vec())
     }
 
     pub fn as_bytes(&self) -> Vec<u8> {
@@ -53,20 +274,39 @@ impl Chunk {
             .to_be_bytes()
             .iter()
             .chain(self.chunk_type.bytes().iter())
-            .chain(self.data.iter())
-            .chain(self.crc.to_be_bytes().iter())
+            .chain(self.data().iter())
+            .chain(self.crc().to_be_bytes().iter())
             .copied()
             .collect()
     }
 }
 
+/// Two chunks are equal if they have the same type and data, the length and
+/// crc are derived from those.
+impl PartialEq for Chunk {
+    fn eq(&self, other: &Self) -> bool {
+        self.chunk_type == other.chunk_type && self.data() == other.data()
+    }
+}
+
+impl Eq for Chunk {}
+
 #[derive(Debug, thiserror::Error, PartialEq, Eq)]
 pub enum ChunkParseError {
     #[error("chunk did not contain all the required data")]
     Incomplete,
 
+    #[error("the chunk needs {needed} bytes, but only {available} are left")]
+    Truncated { needed: u64, available: u64 },
+
     #[error("invalid length field (expected {expected:?}, found {found:?})")]
-    InvalidLengthField { expected: u32, found: u32 },
+    InvalidLengthField { expected: u64, found: u32 },
+
+    #[error("the length field {0} is larger than the maximum of 2^31 - 1")]
+    LengthOutOfRange(u32),
+
+    #[error("a chunk can hold at most 2^31 - 1 bytes of data, got {0}")]
+    DataTooLong(u64),
 
     #[error(transparent)]
     InvalidChunkType(#[from] ChunkTypeParseError),
@@ -79,40 +319,38 @@ impl TryFrom<&[u8]> for Chunk {
     type Error = ChunkParseError;
 
     fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
-        // length (4) + type (4) + data (0) + crc (4) => 12
-        // 12 is the smallest chunk that can exist. By checking the length
-        // beforehand we can ensure that there will be no panics.
-        if value.len() < 12 {
-            return Err(ChunkParseError::Incomplete);
-        }
-
-        let length = u32::from_be_bytes(value[..4].try_into().unwrap());
-
-        // The size of all fields except `data` are 12 bytes in total.
-        if length != value.len() as u32 - 12 {
-            return Err(ChunkParseError::InvalidLengthField {
-                expected: value.len() as u32 - 12,
-                found: length,
-            });
-        }
-
-        let chunk_type: [u8; 4] = value[4..8].try_into().unwrap();
-        let chunk_type = ChunkType::try_from(chunk_type)?;
-
-        let data = Vec::from(&value[8..value.len() - 4]);
-        let crc = u32::from_be_bytes(value[value.len() - 4..].try_into().unwrap());
+        let (chunk_type, data) = Self::parse_header(value)?;
+        let data = Data::Owned(value[data].to_vec());
+        Self::checked(chunk_type, data, Self::stored_crc(value))
+    }
+}
 
-        let chunk = Self::new(chunk_type, data);
-        if crc != chunk.crc() {
-            return Err(ChunkParseError::InvalidChecksum);
-        }
+/// Generates chunks with a matching length and checksum. Data longer than
+/// a chunk can hold is rejected as [`arbitrary::Error::IncorrectFormat`].
+#[cfg(feature = "arbitrary")]
+impl<'a> arbitrary::Arbitrary<'a> for Chunk {
+    fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
+        Self::try_new(ChunkType::arbitrary(u)?, Vec::arbitrary(u)?)
+            .map_err(|_| arbitrary::Error::IncorrectFormat)
+    }
+}
 
-        Ok(chunk)
+#[cfg(any(test, feature = "test-util"))]
+impl Chunk {
+    /// Generates chunks of any valid type with up to `max_length` bytes of
+    /// data
+    pub fn arbitrary_valid(max_length: usize) -> impl proptest::strategy::Strategy<Value = Self> {
+        use proptest::prelude::*;
+        (
+            ChunkType::arbitrary_valid(),
+            proptest::collection::vec(any::<u8>(), 0..=max_length),
+        )
+            .prop_map(|(chunk_type, data)| Self::new(chunk_type, data))
     }
 }
 
 impl Display for Chunk {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         write!(
             f,
             "{{ length: {:4}, type: {}, data: {}, crc: {:10} }}",
@@ -120,7 +358,7 @@ impl Display for Chunk {
             self.chunk_type,
             self.data_as_string()
                 .unwrap_or_else(|_| "<Invalid UTF-8>".to_owned()),
-            self.crc
+            self.crc()
         )
     }
 }
@@ -160,6 +398,109 @@ mod tests {
         assert_eq!(chunk.crc(), 2882656334);
     }
 
+    #[test]
+    fn test_lazy_crc() {
+        let chunk = Chunk::new(ChunkType::from_str("RuSt").unwrap(), b"hidden".to_vec());
+        assert_eq!(chunk.crc.get(), None);
+        let crc = chunk.crc();
+        assert_eq!(chunk.crc.get(), Some(crc));
+        assert_eq!(chunk.clone().crc.get(), Some(crc));
+
+        // Parsing checks the crc, so it is known afterwards
+        let parsed = Chunk::try_from(&chunk.as_bytes()[..]).unwrap();
+        assert_eq!(parsed.crc.get(), Some(crc));
+    }
+
+    #[test]
+    fn test_from_shared() {
+        let chunk = testing_chunk();
+        let mut file = b"junk".to_vec();
+        file.extend(chunk.as_bytes());
+        let bytes: Arc<[u8]> = file.into();
+
+        let shared = Chunk::from_shared(bytes.clone(), 4..bytes.len()).unwrap();
+        assert_eq!(shared, chunk);
+        assert_eq!(shared.crc(), chunk.crc());
+        assert_eq!(shared.as_bytes(), chunk.as_bytes());
+        assert_eq!(shared.data().as_ptr(), bytes[12..].as_ptr());
+
+        assert_eq!(
+            Chunk::from_shared(bytes.clone(), 4..bytes.len() + 1).unwrap_err(),
+            ChunkParseError::Incomplete
+        );
+        assert!(Chunk::from_shared(bytes.clone(), 3..bytes.len()).is_err());
+    }
+
+    #[test]
+    fn test_set_data() {
+        let mut chunk = Chunk::new(ChunkType::from_str("RuSt").unwrap(), b"hidden".to_vec());
+        chunk
+            .set_data(b"This is where your secret message will be!".to_vec())
+            .unwrap();
+        assert_eq!(chunk.length(), 42);
+        assert_eq!(chunk.crc(), 2882656334);
+        assert_eq!(chunk, testing_chunk());
+
+        chunk.map_data(|data| data[..13].to_vec()).unwrap();
+        assert_eq!(chunk.crc.get(), None);
+        assert_eq!(chunk.data(), b"This is where");
+        assert_eq!(chunk.length(), 13);
+        assert_eq!(
+            chunk.crc(),
+            Chunk::crc_of(chunk.chunk_type(), b"This is where")
+        );
+        assert_eq!(Chunk::try_from(&chunk.as_bytes()[..]), Ok(chunk));
+    }
+
+    #[test]
+    fn test_length_field_bounds() {
+        assert_eq!(length_field(0), Ok(0));
+        assert_eq!(length_field((1 << 31) - 1), Ok(MAX_CHUNK_LENGTH));
+        assert_eq!(
+            length_field(1 << 31),
+            Err(ChunkParseError::DataTooLong(1 << 31))
+        );
+        // Would truncate to 5 as a u32
+        let wrapping = (1u64 << 32) + 5;
+        if let Ok(length) = usize::try_from(wrapping) {
+            assert_eq!(
+                length_field(length),
+                Err(ChunkParseError::DataTooLong(wrapping))
+            );
+        }
+    }
+
+    #[test]
+    fn test_length_out_of_range() {
+        for length in [1u32 << 31, u32::MAX - 11, u32::MAX] {
+            let mut bytes = length.to_be_bytes().to_vec();
+            bytes.extend_from_slice(b"RuSt\0\0\0\0");
+            assert_eq!(
+                Chunk::try_from(bytes.as_ref()),
+                Err(ChunkParseError::LengthOutOfRange(length))
+            );
+        }
+
+        let mut bytes = MAX_CHUNK_LENGTH.to_be_bytes().to_vec();
+        bytes.extend_from_slice(b"RuSt\0\0\0\0");
+        assert_eq!(
+            Chunk::try_from(bytes.as_ref()),
+            Err(ChunkParseError::InvalidLengthField {
+                expected: 0,
+                found: MAX_CHUNK_LENGTH
+            })
+        );
+    }
+
+    #[test]
+    fn test_fuzz_corpus() {
+        for (name, input) in crate::png::tests::fuzz_corpus("parse_chunk") {
+            if let Ok(chunk) = Chunk::try_from(input.as_ref()) {
+                assert_eq!(chunk.as_bytes(), input, "{}", name);
+            }
+        }
+    }
+
     #[test]
     fn test_chunk_length() {
         let chunk = testing_chunk();
@@ -180,6 +521,19 @@ mod tests {
         assert_eq!(chunk_string, expected_chunk_string);
     }
 
+    #[test]
+    fn test_crc_of() {
+        let chunk_type = ChunkType::from_str("RuSt").unwrap();
+        let data = b"This is where your secret message will be!";
+        assert_eq!(Chunk::crc_of(&chunk_type, data), 2882656334);
+
+        let mut hasher = CrcHasher::new(&chunk_type);
+        for piece in data.chunks(5) {
+            hasher.update(piece);
+        }
+        assert_eq!(hasher.finalize(), 2882656334);
+    }
+
     #[test]
     fn test_chunk_crc() {
         let chunk = testing_chunk();
@@ -267,6 +621,15 @@ mod tests {
         assert_eq!(chunk.err().unwrap(), ChunkParseError::Incomplete);
     }
 
+    #[test]
+    fn test_chunk_clone_eq() {
+        let chunk = testing_chunk();
+        assert_eq!(chunk.clone(), chunk);
+
+        let other = Chunk::new(ChunkType::from_str("RuSt").unwrap(), b"other".to_vec());
+        assert_ne!(chunk, other);
+    }
+
     // TODO: Add 2 tests for invalid checksum and chunk type
 
     #[test]
@@ -289,4 +652,13 @@ mod tests {
 
         let _chunk_string = format!("{}", chunk);
     }
+
+    proptest::proptest! {
+        #[test]
+        fn prop_roundtrip(chunk in Chunk::arbitrary_valid(1024)) {
+            let bytes = chunk.as_bytes();
+            proptest::prop_assert_eq!(bytes.len(), chunk.length() as usize + 12);
+            proptest::prop_assert_eq!(Chunk::try_from(bytes.as_ref()), Ok(chunk));
+        }
+    }
 }
@@ -1,15 +1,386 @@
-use std::{fmt::Display, str::FromStr};
+use alloc::string::String;
+use core::{fmt::Display, str::FromStr};
+
+/// The chunk types defined by the PNG specification (third edition,
+/// including APNG) and the registered extensions
+pub const STANDARD_TYPES: &[&[u8; 4]] = &[
+    b"IHDR", b"PLTE", b"IDAT", b"IEND", b"tRNS", b"cHRM", b"gAMA", b"iCCP", b"sBIT", b"sRGB",
+    b"cICP", b"mDCV", b"cLLI", b"tEXt", b"zTXt", b"iTXt", b"bKGD", b"hIST", b"pHYs", b"sPLT",
+    b"eXIf", b"tIME", b"acTL", b"fcTL", b"fdAT", b"oFFs", b"pCAL", b"sCAL", b"gIFg", b"gIFx",
+    b"gIFt", b"sTER", b"dSIG", b"fRAc",
+];
+
+/// Explains what makes a chunk type, for error messages and help texts
+pub const SYNTAX_HELP: &str = "\
+A chunk type is 4 ASCII letters. The case of each letter sets a property:
+  1st letter: uppercase if critical, lowercase if ancillary (decoders may ignore it)
+  2nd letter: uppercase if public (registered), lowercase if private
+  3rd letter: uppercase, lowercase is reserved
+  4th letter: uppercase if unsafe to copy, lowercase if editors may copy it unchanged
+Data of your own belongs in ancillary, private, safe-to-copy types, e.g. ruSt or prVt.";
+
+/// How many chunks of a registered type a PNG may have
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Multiplicity {
+    /// At most one
+    Single,
+    /// Any number
+    Multiple,
+}
+
+/// What is known about a chunk type of the specification, a registered
+/// extension, well-known software or pngme, see
+/// [`ChunkType::registered_info`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RegisteredInfo {
+    pub chunk_type: [u8; 4],
+    pub name: &'static str,
+    pub purpose: &'static str,
+    pub multiplicity: Multiplicity,
+}
+
+impl RegisteredInfo {
+    const fn new(
+        chunk_type: [u8; 4],
+        name: &'static str,
+        purpose: &'static str,
+        multiplicity: Multiplicity,
+    ) -> Self {
+        Self {
+            chunk_type,
+            name,
+            purpose,
+            multiplicity,
+        }
+    }
+}
+
+/// The [`STANDARD_TYPES`] and the types written by common software and pngme
+pub const REGISTERED_TYPES: &[RegisteredInfo] = &[
+    RegisteredInfo::new(
+        *b"IHDR",
+        "Image header",
+        "the width, height, bit depth, color type and interlacing",
+        Multiplicity::Single,
+    ),
+    RegisteredInfo::new(
+        *b"PLTE",
+        "Palette",
+        "the colors of indexed images",
+        Multiplicity::Single,
+    ),
+    RegisteredInfo::new(
+        *b"IDAT",
+        "Image data",
+        "the compressed, filtered scanlines",
+        Multiplicity::Multiple,
+    ),
+    RegisteredInfo::new(
+        *b"IEND",
+        "Image trailer",
+        "marks the end of the PNG",
+        Multiplicity::Single,
+    ),
+    RegisteredInfo::new(
+        *b"tRNS",
+        "Transparency",
+        "the alpha of palette entries, or a transparent color",
+        Multiplicity::Single,
+    ),
+    RegisteredInfo::new(
+        *b"cHRM",
+        "Primary chromaticities",
+        "the chromaticities of the primaries and the white point",
+        Multiplicity::Single,
+    ),
+    RegisteredInfo::new(
+        *b"gAMA",
+        "Image gamma",
+        "the gamma the image was encoded with",
+        Multiplicity::Single,
+    ),
+    RegisteredInfo::new(
+        *b"iCCP",
+        "Embedded ICC profile",
+        "the color space as an ICC profile",
+        Multiplicity::Single,
+    ),
+    RegisteredInfo::new(
+        *b"sBIT",
+        "Significant bits",
+        "the original bit depth of the samples",
+        Multiplicity::Single,
+    ),
+    RegisteredInfo::new(
+        *b"sRGB",
+        "Standard RGB color space",
+        "the image is sRGB, rendered with the given intent",
+        Multiplicity::Single,
+    ),
+    RegisteredInfo::new(
+        *b"cICP",
+        "Coding-independent code points",
+        "the color space as signalled in video, e.g. for HDR",
+        Multiplicity::Single,
+    ),
+    RegisteredInfo::new(
+        *b"mDCV",
+        "Mastering display color volume",
+        "the display the image was mastered on",
+        Multiplicity::Single,
+    ),
+    RegisteredInfo::new(
+        *b"cLLI",
+        "Content light level information",
+        "the brightest and average light level of the image",
+        Multiplicity::Single,
+    ),
+    RegisteredInfo::new(
+        *b"tEXt",
+        "Textual data",
+        "a Latin-1 keyword and text",
+        Multiplicity::Multiple,
+    ),
+    RegisteredInfo::new(
+        *b"zTXt",
+        "Compressed textual data",
+        "a Latin-1 keyword and compressed text",
+        Multiplicity::Multiple,
+    ),
+    RegisteredInfo::new(
+        *b"iTXt",
+        "International textual data",
+        "a keyword and UTF-8 text, which may be compressed",
+        Multiplicity::Multiple,
+    ),
+    RegisteredInfo::new(
+        *b"bKGD",
+        "Background color",
+        "the color to show the image on",
+        Multiplicity::Single,
+    ),
+    RegisteredInfo::new(
+        *b"hIST",
+        "Image histogram",
+        "how often each palette entry is used",
+        Multiplicity::Single,
+    ),
+    RegisteredInfo::new(
+        *b"pHYs",
+        "Physical pixel dimensions",
+        "the size or aspect ratio of the pixels",
+        Multiplicity::Single,
+    ),
+    RegisteredInfo::new(
+        *b"sPLT",
+        "Suggested palette",
+        "a palette for displays with fewer colors",
+        Multiplicity::Multiple,
+    ),
+    RegisteredInfo::new(
+        *b"eXIf",
+        "Exchangeable image file profile",
+        "Exif metadata",
+        Multiplicity::Single,
+    ),
+    RegisteredInfo::new(
+        *b"tIME",
+        "Image last-modification time",
+        "when the image was last changed",
+        Multiplicity::Single,
+    ),
+    RegisteredInfo::new(
+        *b"acTL",
+        "Animation control",
+        "the number of frames and plays of an APNG",
+        Multiplicity::Single,
+    ),
+    RegisteredInfo::new(
+        *b"fcTL",
+        "Frame control",
+        "the size, position, delay and blending of an APNG frame",
+        Multiplicity::Multiple,
+    ),
+    RegisteredInfo::new(
+        *b"fdAT",
+        "Frame data",
+        "the image data of an APNG frame",
+        Multiplicity::Multiple,
+    ),
+    RegisteredInfo::new(
+        *b"oFFs",
+        "Image offset",
+        "the position of the image on a page",
+        Multiplicity::Single,
+    ),
+    RegisteredInfo::new(
+        *b"pCAL",
+        "Calibration of pixel values",
+        "maps the samples to physical values",
+        Multiplicity::Single,
+    ),
+    RegisteredInfo::new(
+        *b"sCAL",
+        "Physical scale of image subject",
+        "the physical size the pixels cover",
+        Multiplicity::Single,
+    ),
+    RegisteredInfo::new(
+        *b"gIFg",
+        "GIF graphic control extension",
+        "the delay and disposal of a frame converted from GIF",
+        Multiplicity::Multiple,
+    ),
+    RegisteredInfo::new(
+        *b"gIFx",
+        "GIF application extension",
+        "application data converted from GIF",
+        Multiplicity::Multiple,
+    ),
+    RegisteredInfo::new(
+        *b"gIFt",
+        "GIF plain text extension",
+        "text converted from GIF (deprecated)",
+        Multiplicity::Multiple,
+    ),
+    RegisteredInfo::new(
+        *b"sTER",
+        "Indicator of stereo image",
+        "the image is a side-by-side stereo pair",
+        Multiplicity::Single,
+    ),
+    RegisteredInfo::new(
+        *b"dSIG",
+        "Digital signature",
+        "a signature over the chunks",
+        Multiplicity::Multiple,
+    ),
+    RegisteredInfo::new(
+        *b"fRAc",
+        "Fractal image parameters",
+        "the parameters of a fractal image",
+        Multiplicity::Multiple,
+    ),
+    RegisteredInfo::new(
+        *b"vpAg",
+        "Virtual page",
+        "the canvas size, written by ImageMagick",
+        Multiplicity::Single,
+    ),
+    RegisteredInfo::new(
+        *b"caBX",
+        "C2PA manifest store",
+        "content credentials, see the provenance command",
+        Multiplicity::Single,
+    ),
+    RegisteredInfo::new(
+        *b"iDOT",
+        "Apple iDOT",
+        "where the image data splits for parallel decoding, written by Apple",
+        Multiplicity::Single,
+    ),
+    RegisteredInfo::new(
+        *b"CgBI",
+        "Apple CgBI",
+        "marks Xcode's iOS-optimized PNGs, which other decoders can't read",
+        Multiplicity::Single,
+    ),
+    RegisteredInfo::new(
+        *b"npTc",
+        "Nine-patch",
+        "the stretchable regions of an Android nine-patch image",
+        Multiplicity::Single,
+    ),
+    RegisteredInfo::new(
+        *b"npLb",
+        "Nine-patch layout bounds",
+        "the layout bounds of an Android nine-patch image",
+        Multiplicity::Single,
+    ),
+    RegisteredInfo::new(
+        *b"npOl",
+        "Nine-patch outline",
+        "the outline of an Android nine-patch image",
+        Multiplicity::Single,
+    ),
+    RegisteredInfo::new(
+        *b"mkBF",
+        "Fireworks data",
+        "editable document data of Adobe Fireworks",
+        Multiplicity::Multiple,
+    ),
+    RegisteredInfo::new(
+        *b"mkBS",
+        "Fireworks data",
+        "editable document data of Adobe Fireworks",
+        Multiplicity::Multiple,
+    ),
+    RegisteredInfo::new(
+        *b"mkBT",
+        "Fireworks data",
+        "editable document data of Adobe Fireworks",
+        Multiplicity::Multiple,
+    ),
+    RegisteredInfo::new(
+        *b"mkTS",
+        "Fireworks data",
+        "editable document data of Adobe Fireworks",
+        Multiplicity::Multiple,
+    ),
+    RegisteredInfo::new(
+        *b"stMp",
+        "pngme stamp",
+        "build metadata, see the stamp command",
+        Multiplicity::Single,
+    ),
+    RegisteredInfo::new(
+        *b"pmMf",
+        "pngme manifest",
+        "how payloads were stored, see encode --manifest",
+        Multiplicity::Single,
+    ),
+    RegisteredInfo::new(
+        *b"pmHs",
+        "pngme history",
+        "the changes which can be undone, see --history",
+        Multiplicity::Multiple,
+    ),
+    RegisteredInfo::new(
+        *b"pmAt",
+        "pngme attestation",
+        "a signed record of an edit, see --attest",
+        Multiplicity::Multiple,
+    ),
+    RegisteredInfo::new(
+        *b"fpRt",
+        "pngme fingerprint",
+        "the marker of a fingerprinted copy",
+        Multiplicity::Single,
+    ),
+    RegisteredInfo::new(
+        *b"paDd",
+        "pngme padding",
+        "cover padding, see encode --pad-to",
+        Multiplicity::Single,
+    ),
+];
 
 /// A representation of a PNG 1.2 conform chunk type
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct ChunkType([u8; 4]);
 
+// Parsed files are shared between threads, see `Png::parse_shared`
+const _: () = {
+    const fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<ChunkType>();
+};
+
 #[derive(Debug, thiserror::Error, PartialEq, Eq)]
 pub enum ChunkTypeParseError {
     #[error("expected a length of 4 bytes but got {0} instead")]
     InvalidLength(usize),
 
-    #[error("found an invalid byte {0}, only [A-Za-z] allowed")]
+    #[error("found an invalid byte '{}', only [A-Za-z] allowed", core::ascii::escape_default(*.0))]
     InvalidByte(u8),
 }
 
@@ -36,8 +407,38 @@ impl FromStr for ChunkType {
     }
 }
 
+/// Only generates valid types, with each property bit set at random
+#[cfg(feature = "arbitrary")]
+impl<'a> arbitrary::Arbitrary<'a> for ChunkType {
+    fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
+        let mut bytes = [0; 4];
+        for byte in &mut bytes {
+            let letter = u.int_in_range(0..=51)?;
+            *byte = if letter < 26 {
+                b'A' + letter
+            } else {
+                b'a' + letter - 26
+            };
+        }
+        Ok(Self(bytes))
+    }
+
+    fn size_hint(_depth: usize) -> (usize, Option<usize>) {
+        (4, Some(4))
+    }
+}
+
+#[cfg(any(test, feature = "test-util"))]
+impl ChunkType {
+    /// Generates valid chunk types, with each property bit set at random
+    pub fn arbitrary_valid() -> impl proptest::strategy::Strategy<Value = Self> {
+        use proptest::prelude::*;
+        "[A-Za-z]{4}".prop_map(|letters| Self(letters.as_bytes().try_into().unwrap()))
+    }
+}
+
 impl Display for ChunkType {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         write!(f, "{}", String::from_utf8(self.0.to_vec()).unwrap())
     }
 }
@@ -67,6 +468,25 @@ impl ChunkType {
     pub fn is_safe_to_copy(&self) -> bool {
         !nth_bit(self.0[3], 5)
     }
+
+    /// Whether the type is one of the [`STANDARD_TYPES`]
+    pub fn is_standard(&self) -> bool {
+        STANDARD_TYPES.contains(&&self.0)
+    }
+
+    /// The name, purpose and allowed multiplicity of the type, if it is one
+    /// of the [`REGISTERED_TYPES`]
+    pub fn registered_info(&self) -> Option<&'static RegisteredInfo> {
+        REGISTERED_TYPES
+            .iter()
+            .find(|info| info.chunk_type == self.0)
+    }
+
+    /// Whether a PNG may only have one chunk of this type
+    pub fn is_single(&self) -> bool {
+        self.registered_info()
+            .is_some_and(|info| info.multiplicity == Multiplicity::Single)
+    }
 }
 
 #[inline]
@@ -159,6 +579,39 @@ mod tests {
 
         let chunk = ChunkType::try_from(b"Ru1t".to_owned());
         assert!(chunk.is_err());
+
+        let err = ChunkType::try_from(*b"Ru\0t").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "found an invalid byte '\\x00', only [A-Za-z] allowed"
+        );
+    }
+
+    #[test]
+    pub fn test_chunk_type_is_standard() {
+        assert!(ChunkType::from_str("IDAT").unwrap().is_standard());
+        assert!(ChunkType::from_str("eXIf").unwrap().is_standard());
+        assert!(!ChunkType::from_str("RuSt").unwrap().is_standard());
+        assert!(!ChunkType::from_str("idat").unwrap().is_standard());
+    }
+
+    #[test]
+    pub fn test_registered_info() {
+        for chunk_type in STANDARD_TYPES {
+            assert!(ChunkType::try_from(**chunk_type)
+                .unwrap()
+                .registered_info()
+                .is_some());
+        }
+        let info = ChunkType::from_str("vpAg")
+            .unwrap()
+            .registered_info()
+            .unwrap();
+        assert_eq!(info.name, "Virtual page");
+        assert!(ChunkType::from_str("gAMA").unwrap().is_single());
+        assert!(!ChunkType::from_str("tEXt").unwrap().is_single());
+        assert_eq!(ChunkType::from_str("RuSt").unwrap().registered_info(), None);
+        assert!(!ChunkType::from_str("RuSt").unwrap().is_single());
     }
 
     #[test]
@@ -174,4 +627,12 @@ mod tests {
         let _chunk_string = format!("{}", chunk_type_1);
         let _are_chunks_equal = chunk_type_1 == chunk_type_2;
     }
+
+    proptest::proptest! {
+        #[test]
+        fn prop_roundtrip(chunk_type in ChunkType::arbitrary_valid()) {
+            proptest::prop_assert_eq!(ChunkType::from_str(&chunk_type.to_string()), Ok(chunk_type.clone()));
+            proptest::prop_assert_eq!(ChunkType::try_from(chunk_type.bytes()), Ok(chunk_type));
+        }
+    }
 }
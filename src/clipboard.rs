@@ -0,0 +1,45 @@
+/// Why the system clipboard couldn't be used
+#[derive(Debug, thiserror::Error)]
+pub enum ClipboardError {
+    #[error("pngme was built without clipboard support, rebuild it with --features clipboard")]
+    Unsupported,
+
+    #[error("the clipboard doesn't hold any text")]
+    Empty,
+
+    #[error("the payload is not text, so it can't be copied to the clipboard")]
+    NotText,
+
+    #[cfg(feature = "clipboard")]
+    #[error("failed to access the clipboard")]
+    Access(#[from] arboard::Error),
+}
+
+/// Reads the text on the clipboard
+#[cfg(feature = "clipboard")]
+pub fn read_text() -> Result<String, ClipboardError> {
+    match arboard::Clipboard::new()?.get_text() {
+        Ok(text) if !text.is_empty() => Ok(text),
+        Ok(_) | Err(arboard::Error::ContentNotAvailable) => Err(ClipboardError::Empty),
+        Err(err) => Err(err.into()),
+    }
+}
+
+#[cfg(not(feature = "clipboard"))]
+pub fn read_text() -> Result<String, ClipboardError> {
+    Err(ClipboardError::Unsupported)
+}
+
+/// Puts `data` on the clipboard as text. On Linux, the text stays available
+/// after pngme exits only if a clipboard manager is running.
+#[cfg(feature = "clipboard")]
+pub fn write_text(data: &[u8]) -> Result<(), ClipboardError> {
+    let text = std::str::from_utf8(data).map_err(|_| ClipboardError::NotText)?;
+    arboard::Clipboard::new()?.set_text(text)?;
+    Ok(())
+}
+
+#[cfg(not(feature = "clipboard"))]
+pub fn write_text(_data: &[u8]) -> Result<(), ClipboardError> {
+    Err(ClipboardError::Unsupported)
+}
@@ -0,0 +1,271 @@
+use crate::{
+    audit::{Finding, Location, Rule},
+    chunk::Chunk,
+    chunk_type::ChunkType,
+    png::Png,
+};
+use std::{
+    fmt::{self, Display},
+    str::FromStr,
+};
+
+/// The gamma implied by `sRGB`, times 100000
+pub const SRGB_GAMMA: u32 = 45455;
+
+/// The white point and the red, green and blue primaries implied by `sRGB`,
+/// as x and y times 100000, in the order of the `cHRM` chunk
+pub const SRGB_CHROMATICITIES: [u32; 8] = [31270, 32900, 64000, 33000, 30000, 60000, 15000, 6000];
+
+/// The color management chunks, which may only appear once each
+pub const COLOR_CHUNKS: &[&[u8; 4]] = &[b"gAMA", b"cHRM", b"sRGB", b"iCCP"];
+
+/// How colors outside the gamut of the output device are mapped, from the
+/// `sRGB` chunk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RenderingIntent {
+    Perceptual,
+    RelativeColorimetric,
+    Saturation,
+    AbsoluteColorimetric,
+}
+
+impl RenderingIntent {
+    pub(crate) fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            0 => Some(Self::Perceptual),
+            1 => Some(Self::RelativeColorimetric),
+            2 => Some(Self::Saturation),
+            3 => Some(Self::AbsoluteColorimetric),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn to_byte(self) -> u8 {
+        self as u8
+    }
+}
+
+impl Display for RenderingIntent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Perceptual => "perceptual",
+            Self::RelativeColorimetric => "relative colorimetric",
+            Self::Saturation => "saturation",
+            Self::AbsoluteColorimetric => "absolute colorimetric",
+        })
+    }
+}
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+#[error("unknown rendering intent '{0}', expected perceptual, relative, saturation or absolute")]
+pub struct UnknownIntentError(String);
+
+impl FromStr for RenderingIntent {
+    type Err = UnknownIntentError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "perceptual" => Ok(Self::Perceptual),
+            "relative" => Ok(Self::RelativeColorimetric),
+            "saturation" => Ok(Self::Saturation),
+            "absolute" => Ok(Self::AbsoluteColorimetric),
+            other => Err(UnknownIntentError(other.to_owned())),
+        }
+    }
+}
+
+/// A color management chunk as seen by [`verify`](crate::verify::verify)
+pub(crate) struct ColorChunk<'a> {
+    pub chunk_type: [u8; 4],
+    pub location: Location,
+    pub data: &'a [u8],
+}
+
+fn chromaticities(data: &[u8]) -> Vec<u32> {
+    data.chunks_exact(4)
+        .map(|value| u32::from_be_bytes(value.try_into().unwrap()))
+        .collect()
+}
+
+/// Reports color management chunks with invalid contents, and combinations
+/// that contradict each other: `iCCP` next to `sRGB`, and `gAMA` or `cHRM`
+/// values other than the ones `sRGB` implies
+pub(crate) fn check(chunks: &[ColorChunk<'_>]) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    let mut finding = |location, message| {
+        findings.push(Finding {
+            rule: Rule::ColorConflict,
+            location: Some(location),
+            message,
+        })
+    };
+    let find = |chunk_type: &[u8; 4]| chunks.iter().find(|chunk| &chunk.chunk_type == chunk_type);
+
+    let srgb = find(b"sRGB");
+    if let Some(chunk) = srgb {
+        if chunk.data.len() != 1 || RenderingIntent::from_byte(chunk.data[0]).is_none() {
+            finding(
+                chunk.location,
+                "the sRGB chunk doesn't hold a valid rendering intent".to_owned(),
+            );
+        }
+    }
+
+    if let Some(chunk) = find(b"gAMA") {
+        match <[u8; 4]>::try_from(chunk.data).map(u32::from_be_bytes) {
+            Ok(0) | Err(_) => finding(
+                chunk.location,
+                "the gAMA chunk doesn't hold a valid gamma".to_owned(),
+            ),
+            Ok(gamma) if srgb.is_some() && gamma != SRGB_GAMMA => finding(
+                chunk.location,
+                format!(
+                    "the gAMA chunk declares a gamma of {:.5}, but sRGB implies {:.5}",
+                    gamma as f64 / 100000.0,
+                    SRGB_GAMMA as f64 / 100000.0
+                ),
+            ),
+            Ok(_) => {}
+        }
+    }
+
+    if let Some(chunk) = find(b"cHRM") {
+        if chunk.data.len() != 32 {
+            finding(
+                chunk.location,
+                format!(
+                    "the cHRM chunk has {} bytes of data instead of 32",
+                    chunk.data.len()
+                ),
+            );
+        } else if srgb.is_some() && chromaticities(chunk.data) != SRGB_CHROMATICITIES {
+            finding(
+                chunk.location,
+                "the cHRM chunk declares other chromaticities than the sRGB primaries".to_owned(),
+            );
+        }
+    }
+
+    if let (Some(_), Some(chunk)) = (srgb, find(b"iCCP")) {
+        finding(
+            chunk.location,
+            "both an sRGB and an iCCP chunk declare the color space".to_owned(),
+        );
+    }
+
+    findings
+}
+
+/// Declares the image as sRGB with `intent`: removes `iCCP` and writes
+/// `sRGB` together with the matching `gAMA` and `cHRM` for decoders which
+/// don't understand `sRGB`, replacing any existing ones. Returns the number of
+/// chunks removed.
+pub fn set_srgb(png: &mut Png, intent: RenderingIntent) -> usize {
+    let before = png.len();
+    png.retain(|chunk| !COLOR_CHUNKS.contains(&&chunk.chunk_type().bytes()));
+    let removed = before - png.len();
+
+    let chrm = SRGB_CHROMATICITIES
+        .iter()
+        .flat_map(|value| value.to_be_bytes())
+        .collect();
+    let chunks = [
+        (b"sRGB", vec![intent.to_byte()]),
+        (b"gAMA", SRGB_GAMMA.to_be_bytes().to_vec()),
+        (b"cHRM", chrm),
+    ];
+
+    // Color management chunks go before PLTE and IDAT, right after IHDR will do
+    let start = png
+        .chunks()
+        .iter()
+        .position(|chunk| &chunk.chunk_type().bytes() == b"IHDR")
+        .map_or(0, |index| index + 1);
+    for (offset, (chunk_type, data)) in chunks.into_iter().enumerate() {
+        png.insert_chunk(
+            start + offset,
+            Chunk::new(ChunkType::try_from(*chunk_type).unwrap(), data),
+        );
+    }
+    removed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cancel::CancellationToken, png::tests::PNG_FILE, verify::verify};
+
+    fn chunk(chunk_type: &[u8; 4], data: Vec<u8>) -> Chunk {
+        Chunk::new(ChunkType::try_from(*chunk_type).unwrap(), data)
+    }
+
+    /// The test PNG with its own color management chunks replaced by `chunks`
+    fn with_chunks(chunks: Vec<Chunk>) -> Png {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        png.retain(|chunk| !COLOR_CHUNKS.contains(&&chunk.chunk_type().bytes()));
+        for chunk in chunks.into_iter().rev() {
+            png.insert_chunk(1, chunk);
+        }
+        png
+    }
+
+    fn messages(png: &Png) -> Vec<String> {
+        verify(&png.as_bytes(), &CancellationToken::new())
+            .unwrap()
+            .into_iter()
+            .filter(|finding| finding.rule == Rule::ColorConflict)
+            .map(|finding| finding.message)
+            .collect()
+    }
+
+    #[test]
+    fn test_consistent() {
+        let mut png = with_chunks(vec![]);
+        assert!(messages(&png).is_empty());
+        set_srgb(&mut png, RenderingIntent::Perceptual);
+        assert!(messages(&png).is_empty());
+    }
+
+    #[test]
+    fn test_conflicts() {
+        let png = with_chunks(vec![
+            chunk(b"sRGB", vec![0]),
+            chunk(b"gAMA", 100000u32.to_be_bytes().to_vec()),
+            chunk(b"cHRM", vec![0; 32]),
+            chunk(b"iCCP", b"profile\0\0".to_vec()),
+        ]);
+        assert_eq!(
+            messages(&png),
+            [
+                "the gAMA chunk declares a gamma of 1.00000, but sRGB implies 0.45455",
+                "the cHRM chunk declares other chromaticities than the sRGB primaries",
+                "both an sRGB and an iCCP chunk declare the color space",
+            ]
+        );
+
+        let png = with_chunks(vec![chunk(b"sRGB", vec![4]), chunk(b"gAMA", vec![0; 4])]);
+        assert_eq!(
+            messages(&png),
+            [
+                "the sRGB chunk doesn't hold a valid rendering intent",
+                "the gAMA chunk doesn't hold a valid gamma",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_set_srgb() {
+        let mut png = with_chunks(vec![
+            chunk(b"iCCP", b"profile\0\0".to_vec()),
+            chunk(b"gAMA", 100000u32.to_be_bytes().to_vec()),
+        ]);
+        assert_eq!(set_srgb(&mut png, RenderingIntent::Saturation), 2);
+
+        let types: Vec<String> = png.chunks()[..4]
+            .iter()
+            .map(|chunk| chunk.chunk_type().to_string())
+            .collect();
+        assert_eq!(types, ["IHDR", "sRGB", "gAMA", "cHRM"]);
+        assert_eq!(png.chunks()[1].data(), [2]);
+    }
+}
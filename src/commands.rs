@@ -1,51 +1,2718 @@
-use crate::{chunk::Chunk, chunk_type::ChunkType, png::Png};
+use crate::{
+    advice::{self, Sensitivity},
+    ancillary::{self, Ancillary},
+    archive::Carrier,
+    attest::{self, SigningKey, VerifyingKey},
+    attributes::{self, Preserve},
+    audit::{self, Finding, Rule, Severity},
+    audit_log::{self, LogEntry},
+    batch::{self, FileOutcome, InputOrder, Outcome, Summary},
+    cancel::CancellationToken,
+    chunk::CrcHasher,
+    chunk_type::ChunkType,
+    clipboard,
+    color::{self, RenderingIntent},
+    console::{self, errln, outln, Paging, Style, StyledText},
+    container::{self, Container, StegoContainer},
+    date, decodable,
+    dedup::{self, DedupIndex},
+    detect,
+    display::{self, DisplayOptions},
+    filter::StripFilter,
+    fingerprint::{self, Identification},
+    history::{self, HistoryEntry},
+    ihdr::ColorType,
+    json::{self, Query, QueryError},
+    license::License,
+    lock::{FileLock, LockMode},
+    manifest::{Manifest, ManifestEntry, Transform},
+    meta,
+    normalize::{self, Normalized},
+    optimize::{self, Refiltered, Savings},
+    output::OutputFormat,
+    padding::{self, Padding},
+    palette::{self, PaletteFormat},
+    payload::{self, ContentType, Envelope, Part},
+    png::{self, KeepAll, Png, PngParseError},
+    profile::{self, Profile},
+    provenance,
+    raster::{PixelComparison, Raster, FILTER_NAMES},
+    report::{FileFindings, ReportFormat},
+    scatter,
+    select::{Selection, TypePattern},
+    stamp::Stamp,
+    suggest,
+    text::{self, Conversion, TextChunk},
+    transport::Transport,
+    walk::{self, ScanOptions},
+    watermark, xmp,
+};
 use anyhow::{bail, Context, Result};
-use std::{fs, path::Path};
+use base64::prelude::{Engine, BASE64_STANDARD};
+use flate2::read::MultiGzDecoder;
+use std::{
+    ffi::OsString,
+    fs,
+    io::{Read, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc,
+    },
+    thread,
+};
 
-fn try_read_png<P: AsRef<Path>>(path: P) -> Result<Png> {
-    Ok(Png::try_from(
-        fs::read(path)
-            .context("Failed to open PNG file")?
-            .as_slice(),
+/// Options which apply to every command
+#[derive(Debug, Clone, Default)]
+pub struct GlobalOptions {
+    pub cancel: CancellationToken,
+    /// Make written files only depend on their logical content, see
+    /// [`normalize::deterministic`]
+    pub deterministic: bool,
+    /// The format of the input file, detected from its contents if not set
+    pub container: Option<Container>,
+    /// The file to operate on when the input is a zip or tar archive
+    pub entry: Option<String>,
+    /// Decode PNGs with a real decoder before writing them, see
+    /// [`decodable::check`]
+    pub check_decodable: bool,
+    /// Lock the file while the command runs, see [`FileLock::acquire`]
+    pub lock: bool,
+    /// What is carried over when the PNG is written to another file, see
+    /// [`attributes::carry_over`]
+    pub preserve: Preserve,
+    /// Append an entry to this log for every file a command writes, see
+    /// [`audit_log`]
+    pub audit_log: Option<PathBuf>,
+    /// Sign a record of every edit with this key, see [`attest::attest`]
+    pub attest: Option<SigningKey>,
+    /// How payloads given as arguments or on stdin, and files read from
+    /// stdin, are encoded
+    pub stdin_format: Transport,
+    /// How files written to stdout are encoded
+    pub stdout_format: Transport,
+    /// Don't confirm what commands changed, see [`confirm`]
+    pub quiet: bool,
+}
+
+/// Locks the file at `path` for a command, telling the user if another
+/// process holds the lock. None if locking is turned off.
+pub fn lock_file(path: &Path, mode: LockMode, options: &GlobalOptions) -> Result<Option<FileLock>> {
+    if !options.lock {
+        return Ok(None);
+    }
+    let lock = FileLock::acquire(path, mode, &options.cancel, || {
+        errln!(
+            "Waiting for another process to finish with {}",
+            path.display()
+        )
+    })?;
+    Ok(Some(lock))
+}
+
+/// Copies the extended attributes, owner and permissions of `from` to `to`,
+/// the file a command wrote its result to instead of modifying `from`.
+/// Failing to do so only warns, as the file has been written.
+pub fn carry_over_attributes(from: &Path, to: &Path, options: &GlobalOptions) {
+    if let Err(err) = attributes::carry_over(from, to, options.preserve) {
+        errln!(
+            "Warning: failed to carry over the attributes of {} to {}: {}",
+            from.display(),
+            to.display(),
+            err
+        );
+    }
+}
+
+/// Appends an entry to the audit log, if there is one, for `operation`,
+/// which turned the file `before` read from `input` into the file at
+/// `written`. Nothing is logged if the file was written back unchanged.
+pub fn log_operation(
+    operation: &str,
+    input: &Path,
+    written: &Path,
+    before: &[u8],
+    options: &GlobalOptions,
+) -> Result<()> {
+    let Some(log) = &options.audit_log else {
+        return Ok(());
+    };
+    let after = fs::read(written).context("Failed to open the written file")?;
+    if input == written && before == after {
+        return Ok(());
+    }
+    let mut entry = LogEntry::new(operation, date::now(), written, before, &after);
+    if input != written {
+        entry.input = Some(input.display().to_string());
+    }
+    let (_, before) = Carrier::unpack(before.to_vec(), options.entry.as_deref())?;
+    let (_, after) = Carrier::unpack(after, options.entry.as_deref())?;
+    if Png::is_png(&before) && Png::is_png(&after) {
+        entry = entry.with_changes(&parse_png(&before, options)?, &parse_png(&after, options)?);
+    }
+    audit_log::append(log, &entry).context("Failed to write the audit log")
+}
+
+/// Prints what `operation` changed, e.g. `Wrote out.png (4.6 KiB): added
+/// ruSt`, unless `--quiet` was given. `before` is the file it read.
+pub fn confirm(
+    operation: &str,
+    input: &Path,
+    written: &Path,
+    before: &[u8],
+    options: &GlobalOptions,
+) -> Result<()> {
+    if options.quiet {
+        return Ok(());
+    }
+    let after = fs::read(written).context("Failed to open the written file")?;
+    if input == written && before == after {
+        outln!("{}: unchanged", written.display());
+        return Ok(());
+    }
+    let mut changes = Vec::new();
+    let (_, before) = Carrier::unpack(before.to_vec(), options.entry.as_deref())?;
+    let (_, unpacked) = Carrier::unpack(after.clone(), options.entry.as_deref())?;
+    if Png::is_png(&before) && Png::is_png(&unpacked) {
+        let before = parse_png(&before, options)?;
+        let after = parse_png(&unpacked, options)?;
+        if let Some(diff) = HistoryEntry::diff(operation, 0, &before, &after) {
+            let types = |types: Vec<String>| types.join(", ");
+            if !diff.added.is_empty() {
+                let added = diff.added.into_iter().map(|chunk| chunk.chunk_type);
+                changes.push(format!("added {}", types(added.collect())));
+            }
+            if !diff.removed.is_empty() {
+                let removed = diff.removed.into_iter().map(|chunk| chunk.chunk_type);
+                changes.push(format!("removed {}", types(removed.collect())));
+            }
+        }
+    }
+    let size = display::human_size(after.len() as u64);
+    match changes.is_empty() {
+        true => outln!("Wrote {} ({})", written.display(), size),
+        false => outln!(
+            "Wrote {} ({}): {}",
+            written.display(),
+            size,
+            changes.join("; ")
+        ),
+    }
+    Ok(())
+}
+
+/// Like [`outln!`], but for what a command did to the file it wrote to
+/// `path`. The line goes to stderr if the file went to stdout, where it
+/// would end up in the file.
+macro_rules! statusln {
+    ($path:expr, $($arg:tt)*) => {
+        match is_stdout($path) {
+            true => errln!($($arg)*),
+            false => outln!($($arg)*),
+        }
+    };
+}
+
+/// Reads the file at `path`, or stdin if it is `-`, unpacking it if it is
+/// compressed or archived
+fn read_file<P: AsRef<Path>>(path: P, options: &GlobalOptions) -> Result<(Vec<u8>, Carrier)> {
+    let bytes = if is_stdout(&path) {
+        let mut bytes = Vec::new();
+        std::io::stdin()
+            .read_to_end(&mut bytes)
+            .context("Failed to read the file from stdin")?;
+        options
+            .stdin_format
+            .decode(bytes)
+            .context("Failed to decode the file read from stdin")?
+    } else {
+        fs::read(path).context("Failed to open file")?
+    };
+    let (carrier, bytes) = Carrier::unpack(bytes, options.entry.as_deref())?;
+    Ok((bytes, carrier))
+}
+
+/// The file a command writes: `output` if given, or else `path` itself
+fn output_path<'a, P: AsRef<Path>, Q: AsRef<Path>>(path: &'a P, output: Option<&'a Q>) -> &'a Path {
+    output.map_or(path.as_ref(), AsRef::as_ref)
+}
+
+/// Whether `path` is `-`, which stands for stdout as an output
+pub fn is_stdout<P: AsRef<Path>>(path: P) -> bool {
+    path.as_ref() == Path::new("-")
+}
+
+/// Writes `bytes` to `path`, or to stdout if it is `-`, packed the way the
+/// input file was
+fn write_file<P: AsRef<Path>>(
+    path: P,
+    bytes: Vec<u8>,
+    carrier: &Carrier,
+    options: &GlobalOptions,
+) -> Result<()> {
+    let bytes = carrier.pack(bytes)?;
+    if is_stdout(&path) {
+        // Rust writes stdout unchanged, there is no text mode translating
+        // line endings on Windows, but its console only takes UTF-8
+        if options.stdout_format == Transport::Raw && console::stdout_is_terminal() {
+            bail!("refusing to write a binary file to the terminal, redirect stdout or use --stdout-format base64");
+        }
+        let mut stdout = std::io::stdout().lock();
+        stdout.write_all(&options.stdout_format.encode(bytes))?;
+        stdout.flush()?;
+    } else {
+        fs::write(path, bytes)?;
+    }
+    Ok(())
+}
+
+fn parse_png(bytes: &[u8], options: &GlobalOptions) -> Result<Png> {
+    Ok(Png::parse_cancellable(
+        bytes,
+        &mut KeepAll,
+        &options.cancel,
     )?)
 }
 
-pub fn encode<P: AsRef<Path>>(
+fn try_read_png<P: AsRef<Path>>(path: P, options: &GlobalOptions) -> Result<(Png, Carrier)> {
+    let (bytes, carrier) = read_file(path, options)?;
+    Ok((parse_png(&bytes, options)?, carrier))
+}
+
+fn read_container<P: AsRef<Path>>(
+    path: P,
+    options: &GlobalOptions,
+) -> Result<(Box<dyn StegoContainer>, Carrier)> {
+    let (bytes, carrier) = read_file(path, options)?;
+    let container = match container::parse(&bytes, options.container, &options.cancel) {
+        Ok(container) => container,
+        Err(err) => {
+            // Only a wrong --container can make the detected backend fail
+            return Err(match Container::detect(&bytes) {
+                Some(detected) if options.container.is_some_and(|chosen| chosen != detected) => {
+                    anyhow::Error::new(err).context(format!(
+                        "the file looks like {}, try --container {}",
+                        detected,
+                        detected.to_string().to_lowercase()
+                    ))
+                }
+                _ => err.into(),
+            });
+        }
+    };
+    Ok((container, carrier))
+}
+
+fn write_png<P: AsRef<Path>>(
+    path: P,
+    png: Png,
+    carrier: &Carrier,
+    options: &GlobalOptions,
+) -> Result<()> {
+    let png = match options.deterministic {
+        true => normalize::deterministic(png).png,
+        false => png,
+    };
+    let bytes = png.as_bytes();
+    if options.check_decodable {
+        decodable::check(&bytes).context("Refusing to write an image viewers can't decode")?;
+    }
+    write_file(path, bytes, carrier, options)
+}
+
+fn write_container<P: AsRef<Path>>(
+    path: P,
+    container: &dyn StegoContainer,
+    carrier: &Carrier,
+    options: &GlobalOptions,
+) -> Result<()> {
+    match container.as_png() {
+        Some(png) => write_png(path, png.clone(), carrier, options),
+        None => write_file(path, container.to_bytes(), carrier, options),
+    }
+}
+
+/// The data to encode
+#[derive(Debug, Clone)]
+pub enum Message<'a> {
+    Text(String),
+    File(&'a Path),
+    /// The text on the system clipboard
+    Clipboard,
+    /// Everything read from stdin, byte for byte
+    Stdin,
+}
+
+/// Adds `entry` to the manifest of the PNG in `container`
+fn with_manifest_entry(container: &dyn StegoContainer, entry: ManifestEntry) -> Result<Png> {
+    let Some(png) = container.as_png() else {
+        bail!("a manifest can only be written to PNG files");
+    };
+    let mut png = png.clone();
+    let mut manifest = match Manifest::read(&png) {
+        Some(manifest) => manifest.context("Failed to read the existing manifest")?,
+        None => Manifest::default(),
+    };
+    manifest.entries.push(entry);
+    manifest.write(&mut png);
+    Ok(png)
+}
+
+/// How a decoded payload is turned into text
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PayloadFormat {
+    /// As is, the payload has to be UTF-8
+    #[default]
+    Raw,
+    /// Standard base64 with padding
+    Base64,
+    /// Lowercase hexadecimal, two digits per byte
+    Hex,
+    /// UTF-8 with control characters and invalid bytes escaped
+    Escaped,
+}
+
+impl PayloadFormat {
+    fn encode(self, data: Vec<u8>) -> Vec<u8> {
+        match self {
+            Self::Raw => data,
+            Self::Base64 => BASE64_STANDARD.encode(data).into_bytes(),
+            Self::Hex => payload::hex(&data).into_bytes(),
+            Self::Escaped => display::escape(&data).into_bytes(),
+        }
+    }
+}
+
+/// Where a decoded payload goes
+#[derive(Debug, Clone, Copy)]
+pub enum Destination<'a> {
+    /// Print it as text
+    Print(PayloadFormat),
+    File(&'a Path),
+    /// Put it on the system clipboard as text
+    Clipboard(PayloadFormat),
+}
+
+/// How the payload is wrapped before it is stored, and when
+#[derive(Debug, Clone)]
+pub struct EnvelopeOptions {
+    /// Seal the payload in an [`Envelope`] holding its SHA-256. Without it,
+    /// the payload is stored as is.
+    pub integrity: bool,
+    /// When the payload expires, in seconds since the unix epoch
+    pub expires: Option<i64>,
+    /// What kind of data the payload is, so `decode` can handle it
+    pub content_type: Option<ContentType>,
+    /// Record how the payload was stored in the [`Manifest`] of the PNG
+    pub manifest: bool,
+    /// Leave the file alone if a chunk of the type already holds the
+    /// payload, byte for byte. Only applies when it is modified in place.
+    pub if_changed: bool,
+    /// Scatter the payload across small chunks placed by this key, see
+    /// [`scatter::scatter`]
+    pub scatter: Option<String>,
+    /// Pad the file, so its size doesn't give away the size of the payload
+    pub padding: Option<Padding>,
+    /// Warn when the payload looks sensitive, see [`advice::sensitivity`]
+    pub advice: bool,
+}
+
+impl EnvelopeOptions {
+    /// The transforms recorded in the manifest
+    fn transforms(&self) -> Vec<Transform> {
+        let mut transforms = Vec::new();
+        if self.integrity {
+            transforms.push(Transform::Integrity);
+        }
+        if self.expires.is_some() {
+            transforms.push(Transform::Expiry);
+        }
+        if self.content_type.is_some() {
+            transforms.push(Transform::ContentType);
+        }
+        transforms
+    }
+}
+
+impl Default for EnvelopeOptions {
+    fn default() -> Self {
+        Self {
+            integrity: true,
+            expires: None,
+            content_type: None,
+            manifest: false,
+            if_changed: false,
+            scatter: None,
+            padding: None,
+            advice: true,
+        }
+    }
+}
+
+/// Stores `message` in a new chunk, or segment or block depending on the
+/// format. `chunk_type` is the key in the format's notation, see
+/// [`StegoContainer`].
+///
+/// With `spread`, the payload is split into parts which are stored in `path`
+/// and each of the `spread` files, proportionally to their size.
+pub fn encode<P: AsRef<Path>, Q: AsRef<Path>, R: AsRef<Path>>(
     path: P,
-    chunk_type: ChunkType,
-    message: String,
-    output: Option<P>,
+    chunk_type: &str,
+    message: Message,
+    output: Option<Q>,
+    spread: &[R],
+    envelope: &EnvelopeOptions,
+    options: &GlobalOptions,
 ) -> Result<()> {
+    let payload = match message {
+        Message::Text(text) => options
+            .stdin_format
+            .decode(text.into_bytes())
+            .context("Failed to decode the payload")?,
+        Message::File(file) => fs::read(file).context("Failed to read payload file")?,
+        Message::Clipboard => clipboard::read_text()?.into_bytes(),
+        Message::Stdin => {
+            let mut payload = Vec::new();
+            std::io::stdin()
+                .read_to_end(&mut payload)
+                .context("Failed to read payload from stdin")?;
+            options
+                .stdin_format
+                .decode(payload)
+                .context("Failed to decode the payload read from stdin")?
+        }
+    };
+    if envelope.advice {
+        if let Some(sensitivity) = advice::sensitivity(&payload) {
+            warn_sensitive(sensitivity);
+        }
+    }
+    let payload = if envelope.integrity {
+        let mut sealed = Envelope::seal(payload);
+        if let Some(expires) = envelope.expires {
+            sealed = sealed.with_expiry(expires);
+        }
+        if let Some(content_type) = &envelope.content_type {
+            sealed = sealed.with_content_type(content_type.clone());
+        }
+        sealed.as_bytes()
+    } else if envelope.expires.is_some() {
+        bail!("an expiry can only be recorded together with the integrity envelope");
+    } else if envelope.content_type.is_some() {
+        bail!("a content type can only be recorded together with the integrity envelope");
+    } else {
+        payload
+    };
+    if !spread.is_empty() {
+        if output.is_some() {
+            bail!("an output file can't be given when spreading a payload");
+        }
+        return encode_spread(path, chunk_type, &payload, spread, envelope, options);
+    }
+    if let Some(key) = &envelope.scatter {
+        let (mut png, carrier) = try_read_png(&path, options)?;
+        scatter::scatter(&mut png, chunk_type, key.as_bytes(), &payload)?;
+        if let Some(padding) = envelope.padding {
+            padding::pad(&mut png, padding)?;
+        }
+        let path = output_path(&path, output.as_ref());
+        return write_png(path, png, &carrier, options);
+    }
+
     // TODO: Maybe make this override an already existing chunk of that type
-    let mut png = try_read_png(&path)?;
-    png.append_chunk(Chunk::new(chunk_type, message.into_bytes()));
+    let (mut container, carrier) = read_container(&path, options)?;
+    let in_place = output
+        .as_ref()
+        .is_none_or(|output| output.as_ref() == path.as_ref());
+    if envelope.if_changed && in_place && container.get(chunk_type)? == Some(&payload[..]) {
+        // The file isn't written at all, so its modification time stays.
+        // confirm reports it as unchanged.
+        return Ok(());
+    }
+    container.insert(chunk_type, payload)?;
+    if envelope.manifest {
+        let entry = ManifestEntry::new(chunk_type, envelope.transforms());
+        container = Box::new(with_manifest_entry(container.as_ref(), entry)?);
+    }
+    if let Some(padding) = envelope.padding {
+        let Some(png) = container.as_png() else {
+            bail!("padding can only be added to PNG files");
+        };
+        let mut png = png.clone();
+        padding::pad(&mut png, padding)?;
+        container = Box::new(png);
+    }
+
+    let path = output_path(&path, output.as_ref());
+    write_container(path, container.as_ref(), &carrier, options)
+}
+
+/// Explains what can happen to a sensitive payload stored in chunks, and
+/// what to do instead
+fn warn_sensitive(sensitivity: Sensitivity) {
+    errln!(
+        "Warning: the payload looks like {}. Payloads in chunks are removed by most image \
+         optimizers and by the re-encoding of social media uploads, and anyone with the file \
+         can read them.",
+        sensitivity
+    );
+    errln!("  - Keep the original elsewhere, the image isn't a safe copy");
+    errln!("  - Encrypt the payload before encoding it");
+    errln!(
+        "  - To survive optimizers, hide it in the pixels (LSB, see the capacity shown by info), \
+         which lossy re-encoding still destroys"
+    );
+    errln!("  (--no-advice turns this off)");
+}
+
+fn encode_spread<P: AsRef<Path>, Q: AsRef<Path>>(
+    path: P,
+    chunk_type: &str,
+    payload: &[u8],
+    spread: &[Q],
+    envelope: &EnvelopeOptions,
+    options: &GlobalOptions,
+) -> Result<()> {
+    let paths: Vec<&Path> = std::iter::once(path.as_ref())
+        .chain(spread.iter().map(AsRef::as_ref))
+        .collect();
+    let mut carriers = Vec::with_capacity(paths.len());
+    for path in &paths {
+        let (container, carrier) = read_container(path, options)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        carriers.push((container, carrier));
+    }
+
+    let weights: Vec<u64> = carriers
+        .iter()
+        .map(|(container, _)| container.to_bytes().len() as u64)
+        .collect();
+    let parts = payload::split(payload, &weights)?;
 
-    let path = if let Some(out) = output { out } else { path };
-    fs::write(path, png.as_bytes())?;
+    // Insert every part before writing, so no file is modified if one of
+    // them can't hold its part
+    let count = parts.len() as u16;
+    for (index, ((container, _), part)) in carriers.iter_mut().zip(&parts).enumerate() {
+        container.insert(chunk_type, part.as_bytes())?;
+        if envelope.manifest {
+            let mut transforms = envelope.transforms();
+            transforms.push(Transform::Spread {
+                part: index as u16 + 1,
+                parts: count,
+            });
+            let entry = ManifestEntry::new(chunk_type, transforms);
+            *container = Box::new(with_manifest_entry(container.as_ref(), entry)?);
+        }
+    }
+    for ((path, (container, carrier)), part) in paths.iter().zip(&carriers).zip(&parts) {
+        write_container(path, container.as_ref(), carrier, options)?;
+        outln!("{}: {} byte(s)", path.display(), part.data().len());
+    }
     Ok(())
 }
 
-pub fn decode<P: AsRef<Path>>(path: P, chunk_type: &ChunkType) -> Result<()> {
-    let png = try_read_png(path)?;
-    match png.chunk_by_type(chunk_type) {
-        Some(chunk) => Ok(println!(
-            "{}",
-            chunk
-                .data_as_string()
-                .context("Failed to read embedded data in chunk")?
-        )),
-        None => bail!("no chunk with that type found"),
+/// How `decode` checks and handles a payload
+#[derive(Debug, Clone)]
+pub struct DecodeOptions {
+    /// Check sealed payloads against their SHA-256
+    pub integrity: bool,
+    /// Refuse to output expired payloads instead of warning about them
+    pub enforce_expiry: bool,
+    /// Only print what the envelope says about the payload
+    pub show_type: bool,
+    /// Output the payload as stored, ignoring its content type
+    pub as_stored: bool,
+    /// Only output this part of a JSON payload
+    pub query: Option<Query>,
+    /// Reassemble the payload scattered with this key
+    pub scatter: Option<String>,
+}
+
+impl Default for DecodeOptions {
+    fn default() -> Self {
+        Self {
+            integrity: true,
+            enforce_expiry: false,
+            show_type: false,
+            as_stored: false,
+            query: None,
+            scatter: None,
+        }
     }
 }
 
-pub fn remove<P: AsRef<Path>>(path: P, chunk_type: &ChunkType) -> Result<()> {
-    let mut png = try_read_png(&path)?;
-    png.remove_chunk(chunk_type)?;
-    fs::write(path, png.as_bytes())?;
+/// Prints the data stored under `chunk_type`, or writes it to `output`.
+///
+/// With `gather`, the data is reassembled from the parts stored in `path` and
+/// each of the `gather` files by `encode` with `spread`. Sealed payloads are
+/// checked against their SHA-256 and expired ones are only output with a
+/// warning, see [`DecodeOptions`]. Payloads with a content type are
+/// decompressed if they are gzip and pretty-printed if they are JSON. A
+/// query picks a part of a JSON payload, whatever its content type.
+pub fn decode<P: AsRef<Path>, Q: AsRef<Path>>(
+    path: P,
+    chunk_type: &str,
+    gather: &[Q],
+    destination: Destination<'_>,
+    decode_options: &DecodeOptions,
+    options: &GlobalOptions,
+) -> Result<()> {
+    let mut payloads = Vec::with_capacity(gather.len() + 1);
+    if let Some(key) = &decode_options.scatter {
+        let (png, _) = try_read_png(&path, options)?;
+        match scatter::gather(&png, chunk_type, key.as_bytes())? {
+            Some(data) => payloads.push(data),
+            None => bail!("no payload scattered with that key found"),
+        }
+    }
+    for path in std::iter::once(path.as_ref())
+        .chain(gather.iter().map(AsRef::as_ref))
+        .filter(|_| decode_options.scatter.is_none())
+    {
+        let (container, _) = read_container(path, options)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        match container.get(chunk_type)? {
+            Some(data) => payloads.push(data.to_vec()),
+            None => bail!(
+                "{}: {}",
+                path.display(),
+                container::not_found(container.as_ref(), chunk_type)
+            ),
+        }
+    }
+
+    let data = if gather.is_empty() && Part::try_from(&payloads[0][..]).is_err() {
+        payloads.remove(0)
+    } else {
+        let parts = payloads
+            .iter()
+            .map(|data| Part::try_from(&data[..]))
+            .collect::<Result<_, _>>()?;
+        payload::join(parts)?
+    };
+    // The status goes to stderr to keep the printed payload intact
+    let envelope = Envelope::try_from(&data[..]);
+    if decode_options.show_type {
+        return show_type(envelope.ok().as_ref(), data.len());
+    }
+    match &envelope {
+        Ok(sealed) if sealed.is_expired(date::now()) => {
+            let expires = date::format_utc(sealed.expires().unwrap());
+            if decode_options.enforce_expiry {
+                bail!("the payload expired on {}", expires);
+            }
+            errln!("Warning: the payload expired on {}", expires);
+        }
+        _ => {}
+    }
+    let content_type = match &envelope {
+        Ok(envelope) if !decode_options.as_stored => envelope.content_type().cloned(),
+        _ => None,
+    };
+    let data = match envelope {
+        Ok(envelope) if !decode_options.integrity => envelope.into_payload(),
+        Ok(envelope) => {
+            let payload = envelope.open()?;
+            errln!("Integrity: OK, the SHA-256 of the payload matches");
+            payload
+        }
+        Err(_) => {
+            if decode_options.integrity {
+                errln!("Integrity: unknown, the payload has no checksum");
+            }
+            data
+        }
+    };
+    let data = match &content_type {
+        Some(content_type) if content_type.is_gzip() => {
+            let mut unpacked = Vec::new();
+            MultiGzDecoder::new(&data[..])
+                .read_to_end(&mut unpacked)
+                .context("Failed to decompress the gzip payload")?;
+            errln!("Content type: {}, decompressed", content_type);
+            unpacked
+        }
+        _ => data,
+    };
+    let data = match (&decode_options.query, &content_type) {
+        (Some(query), _) => {
+            let text = std::str::from_utf8(&data).map_err(|_| QueryError::InvalidJson)?;
+            query.evaluate(text)?.into_bytes()
+        }
+        (None, Some(content_type)) => present(content_type, data, destination),
+        (None, None) => data,
+    };
+
+    match destination {
+        // Another program reading the payload gets it byte for byte, only
+        // the terminal needs it escaped and ended by a newline
+        Destination::Print(PayloadFormat::Raw) if console::stdout_is_piped() => {
+            let mut stdout = std::io::stdout().lock();
+            stdout.write_all(&data)?;
+            stdout.flush()?;
+        }
+        Destination::Print(format) => {
+            let data = format.encode(data);
+            match std::str::from_utf8(&data) {
+                Ok(text) => outln!("{}", text),
+                Err(_) => bail!(
+                    "the payload is not UTF-8 text, print it with --escape, --base64 or --hex or write it to a file with -o"
+                ),
+            }
+        }
+        Destination::File(output) => {
+            fs::write(output, data).context("Failed to write payload file")?
+        }
+        Destination::Clipboard(format) => clipboard::write_text(&format.encode(data))?,
+    }
     Ok(())
 }
 
-pub fn print<P: AsRef<Path>>(path: P) -> Result<()> {
-    println!("{}", try_read_png(path)?);
+/// Prints the metadata of a payload of `len` bytes
+fn show_type(envelope: Option<&Envelope>, len: usize) -> Result<()> {
+    let Some(envelope) = envelope else {
+        outln!("Content type: unknown, the payload has no envelope");
+        outln!("Size: {} bytes", len);
+        return Ok(());
+    };
+    match envelope.content_type() {
+        Some(content_type) => outln!("Content type: {}", content_type),
+        None => outln!("Content type: unknown, none was recorded"),
+    }
+    if let Some(expires) = envelope.expires() {
+        outln!("Expires: {}", date::format_utc(expires));
+    }
+    outln!("Size: {} bytes", envelope.payload().len());
     Ok(())
 }
+
+/// Pretty-prints JSON and warns before binary data is printed to a
+/// terminal
+fn present(content_type: &ContentType, data: Vec<u8>, destination: Destination<'_>) -> Vec<u8> {
+    let Destination::Print(format) = destination else {
+        return data;
+    };
+    if content_type.is_json() && format == PayloadFormat::Raw {
+        let pretty = std::str::from_utf8(&data).ok().and_then(json::pretty);
+        return match pretty {
+            Some(pretty) => pretty.into_bytes(),
+            None => {
+                errln!("Warning: the payload is not valid JSON, printing it as is");
+                data
+            }
+        };
+    }
+    if !content_type.is_text() && format == PayloadFormat::Raw && console::stdout_is_terminal() {
+        errln!(
+            "Warning: the payload is {}, which isn't text, write it to a file with -o",
+            content_type
+        );
+    }
+    data
+}
+
+/// Removes the chunk of `chunk_type`, or the fragments of the payload
+/// scattered with the key `scatter`, and writes the file to `output`, or
+/// back to `path` if there is none
+pub fn remove<P: AsRef<Path>, Q: AsRef<Path>>(
+    path: P,
+    chunk_type: &str,
+    scatter: Option<&str>,
+    output: Option<Q>,
+    options: &GlobalOptions,
+) -> Result<()> {
+    if let Some(key) = scatter {
+        let (mut png, carrier) = try_read_png(&path, options)?;
+        if scatter::remove(&mut png, chunk_type, key.as_bytes()) == 0 {
+            bail!("no payload scattered with that key found");
+        }
+        let path = output_path(&path, output.as_ref());
+        return write_png(path, png, &carrier, options);
+    }
+    let (mut container, carrier) = read_container(&path, options)?;
+    if container.remove(chunk_type)?.is_none() {
+        bail!(container::not_found(container.as_ref(), chunk_type));
+    }
+    let path = output_path(&path, output.as_ref());
+    write_container(path, container.as_ref(), &carrier, options)
+}
+
+/// Removes the chunk and writes its data to `data_path`, in one step
+pub fn extract<P: AsRef<Path>, Q: AsRef<Path>, R: AsRef<Path>>(
+    path: P,
+    chunk_type: &str,
+    data_path: R,
+    output: Option<Q>,
+    options: &GlobalOptions,
+) -> Result<()> {
+    let (mut container, carrier) = read_container(&path, options)?;
+    let Some(data) = container.remove(chunk_type)? else {
+        bail!(container::not_found(container.as_ref(), chunk_type));
+    };
+    // Write the data first, so it isn't lost if that fails
+    fs::write(data_path, &data).context("Failed to write data file")?;
+
+    let path = output_path(&path, output.as_ref());
+    write_container(path, container.as_ref(), &carrier, options)
+}
+
+/// Puts the chunks into the given order, `order[i]` being the current index
+/// of the chunk that should end up at position `i`
+pub fn reorder<P: AsRef<Path>, Q: AsRef<Path>>(
+    path: P,
+    order: &[usize],
+    output: Option<Q>,
+    options: &GlobalOptions,
+) -> Result<()> {
+    let (png, carrier) = try_read_png(&path, options)?;
+    let chunks = png.chunks();
+    let mut seen = vec![false; chunks.len()];
+    for &index in order {
+        match seen.get_mut(index) {
+            Some(true) => bail!("chunk {} is listed more than once", index),
+            Some(seen) => *seen = true,
+            None => bail!("there is no chunk {}, the PNG has {}", index, chunks.len()),
+        }
+    }
+    if let Some(missing) = seen.iter().position(|seen| !seen) {
+        bail!("chunk {} is missing from the order", missing);
+    }
+    let png = Png::from_chunks(order.iter().map(|&index| chunks[index].clone()).collect());
+
+    let path = output_path(&path, output.as_ref());
+    write_png(path, png, &carrier, options)
+}
+
+pub fn normalize<P: AsRef<Path>, Q: AsRef<Path>>(
+    path: P,
+    output: Option<Q>,
+    options: &GlobalOptions,
+) -> Result<()> {
+    let (png, carrier) = try_read_png(&path, options)?;
+    let Normalized {
+        png,
+        moved,
+        merged,
+        dropped,
+    } = match options.deterministic {
+        true => normalize::deterministic(png),
+        false => normalize::normalize(png),
+    };
+
+    let path = output_path(&path, output.as_ref());
+    write_png(path, png, &carrier, options)?;
+    statusln!(
+        path,
+        "Moved {} chunk(s), merged {} duplicate(s), dropped {} timestamp(s)",
+        moved,
+        merged,
+        dropped
+    );
+    Ok(())
+}
+
+/// Shrinks the PNG losslessly and reports the bytes saved by each technique
+pub fn optimize<P: AsRef<Path>, Q: AsRef<Path>>(
+    path: P,
+    recompress: bool,
+    output: Option<Q>,
+    options: &GlobalOptions,
+) -> Result<()> {
+    let (png, carrier) = try_read_png(&path, options)?;
+    let (png, savings) = optimize::optimize(png, recompress)?;
+
+    let path = output_path(&path, output.as_ref());
+    write_png(path, png, &carrier, options)?;
+    let Savings {
+        redundant,
+        merged,
+        recompressed,
+        trailing,
+    } = savings;
+    statusln!(path, "Redundant chunks: {} bytes", redundant);
+    statusln!(path, "Merged IDAT chunks: {} bytes", merged);
+    if recompress {
+        statusln!(path, "Recompressed image data: {} bytes", recompressed);
+    }
+    statusln!(path, "Trailing data: {} bytes", trailing);
+    statusln!(path, "Saved {} bytes in total", savings.total());
+    Ok(())
+}
+
+/// Rewrites the image data of the PNG with or without Adam7 interlacing
+pub fn interlace<P: AsRef<Path>, Q: AsRef<Path>>(
+    path: P,
+    interlaced: bool,
+    output: Option<Q>,
+    options: &GlobalOptions,
+) -> Result<()> {
+    let (mut png, carrier) = try_read_png(&path, options)?;
+    let raster = Raster::decode(&png).context("Failed to decode the pixels")?;
+    let state = if interlaced {
+        "interlaced"
+    } else {
+        "not interlaced"
+    };
+    if raster.header.interlaced == interlaced && output.is_none() {
+        outln!("The image is already {}", state);
+        return Ok(());
+    }
+    let before = optimize::idat_size(&png);
+    raster.store(&mut png, interlaced)?;
+    let after = optimize::idat_size(&png);
+
+    let path = output_path(&path, output.as_ref());
+    write_png(path, png, &carrier, options)?;
+    statusln!(
+        path,
+        "The image is now {}, with {} bytes of image data (was {})",
+        state,
+        after,
+        before
+    );
+    Ok(())
+}
+
+/// Prints the filter type of every scanline, pass by pass for interlaced
+/// images, and how many scanlines use each
+pub fn show_filters<P: AsRef<Path>>(path: P, options: &GlobalOptions) -> Result<()> {
+    let (png, _) = try_read_png(path, options)?;
+    let raster = Raster::decode(&png).context("Failed to decode the pixels")?;
+    let mut counts = [0; FILTER_NAMES.len()];
+    for (pass, filters) in raster.filters().iter().enumerate() {
+        for (row, &filter) in filters.iter().enumerate() {
+            counts[filter as usize] += 1;
+            if raster.header.interlaced {
+                outln!(
+                    "Pass {} scanline {}: {}",
+                    pass + 1,
+                    row,
+                    FILTER_NAMES[filter as usize]
+                );
+            } else {
+                outln!("Scanline {}: {}", row, FILTER_NAMES[filter as usize]);
+            }
+        }
+    }
+    let total: Vec<String> = FILTER_NAMES
+        .iter()
+        .zip(counts)
+        .filter(|(_, count)| *count > 0)
+        .map(|(name, count)| format!("{} {}", name, count))
+        .collect();
+    outln!("Total: {}", total.join(", "));
+    Ok(())
+}
+
+/// Chooses the filters of the scanlines again and recompresses the image
+/// data, keeping the result if it's smaller
+pub fn refilter<P: AsRef<Path>, Q: AsRef<Path>>(
+    path: P,
+    output: Option<Q>,
+    options: &GlobalOptions,
+) -> Result<()> {
+    let (mut png, carrier) = try_read_png(&path, options)?;
+    let Refiltered { before, after } = optimize::refilter(&mut png)?;
+    if after == before && output.is_none() {
+        outln!(
+            "The image data can't be made smaller than {} bytes by refiltering",
+            before
+        );
+        return Ok(());
+    }
+
+    let path = output_path(&path, output.as_ref());
+    write_png(path, png, &carrier, options)?;
+    statusln!(
+        path,
+        "Image data: {} bytes (was {}), saved {} bytes",
+        after,
+        before,
+        before - after
+    );
+    Ok(())
+}
+
+/// Embeds `identifier` as an invisible watermark derived from `key`
+pub fn embed_watermark<P: AsRef<Path>, Q: AsRef<Path>>(
+    path: P,
+    identifier: &str,
+    key: &str,
+    strength: u8,
+    output: Option<Q>,
+    options: &GlobalOptions,
+) -> Result<()> {
+    let (mut png, carrier) = try_read_png(&path, options)?;
+    watermark::embed(&mut png, identifier, key.as_bytes(), strength)?;
+
+    let path = output_path(&path, output.as_ref());
+    write_png(path, png, &carrier, options)?;
+    statusln!(path, "Embedded the watermark {}", identifier);
+    Ok(())
+}
+
+/// Prints the watermark embedded with `key`, failing if none is found
+pub fn detect_watermark<P: AsRef<Path>>(path: P, key: &str, options: &GlobalOptions) -> Result<()> {
+    let (png, _) = try_read_png(path, options)?;
+    let detection = watermark::detect(&png, key.as_bytes())?;
+    let confidence = (detection.confidence() * 100.0).round();
+    match detection.identifier {
+        Some(identifier) => {
+            outln!("Watermark: {} (confidence {}%)", identifier, confidence);
+            Ok(())
+        }
+        None => bail!(
+            "no watermark found with this key (confidence {}%)",
+            confidence
+        ),
+    }
+}
+
+/// Marks the PNG as the copy for `recipient`
+pub fn fingerprint<P: AsRef<Path>, Q: AsRef<Path>>(
+    path: P,
+    recipient: &str,
+    key: &str,
+    strength: u8,
+    output: Option<Q>,
+    options: &GlobalOptions,
+) -> Result<()> {
+    let (mut png, carrier) = try_read_png(&path, options)?;
+    let watermarked = fingerprint::embed(&mut png, key.as_bytes(), recipient, strength)?;
+    if !watermarked {
+        errln!("Warning: the image can't hold a watermark, the fingerprint is only stored in a chunk, which is easily removed");
+    }
+
+    let path = output_path(&path, output.as_ref());
+    write_png(path, png, &carrier, options)?;
+    statusln!(path, "Fingerprinted the copy for {}", recipient);
+    Ok(())
+}
+
+/// Prints which of `recipients`, and of the recipients listed one per line
+/// in `recipients_file`, the PNG was fingerprinted for. Fails if it has no
+/// fingerprint.
+pub fn identify_fingerprint<P: AsRef<Path>, Q: AsRef<Path>>(
+    path: P,
+    key: &str,
+    mut recipients: Vec<String>,
+    recipients_file: Option<Q>,
+    options: &GlobalOptions,
+) -> Result<()> {
+    if let Some(file) = recipients_file {
+        let file = file.as_ref();
+        let list = fs::read_to_string(file)
+            .with_context(|| format!("Failed to read {}", file.display()))?;
+        recipients.extend(
+            list.lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty())
+                .map(String::from),
+        );
+    }
+    let (png, _) = try_read_png(path, options)?;
+    let Identification { chunk, watermark } =
+        fingerprint::identify(&png, key.as_bytes(), &recipients);
+    if chunk.is_none() && watermark.is_none() {
+        bail!("no fingerprint found with this key");
+    }
+    for (source, finding) in [("Chunk", chunk), ("Watermark", watermark)] {
+        match finding {
+            Some(fingerprint::Recipient::Known(recipient)) => outln!("{}: {}", source, recipient),
+            Some(fingerprint::Recipient::Unknown) => outln!("{}: unknown recipient", source),
+            None => outln!("{}: none", source),
+        }
+    }
+    Ok(())
+}
+
+/// Declares the PNG as sRGB with the given rendering intent, replacing any
+/// other color management chunks
+pub fn fix_color<P: AsRef<Path>, Q: AsRef<Path>>(
+    path: P,
+    intent: RenderingIntent,
+    output: Option<Q>,
+    options: &GlobalOptions,
+) -> Result<()> {
+    let (mut png, carrier) = try_read_png(&path, options)?;
+    let removed = color::set_srgb(&mut png, intent);
+
+    let path = output_path(&path, output.as_ref());
+    write_png(path, png, &carrier, options)?;
+    statusln!(
+        path,
+        "Declared sRGB with {} intent, replaced {} color chunk(s)",
+        intent,
+        removed
+    );
+    Ok(())
+}
+
+/// Compresses `tEXt` chunks of at least `min_size` bytes into `zTXt` chunks
+pub fn optimize_text<P: AsRef<Path>, Q: AsRef<Path>>(
+    path: P,
+    min_size: usize,
+    output: Option<Q>,
+    options: &GlobalOptions,
+) -> Result<()> {
+    let (mut png, carrier) = try_read_png(&path, options)?;
+    let Conversion { chunks, bytes } = text::compress_texts(&mut png, min_size);
+
+    let path = output_path(&path, output.as_ref());
+    write_png(path, png, &carrier, options)?;
+    statusln!(
+        path,
+        "Compressed {} text chunk(s), saved {} bytes",
+        chunks,
+        bytes
+    );
+    Ok(())
+}
+
+pub fn decompress_text<P: AsRef<Path>, Q: AsRef<Path>>(
+    path: P,
+    output: Option<Q>,
+    options: &GlobalOptions,
+) -> Result<()> {
+    let (mut png, carrier) = try_read_png(&path, options)?;
+    let Conversion { chunks, bytes } = text::decompress_texts(&mut png)?;
+
+    let path = output_path(&path, output.as_ref());
+    write_png(path, png, &carrier, options)?;
+    statusln!(
+        path,
+        "Decompressed {} text chunk(s), added {} bytes",
+        chunks,
+        bytes
+    );
+    Ok(())
+}
+
+/// Records how an operation changed the PNG at `path` in its history.
+/// `before` is the file as it was before the operation. Nothing is recorded
+/// if the PNG didn't change, or if either version isn't a PNG.
+pub fn record_history<P: AsRef<Path>>(
+    before: Vec<u8>,
+    path: P,
+    operation: &str,
+    options: &GlobalOptions,
+) -> Result<()> {
+    let (_, before) = Carrier::unpack(before, options.entry.as_deref())?;
+    let (after, carrier) = read_file(&path, options)?;
+    if !Png::is_png(&before) || !Png::is_png(&after) {
+        return Ok(());
+    }
+    let before = parse_png(&before, options)?;
+    let mut after = parse_png(&after, options)?;
+
+    if let Some(entry) = HistoryEntry::diff(operation, date::now(), &before, &after) {
+        history::record(&mut after, &entry);
+        write_png(path, after, &carrier, options)?;
+    }
+    Ok(())
+}
+
+/// Reads the key `--attest` signs edits with
+pub fn read_signing_key<P: AsRef<Path>>(path: P) -> Result<SigningKey> {
+    let key_file = fs::read(path).context("Failed to open the key file")?;
+    Ok(attest::signing_key(&key_file)?)
+}
+
+/// Appends an attestation of `operation`, which turned the file `before`
+/// into the PNG at `path`, signed with the key of `--attest`
+pub fn attest_edit<P: AsRef<Path>>(
+    before: Vec<u8>,
+    path: P,
+    operation: &str,
+    options: &GlobalOptions,
+) -> Result<()> {
+    let Some(key) = &options.attest else {
+        return Ok(());
+    };
+    let (_, before) = Carrier::unpack(before, options.entry.as_deref())?;
+    let (after, carrier) = read_file(&path, options)?;
+    if !Png::is_png(&before) || !Png::is_png(&after) {
+        bail!("edits can only be attested in PNG files");
+    }
+    let before = parse_png(&before, options)?;
+    let mut after = parse_png(&after, options)?;
+    attest::attest(&mut after, &before, operation, date::now(), key)
+        .context("Failed to attest the edit")?;
+    write_png(path, after, &carrier, options)
+}
+
+/// Writes a new key for `--attest` to `path`, which must not exist yet, and
+/// prints its public key
+pub fn generate_attestation_key<P: AsRef<Path>>(path: P) -> Result<()> {
+    let key = attest::generate_key().context("Failed to generate a key")?;
+    let mut file = fs::OpenOptions::new();
+    file.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut file, 0o600);
+    file.open(&path)
+        .context("Failed to create the key file")?
+        .write_all(attest::key_file(&key).as_bytes())?;
+    outln!(
+        "Public key: {}",
+        attest::public_key_hex(&key.verifying_key())
+    );
+    Ok(())
+}
+
+/// Prints the public key of the key file at `path`, which attestations are
+/// verified with
+pub fn print_public_key<P: AsRef<Path>>(path: P) -> Result<()> {
+    let key = read_signing_key(path)?;
+    outln!("{}", attest::public_key_hex(&key.verifying_key()));
+    Ok(())
+}
+
+/// Prints the attestations of the PNG and fails unless they are all signed
+/// by one of `authorized` and the file hasn't changed since the last one
+pub fn verify_attestations<P: AsRef<Path>>(
+    path: P,
+    authorized: &[VerifyingKey],
+    options: &GlobalOptions,
+) -> Result<()> {
+    let (png, _) = try_read_png(path, options)?;
+    let chain = attest::verify(&png, authorized)?;
+    for (index, attestation) in chain.iter().enumerate() {
+        let record = &attestation.record;
+        let changes = record
+            .added
+            .iter()
+            .map(|chunk| format!("added {}", chunk.chunk_type))
+            .chain(
+                record
+                    .removed
+                    .iter()
+                    .map(|chunk| format!("removed {}", chunk.chunk_type)),
+            )
+            .collect::<Vec<_>>()
+            .join(", ");
+        outln!(
+            "{}\t{}\t{}\t{}\t{}",
+            index + 1,
+            date::format_utc(record.timestamp),
+            record.operation,
+            attest::public_key_hex(&attestation.public_key),
+            changes
+        );
+    }
+    outln!(
+        "OK, the file is as left by the last of {} attested edit(s)",
+        chain.len()
+    );
+    Ok(())
+}
+
+/// Prints the operations recorded in the history of the PNG
+pub fn show_history<P: AsRef<Path>>(path: P, options: &GlobalOptions) -> Result<()> {
+    let (png, _) = try_read_png(path, options)?;
+    let entries = history::read(&png)?;
+    if entries.is_empty() {
+        outln!("No history, the file was modified without --history");
+    }
+    for (index, entry) in entries.iter().enumerate() {
+        let changes = entry
+            .added
+            .iter()
+            .map(|chunk| format!("added {}", chunk.chunk_type))
+            .chain(entry.removed.iter().map(|chunk| {
+                let sha256: String = chunk.sha256[..4]
+                    .iter()
+                    .map(|byte| format!("{:02x}", byte))
+                    .collect();
+                format!("removed {} (sha256 {}…)", chunk.chunk_type, sha256)
+            }))
+            .collect::<Vec<_>>()
+            .join(", ");
+        outln!(
+            "{}\t{}\t{}\t{}",
+            index + 1,
+            date::format_utc(entry.timestamp),
+            entry.operation,
+            changes
+        );
+    }
+    Ok(())
+}
+
+/// Reverts the last operation recorded in the history of the PNG
+pub fn undo<P: AsRef<Path>, Q: AsRef<Path>>(
+    path: P,
+    output: Option<Q>,
+    options: &GlobalOptions,
+) -> Result<()> {
+    let (mut png, carrier) = try_read_png(&path, options)?;
+    let entry = history::undo(&mut png)?;
+
+    let path = output_path(&path, output.as_ref());
+    write_png(path, png, &carrier, options)?;
+    statusln!(
+        path,
+        "Reverted {} from {}",
+        entry.operation,
+        date::format_utc(entry.timestamp)
+    );
+    Ok(())
+}
+
+/// Prints the payloads recorded in the manifest, and with `all` every chunk
+/// with its contents decoded where pngme knows the chunk type. The sizes of
+/// other chunks are printed like `1.5 KiB` unless `exact_sizes` is set.
+pub fn info<P: AsRef<Path>>(
+    path: P,
+    all: bool,
+    exact_sizes: bool,
+    options: &GlobalOptions,
+) -> Result<()> {
+    let (png, _) = try_read_png(&path, options)?;
+    if all {
+        print_chunk_contents(&png, exact_sizes)?;
+        outln!("");
+    }
+    let manifest = match Manifest::read(&png) {
+        Some(manifest) => manifest?,
+        None => {
+            outln!("No manifest, the file was encoded without --manifest");
+            return Ok(());
+        }
+    };
+
+    let path = path.as_ref().display();
+    for entry in &manifest.entries {
+        let transforms: Vec<String> = entry.transforms.iter().map(ToString::to_string).collect();
+        outln!(
+            "{}: {} (payload version {}, written by pngme {})",
+            entry.chunk_type,
+            match transforms.is_empty() {
+                true => "stored as is".to_owned(),
+                false => transforms.join(", "),
+            },
+            entry.version,
+            entry.written_by
+        );
+
+        let present = (entry.chunk_type.parse::<ChunkType>())
+            .is_ok_and(|chunk_type| png.chunk_by_type(&chunk_type).is_some());
+        if !present {
+            outln!("  The chunk has been removed since");
+            continue;
+        }
+        let mut decode = format!("pngme {} decode {}", path, entry.chunk_type);
+        for transform in &entry.transforms {
+            if let Transform::Spread { parts, .. } = transform {
+                decode += &format!(" --gather <the other {} file(s)>", parts - 1);
+            }
+        }
+        outln!("  Decode with: {}", decode);
+    }
+    Ok(())
+}
+
+/// Prints the index, type and contents of each chunk in columns
+fn print_chunk_contents(png: &Png, exact_sizes: bool) -> Result<()> {
+    let header = png.image_header()?;
+    let mut rows = Vec::with_capacity(png.chunks().len());
+    for (index, chunk) in png.chunks().iter().enumerate() {
+        let contents = match &chunk.chunk_type().bytes() {
+            b"IHDR" => format!(
+                "{}x{}, {}, {} bits per sample{}",
+                header.width,
+                header.height,
+                header.color_type,
+                header.bit_depth,
+                if header.interlaced {
+                    ", interlaced"
+                } else {
+                    ""
+                }
+            ),
+            _ => match (
+                Ancillary::decode(chunk, &header),
+                TextChunk::from_chunk(chunk),
+            ) {
+                (Some(Ok(decoded)), _) => decoded.to_string(),
+                (_, Some(Ok(text))) => format!("{}: {}", text.keyword, text.text.escape_debug()),
+                (Some(Err(err)), _) => format!("invalid, {}", err),
+                (_, Some(Err(err))) => format!("invalid, {}", err),
+                (None, None) if exact_sizes => format!("{} bytes", chunk.length()),
+                (None, None) => display::human_size(chunk.length() as u64),
+            },
+        };
+        rows.push(vec![
+            index.to_string(),
+            chunk.chunk_type().to_string(),
+            contents,
+        ]);
+    }
+    outln!("{}", display::columns(&rows, &[true]));
+    Ok(())
+}
+
+/// Writes a stamp into the PNG. Fields set in `stamp` take precedence over
+/// the ones read from the JSON file at `from_json`.
+pub fn stamp<P: AsRef<Path>, Q: AsRef<Path>, R: AsRef<Path>>(
+    path: P,
+    stamp: Stamp,
+    from_json: Option<R>,
+    output: Option<Q>,
+    options: &GlobalOptions,
+) -> Result<()> {
+    let (mut png, carrier) = try_read_png(&path, options)?;
+
+    let stamp = match from_json {
+        Some(json_path) => {
+            let json_path = json_path.as_ref();
+            let json = fs::read_to_string(json_path).context("Failed to read JSON file")?;
+            let mut from_file = Stamp::from_json_object(&json)
+                .with_context(|| format!("Invalid stamp in {}", json_path.display()))?;
+            from_file.merge(stamp);
+            from_file
+        }
+        None => stamp,
+    };
+    stamp.write(&mut png);
+
+    let path = output_path(&path, output.as_ref());
+    write_png(path, png, &carrier, options)
+}
+
+pub fn show_stamp<P: AsRef<Path>>(path: P, options: &GlobalOptions) -> Result<()> {
+    let (png, _) = try_read_png(path, options)?;
+    let stamp = match Stamp::read(&png) {
+        Some(stamp) => stamp.context("Failed to read stamp")?,
+        None => bail!("the PNG has no stamp"),
+    };
+    for (key, value) in stamp.entries() {
+        outln!("{}: {}", key, value);
+    }
+    Ok(())
+}
+
+pub fn set_license<P: AsRef<Path>, Q: AsRef<Path>>(
+    path: P,
+    license: &License,
+    xmp: bool,
+    output: Option<Q>,
+    options: &GlobalOptions,
+) -> Result<()> {
+    let (mut png, carrier) = try_read_png(&path, options)?;
+    license.write(&mut png, xmp)?;
+
+    let path = output_path(&path, output.as_ref());
+    write_png(path, png, &carrier, options)
+}
+
+pub fn show_license<P: AsRef<Path>>(path: P, options: &GlobalOptions) -> Result<()> {
+    let (png, _) = try_read_png(path, options)?;
+    let Some(license) = License::read(&png) else {
+        bail!("the PNG has no license information");
+    };
+
+    outln!("License: {}", license.spdx);
+    if let Some(author) = license.author {
+        outln!("Author: {}", author);
+    }
+    if let Some(copyright) = license.copyright {
+        outln!("Copyright: {}", copyright);
+    }
+    if xmp::read(&png).is_some() {
+        outln!("XMP: present");
+    }
+    Ok(())
+}
+
+/// Stores a text chunk. Keywords the PNG spec doesn't define are reported,
+/// and refused if `keyword_strict` is set.
+pub fn set_text<P: AsRef<Path>, Q: AsRef<Path>>(
+    path: P,
+    text: &TextChunk,
+    keyword_strict: bool,
+    output: Option<Q>,
+    options: &GlobalOptions,
+) -> Result<()> {
+    text::validate_keyword(&text.keyword)?;
+    if !text::is_registered_keyword(&text.keyword) {
+        let problem = match text::registered_spelling(&text.keyword) {
+            Some(registered) => format!(
+                "{} is not a registered keyword, keywords are case sensitive (did you mean {}?)",
+                text.keyword, registered
+            ),
+            None => format!("{} is not a registered keyword", text.keyword),
+        };
+        if keyword_strict {
+            bail!(problem);
+        }
+        errln!("Warning: {}", problem);
+    }
+
+    let (mut png, carrier) = try_read_png(&path, options)?;
+    text::set_text(&mut png, text)?;
+
+    let path = output_path(&path, output.as_ref());
+    write_png(path, png, &carrier, options)
+}
+
+pub fn get_text<P: AsRef<Path>>(path: P, keyword: &str, options: &GlobalOptions) -> Result<()> {
+    let (png, _) = try_read_png(path, options)?;
+    let Some(text) = text::find_text(&png, keyword) else {
+        bail!("the PNG has no text chunk with the keyword {}", keyword);
+    };
+    outln!("{}", text.text);
+    Ok(())
+}
+
+pub fn get_meta<P: AsRef<Path>>(path: P, keyword: &str, options: &GlobalOptions) -> Result<()> {
+    let (png, _) = try_read_png(path, options)?;
+    let Some(entry) = meta::get(&png, keyword) else {
+        bail!("the PNG has no {} metadata", keyword);
+    };
+    outln!("{}", entry.value);
+    Ok(())
+}
+
+/// Prints one `{keyword}: {value} ({source})` line per keyword, with the
+/// value escaped to fit on the line
+pub fn list_meta<P: AsRef<Path>>(path: P, options: &GlobalOptions) -> Result<()> {
+    let (png, _) = try_read_png(path, options)?;
+    for entry in meta::list(&png) {
+        outln!(
+            "{}: {} ({})",
+            entry.keyword,
+            entry.value.escape_debug(),
+            entry.source
+        );
+    }
+    Ok(())
+}
+
+/// Stores a decoded ancillary chunk, see [`ancillary::store`]
+pub fn set_ancillary<P: AsRef<Path>, Q: AsRef<Path>>(
+    path: P,
+    value: &Ancillary,
+    output: Option<Q>,
+    options: &GlobalOptions,
+) -> Result<()> {
+    let (mut png, carrier) = try_read_png(&path, options)?;
+    ancillary::store(&mut png, value)?;
+
+    let path = output_path(&path, output.as_ref());
+    write_png(path, png, &carrier, options)
+}
+
+/// Prints the `oFFs`, `sCAL` and `pCAL` chunks of the PNG
+pub fn show_extensions<P: AsRef<Path>>(path: P, options: &GlobalOptions) -> Result<()> {
+    let (png, _) = try_read_png(path, options)?;
+    let header = png.image_header()?;
+    let extensions: Vec<_> = png
+        .chunks()
+        .iter()
+        .filter(|chunk| matches!(&chunk.chunk_type().bytes(), b"oFFs" | b"sCAL" | b"pCAL"))
+        .collect();
+    if extensions.is_empty() {
+        bail!("the PNG has no oFFs, sCAL or pCAL chunk");
+    }
+    for chunk in extensions {
+        match Ancillary::decode(chunk, &header) {
+            Some(Ok(decoded)) => outln!("{}", decoded),
+            Some(Err(err)) => errln!("{}", err),
+            None => unreachable!("oFFs, sCAL and pCAL are decoded"),
+        }
+    }
+    Ok(())
+}
+
+pub fn extract_xmp<P: AsRef<Path>, Q: AsRef<Path>>(
+    path: P,
+    output: Option<Q>,
+    options: &GlobalOptions,
+) -> Result<()> {
+    let (png, _) = try_read_png(path, options)?;
+    let Some(packet) = xmp::read(&png) else {
+        bail!("the PNG has no XMP packet");
+    };
+
+    match output {
+        Some(output) => fs::write(output, packet).context("Failed to write XMP file")?,
+        None => outln!("{}", packet),
+    }
+    Ok(())
+}
+
+pub fn embed_xmp<P: AsRef<Path>, Q: AsRef<Path>, R: AsRef<Path>>(
+    path: P,
+    packet_path: R,
+    output: Option<Q>,
+    options: &GlobalOptions,
+) -> Result<()> {
+    let (mut png, carrier) = try_read_png(&path, options)?;
+    let packet = fs::read_to_string(packet_path).context("Failed to read XMP file")?;
+    xmp::write(&mut png, &packet)?;
+
+    let path = output_path(&path, output.as_ref());
+    write_png(path, png, &carrier, options)
+}
+
+pub fn get_xmp<P: AsRef<Path>>(path: P, key: &str, options: &GlobalOptions) -> Result<()> {
+    let (png, _) = try_read_png(path, options)?;
+    let Some(packet) = xmp::read(&png) else {
+        bail!("the PNG has no XMP packet");
+    };
+
+    let values = xmp::get(&packet, key)?;
+    if values.is_empty() {
+        bail!("the XMP packet has no {}", key);
+    }
+    for value in values {
+        outln!("{}", value);
+    }
+    Ok(())
+}
+
+pub fn show_provenance<P: AsRef<Path>>(path: P, options: &GlobalOptions) -> Result<()> {
+    let (png, _) = try_read_png(path, options)?;
+    let manifests = provenance::manifests(&png).context("Failed to read C2PA manifest")?;
+    // Manifests can also live on a server, referenced from the XMP metadata
+    let remote = xmp::read(&png)
+        .and_then(|packet| xmp::get(&packet, "dcterms:provenance").ok())
+        .and_then(|values| values.into_iter().next());
+
+    if manifests.is_empty() && remote.is_none() {
+        bail!("the PNG has no C2PA manifest");
+    }
+
+    for (index, manifest) in manifests.iter().enumerate() {
+        let active = if index + 1 == manifests.len() {
+            " (active)"
+        } else {
+            ""
+        };
+        outln!("Manifest {}{}", manifest.label, active);
+        if let Some(claim_generator) = &manifest.claim_generator {
+            outln!("  Claim generator: {}", claim_generator);
+        }
+        if let Some(title) = &manifest.title {
+            outln!("  Title: {}", title);
+        }
+        outln!(
+            "  Signer: {}",
+            manifest.signer.as_deref().unwrap_or("unknown")
+        );
+        outln!("  Assertions: {}", manifest.assertions.join(", "));
+    }
+    if let Some(remote) = remote {
+        outln!("Remote manifest: {}", remote);
+    }
+    Ok(())
+}
+
+pub fn strip_provenance<P: AsRef<Path>, Q: AsRef<Path>>(
+    path: P,
+    output: Option<Q>,
+    options: &GlobalOptions,
+) -> Result<()> {
+    let (mut png, carrier) = try_read_png(&path, options)?;
+    let removed = provenance::strip(&mut png);
+
+    let path = output_path(&path, output.as_ref());
+    write_png(path, png, &carrier, options)?;
+    statusln!(path, "Removed {} manifest store(s)", removed);
+    Ok(())
+}
+
+pub fn print<P: AsRef<Path>>(
+    path: P,
+    display: &DisplayOptions,
+    selection: &Selection,
+    format: Option<&OutputFormat>,
+    paging: Paging,
+    options: &GlobalOptions,
+) -> Result<()> {
+    let (png, _) = try_read_png(path, options)?;
+    let chunks = selection.select(&png);
+    let text = match format {
+        Some(format) => format.render_styled(&chunks, display),
+        None => StyledText::plain(display.selected(&chunks).to_string()),
+    };
+    console::page(&text, paging);
+    Ok(())
+}
+
+/// Lists the selected chunks of a PNG using `format`. For other formats, the
+/// index, key and size of each segment are printed in columns.
+pub fn list<P: AsRef<Path>>(
+    path: P,
+    display: &DisplayOptions,
+    selection: &Selection,
+    format: Option<&OutputFormat>,
+    paging: Paging,
+    options: &GlobalOptions,
+) -> Result<()> {
+    let (container, _) = read_container(path, options)?;
+    let text = match container.as_png() {
+        Some(png) => {
+            let format = format.cloned().unwrap_or_default();
+            format.render_styled(&selection.select(png), display)
+        }
+        None => {
+            if format.is_some() {
+                bail!("--format is only supported for PNG files");
+            }
+            if selection.filters_properties() {
+                bail!("filtering by chunk properties is only supported for PNG files");
+            }
+            if selection.reorders() {
+                bail!("sorting is only supported for PNG files");
+            }
+            let segments = container.segments();
+            let matching = segments
+                .iter()
+                .enumerate()
+                .filter(|(_, segment)| selection.matches_type(&segment.kind));
+            let rows: Vec<Vec<String>> = selection
+                .window(matching)
+                .map(|(index, segment)| {
+                    vec![
+                        index.to_string(),
+                        segment.kind.clone(),
+                        display.size(segment.length as u64),
+                    ]
+                })
+                .collect();
+            StyledText::plain(display::columns(&rows, &[true, false, true]))
+        }
+    };
+    console::page(&text, paging);
+    Ok(())
+}
+
+/// Prints one `{type}\t{offset}\t{length}\t{evidence}` line per chunk
+/// which probably carries a payload
+pub fn detect<P: AsRef<Path>>(path: P, options: &GlobalOptions) -> Result<()> {
+    let (png, _) = try_read_png(path, options)?;
+    let candidates = detect::detect(&png);
+    if candidates.is_empty() {
+        outln!("No payloads found");
+    }
+    for candidate in candidates {
+        outln!(
+            "{}\t{}\t{}\t{}",
+            candidate.chunk_type,
+            candidate.offset,
+            candidate.length,
+            candidate.evidence
+        );
+    }
+    Ok(())
+}
+
+/// Prints chunk types suggested for private data which the PNG doesn't use
+/// yet, each with what its letters mean
+pub fn suggest_chunk_types<P: AsRef<Path>>(
+    path: P,
+    project: Option<&str>,
+    count: usize,
+    options: &GlobalOptions,
+) -> Result<()> {
+    let (png, _) = try_read_png(path, options)?;
+    let used: Vec<ChunkType> = png
+        .chunks()
+        .iter()
+        .map(|chunk| chunk.chunk_type().clone())
+        .collect();
+    for chunk_type in suggest::suggest(project, count, &used)? {
+        let [a, b, c, d] = chunk_type.bytes().map(char::from);
+        outln!(
+            "{}\tancillary ({}), private ({}), reserved bit unset ({}), safe to copy ({})",
+            chunk_type,
+            a,
+            b,
+            c,
+            d
+        );
+    }
+    Ok(())
+}
+
+/// Lists the palette entries with their index, color and alpha
+pub fn show_palette<P: AsRef<Path>>(path: P, options: &GlobalOptions) -> Result<()> {
+    let (png, _) = try_read_png(path, options)?;
+    for (index, color) in palette::read(&png)?.iter().enumerate() {
+        outln!(
+            "{}\t#{:02x}{:02x}{:02x}\t{}",
+            index,
+            color.red,
+            color.green,
+            color.blue,
+            color.alpha
+        );
+    }
+    Ok(())
+}
+
+pub fn export_palette<P: AsRef<Path>, Q: AsRef<Path>>(
+    path: P,
+    format: PaletteFormat,
+    output: Q,
+    options: &GlobalOptions,
+) -> Result<()> {
+    let (png, _) = try_read_png(&path, options)?;
+    let colors = palette::read(&png)?;
+    let bytes = match format {
+        PaletteFormat::Gpl => {
+            let name = path
+                .as_ref()
+                .file_stem()
+                .unwrap_or_default()
+                .to_string_lossy();
+            palette::to_gpl(&colors, &name).into_bytes()
+        }
+        PaletteFormat::Png => palette::to_png(&colors).as_bytes(),
+    };
+    fs::write(output, bytes).context("Failed to write palette file")?;
+    Ok(())
+}
+
+/// Prints the length and CRC of a chunk with `chunk_type` and the contents
+/// of `data_path` as data, e.g. for crafting chunks in a hex editor. The type
+/// only needs to be 4 bytes long, so invalid types can be crafted too.
+pub fn crc<P: AsRef<Path>>(data_path: P, chunk_type: &str) -> Result<()> {
+    let Ok(type_bytes) = <[u8; 4]>::try_from(chunk_type.as_bytes()) else {
+        bail!("a chunk type is 4 bytes long, got {}", chunk_type.len());
+    };
+    let data = fs::read(data_path).context("Failed to read data file")?;
+    let mut hasher = CrcHasher::from_type_bytes(&type_bytes);
+    hasher.update(&data);
+
+    outln!("Length: {} ({:08x})", data.len(), data.len());
+    outln!("CRC: {:08x}", hasher.finalize());
+    Ok(())
+}
+
+/// Compares the stored CRC of the chunk at `index` with the one computed
+/// from its type and data. Works on files with broken chunks, which can't
+/// be parsed.
+pub fn check_crc<P: AsRef<Path>>(path: P, index: usize, options: &GlobalOptions) -> Result<()> {
+    let (bytes, _) = read_file(path, options)?;
+    if !Png::is_png(&bytes) {
+        return Err(PngParseError::from_signature(&bytes).into());
+    }
+
+    let mut cursor = Png::SIGNATURE.len();
+    let mut current = 0;
+    let chunk = loop {
+        let Some(length) = bytes.get(cursor..cursor + 4) else {
+            bail!("the file has only {} chunk(s)", current);
+        };
+        let length = u32::from_be_bytes(length.try_into().unwrap()) as usize;
+        let end = (cursor + 12).checked_add(length);
+        let Some(chunk) = end.and_then(|end| bytes.get(cursor + 4..end)) else {
+            bail!("chunk {} is truncated", current);
+        };
+        if current == index {
+            break chunk;
+        }
+        cursor += 12 + length;
+        current += 1;
+    };
+
+    let (type_bytes, rest) = chunk.split_at(4);
+    let (data, stored) = rest.split_at(rest.len() - 4);
+    let stored = u32::from_be_bytes(stored.try_into().unwrap());
+    let mut hasher = CrcHasher::from_type_bytes(type_bytes.try_into().unwrap());
+    hasher.update(data);
+    let computed = hasher.finalize();
+    outln!(
+        "Chunk {} ({}): stored CRC {:08x}, computed {:08x}",
+        index,
+        String::from_utf8_lossy(type_bytes),
+        stored,
+        computed
+    );
+    if stored != computed {
+        bail!("the stored CRC doesn't match");
+    }
+    Ok(())
+}
+
+/// Summarizes the image format and whether data can be hidden in the least
+/// significant bits of its pixels
+pub fn capacity<P: AsRef<Path>>(path: P, options: &GlobalOptions) -> Result<()> {
+    let (png, _) = try_read_png(path, options)?;
+    let header = png.image_header()?;
+    outln!("Dimensions: {}x{}", header.width, header.height);
+    outln!(
+        "Color type: {}, {} bit(s) per sample",
+        header.color_type,
+        header.bit_depth
+    );
+    outln!(
+        "Interlaced: {}",
+        if header.interlaced { "yes" } else { "no" }
+    );
+    match header.lsb_capacity() {
+        Ok(bytes) => outln!("LSB capacity: {} bytes", bytes),
+        Err(err) => outln!("LSB capacity: none, {}", err),
+    }
+    Ok(())
+}
+
+/// Checks that the PNGs at `path` and `other` have the same pixels,
+/// regardless of their metadata and how the pixels are compressed. Fails at
+/// the first difference.
+pub fn pixequal<P: AsRef<Path>, Q: AsRef<Path>>(
+    path: P,
+    other: Q,
+    options: &GlobalOptions,
+) -> Result<()> {
+    let decode = |path: &Path| -> Result<(Png, Raster)> {
+        let (png, _) = try_read_png(path, options)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let raster = Raster::decode(&png)
+            .with_context(|| format!("Failed to decode the pixels of {}", path.display()))?;
+        Ok((png, raster))
+    };
+    let (png, raster) = decode(path.as_ref())?;
+    let (other_png, other_raster) = decode(other.as_ref())?;
+
+    let header = raster.header;
+    match raster.compare(&other_raster) {
+        PixelComparison::Identical => {}
+        PixelComparison::DifferentFormat => {
+            let other = other_raster.header;
+            bail!(
+                "the images can't be compared: {}x{} {} with {} bit(s) per sample vs. {}x{} {} with {} bit(s) per sample",
+                header.width,
+                header.height,
+                header.color_type,
+                header.bit_depth,
+                other.width,
+                other.height,
+                other.color_type,
+                other.bit_depth
+            );
+        }
+        PixelComparison::DifferentRow(row) => {
+            bail!("the pixels differ, starting at scanline {}", row)
+        }
+    }
+    // The same indices only mean the same colors with the same palette
+    if header.color_type == ColorType::Indexed {
+        let plte = ChunkType::try_from(*b"PLTE").unwrap();
+        if png.chunk_by_type(&plte) != other_png.chunk_by_type(&plte) {
+            bail!("the palette indices are identical, but the palettes differ");
+        }
+    }
+    outln!(
+        "The pixels are identical ({}x{}, {})",
+        header.width,
+        header.height,
+        header.color_type
+    );
+    Ok(())
+}
+
+/// The chunk type selftest hides its payload in
+const SELFTEST_CHUNK_TYPE: &str = "seLf";
+
+/// A copy of a file in the temporary directory, deleted when dropped
+struct TempCopy(PathBuf);
+
+impl TempCopy {
+    fn new(path: &Path, bytes: &[u8]) -> Result<Self> {
+        // Names which aren't UTF-8 are kept as they are
+        let mut name = OsString::from(format!("pngme-selftest-{}-", std::process::id()));
+        name.push(path.file_name().unwrap_or_default());
+        let copy = std::env::temp_dir().join(name);
+        fs::write(&copy, bytes).context("Failed to copy the file to the temporary directory")?;
+        Ok(Self(copy))
+    }
+}
+
+impl Drop for TempCopy {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+/// Encodes a random payload into a temporary copy of the PNG, decodes it,
+/// removes it again and checks that the copy ends up byte-identical to the
+/// original. The file itself is never written.
+pub fn selftest<P: AsRef<Path>>(path: P, options: &GlobalOptions) -> Result<()> {
+    let original = fs::read(&path).context("Failed to open file")?;
+    let copy = TempCopy::new(path.as_ref(), &original)?;
+    // Normalizing the copy would change it on purpose
+    let options = &GlobalOptions {
+        deterministic: false,
+        ..options.clone()
+    };
+    let payload = padding::random_bytes(64);
+
+    let (mut container, carrier) = read_container(&copy.0, options)?;
+    if container.as_png().is_none() {
+        bail!(
+            "selftest only supports PNGs, not {} files",
+            container.format()
+        );
+    }
+    if container.get(SELFTEST_CHUNK_TYPE)?.is_some() {
+        bail!(
+            "the file already has a {} chunk, remove it to run the test",
+            SELFTEST_CHUNK_TYPE
+        );
+    }
+    container.insert(SELFTEST_CHUNK_TYPE, payload.clone())?;
+    write_container(&copy.0, container.as_ref(), &carrier, options)?;
+    outln!(
+        "Encoded {} random bytes into a {} chunk",
+        payload.len(),
+        SELFTEST_CHUNK_TYPE
+    );
+
+    let (mut container, carrier) =
+        read_container(&copy.0, options).context("Failed to read the file back after encoding")?;
+    match container.get(SELFTEST_CHUNK_TYPE)? {
+        Some(data) if data == payload => outln!("Decoded the same bytes back"),
+        Some(_) => bail!("the decoded payload differs from the encoded one"),
+        None => bail!("the payload is missing after writing the file"),
+    }
+
+    container.remove(SELFTEST_CHUNK_TYPE)?;
+    write_container(&copy.0, container.as_ref(), &carrier, options)?;
+    let stripped = fs::read(&copy.0).context("Failed to read the file back after removing")?;
+    if stripped != original {
+        match original.iter().zip(&stripped).position(|(a, b)| a != b) {
+            Some(offset) => bail!(
+                "the file differs from the original after removing the payload, starting at byte {}",
+                offset
+            ),
+            None => bail!(
+                "the file differs from the original after removing the payload: {} bytes instead of {}",
+                stripped.len(),
+                original.len()
+            ),
+        }
+    }
+    outln!("Removed it again, the file is byte-identical to the original");
+    Ok(())
+}
+
+/// How [`batch`] applies a command to a directory
+#[derive(Debug, Clone, Default)]
+pub struct BatchOptions {
+    /// Stop at the first file that fails instead of carrying on
+    pub fail_fast: bool,
+    /// The number of files to process at the same time, one if 0
+    pub jobs: usize,
+    /// Also write the outcomes as JSON to this file
+    pub report: Option<PathBuf>,
+    /// Which files of the directory to process
+    pub scan: ScanOptions,
+}
+
+/// Runs `process` on every PNG in `dir` selected by `batch.scan` and prints
+/// a summary of the outcomes. Other files are skipped. Fails if any file failed, but only
+/// after all files were processed unless `fail_fast` is set.
+///
+/// With several jobs, the output of each file is held back until the files
+/// before it are done, so the output and the report are in the same order
+/// on every run.
+pub fn batch<F>(dir: &Path, batch: &BatchOptions, process: F) -> Result<()>
+where
+    F: Fn(&Path) -> Result<()> + Sync,
+{
+    if !dir.is_dir() {
+        bail!("{} is not a directory", dir.display());
+    }
+
+    let files = walk::scan(dir, &batch.scan)
+        .with_context(|| format!("Failed to scan directory {}", dir.display()))?;
+    let outcomes = if batch.jobs > 1 {
+        process_parallel(&files, batch, &process)
+    } else {
+        let mut outcomes = Vec::new();
+        for file in &files {
+            let outcome = process_file(file, &process);
+            let failed = matches!(outcome, Outcome::Failed(_));
+            outcomes.push(FileOutcome::new(file, outcome));
+            if failed && batch.fail_fast {
+                break;
+            }
+        }
+        outcomes
+    };
+
+    outln!("{}", batch::render_table(&outcomes).trim_end());
+    if let Some(report) = &batch.report {
+        fs::write(report, batch::render_json(&outcomes)).context("Failed to write report")?;
+    }
+    let failed = Summary::new(&outcomes).failed;
+    if failed > 0 {
+        bail!("the command failed on {} file(s)", failed);
+    }
+    Ok(())
+}
+
+/// Processes `files` on `batch.jobs` threads, printing the output of each
+/// file in input order
+fn process_parallel<F>(files: &[PathBuf], batch: &BatchOptions, process: &F) -> Vec<FileOutcome>
+where
+    F: Fn(&Path) -> Result<()> + Sync,
+{
+    let next = AtomicUsize::new(0);
+    let stop = AtomicBool::new(false);
+    let (sender, receiver) = mpsc::channel();
+
+    thread::scope(|scope| {
+        for _ in 0..batch.jobs.min(files.len()) {
+            let (sender, next, stop) = (sender.clone(), &next, &stop);
+            // Files are claimed in order, so with --fail-fast the processed
+            // files are always the ones before some point
+            scope.spawn(move || {
+                while !stop.load(Ordering::Relaxed) {
+                    let index = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(file) = files.get(index) else {
+                        break;
+                    };
+                    let (outcome, output) = console::capture(|| process_file(file, process));
+                    if batch.fail_fast && matches!(outcome, Outcome::Failed(_)) {
+                        stop.store(true, Ordering::Relaxed);
+                    }
+                    if sender.send((index, outcome, output)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(sender);
+
+        let mut order = InputOrder::new();
+        let mut outcomes = Vec::new();
+        for (index, outcome, output) in receiver {
+            for (outcome, output) in order.push(index, (outcome, output)) {
+                output.replay();
+                outcomes.push(FileOutcome::new(&files[outcomes.len()], outcome));
+            }
+        }
+        outcomes
+    })
+}
+
+fn process_file<F>(file: &Path, process: &F) -> Outcome
+where
+    F: Fn(&Path) -> Result<()>,
+{
+    // Sniffing first avoids reading large files of other types
+    match png::sniff_file(file) {
+        Ok(true) => {}
+        Ok(false) => return Outcome::Skipped("not a PNG".to_owned()),
+        Err(err) => return Outcome::Failed(err.to_string()),
+    }
+    let before = match fs::read(file) {
+        Ok(bytes) => bytes,
+        Err(err) => return Outcome::Failed(err.to_string()),
+    };
+    if let Err(err) = process(file) {
+        return Outcome::Failed(format!("{:#}", err));
+    }
+    match fs::read(file) {
+        Ok(after) if after == before => Outcome::Unchanged,
+        Ok(_) => Outcome::Modified,
+        Err(err) => Outcome::Failed(err.to_string()),
+    }
+}
+
+/// Audits a PNG, or every `.png` file in a directory tree
+pub fn audit<P: AsRef<Path>>(
+    path: P,
+    deny: &[Rule],
+    report: Option<(&Path, ReportFormat)>,
+    options: &GlobalOptions,
+) -> Result<()> {
+    let path = path.as_ref();
+    if !path.is_dir() {
+        let (bytes, _) = read_file(path, options)?;
+        let findings = audit::audit(&bytes, &options.cancel)?;
+        print_findings(&findings, "No findings");
+        write_report(&[(path, &findings)], report)?;
+        return check_denied(&findings, deny);
+    }
+
+    let mut audited = Vec::new();
+    let mut failed = 0;
+    for file in png_files(path)? {
+        let findings = read_file(&file, options)
+            .and_then(|(bytes, _)| Ok(audit::audit(&bytes, &options.cancel)?));
+        match findings {
+            Ok(findings) => {
+                for finding in &findings {
+                    outln!("{}: {}", file.display(), painted(finding));
+                }
+                audited.push((file, findings));
+            }
+            Err(err) => {
+                errln!("{}: {:#}", file.display(), err);
+                failed += 1;
+            }
+        }
+    }
+    if audited.iter().all(|(_, findings)| findings.is_empty()) {
+        outln!("No findings in {} file(s)", audited.len());
+    }
+
+    let files: Vec<(&Path, &[Finding])> = audited
+        .iter()
+        .map(|(file, findings)| (file.as_path(), findings.as_slice()))
+        .collect();
+    write_report(&files, report)?;
+    let findings: Vec<Finding> = audited.into_iter().flat_map(|(_, f)| f).collect();
+    check_denied(&findings, deny)?;
+    if failed > 0 {
+        bail!("{} file(s) could not be audited", failed);
+    }
+    Ok(())
+}
+
+/// Reports ancillary chunks with identical data across the PNGs in `path`,
+/// a directory scanned according to `scan` or a single file. Chunks with less
+/// than `min_size` bytes of data are ignored. Other files are skipped.
+pub fn dedup_scan<P: AsRef<Path>>(
+    path: P,
+    scan: &ScanOptions,
+    min_size: u32,
+    json: bool,
+    options: &GlobalOptions,
+) -> Result<()> {
+    let path = path.as_ref();
+    let files = if path.is_dir() {
+        walk::scan(path, scan).with_context(|| format!("Failed to scan {}", path.display()))?
+    } else {
+        vec![path.to_path_buf()]
+    };
+
+    let mut index = DedupIndex::new(min_size);
+    let mut failed = 0;
+    for file in files {
+        options.cancel.check()?;
+        let png = read_file(&file, options).and_then(|(bytes, _)| {
+            if !Png::is_png(&bytes) {
+                return Ok(None);
+            }
+            Ok(Some(parse_png(&bytes, options)?))
+        });
+        match png {
+            Ok(Some(png)) => index.add(&file.display().to_string(), &png),
+            Ok(None) => {}
+            Err(err) => {
+                errln!("{}: {:#}", file.display(), err);
+                failed += 1;
+            }
+        }
+    }
+
+    let duplicates = index.duplicates();
+    if json {
+        outln!("{}", dedup::render_json(index.files(), &duplicates));
+    } else {
+        outln!("{}", dedup::render_text(index.files(), &duplicates));
+    }
+    if failed > 0 {
+        bail!("{} file(s) could not be read", failed);
+    }
+    Ok(())
+}
+
+/// Streams the PNG at `path`, or stdin if it is `-`, to stdout without its
+/// metadata, see [`StripFilter`]
+pub fn stream_strip<P: AsRef<Path>>(
+    path: P,
+    keep: Vec<TypePattern>,
+    options: &GlobalOptions,
+) -> Result<()> {
+    let path = path.as_ref();
+    if console::stdout_is_terminal() {
+        bail!("refusing to write a binary file to the terminal, redirect stdout");
+    }
+    let reader: Box<dyn Read> = if path == Path::new("-") {
+        Box::new(std::io::stdin().lock())
+    } else {
+        Box::new(fs::File::open(path).context("Failed to open file")?)
+    };
+    let writer = std::io::BufWriter::new(std::io::stdout().lock());
+    let summary = StripFilter::new()
+        .keep(keep)
+        .with_cancellation(options.cancel.clone())
+        .run(reader, writer)?;
+    // stdout carries the image
+    errln!(
+        "Removed {} chunk(s), {} bytes",
+        summary.removed,
+        summary.removed_bytes
+    );
+    Ok(())
+}
+
+fn check_denied(findings: &[Finding], deny: &[Rule]) -> Result<()> {
+    let denied = findings
+        .iter()
+        .filter(|finding| deny.contains(&finding.rule))
+        .count();
+    if denied > 0 {
+        bail!("{} finding(s) violate denied rules", denied);
+    }
+    Ok(())
+}
+
+/// Collects the `.png` files in a directory tree, in a stable order.
+/// Symbolic links are not followed.
+fn png_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut dirs = vec![dir.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let entries = fs::read_dir(&dir)
+            .with_context(|| format!("Failed to read directory {}", dir.display()))?;
+        for entry in entries {
+            let entry = entry?;
+            let file_type = entry.file_type()?;
+            let path = entry.path();
+            if file_type.is_dir() {
+                dirs.push(path);
+            } else if file_type.is_file()
+                && path
+                    .extension()
+                    .is_some_and(|extension| extension.eq_ignore_ascii_case("png"))
+            {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Verifies the file with the checks of the profile `profile`, looked up in
+/// the JSON `config` file first, see [`profile::load`]
+pub fn verify<P: AsRef<Path>, Q: AsRef<Path>>(
+    path: P,
+    profile: Option<&str>,
+    config: Option<Q>,
+    report: Option<(&Path, ReportFormat)>,
+    options: &GlobalOptions,
+) -> Result<()> {
+    let config = config
+        .map(|config| {
+            let config = config.as_ref();
+            fs::read_to_string(config)
+                .with_context(|| format!("Failed to read config file {}", config.display()))
+        })
+        .transpose()?;
+    let profile = match profile {
+        Some(name) => profile::load(name, config.as_deref())?,
+        None => Profile::default(),
+    };
+    let (bytes, _) = read_file(&path, options)?;
+    let findings = profile::check(&bytes, &profile, &options.cancel)?;
+    print_findings(&findings, "OK");
+    write_report(&[(path.as_ref(), &findings)], report)?;
+
+    let failures = findings
+        .iter()
+        .filter(|finding| profile.fails(finding))
+        .count();
+    if failures > 0 {
+        bail!("verification failed with {} finding(s)", failures);
+    }
+    Ok(())
+}
+
+fn print_findings(findings: &[Finding], none: &str) {
+    if findings.is_empty() {
+        outln!("{}", console::paint(none, Style::Success));
+    }
+    for finding in findings {
+        outln!("{}", painted(finding));
+    }
+}
+
+/// Renders a finding like its `Display` impl, with the severity in its color
+fn painted(finding: &Finding) -> PaintedFinding<'_> {
+    PaintedFinding(finding)
+}
+
+/// A [`Finding`] with its severity [`console::paint`]ed. Unlike a painted
+/// string, its message, which may quote the file, is printed as data.
+struct PaintedFinding<'a>(&'a Finding);
+
+impl std::fmt::Display for PaintedFinding<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let finding = self.0;
+        let severity = finding.rule.severity();
+        let style = match severity {
+            Severity::Error => Style::Error,
+            Severity::Warning => Style::Warning,
+            Severity::Info => Style::Info,
+        };
+        write!(
+            f,
+            "{}[{} {}]: {}",
+            console::paint(severity, style),
+            finding.rule.id(),
+            finding.rule.name(),
+            finding.message
+        )
+    }
+}
+
+fn write_report(
+    files: &[(&Path, &[Finding])],
+    report: Option<(&Path, ReportFormat)>,
+) -> Result<()> {
+    if let Some((report_path, format)) = report {
+        let paths: Vec<String> = files
+            .iter()
+            .map(|(path, _)| path.to_string_lossy().into_owned())
+            .collect();
+        let files: Vec<FileFindings> = files
+            .iter()
+            .zip(&paths)
+            .map(|((_, findings), path)| FileFindings { path, findings })
+            .collect();
+        let report = format.render(&files);
+        fs::write(report_path, report).context("Failed to write report")?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{console::capture, png::tests::PNG_FILE};
+
+    /// A directory in the temporary directory, removed when dropped
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!("pngme-{}-{}", name, std::process::id()));
+            fs::create_dir_all(&dir).unwrap();
+            Self(dir)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    const NO_SPREAD: &[PathBuf] = &[];
+
+    fn encode_text<Q: AsRef<Path>>(
+        path: &Path,
+        text: &str,
+        output: Option<Q>,
+        envelope: &EnvelopeOptions,
+        options: &GlobalOptions,
+    ) -> Result<()> {
+        let message = Message::Text(text.to_owned());
+        encode(path, "ruSt", message, output, NO_SPREAD, envelope, options)
+    }
+
+    /// Decodes the ruSt payload of `path`, returning what was printed
+    fn decode_printed(path: &Path, format: PayloadFormat) -> String {
+        let (decoded, captured) = capture(|| {
+            decode(
+                path,
+                "ruSt",
+                NO_SPREAD,
+                Destination::Print(format),
+                &DecodeOptions::default(),
+                &GlobalOptions::default(),
+            )
+        });
+        decoded.unwrap();
+        captured.stdout().to_owned()
+    }
+
+    /// Encodes a payload into a file at `path` and decodes it from the copy
+    /// written next to it
+    fn round_trip(dir: &Path, name: &std::ffi::OsStr) {
+        let path = dir.join(name);
+        fs::write(&path, PNG_FILE).unwrap();
+        let mut copy = OsString::from("copy of ");
+        copy.push(name);
+        let output = dir.join(copy);
+        let options = GlobalOptions::default();
+        encode_text(
+            &path,
+            "hello",
+            Some(&output),
+            &EnvelopeOptions::default(),
+            &options,
+        )
+        .unwrap();
+        assert_eq!(decode_printed(&output, PayloadFormat::Raw), "hello\n");
+    }
+
+    #[test]
+    fn test_unusual_paths() {
+        let dir = TempDir::new("commands paths");
+        for name in ["space name.png", "ünïcode 画.png"] {
+            round_trip(&dir.0, name.as_ref());
+        }
+    }
+
+    /// Linux file names are bytes, which needn't be UTF-8
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_non_utf8_path() {
+        use std::os::unix::ffi::OsStrExt;
+        let dir = TempDir::new("commands-bytes");
+        round_trip(&dir.0, std::ffi::OsStr::from_bytes(b"caf\xe9.png"));
+    }
+
+    /// A \\?\ path, which isn't limited to 260 characters
+    #[cfg(windows)]
+    #[test]
+    fn test_long_path() {
+        let dir = TempDir::new("commands-long");
+        let deep = ["a", "b", "c"]
+            .iter()
+            .fold(dir.0.canonicalize().unwrap(), |path, name| {
+                path.join(name.repeat(100))
+            });
+        assert!(deep.to_str().unwrap().starts_with(r"\\?\"));
+        fs::create_dir_all(&deep).unwrap();
+        round_trip(&deep, "dice.png".as_ref());
+    }
+
+    #[test]
+    fn test_if_changed() {
+        let dir = TempDir::new("commands-if-changed");
+        let path = dir.0.join("in.png");
+        fs::write(&path, PNG_FILE).unwrap();
+        let options = GlobalOptions::default();
+        let envelope = EnvelopeOptions {
+            if_changed: true,
+            ..EnvelopeOptions::default()
+        };
+        encode_text(&path, "hello", None::<&Path>, &envelope, &options).unwrap();
+        let encoded = fs::read(&path).unwrap();
+        assert_ne!(encoded, PNG_FILE);
+
+        // The same payload again leaves the file alone
+        let modified = fs::metadata(&path).unwrap().modified().unwrap();
+        encode_text(&path, "hello", None::<&Path>, &envelope, &options).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), encoded);
+        assert_eq!(fs::metadata(&path).unwrap().modified().unwrap(), modified);
+        // so does naming the file itself as the output
+        encode_text(&path, "hello", Some(&path), &envelope, &options).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), encoded);
+
+        // but another output file is always written
+        let output = dir.0.join("out.png");
+        encode_text(&path, "hello", Some(&output), &envelope, &options).unwrap();
+        assert!(fs::read(&output).unwrap().len() > encoded.len());
+
+        // and a different payload is added
+        encode_text(&path, "other", None::<&Path>, &envelope, &options).unwrap();
+        assert_ne!(fs::read(&path).unwrap(), encoded);
+    }
+
+    #[test]
+    fn test_decode_formats() {
+        let dir = TempDir::new("commands-formats");
+        let path = dir.0.join("in.png");
+        fs::write(&path, PNG_FILE).unwrap();
+        let options = GlobalOptions {
+            stdin_format: Transport::Base64,
+            ..GlobalOptions::default()
+        };
+        // The bytes 00 ff 0a, which aren't UTF-8
+        let envelope = EnvelopeOptions::default();
+        encode_text(&path, "AP8K", None::<&Path>, &envelope, &options).unwrap();
+
+        assert_eq!(decode_printed(&path, PayloadFormat::Base64), "AP8K\n");
+        assert_eq!(decode_printed(&path, PayloadFormat::Hex), "00ff0a\n");
+        let (decoded, captured) = capture(|| {
+            decode(
+                &path,
+                "ruSt",
+                NO_SPREAD,
+                Destination::Print(PayloadFormat::Raw),
+                &DecodeOptions::default(),
+                &GlobalOptions::default(),
+            )
+        });
+        assert!(decoded.unwrap_err().to_string().contains("--base64"));
+        assert_eq!(captured.stdout(), "");
+    }
+
+    #[test]
+    fn test_decode_escapes_printed_text() {
+        let dir = TempDir::new("commands-escape");
+        let path = dir.0.join("in.png");
+        fs::write(&path, PNG_FILE).unwrap();
+        let options = GlobalOptions::default();
+        let envelope = EnvelopeOptions::default();
+        encode_text(
+            &path,
+            "line1\r\nline2\x1b[31m",
+            None::<&Path>,
+            &envelope,
+            &options,
+        )
+        .unwrap();
+        // Printed like on a terminal, the carriage return and the escape
+        // sequence can't move the cursor or recolor it
+        assert_eq!(
+            decode_printed(&path, PayloadFormat::Raw),
+            "line1\\x0d\nline2\\x1b[31m\n"
+        );
+    }
+
+    #[test]
+    fn test_confirm() {
+        let dir = TempDir::new("commands-confirm");
+        let path = dir.0.join("in.png");
+        let output = dir.0.join("out.png");
+        fs::write(&path, PNG_FILE).unwrap();
+        let options = GlobalOptions::default();
+        encode_text(
+            &path,
+            "hello",
+            Some(&output),
+            &EnvelopeOptions::default(),
+            &options,
+        )
+        .unwrap();
+
+        let (confirmed, captured) =
+            capture(|| confirm("encode", &path, &output, &PNG_FILE, &options));
+        confirmed.unwrap();
+        let size = display::human_size(fs::metadata(&output).unwrap().len());
+        assert_eq!(
+            captured.stdout(),
+            format!("Wrote {} ({}): added ruSt\n", output.display(), size)
+        );
+
+        let (confirmed, captured) =
+            capture(|| confirm("encode", &path, &path, &PNG_FILE, &options));
+        confirmed.unwrap();
+        assert_eq!(
+            captured.stdout(),
+            format!("{}: unchanged\n", path.display())
+        );
+
+        let quiet = GlobalOptions {
+            quiet: true,
+            ..GlobalOptions::default()
+        };
+        let (confirmed, captured) =
+            capture(|| confirm("encode", &path, &output, &PNG_FILE, &quiet));
+        confirmed.unwrap();
+        assert_eq!(captured, Default::default());
+    }
+}
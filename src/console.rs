@@ -0,0 +1,575 @@
+use std::{
+    borrow::Cow,
+    cell::{Cell, RefCell},
+    env,
+    fmt::{self, Write as _},
+    io::{self, IsTerminal, Write},
+    process::{Command, Stdio},
+    str::FromStr,
+    sync::atomic::{AtomicBool, AtomicU8, Ordering},
+};
+
+thread_local! {
+    static CAPTURED: RefCell<Option<Captured>> = const { RefCell::new(None) };
+}
+
+/// Whether lines are printed without [`sanitize`]
+static RAW: AtomicBool = AtomicBool::new(false);
+
+/// Prints everything as is from now on, including terminal control
+/// characters. Used for `--raw`.
+pub fn set_raw(raw: bool) {
+    RAW.store(raw, Ordering::Relaxed);
+}
+
+/// When output is colored
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ColorChoice {
+    /// If the output goes to a terminal and `$NO_COLOR` isn't set
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+#[error("unknown color mode '{0}', expected auto, always or never")]
+pub struct UnknownColorChoiceError(String);
+
+impl FromStr for ColorChoice {
+    type Err = UnknownColorChoiceError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(Self::Auto),
+            "always" => Ok(Self::Always),
+            "never" => Ok(Self::Never),
+            other => Err(UnknownColorChoiceError(other.to_owned())),
+        }
+    }
+}
+
+/// The [`ColorChoice`], stored as its index
+static COLOR: AtomicU8 = AtomicU8::new(0);
+
+/// Colors the output from now on as `choice` says. Used for `--color`.
+pub fn set_color(choice: ColorChoice) {
+    COLOR.store(choice as u8, Ordering::Relaxed);
+}
+
+/// Whether text printed to stdout, or stderr, is shown in color
+fn colors_enabled(stderr: bool) -> bool {
+    match COLOR.load(Ordering::Relaxed) {
+        n if n == ColorChoice::Always as u8 => true,
+        n if n == ColorChoice::Never as u8 => false,
+        _ => {
+            let terminal = match stderr {
+                true => io::stderr().is_terminal(),
+                false => io::stdout().is_terminal(),
+            };
+            terminal
+                && env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+                && env::var_os("TERM").is_none_or(|term| term != "dumb")
+        }
+    }
+}
+
+/// What a piece of output is, which decides its color
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Style {
+    /// A critical chunk type
+    Critical,
+    /// An ancillary chunk type
+    Ancillary,
+    /// A failed check
+    Error,
+    Warning,
+    Info,
+    /// A passed check
+    Success,
+}
+
+impl Style {
+    const ALL: [Self; 6] = [
+        Self::Critical,
+        Self::Ancillary,
+        Self::Error,
+        Self::Warning,
+        Self::Info,
+        Self::Success,
+    ];
+
+    /// The SGR parameters of the style in the terminal
+    fn sgr(self) -> &'static str {
+        match self {
+            Self::Critical => "1",
+            Self::Ancillary => "36",
+            Self::Error => "1;31",
+            Self::Warning => "33",
+            Self::Info => "34",
+            Self::Success => "32",
+        }
+    }
+
+    /// The noncharacter which starts text of this style until
+    /// [`STYLE_END`] in text rendered by [`paint`]. Printed lines keep their
+    /// styles apart from their text, see [`StyledText`].
+    fn marker(self) -> char {
+        char::from_u32(0xfdd0 + self as u32).unwrap()
+    }
+
+    fn from_marker(c: char) -> Option<Self> {
+        Self::ALL.into_iter().find(|style| style.marker() == c)
+    }
+}
+
+const STYLE_END: char = '\u{fddf}';
+
+fn is_marker(c: char) -> bool {
+    ('\u{fdd0}'..='\u{fddf}').contains(&c)
+}
+
+thread_local! {
+    /// Set while a style marker is written, which is how a [`StyledText`]
+    /// tells the markers of painted text from the same characters in data
+    static MARKING: Cell<bool> = const { Cell::new(false) };
+}
+
+fn write_marker(f: &mut fmt::Formatter<'_>, marker: char) -> fmt::Result {
+    MARKING.set(true);
+    let written = f.write_char(marker);
+    MARKING.set(false);
+    written
+}
+
+/// Text shown in a [`Style`] when printed with [`outln!`] or [`errln!`]
+#[derive(Debug, Clone, Copy)]
+pub struct Painted<T> {
+    value: T,
+    style: Style,
+}
+
+/// Marks `value` to be shown in `style` where the output is colored, see
+/// [`set_color`]. Painted text rendered into a string keeps its style only
+/// if the string is turned into a [`StyledText`] with
+/// [`StyledText::from_painted`].
+pub fn paint<T: fmt::Display>(value: T, style: Style) -> Painted<T> {
+    Painted { value, style }
+}
+
+impl<T: fmt::Display> fmt::Display for Painted<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_marker(f, self.style.marker())?;
+        write!(f, "{}", self.value)?;
+        write_marker(f, STYLE_END)
+    }
+}
+
+/// The number of characters of `text` shown, leaving out style markers
+pub fn width(text: &str) -> usize {
+    text.chars().filter(|c| !is_marker(*c)).count()
+}
+
+/// Text with the byte offsets where styles start and end, kept apart from
+/// the text so data from files can't pass for them. Formatting it writes
+/// its styles as markers again, which a line printed with [`outln!`] or
+/// [`errln!`] takes over.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StyledText {
+    text: String,
+    /// Where a style starts, or with None where it ends
+    styles: Vec<(usize, Option<Style>)>,
+}
+
+impl StyledText {
+    /// Text without styles, with any style markers in it left as they are
+    pub fn plain(text: String) -> Self {
+        Self {
+            text,
+            styles: Vec::new(),
+        }
+    }
+
+    /// Takes the style markers in `text`, which was rendered with [`paint`],
+    /// as its styles. `text` must not hold data from files, whose markers
+    /// would be taken too.
+    pub fn from_painted(text: &str) -> Self {
+        let mut styled = Self::default();
+        for c in text.chars() {
+            match c {
+                STYLE_END => styled.styles.push((styled.text.len(), None)),
+                c if is_marker(c) => {
+                    let style = Style::from_marker(c);
+                    styled.styles.push((styled.text.len(), style));
+                }
+                c => styled.text.push(c),
+            }
+        }
+        styled
+    }
+
+    /// The text without its styles
+    pub fn as_str(&self) -> &str {
+        &self.text
+    }
+
+    fn push_str(&mut self, text: &str) {
+        self.text.push_str(text);
+    }
+
+    fn append(&mut self, other: &StyledText) {
+        let offset = self.text.len();
+        self.styles.extend(
+            other
+                .styles
+                .iter()
+                .map(|&(position, style)| (offset + position, style)),
+        );
+        self.text.push_str(&other.text);
+    }
+
+    /// The text with its styles as escape sequences if `colored`
+    fn render(&self, colored: bool) -> Cow<'_, str> {
+        if !colored || self.styles.is_empty() {
+            return Cow::Borrowed(&self.text);
+        }
+        let mut rendered = String::with_capacity(self.text.len());
+        let mut start = 0;
+        for &(position, style) in &self.styles {
+            rendered.push_str(&self.text[start..position]);
+            match style {
+                Some(style) => write!(rendered, "\x1b[{}m", style.sgr()).unwrap(),
+                None => rendered.push_str("\x1b[0m"),
+            }
+            start = position;
+        }
+        rendered.push_str(&self.text[start..]);
+        Cow::Owned(rendered)
+    }
+}
+
+impl fmt::Display for StyledText {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut start = 0;
+        for &(position, style) in &self.styles {
+            f.write_str(&self.text[start..position])?;
+            write_marker(f, style.map_or(STYLE_END, Style::marker))?;
+            start = position;
+        }
+        f.write_str(&self.text[start..])
+    }
+}
+
+fn is_unsafe(c: char) -> bool {
+    c.is_control() && c != '\n' && c != '\t'
+}
+
+/// Escapes control characters other than newlines and tabs as `\xNN`, so
+/// data from a file can't move the cursor, recolor or clear the terminal.
+pub fn sanitize(text: &str) -> Cow<'_, str> {
+    if !text.chars().any(is_unsafe) {
+        return Cow::Borrowed(text);
+    }
+    let mut sanitized = String::with_capacity(text.len());
+    for c in text.chars() {
+        if is_unsafe(c) {
+            for byte in c.encode_utf8(&mut [0; 4]).bytes() {
+                write!(sanitized, "\\x{:02x}", byte).unwrap();
+            }
+        } else {
+            sanitized.push(c);
+        }
+    }
+    Cow::Owned(sanitized)
+}
+
+/// Builds a printed line: [`prepare`]s the text and collects the markers
+/// written by [`Painted`] and [`StyledText`] as its styles
+struct LineWriter(StyledText);
+
+impl fmt::Write for LineWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let line = &mut self.0;
+        if MARKING.get() {
+            for c in s.chars() {
+                line.styles.push((line.text.len(), Style::from_marker(c)));
+            }
+        } else {
+            line.text.push_str(&prepare(s));
+        }
+        Ok(())
+    }
+}
+
+/// Formats `args` into a line to print
+fn format_line(args: fmt::Arguments<'_>) -> StyledText {
+    let mut writer = LineWriter(StyledText::default());
+    fmt::Write::write_fmt(&mut writer, args).unwrap();
+    writer.0
+}
+
+/// What a command printed while its output was captured
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Captured {
+    stdout: StyledText,
+    stderr: StyledText,
+}
+
+impl Captured {
+    /// The captured text printed to stdout, without styles
+    pub fn stdout(&self) -> &str {
+        self.stdout.as_str()
+    }
+
+    /// The captured text printed to stderr, without styles
+    pub fn stderr(&self) -> &str {
+        self.stderr.as_str()
+    }
+
+    /// Prints the captured output to the real stdout and stderr
+    pub fn replay(&self) {
+        print!("{}", self.stdout.render(colors_enabled(false)));
+        eprint!("{}", self.stderr.render(colors_enabled(true)));
+    }
+}
+
+/// Runs `f`, collecting what it prints with [`outln!`] and [`errln!`] on this
+/// thread instead of printing it
+pub fn capture<T>(f: impl FnOnce() -> T) -> (T, Captured) {
+    let outer = CAPTURED.with(|captured| captured.replace(Some(Captured::default())));
+    let value = f();
+    let captured = CAPTURED.with(|captured| captured.replace(outer));
+    (value, captured.unwrap_or_default())
+}
+
+/// Sanitizes `text` unless [`set_raw`] was called
+fn prepare(text: &str) -> Cow<'_, str> {
+    if RAW.load(Ordering::Relaxed) {
+        Cow::Borrowed(text)
+    } else {
+        sanitize(text)
+    }
+}
+
+fn is_capturing() -> bool {
+    CAPTURED.with(|captured| captured.borrow().is_some())
+}
+
+#[doc(hidden)]
+pub fn print_line(args: fmt::Arguments<'_>, stderr: bool) {
+    let line = format_line(args);
+    let printed = CAPTURED.with(|captured| match captured.borrow_mut().as_mut() {
+        Some(captured) => {
+            let stream = if stderr {
+                &mut captured.stderr
+            } else {
+                &mut captured.stdout
+            };
+            stream.append(&line);
+            stream.push_str("\n");
+            true
+        }
+        None => false,
+    });
+    if !printed {
+        if stderr {
+            eprintln!("{}", line.render(colors_enabled(true)));
+        } else {
+            println!("{}", line.render(colors_enabled(false)));
+        }
+    }
+}
+
+/// When output is shown through a pager
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Paging {
+    /// Always print directly
+    #[default]
+    Never,
+
+    /// If stdout is a terminal and the output is longer than the screen,
+    /// which is assumed to have `$LINES` or 24 rows
+    Auto,
+
+    /// Whenever stdout is a terminal
+    Always,
+}
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+#[error("unknown paging mode '{0}', expected never, auto or always")]
+pub struct UnknownPagingError(String);
+
+impl FromStr for Paging {
+    type Err = UnknownPagingError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "never" => Ok(Self::Never),
+            "auto" => Ok(Self::Auto),
+            "always" => Ok(Self::Always),
+            other => Err(UnknownPagingError(other.to_owned())),
+        }
+    }
+}
+
+/// Whether printed output ends up on a terminal, and not in a pipe, a file or
+/// a capture
+pub fn stdout_is_terminal() -> bool {
+    !is_capturing() && io::stdout().is_terminal()
+}
+
+/// Whether stdout goes to a pipe or a file, where what is printed is read by
+/// another program and not shown
+pub fn stdout_is_piped() -> bool {
+    !is_capturing() && !io::stdout().is_terminal()
+}
+
+impl Paging {
+    fn applies_to(self, text: &str) -> bool {
+        let screen = || {
+            env::var("LINES")
+                .ok()
+                .and_then(|lines| lines.parse().ok())
+                .unwrap_or(24)
+        };
+        match self {
+            Self::Never => false,
+            _ if !stdout_is_terminal() => false,
+            Self::Auto => text.lines().count() >= screen(),
+            Self::Always => true,
+        }
+    }
+}
+
+/// Prints `text` like [`outln!`], but through the pager in `$PAGER` (`less`
+/// by default) if `paging` asks for it. Captured output is never paged, and
+/// `text` is printed directly if the pager can't be started.
+pub fn page(text: impl fmt::Display, paging: Paging) {
+    let text = format_line(format_args!("{}", text));
+    if !paging.applies_to(text.as_str()) || run_pager(&text).is_err() {
+        outln!("{}", text);
+    }
+}
+
+fn run_pager(text: &StyledText) -> io::Result<()> {
+    let pager = env::var("PAGER")
+        .ok()
+        .filter(|pager| !pager.trim().is_empty())
+        .unwrap_or_else(|| "less".to_owned());
+    let mut words = pager.split_whitespace();
+    let mut command = Command::new(words.next().unwrap());
+    command.args(words).stdin(Stdio::piped());
+    if env::var_os("LESS").is_none() {
+        // Let less exit right away if everything fits, like git does
+        command.env("LESS", "FRX");
+    }
+
+    let mut child = command.spawn()?;
+    let mut stdin = child.stdin.take().unwrap();
+    // Quitting the pager before the end closes the pipe, which is fine
+    let _ = writeln!(stdin, "{}", text.render(colors_enabled(false)));
+    drop(stdin);
+    child.wait()?;
+    Ok(())
+}
+
+/// Like `println!`, but captured by [`capture`] and [`sanitize`]d unless
+/// [`set_raw`] was called
+macro_rules! outln {
+    ($($arg:tt)*) => {
+        $crate::console::print_line(format_args!($($arg)*), false)
+    };
+}
+
+/// Like `eprintln!`, but captured by [`capture`] and [`sanitize`]d unless
+/// [`set_raw`] was called
+macro_rules! errln {
+    ($($arg:tt)*) => {
+        $crate::console::print_line(format_args!($($arg)*), true)
+    };
+}
+
+pub(crate) use {errln, outln};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_capture() {
+        let (value, captured) = capture(|| {
+            outln!("{} chunk(s)", 3);
+            errln!("Warning: {}", "expired");
+            let ((), inner) = capture(|| outln!("inner"));
+            assert_eq!(inner.stdout(), "inner\n");
+            7
+        });
+        assert_eq!(value, 7);
+        assert_eq!(captured.stdout(), "3 chunk(s)\n");
+        assert_eq!(captured.stderr(), "Warning: expired\n");
+    }
+
+    #[test]
+    fn test_sanitize() {
+        assert!(matches!(sanitize("a\tb\nc"), Cow::Borrowed("a\tb\nc")));
+        assert_eq!(sanitize("\x1b[2Jspoofed\r"), "\\x1b[2Jspoofed\\x0d");
+        assert_eq!(sanitize("\u{9b}31m grün"), "\\xc2\\x9b31m grün");
+
+        let ((), captured) = capture(|| outln!("{}", "\x07bell"));
+        assert_eq!(captured.stdout(), "\\x07bell\n");
+    }
+
+    #[test]
+    fn test_styles() {
+        let line = format!(
+            "{} {}",
+            paint("IHDR", Style::Critical),
+            paint(1, Style::Error)
+        );
+        assert_eq!(width(&line), 6);
+        let styled = StyledText::from_painted(&line);
+        assert_eq!(styled.render(false), "IHDR 1");
+        assert_eq!(styled.render(true), "\x1b[1mIHDR\x1b[0m \x1b[1;31m1\x1b[0m");
+        assert!(matches!(
+            StyledText::plain("plain".to_owned()).render(true),
+            Cow::Borrowed("plain")
+        ));
+        // Escape sequences only come from the markers, never from the text
+        let ((), captured) = capture(|| outln!("{}", paint("\x1b[2J", Style::Info)));
+        assert_eq!(captured.stdout.render(true), "\x1b[34m\\x1b[2J\x1b[0m\n");
+        assert_eq!("never".parse(), Ok(ColorChoice::Never));
+    }
+
+    #[test]
+    fn test_markers_in_data() {
+        // The same characters in data are printed as they are
+        let data = format!("{}", paint("ruSt", Style::Error));
+        let ((), captured) = capture(|| {
+            outln!("{} {}", paint("IHDR", Style::Critical), data);
+            outln!("{}", StyledText::plain(data.clone()));
+        });
+        assert_eq!(
+            captured.stdout.render(true),
+            format!("\x1b[1mIHDR\x1b[0m {}\n{}\n", data, data)
+        );
+        assert_eq!(captured.stdout(), format!("IHDR {}\n{}\n", data, data));
+
+        // Painted text in a styled text keeps its style when printed
+        let styled = StyledText::from_painted(&data);
+        let ((), captured) = capture(|| outln!("> {}", styled));
+        assert_eq!(captured.stdout.render(true), "> \x1b[1;31mruSt\x1b[0m\n");
+    }
+
+    #[test]
+    fn test_capture_is_per_thread() {
+        let ((), captured) = capture(|| {
+            std::thread::spawn(|| {
+                let ((), captured) = capture(|| outln!("other thread"));
+                assert_eq!(captured.stdout(), "other thread\n");
+            })
+            .join()
+            .unwrap();
+        });
+        assert_eq!(captured, Captured::default());
+    }
+}
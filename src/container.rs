@@ -0,0 +1,452 @@
+use crate::{
+    cancel::CancellationToken,
+    chunk::Chunk,
+    chunk_type::{self, ChunkType},
+    gif::{ApplicationExtension, ApplicationId, Block, Gif, GifError},
+    jpeg::{Jpeg, JpegError, Marker, Segment},
+    magic::Format,
+    png::{KeepAll, Png, PngParseError},
+    riff::{FourCc, Riff, RiffChunk, RiffError},
+};
+use std::{fmt::Display, str::FromStr};
+
+/// A file format that can carry data in chunks, segments or blocks next to
+/// the image data.
+///
+/// Segments are addressed by a key in the format's own notation: a chunk type
+/// for PNG, a marker like `APP11` for JPEG, a FOURCC for WebP and an
+/// application id for GIF.
+pub trait StegoContainer {
+    fn parse(bytes: &[u8], cancel: &CancellationToken) -> Result<Self, ContainerError>
+    where
+        Self: Sized;
+
+    fn format(&self) -> Container;
+
+    /// Describes every segment in the file, in order
+    fn segments(&self) -> Vec<SegmentInfo>;
+
+    /// Stores `data` in a new segment
+    fn insert(&mut self, key: &str, data: Vec<u8>) -> Result<(), ContainerError>;
+
+    /// Returns the data of the first segment matching `key`
+    fn get(&self, key: &str) -> Result<Option<&[u8]>, ContainerError>;
+
+    /// Removes the first segment matching `key`, returning its data
+    fn remove(&mut self, key: &str) -> Result<Option<Vec<u8>>, ContainerError>;
+
+    fn to_bytes(&self) -> Vec<u8>;
+
+    /// Gives access to PNG specific functionality
+    fn as_png(&self) -> Option<&Png> {
+        None
+    }
+}
+
+/// A segment as shown in listings
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SegmentInfo {
+    /// The segment's key or, for segments which can't hold data, a
+    /// description of it
+    pub kind: String,
+    pub offset: usize,
+    /// The length of the segment's data
+    pub length: usize,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ContainerError {
+    #[error(transparent)]
+    Png(#[from] PngParseError),
+
+    #[error(transparent)]
+    Jpeg(#[from] JpegError),
+
+    #[error(transparent)]
+    Riff(#[from] RiffError),
+
+    #[error(transparent)]
+    Gif(#[from] GifError),
+
+    #[error("{0}")]
+    InvalidKey(String),
+}
+
+/// Parses a segment key, keeping only the message of the error
+pub(crate) fn parse_key<K: FromStr>(key: &str) -> Result<K, ContainerError>
+where
+    K::Err: Display,
+{
+    key.parse()
+        .map_err(|err: K::Err| ContainerError::InvalidKey(err.to_string()))
+}
+
+/// Explains that no segment matches `key`, suggesting the keys which only
+/// differ from it in case and listing the ones present otherwise
+pub fn not_found(container: &dyn StegoContainer, key: &str) -> String {
+    let mut keys: Vec<String> = Vec::new();
+    for segment in container.segments() {
+        if !keys.contains(&segment.kind) {
+            keys.push(segment.kind);
+        }
+    }
+    let similar: Vec<&str> = keys
+        .iter()
+        .filter(|present| present.eq_ignore_ascii_case(key))
+        .map(|present| present.as_str())
+        .collect();
+    if !similar.is_empty() {
+        format!(
+            "no '{}' found; did you mean '{}'?",
+            key,
+            similar.join("' or '")
+        )
+    } else if keys.is_empty() {
+        format!("no '{}' found, the file is empty", key)
+    } else {
+        format!("no '{}' found, the file has {}", key, keys.join(", "))
+    }
+}
+
+/// Whether `key` addresses segments in any of the formats
+pub fn is_key(key: &str) -> bool {
+    key.parse::<ChunkType>().is_ok()
+        || key.parse::<Marker>().is_ok()
+        || key.parse::<FourCc>().is_ok()
+        || key.parse::<ApplicationId>().is_ok()
+}
+
+/// Parses a PNG chunk type, explaining what makes one if it's invalid
+fn parse_chunk_type(key: &str) -> Result<ChunkType, ContainerError> {
+    key.parse().map_err(|err| {
+        ContainerError::InvalidKey(format!("{}\n\n{}", err, chunk_type::SYNTAX_HELP))
+    })
+}
+
+/// The file formats chunks or segments can be stored in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Container {
+    Png,
+    Jpeg,
+    /// A WebP file, or any other RIFF container when chosen explicitly
+    Webp,
+    Gif,
+}
+
+impl Container {
+    /// Recognizes the format from the first bytes of a file
+    pub fn detect(bytes: &[u8]) -> Option<Self> {
+        Format::detect(bytes).and_then(Format::container)
+    }
+}
+
+/// Parses a file in the given format, or the detected one. Unknown files are
+/// parsed as PNG, so they fail with the PNG parse error.
+pub fn parse(
+    bytes: &[u8],
+    format: Option<Container>,
+    cancel: &CancellationToken,
+) -> Result<Box<dyn StegoContainer>, ContainerError> {
+    let format = format
+        .or_else(|| Container::detect(bytes))
+        .unwrap_or(Container::Png);
+    Ok(match format {
+        Container::Png => Box::new(Png::parse(bytes, cancel)?),
+        Container::Jpeg => Box::new(Jpeg::parse(bytes, cancel)?),
+        Container::Webp => Box::new(Riff::parse(bytes, cancel)?),
+        Container::Gif => Box::new(Gif::parse(bytes, cancel)?),
+    })
+}
+
+impl StegoContainer for Png {
+    fn parse(bytes: &[u8], cancel: &CancellationToken) -> Result<Self, ContainerError> {
+        Ok(Png::parse_cancellable(bytes, &mut KeepAll, cancel)?)
+    }
+
+    fn format(&self) -> Container {
+        Container::Png
+    }
+
+    fn segments(&self) -> Vec<SegmentInfo> {
+        self.chunks_with_offsets()
+            .map(|(offset, chunk)| SegmentInfo {
+                kind: chunk.chunk_type().to_string(),
+                offset,
+                length: chunk.length() as usize,
+            })
+            .collect()
+    }
+
+    fn insert(&mut self, key: &str, data: Vec<u8>) -> Result<(), ContainerError> {
+        let chunk = Chunk::try_new(parse_chunk_type(key)?, data).map_err(PngParseError::from)?;
+        self.append_chunk(chunk);
+        Ok(())
+    }
+
+    fn get(&self, key: &str) -> Result<Option<&[u8]>, ContainerError> {
+        Ok(self.chunk_by_type(&parse_chunk_type(key)?).map(Chunk::data))
+    }
+
+    fn remove(&mut self, key: &str) -> Result<Option<Vec<u8>>, ContainerError> {
+        let chunk_type = parse_chunk_type(key)?;
+        if self.chunk_by_type(&chunk_type).is_none() {
+            return Ok(None);
+        }
+        Ok(self
+            .remove_chunk(&chunk_type)
+            .ok()
+            .map(|chunk| chunk.data().to_vec()))
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        self.as_bytes()
+    }
+
+    fn as_png(&self) -> Option<&Png> {
+        Some(self)
+    }
+}
+
+impl StegoContainer for Jpeg {
+    fn parse(bytes: &[u8], _cancel: &CancellationToken) -> Result<Self, ContainerError> {
+        Ok(Jpeg::try_from(bytes)?)
+    }
+
+    fn format(&self) -> Container {
+        Container::Jpeg
+    }
+
+    fn segments(&self) -> Vec<SegmentInfo> {
+        self.segments_with_offsets()
+            .map(|(offset, segment)| SegmentInfo {
+                kind: segment.marker().to_string(),
+                offset,
+                length: segment.data().len(),
+            })
+            .collect()
+    }
+
+    fn insert(&mut self, key: &str, data: Vec<u8>) -> Result<(), ContainerError> {
+        self.insert_segment(Segment::new(parse_key(key)?, data)?);
+        Ok(())
+    }
+
+    fn get(&self, key: &str) -> Result<Option<&[u8]>, ContainerError> {
+        Ok(self.segment_by_marker(parse_key(key)?).map(Segment::data))
+    }
+
+    fn remove(&mut self, key: &str) -> Result<Option<Vec<u8>>, ContainerError> {
+        Ok(self
+            .remove_segment(parse_key(key)?)
+            .map(|segment| segment.data().to_vec()))
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        self.as_bytes()
+    }
+}
+
+impl StegoContainer for Riff {
+    fn parse(bytes: &[u8], _cancel: &CancellationToken) -> Result<Self, ContainerError> {
+        Ok(Riff::try_from(bytes)?)
+    }
+
+    fn format(&self) -> Container {
+        Container::Webp
+    }
+
+    fn segments(&self) -> Vec<SegmentInfo> {
+        self.chunks_with_offsets()
+            .map(|(offset, chunk)| SegmentInfo {
+                kind: chunk.id().to_string(),
+                offset,
+                length: chunk.data().len(),
+            })
+            .collect()
+    }
+
+    fn insert(&mut self, key: &str, data: Vec<u8>) -> Result<(), ContainerError> {
+        self.append_chunk(RiffChunk::new(parse_key(key)?, data)?);
+        Ok(())
+    }
+
+    fn get(&self, key: &str) -> Result<Option<&[u8]>, ContainerError> {
+        Ok(self.chunk_by_id(parse_key(key)?).map(RiffChunk::data))
+    }
+
+    fn remove(&mut self, key: &str) -> Result<Option<Vec<u8>>, ContainerError> {
+        Ok(self
+            .remove_chunk(parse_key(key)?)
+            .map(|chunk| chunk.data().to_vec()))
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        self.as_bytes()
+    }
+}
+
+impl StegoContainer for Gif {
+    fn parse(bytes: &[u8], _cancel: &CancellationToken) -> Result<Self, ContainerError> {
+        Ok(Gif::try_from(bytes)?)
+    }
+
+    fn format(&self) -> Container {
+        Container::Gif
+    }
+
+    /// Blocks other than application extensions are listed with their size
+    /// in the file as length
+    fn segments(&self) -> Vec<SegmentInfo> {
+        self.blocks_with_offsets()
+            .map(|(offset, block)| SegmentInfo {
+                kind: block.name(),
+                offset,
+                length: match block {
+                    Block::Application(extension) => extension.data().len(),
+                    other => other.size(),
+                },
+            })
+            .collect()
+    }
+
+    fn insert(&mut self, key: &str, data: Vec<u8>) -> Result<(), ContainerError> {
+        self.insert_application(ApplicationExtension::new(parse_key(key)?, data));
+        Ok(())
+    }
+
+    fn get(&self, key: &str) -> Result<Option<&[u8]>, ContainerError> {
+        Ok(self
+            .application_by_id(parse_key(key)?)
+            .map(ApplicationExtension::data))
+    }
+
+    fn remove(&mut self, key: &str) -> Result<Option<Vec<u8>>, ContainerError> {
+        Ok(self
+            .remove_application(parse_key(key)?)
+            .map(|extension| extension.data().to_vec()))
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        self.as_bytes()
+    }
+}
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+#[error("unknown container '{0}', expected png, jpeg, webp or gif")]
+pub struct UnknownContainerError(String);
+
+impl Display for Container {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Png => "PNG",
+            Self::Jpeg => "JPEG",
+            Self::Webp => "WebP",
+            Self::Gif => "GIF",
+        })
+    }
+}
+
+impl FromStr for Container {
+    type Err = UnknownContainerError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "png" => Ok(Self::Png),
+            "jpeg" | "jpg" => Ok(Self::Jpeg),
+            "webp" | "riff" => Ok(Self::Webp),
+            "gif" => Ok(Self::Gif),
+            other => Err(UnknownContainerError(other.to_owned())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        gif::tests::testing_gif, jpeg::tests::testing_jpeg, png::tests::PNG_FILE,
+        riff::tests::testing_webp,
+    };
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(Container::detect(&PNG_FILE), Some(Container::Png));
+        assert_eq!(Container::detect(&testing_jpeg()), Some(Container::Jpeg));
+        assert_eq!(Container::detect(&testing_webp()), Some(Container::Webp));
+        assert_eq!(Container::detect(b"RIFF\0\0\0\0WAVE"), None);
+        assert_eq!(Container::detect(&testing_gif()), Some(Container::Gif));
+        assert_eq!(Container::detect(b"GIF90a"), None);
+    }
+
+    /// Runs the same round trip through every format
+    #[test]
+    fn test_insert_get_remove() {
+        let cancel = CancellationToken::new();
+        let files = [
+            (PNG_FILE.to_vec(), "ruSt"),
+            (testing_jpeg(), "APP11"),
+            (testing_webp(), "RuSt"),
+            (testing_gif(), "PNGMEPNG"),
+        ];
+
+        for (bytes, key) in files {
+            let mut container = parse(&bytes, None, &cancel).unwrap();
+            let count = container.segments().len();
+
+            container.insert(key, b"hidden".to_vec()).unwrap();
+            let written = container.to_bytes();
+            let mut container = parse(&written, None, &cancel).unwrap();
+            assert_eq!(container.segments().len(), count + 1);
+            assert_eq!(container.get(key).unwrap(), Some(&b"hidden"[..]));
+
+            assert_eq!(container.remove(key).unwrap(), Some(b"hidden".to_vec()));
+            assert_eq!(container.remove(key).unwrap(), None);
+            assert_eq!(container.to_bytes(), bytes);
+        }
+    }
+
+    #[test]
+    fn test_invalid_key() {
+        let jpeg = parse(&testing_jpeg(), None, &CancellationToken::new()).unwrap();
+        assert!(matches!(
+            jpeg.get("RuSt"),
+            Err(ContainerError::InvalidKey(_))
+        ));
+    }
+
+    #[test]
+    fn test_is_key() {
+        for key in ["ruSt", "APP11", "com", "XMP ", "NETSCAPE2.0"] {
+            assert!(is_key(key), "{}", key);
+        }
+        for key in ["rusty", "APP16", "ru\u{e9}t", ""] {
+            assert!(!is_key(key), "{}", key);
+        }
+    }
+
+    #[test]
+    fn test_not_found() {
+        let png = parse(&PNG_FILE, None, &CancellationToken::new()).unwrap();
+        assert_eq!(
+            not_found(png.as_ref(), "rust"),
+            "no 'rust' found; did you mean 'RuSt'?"
+        );
+        assert_eq!(
+            not_found(png.as_ref(), "teXt"),
+            "no 'teXt' found, the file has IHDR, sRGB, gAMA, pHYs, IDAT, RuSt, IEND"
+        );
+    }
+
+    #[test]
+    fn test_segments() {
+        let gif = parse(&testing_gif(), None, &CancellationToken::new()).unwrap();
+        assert_eq!(
+            gif.segments()[0],
+            SegmentInfo {
+                kind: "NETSCAPE2.0".to_owned(),
+                offset: 19,
+                length: 3,
+            }
+        );
+    }
+}
@@ -0,0 +1,123 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Converts days since 1970-01-01 into a (year, month, day) triple.
+///
+/// See <http://howardhinnant.github.io/date_algorithms.html#civil_from_days>
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// Converts a (year, month, day) triple into days since 1970-01-01, the
+/// inverse of [`civil_from_days`]
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = year - i64::from(month <= 2);
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = i64::from((month + 9) % 12);
+    let doy = (153 * mp + 2) / 5 + i64::from(day) - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+/// Parses a UTC date (`2025-12-31`, meaning its start) or an RFC 3339 UTC
+/// timestamp as written by [`format_utc`] into seconds since the unix epoch
+pub fn parse_utc(s: &str) -> Option<i64> {
+    let (date, time) = match s.split_once('T') {
+        Some((date, time)) => (date, Some(time.strip_suffix('Z')?)),
+        None => (s, None),
+    };
+
+    let mut parts = date.splitn(3, '-');
+    let year: i64 = parts.next()?.parse().ok()?;
+    let month: u32 = parts.next()?.parse().ok()?;
+    let day: u32 = parts.next()?.parse().ok()?;
+    if !(1..=12).contains(&month)
+        || day == 0
+        || civil_from_days(days_from_civil(year, month, day)) != (year, month, day)
+    {
+        return None;
+    }
+
+    let secs = match time {
+        Some(time) => {
+            let mut parts = time.splitn(3, ':');
+            let hours: i64 = parts.next()?.parse().ok()?;
+            let minutes: i64 = parts.next()?.parse().ok()?;
+            let seconds: i64 = parts.next()?.parse().ok()?;
+            if hours > 23 || minutes > 59 || seconds > 59 {
+                return None;
+            }
+            hours * 3600 + minutes * 60 + seconds
+        }
+        None => 0,
+    };
+    Some(days_from_civil(year, month, day) * 86400 + secs)
+}
+
+/// Formats seconds since the unix epoch as an RFC 3339 UTC timestamp, e.g.
+/// `2023-04-01T12:00:00Z`
+pub fn format_utc(secs: i64) -> String {
+    let (year, month, day) = civil_from_days(secs.div_euclid(86400));
+    let time = secs.rem_euclid(86400);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        time / 3600,
+        time % 3600 / 60,
+        time % 60
+    )
+}
+
+/// Returns the current time in seconds since the unix epoch.
+///
+/// Honors `SOURCE_DATE_EPOCH` (see <https://reproducible-builds.org/specs/source-date-epoch/>)
+/// so reproducible builds get a fixed timestamp.
+pub fn now() -> i64 {
+    if let Some(epoch) = std::env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|epoch| epoch.trim().parse().ok())
+    {
+        return epoch;
+    }
+
+    match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(duration) => duration.as_secs() as i64,
+        Err(err) => -(err.duration().as_secs() as i64),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_utc() {
+        assert_eq!(format_utc(0), "1970-01-01T00:00:00Z");
+        assert_eq!(format_utc(951782400), "2000-02-29T00:00:00Z");
+        assert_eq!(format_utc(1680350461), "2023-04-01T12:01:01Z");
+        assert_eq!(format_utc(-1), "1969-12-31T23:59:59Z");
+    }
+
+    #[test]
+    fn test_parse_utc() {
+        assert_eq!(parse_utc("1970-01-01"), Some(0));
+        assert_eq!(parse_utc("2000-02-29"), Some(951782400));
+        assert_eq!(parse_utc("2023-04-01T12:01:01Z"), Some(1680350461));
+        assert_eq!(parse_utc("1969-12-31T23:59:59Z"), Some(-1));
+        assert_eq!(parse_utc("2023-02-29"), None);
+        assert_eq!(parse_utc("2023-13-01"), None);
+        assert_eq!(parse_utc("2023-04-01T12:01:01"), None);
+        assert_eq!(parse_utc("yesterday"), None);
+    }
+}
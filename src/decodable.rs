@@ -0,0 +1,139 @@
+use crate::chunk_type::ChunkType;
+
+/// Why an image failed to decode
+#[derive(Debug, thiserror::Error)]
+pub enum DecodableError {
+    #[error("pngme was built without a PNG decoder, rebuild it with --features check-decodable")]
+    Unsupported,
+
+    #[error("the image can't be decoded{}: {message}", in_chunk(.chunk))]
+    Rejected {
+        /// The chunk the decoder was reading when it failed, if it got past
+        /// the signature
+        chunk: Option<ChunkType>,
+        message: String,
+    },
+}
+
+fn in_chunk(chunk: &Option<ChunkType>) -> String {
+    match chunk {
+        Some(chunk) => format!(" (in the {} chunk)", chunk),
+        None => String::new(),
+    }
+}
+
+/// Fully decodes `bytes` with the `png` crate, the decoder behind most Rust
+/// image tools, to make sure viewers will still be able to show the image.
+///
+/// The chunks are streamed through the decoder first, which finds the chunk
+/// an error comes from. The pixels are decoded afterwards, so errors only
+/// found when unfiltering the scanlines are blamed on IDAT.
+#[cfg(feature = "check-decodable")]
+pub fn check(bytes: &[u8]) -> Result<(), DecodableError> {
+    use png::{Decoded, StreamingDecoder};
+
+    let rejected = |chunk, err: png::DecodingError| DecodableError::Rejected {
+        chunk,
+        message: err.to_string(),
+    };
+
+    let mut decoder = StreamingDecoder::new();
+    let mut image_data = Vec::new();
+    let mut chunk = None;
+    let mut rest = bytes;
+    loop {
+        let (consumed, decoded) = decoder
+            .update(rest, &mut image_data)
+            .map_err(|err| rejected(chunk.clone(), err))?;
+        rest = &rest[consumed..];
+        image_data.clear();
+        match decoded {
+            Decoded::ChunkBegin(_, png::chunk::ChunkType(bytes)) => {
+                chunk = ChunkType::try_from(bytes).ok();
+            }
+            Decoded::ImageEnd => break,
+            _ if rest.is_empty() => {
+                return Err(DecodableError::Rejected {
+                    chunk,
+                    message: "the file ends before the IEND chunk".to_owned(),
+                })
+            }
+            _ => {}
+        }
+    }
+
+    let idat = ChunkType::try_from(*b"IDAT").ok();
+    let mut reader = png::Decoder::new(bytes)
+        .read_info()
+        .map_err(|err| rejected(None, err))?;
+    let mut pixels = vec![0; reader.output_buffer_size()];
+    reader
+        .next_frame(&mut pixels)
+        .map_err(|err| rejected(idat, err))?;
+    Ok(())
+}
+
+#[cfg(not(feature = "check-decodable"))]
+pub fn check(_bytes: &[u8]) -> Result<(), DecodableError> {
+    Err(DecodableError::Unsupported)
+}
+
+#[cfg(all(test, feature = "check-decodable"))]
+mod tests {
+    use super::*;
+    use crate::{chunk::Chunk, png::Png};
+    use std::str::FromStr;
+
+    fn png() -> Png {
+        Png::try_from(&crate::png::tests::PNG_FILE[..]).unwrap()
+    }
+
+    fn rejected_chunk(bytes: &[u8]) -> Option<String> {
+        match check(bytes) {
+            Err(DecodableError::Rejected { chunk, .. }) => chunk.map(|chunk| chunk.to_string()),
+            other => panic!("expected the image to be rejected, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_decodable() {
+        assert!(check(&png().as_bytes()).is_ok());
+    }
+
+    #[test]
+    fn test_decodable_with_payload() {
+        let mut png = png();
+        let chunk_type = ChunkType::from_str("ruSt").unwrap();
+        png.insert_before_end(Chunk::new(chunk_type, b"hidden".to_vec()));
+        assert!(check(&png.as_bytes()).is_ok());
+    }
+
+    #[test]
+    fn test_critical_chunk_crc() {
+        let mut bytes = png().as_bytes();
+        // The last byte of the IHDR crc
+        bytes[32] ^= 1;
+        assert_eq!(rejected_chunk(&bytes).as_deref(), Some("IHDR"));
+    }
+
+    #[test]
+    fn test_corrupted_image_data() {
+        let mut png = png();
+        let index = png
+            .chunks()
+            .iter()
+            .position(|chunk| &chunk.chunk_type().bytes() == b"IDAT")
+            .unwrap();
+        png[index]
+            .map_data(|data| data[..data.len() / 2].to_vec())
+            .unwrap();
+        assert_eq!(rejected_chunk(&png.as_bytes()).as_deref(), Some("IDAT"));
+    }
+
+    #[test]
+    fn test_truncated() {
+        let bytes = png().as_bytes();
+        assert!(check(&bytes[..bytes.len() - 12]).is_err());
+        assert_eq!(rejected_chunk(b"not a png"), None);
+    }
+}
@@ -0,0 +1,208 @@
+use crate::{payload, png::Png};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::{collections::HashMap, fmt::Write};
+
+/// Where a chunk was found
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Occurrence {
+    pub path: String,
+    pub index: usize,
+    pub offset: usize,
+}
+
+/// Ancillary chunks with the same type and data found more than once
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DuplicateGroup {
+    #[serde(rename = "type")]
+    pub chunk_type: String,
+    pub length: u32,
+    /// The SHA-256 of the data as hex
+    pub sha256: String,
+    pub occurrences: Vec<Occurrence>,
+}
+
+impl DuplicateGroup {
+    /// The bytes taken up by all copies but one, including the chunk
+    /// framing
+    pub fn wasted(&self) -> u64 {
+        (self.occurrences.len() as u64 - 1) * (self.length as u64 + 12)
+    }
+}
+
+/// A chunk type and the SHA-256 of the data
+type ContentKey = ([u8; 4], [u8; 32]);
+
+/// Collects the ancillary chunks of many PNGs by their content. Only the
+/// hashes are kept, so the PNGs don't have to stay in memory.
+#[derive(Debug, Default)]
+pub struct DedupIndex {
+    /// Chunks with less data are ignored
+    min_size: u32,
+    files: usize,
+    /// The data length and occurrences of every content
+    chunks: HashMap<ContentKey, (u32, Vec<Occurrence>)>,
+}
+
+impl DedupIndex {
+    pub fn new(min_size: u32) -> Self {
+        Self {
+            min_size,
+            ..Self::default()
+        }
+    }
+
+    /// Adds the ancillary chunks of `png`, found at `path`
+    pub fn add(&mut self, path: &str, png: &Png) {
+        self.files += 1;
+        for (index, (offset, chunk)) in png.chunks_with_offsets().enumerate() {
+            if chunk.chunk_type().is_critical() || chunk.length() < self.min_size {
+                continue;
+            }
+            let key: ContentKey = (
+                chunk.chunk_type().bytes(),
+                Sha256::digest(chunk.data()).into(),
+            );
+            let (_, occurrences) = self
+                .chunks
+                .entry(key)
+                .or_insert_with(|| (chunk.length(), Vec::new()));
+            occurrences.push(Occurrence {
+                path: path.to_owned(),
+                index,
+                offset,
+            });
+        }
+    }
+
+    /// The number of PNGs added
+    pub fn files(&self) -> usize {
+        self.files
+    }
+
+    /// The chunks found more than once, the most wasteful first
+    pub fn duplicates(&self) -> Vec<DuplicateGroup> {
+        let mut groups: Vec<DuplicateGroup> = self
+            .chunks
+            .iter()
+            .filter(|(_, (_, occurrences))| occurrences.len() > 1)
+            .map(
+                |((chunk_type, sha256), (length, occurrences))| DuplicateGroup {
+                    chunk_type: String::from_utf8_lossy(chunk_type).into_owned(),
+                    length: *length,
+                    sha256: payload::hex(sha256),
+                    occurrences: occurrences.clone(),
+                },
+            )
+            .collect();
+        // The hash breaks ties, so the order doesn't depend on the HashMap
+        groups.sort_by(|a, b| {
+            b.wasted()
+                .cmp(&a.wasted())
+                .then_with(|| a.chunk_type.cmp(&b.chunk_type))
+                .then_with(|| a.sha256.cmp(&b.sha256))
+        });
+        groups
+    }
+}
+
+#[derive(Serialize)]
+struct Report<'a> {
+    files: usize,
+    wasted_bytes: u64,
+    duplicates: &'a [DuplicateGroup],
+}
+
+/// Renders the duplicates as JSON, for inventory tooling
+pub fn render_json(files: usize, groups: &[DuplicateGroup]) -> String {
+    serde_json::to_string_pretty(&Report {
+        files,
+        wasted_bytes: groups.iter().map(DuplicateGroup::wasted).sum(),
+        duplicates: groups,
+    })
+    .expect("dedup reports are always serializable")
+}
+
+/// Renders one block per group, listing where its copies are
+pub fn render_text(files: usize, groups: &[DuplicateGroup]) -> String {
+    let mut text = String::new();
+    for group in groups {
+        writeln!(
+            text,
+            "{} chunk, {} bytes, {} copies, sha256 {}",
+            group.chunk_type,
+            group.length,
+            group.occurrences.len(),
+            group.sha256
+        )
+        .unwrap();
+        for occurrence in &group.occurrences {
+            writeln!(
+                text,
+                "\t{}\tindex {}\toffset {}",
+                occurrence.path, occurrence.index, occurrence.offset
+            )
+            .unwrap();
+        }
+    }
+    write!(
+        text,
+        "{} duplicated chunk(s) in {} file(s), {} bytes could be saved",
+        groups.len(),
+        files,
+        groups.iter().map(DuplicateGroup::wasted).sum::<u64>()
+    )
+    .unwrap();
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{chunk::Chunk, chunk_type::ChunkType, png::tests::PNG_FILE};
+    use std::str::FromStr;
+
+    fn with_chunk(chunk_type: &str, data: &[u8]) -> Png {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        png.insert_before_end(Chunk::new(
+            ChunkType::from_str(chunk_type).unwrap(),
+            data.to_vec(),
+        ));
+        png
+    }
+
+    #[test]
+    fn test_duplicates() {
+        let mut index = DedupIndex::new(4);
+        index.add("a.png", &with_chunk("iCCP", &[7; 100]));
+        index.add("b.png", &with_chunk("iCCP", &[7; 100]));
+        index.add("c.png", &with_chunk("ruSt", &[7; 100]));
+        assert_eq!(index.files(), 3);
+
+        // gAMA and pHYs are shared too, sRGB is smaller than the minimum and
+        // the same data under another type doesn't count
+        let groups = index.duplicates();
+        let types: Vec<_> = groups
+            .iter()
+            .map(|group| group.chunk_type.as_str())
+            .collect();
+        assert_eq!(types, ["iCCP", "pHYs", "gAMA"]);
+        assert_eq!(groups[0].wasted(), 112);
+        assert_eq!(groups[0].occurrences[1].path, "b.png");
+        assert_eq!(groups[0].occurrences[1].index, 6);
+        assert_eq!(groups[1].occurrences.len(), 3);
+    }
+
+    #[test]
+    fn test_render_json() {
+        let mut index = DedupIndex::new(100);
+        index.add("a.png", &with_chunk("iCCP", &[7; 100]));
+        index.add("b.png", &with_chunk("iCCP", &[7; 100]));
+        let json = render_json(index.files(), &index.duplicates());
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(value["files"], 2);
+        assert_eq!(value["wasted_bytes"], 112);
+        assert_eq!(value["duplicates"][0]["type"], "iCCP");
+        assert_eq!(value["duplicates"][0]["occurrences"][0]["path"], "a.png");
+    }
+}
@@ -0,0 +1,286 @@
+use crate::{
+    chunk::Chunk,
+    chunk_type::ChunkType,
+    fingerprint::FINGERPRINT_CHUNK,
+    padding::PADDING_CHUNK,
+    payload::{Envelope, Part},
+    png::Png,
+    provenance::C2PA_CHUNK_TYPE,
+    stamp::STAMP_CHUNK_TYPE,
+};
+use flate2::read::ZlibDecoder;
+use std::{fmt, io::Read};
+
+/// Data shorter than this is too small to judge its entropy
+const MIN_ENTROPY_LEN: usize = 32;
+
+/// The share of the highest possible entropy data must reach to look like
+/// compressed or encrypted data. Random samples fall short of the maximum, as
+/// not every value occurs equally often.
+const ENTROPY_THRESHOLD: f64 = 0.85;
+
+/// How much of a zlib stream is inflated to check it, which bounds the work
+/// on untrusted files
+const MAX_INFLATE: u64 = 1024 * 1024;
+
+/// Chunk types which give away the tool or convention that wrote them
+pub(crate) const CHUNK_SIGNATURES: [(&[u8; 4], &str); 10] = [
+    (b"ruSt", "a PNGme tutorial implementation"),
+    (b"RuSt", "a PNGme tutorial implementation"),
+    (PADDING_CHUNK, "pngme cover padding"),
+    (FINGERPRINT_CHUNK, "a pngme fingerprint"),
+    (b"stEg", "a hand-rolled chunk encoder"),
+    (b"stEG", "a hand-rolled chunk encoder"),
+    (b"hiDe", "a hand-rolled chunk encoder"),
+    (b"seCr", "a hand-rolled chunk encoder"),
+    (b"flAg", "a CTF challenge"),
+    (b"flAG", "a CTF challenge"),
+];
+
+/// The start of files commonly appended to images with `cat` or `copy /b`,
+/// which viewers ignore after the end of the image
+const APPENDED_SIGNATURES: [(&[u8], &str); 6] = [
+    (b"PK\x03\x04", "a ZIP archive"),
+    (b"Rar!\x1a\x07", "a RAR archive"),
+    (b"7z\xbc\xaf\x27\x1c", "a 7-Zip archive"),
+    (b"\x1f\x8b", "a gzip file"),
+    (b"%PDF-", "a PDF document"),
+    (b"-----BEGIN PGP", "a PGP message"),
+];
+
+/// Why a chunk is believed to carry a hidden payload
+#[derive(Debug, Clone, PartialEq)]
+pub enum Evidence {
+    /// The data is sealed in a pngme integrity envelope
+    Envelope,
+    /// The data is one part of a spread payload
+    Part { index: u16, count: u16 },
+    /// The chunk type belongs to a known tool or convention, which is given
+    Signature(&'static str),
+    /// A private ancillary chunk holding a zlib stream, which tools use to
+    /// compress their payload, with its inflated size
+    Zlib(usize),
+    /// A private ancillary chunk whose data looks compressed or encrypted,
+    /// given in bits per byte
+    HighEntropy(f64),
+}
+
+impl fmt::Display for Evidence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Evidence::Envelope => write!(f, "pngme payload"),
+            Evidence::Part { index, count } => {
+                write!(f, "pngme payload, part {} of {}", index + 1, count)
+            }
+            Evidence::Signature(tool) => write!(f, "chunk type used by {}", tool),
+            Evidence::Zlib(size) => write!(
+                f,
+                "zlib stream of {} bytes, likely a custom encoder compressing its payload",
+                size
+            ),
+            Evidence::HighEntropy(bits) => write!(f, "high entropy ({:.2} bits/byte)", bits),
+        }
+    }
+}
+
+/// A chunk which probably carries a hidden payload
+#[derive(Debug, Clone, PartialEq)]
+pub struct Candidate {
+    pub chunk_type: String,
+    pub offset: usize,
+    pub length: usize,
+    pub evidence: Evidence,
+}
+
+/// The Shannon entropy of `data` in bits per byte, between 0 and 8
+pub fn entropy(data: &[u8]) -> f64 {
+    let mut counts = [0usize; 256];
+    for byte in data {
+        counts[*byte as usize] += 1;
+    }
+    let len = data.len() as f64;
+    counts
+        .iter()
+        .filter(|count| **count > 0)
+        .map(|count| {
+            let p = *count as f64 / len;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+/// The inflated size of `data` if it is a complete zlib stream, looking at
+/// no more than [`MAX_INFLATE`] bytes of output
+fn inflated_size(data: &[u8]) -> Option<usize> {
+    // The header: deflate with a window of at most 32K, no preset dictionary
+    // and a check value
+    let [cmf, flg, ..] = *data else {
+        return None;
+    };
+    if cmf & 0x0f != 8 || cmf >> 4 > 7 || flg & 0x20 != 0 {
+        return None;
+    }
+    if (u16::from(cmf) << 8 | u16::from(flg)) % 31 != 0 {
+        return None;
+    }
+    let mut inflated = Vec::new();
+    ZlibDecoder::new(data)
+        .take(MAX_INFLATE)
+        .read_to_end(&mut inflated)
+        .ok()?;
+    Some(inflated.len())
+}
+
+/// The tool or convention known to write chunks of `chunk_type`
+pub(crate) fn signature(chunk_type: &ChunkType) -> Option<&'static str> {
+    CHUNK_SIGNATURES
+        .iter()
+        .find(|(signature, _)| **signature == chunk_type.bytes())
+        .map(|(_, tool)| *tool)
+}
+
+/// What the data after `IEND` likely is, from its first bytes
+pub fn appended_signature(data: &[u8]) -> Option<&'static str> {
+    APPENDED_SIGNATURES
+        .iter()
+        .find(|(magic, _)| data.starts_with(magic))
+        .map(|(_, what)| *what)
+}
+
+pub(crate) fn evidence(chunk: &Chunk) -> Option<Evidence> {
+    let data = chunk.data();
+    if Envelope::try_from(data).is_ok() {
+        return Some(Evidence::Envelope);
+    }
+    if let Ok(part) = Part::try_from(data) {
+        return Some(Evidence::Part {
+            index: part.index(),
+            count: part.count(),
+        });
+    }
+
+    let chunk_type = chunk.chunk_type();
+    if let Some(tool) = signature(chunk_type) {
+        return Some(Evidence::Signature(tool));
+    }
+    let known = [STAMP_CHUNK_TYPE, C2PA_CHUNK_TYPE].contains(&chunk_type.bytes());
+    if chunk_type.is_critical() || chunk_type.is_public() || known {
+        return None;
+    }
+    if let Some(size) = inflated_size(data) {
+        return Some(Evidence::Zlib(size));
+    }
+    if data.len() < MIN_ENTROPY_LEN {
+        return None;
+    }
+    // Short data can't reach 8 bits per byte, as not every value can occur
+    let bits = entropy(data);
+    let max = (data.len() as f64).log2().min(8.0);
+    (bits >= max * ENTROPY_THRESHOLD).then_some(Evidence::HighEntropy(bits))
+}
+
+/// Finds the chunks that probably carry a payload: chunks written by
+/// `encode`, chunk types known from other tools and, heuristically, private
+/// ancillary chunks with zlib streams or high-entropy data
+pub fn detect(png: &Png) -> Vec<Candidate> {
+    png.chunks_with_offsets()
+        .filter_map(|(offset, chunk)| {
+            Some(Candidate {
+                chunk_type: chunk.chunk_type().to_string(),
+                offset,
+                length: chunk.length() as usize,
+                evidence: evidence(chunk)?,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{payload, png::tests::PNG_FILE};
+    use flate2::{write::ZlibEncoder, Compression};
+    use std::{io::Write, str::FromStr};
+
+    fn chunk(chunk_type: &str, data: Vec<u8>) -> Chunk {
+        Chunk::new(FromStr::from_str(chunk_type).unwrap(), data)
+    }
+
+    /// Bytes that look random, from a simple linear congruential generator
+    fn noise(len: usize) -> Vec<u8> {
+        let mut state: u32 = 12345;
+        (0..len)
+            .map(|_| {
+                state = state.wrapping_mul(1103515245).wrapping_add(12345);
+                (state >> 16) as u8
+            })
+            .collect()
+    }
+
+    fn compressed(data: &[u8]) -> Vec<u8> {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn test_inflated_size() {
+        let stream = compressed(b"hello");
+        assert_eq!(inflated_size(&stream), Some(5));
+        assert_eq!(inflated_size(&stream[..stream.len() - 2]), None);
+        assert_eq!(inflated_size(b"xyz"), None);
+        assert_eq!(inflated_size(&noise(100)), None);
+    }
+
+    #[test]
+    fn test_appended_signature() {
+        assert_eq!(appended_signature(b"PK\x03\x04rest"), Some("a ZIP archive"));
+        assert_eq!(appended_signature(b"PK"), None);
+        assert_eq!(appended_signature(b"junk"), None);
+    }
+
+    #[test]
+    fn test_entropy() {
+        assert_eq!(entropy(b"aaaa"), 0.0);
+        assert_eq!(entropy(b"abab"), 1.0);
+        assert!(entropy(&noise(4096)) > 7.9);
+    }
+
+    #[test]
+    fn test_detect() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let before = png.chunks().len();
+        png.append_chunk(chunk("abCd", Envelope::seal(b"hi".to_vec()).as_bytes()));
+        let part = payload::split(b"hello", &[1, 1]).unwrap().remove(1);
+        png.append_chunk(chunk("efGh", part.as_bytes()));
+        png.append_chunk(chunk("ijKl", noise(256)));
+        // Not private ancillary, or not random enough
+        png.append_chunk(chunk("MnOp", noise(256)));
+        png.append_chunk(chunk("qrSt", vec![b'a'; 256]));
+        png.append_chunk(chunk("zlIb", compressed(&[b'a'; 1000])));
+        png.append_chunk(chunk("hiDe", b"plain text".to_vec()));
+
+        let candidates = detect(&png);
+        let found: Vec<(&str, &Evidence)> = candidates
+            .iter()
+            .map(|candidate| (candidate.chunk_type.as_str(), &candidate.evidence))
+            .collect();
+        assert_eq!(found.len(), 6);
+        // The fixture's RuSt chunk
+        assert_eq!(
+            found[0],
+            (
+                "RuSt",
+                &Evidence::Signature("a PNGme tutorial implementation")
+            )
+        );
+        let found = &found[1..];
+        assert_eq!(found[0], ("abCd", &Evidence::Envelope));
+        assert_eq!(found[1], ("efGh", &Evidence::Part { index: 1, count: 2 }));
+        assert_eq!(found[2].0, "ijKl");
+        assert!(matches!(found[2].1, Evidence::HighEntropy(_)));
+        assert_eq!(found[3], ("zlIb", &Evidence::Zlib(1000)));
+        assert_eq!(found[4].0, "hiDe");
+        assert!(candidates[1].offset > png.chunks_with_offsets().nth(before - 1).unwrap().0);
+    }
+}
@@ -0,0 +1,316 @@
+use crate::{chunk::Chunk, console, png::Png, select::Selected};
+use std::fmt::{Display, Write};
+
+/// How chunk data is rendered as text
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DataFormat {
+    /// Decode as UTF-8, replacing invalid sequences
+    #[default]
+    Utf8Lossy,
+
+    /// Lowercase hexadecimal, two digits per byte
+    Hex,
+
+    /// UTF-8 with control characters and invalid bytes escaped, see [`escape`]
+    Escaped,
+}
+
+/// Renders `data` as UTF-8 text which is safe to print to a terminal.
+/// Control characters and bytes which aren't valid UTF-8 become `\xNN`
+/// escapes, backslashes are doubled so the escapes are unambiguous.
+pub fn escape(data: &[u8]) -> String {
+    let mut escaped = String::with_capacity(data.len());
+    for piece in data.utf8_chunks() {
+        for c in piece.valid().chars() {
+            match c {
+                '\\' => escaped.push_str("\\\\"),
+                c if c.is_control() => {
+                    let mut bytes = [0; 4];
+                    for byte in c.encode_utf8(&mut bytes).bytes() {
+                        write!(escaped, "\\x{:02x}", byte).unwrap();
+                    }
+                }
+                c => escaped.push(c),
+            }
+        }
+        for byte in piece.invalid() {
+            write!(escaped, "\\x{:02x}", byte).unwrap();
+        }
+    }
+    escaped
+}
+
+/// Formats a size for people to read, e.g. `13 B`, `1.5 KiB` or `12.0 MiB`
+pub fn human_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    // Rounded to one decimal, 1023.96 KiB would be printed as 1024.0 KiB
+    while size >= 1023.95 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+/// Lays out `rows` in columns separated by two spaces, one line per row and
+/// without a trailing newline. Columns are left-aligned unless
+/// `right_aligned` says otherwise, the last one isn't padded. Cells may be
+/// [`console::paint`]ed.
+pub fn columns(rows: &[Vec<String>], right_aligned: &[bool]) -> String {
+    let mut widths: Vec<usize> = Vec::new();
+    for row in rows {
+        for (column, cell) in row.iter().enumerate() {
+            let width = console::width(cell);
+            match widths.get_mut(column) {
+                Some(max) => *max = (*max).max(width),
+                None => widths.push(width),
+            }
+        }
+    }
+
+    let mut lines = Vec::with_capacity(rows.len());
+    for row in rows {
+        let mut line = String::new();
+        for (column, cell) in row.iter().enumerate() {
+            if column > 0 {
+                line.push_str("  ");
+            }
+            let padding = " ".repeat(widths[column] - console::width(cell));
+            match right_aligned.get(column) {
+                Some(true) => write!(line, "{}{}", padding, cell).unwrap(),
+                _ if column + 1 == row.len() => line.push_str(cell),
+                _ => write!(line, "{}{}", cell, padding).unwrap(),
+            }
+        }
+        lines.push(line.trim_end().to_owned());
+    }
+    lines.join("\n")
+}
+
+/// Controls how much of a chunk's data gets rendered.
+///
+/// The default renders everything, the same as the [`Display`] impls of
+/// [`Chunk`] and [`Png`] would, except that invalid UTF-8 is rendered lossily.
+#[derive(Debug, Clone, Default)]
+pub struct DisplayOptions {
+    /// Only render the first `max_data` bytes of data
+    pub max_data: Option<usize>,
+
+    pub format: DataFormat,
+
+    /// Chunk types (matched case-insensitively) whose data is not rendered
+    pub hide: Vec<String>,
+
+    /// Render sizes in listings as byte counts instead of e.g. `1.5 KiB`
+    pub exact_sizes: bool,
+}
+
+impl DisplayOptions {
+    pub fn chunk<'a>(&'a self, chunk: &'a Chunk) -> ChunkDisplay<'a> {
+        ChunkDisplay {
+            chunk,
+            options: self,
+        }
+    }
+
+    pub fn png<'a>(&'a self, png: &'a Png) -> PngDisplay<'a> {
+        PngDisplay {
+            chunks: png.chunks().iter().collect(),
+            options: self,
+        }
+    }
+
+    /// Renders the selected chunks like [`DisplayOptions::png`] renders all
+    pub fn selected<'a>(&'a self, chunks: &[Selected<'a>]) -> PngDisplay<'a> {
+        PngDisplay {
+            chunks: chunks.iter().map(|selected| selected.chunk).collect(),
+            options: self,
+        }
+    }
+
+    /// Renders a size in bytes as [`DisplayOptions::exact_sizes`] asks for
+    pub fn size(&self, bytes: u64) -> String {
+        match self.exact_sizes {
+            true => bytes.to_string(),
+            false => human_size(bytes),
+        }
+    }
+
+    /// Renders only the data of a chunk
+    pub fn data<'a>(&'a self, chunk: &'a Chunk) -> DataDisplay<'a> {
+        DataDisplay {
+            chunk,
+            options: self,
+        }
+    }
+
+    fn hides(&self, chunk: &Chunk) -> bool {
+        let chunk_type = chunk.chunk_type().to_string();
+        self.hide
+            .iter()
+            .any(|hidden| hidden.eq_ignore_ascii_case(&chunk_type))
+    }
+
+    fn write_data(&self, f: &mut std::fmt::Formatter<'_>, data: &[u8]) -> std::fmt::Result {
+        let shown = &data[..self.max_data.unwrap_or(data.len()).min(data.len())];
+        match self.format {
+            DataFormat::Utf8Lossy => f.write_str(&String::from_utf8_lossy(shown))?,
+            DataFormat::Hex => {
+                for byte in shown {
+                    write!(f, "{:02x}", byte)?;
+                }
+            }
+            DataFormat::Escaped => f.write_str(&escape(shown))?,
+        }
+
+        if shown.len() < data.len() {
+            write!(f, "... ({} more bytes)", data.len() - shown.len())?;
+        }
+        Ok(())
+    }
+}
+
+/// Renders the data of a [`Chunk`] according to some [`DisplayOptions`]
+pub struct DataDisplay<'a> {
+    chunk: &'a Chunk,
+    options: &'a DisplayOptions,
+}
+
+impl Display for DataDisplay<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.options.hides(self.chunk) {
+            f.write_str("<hidden>")
+        } else {
+            self.options.write_data(f, self.chunk.data())
+        }
+    }
+}
+
+/// Renders a [`Chunk`] according to some [`DisplayOptions`]
+pub struct ChunkDisplay<'a> {
+    chunk: &'a Chunk,
+    options: &'a DisplayOptions,
+}
+
+impl Display for ChunkDisplay<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{{ length: {:4}, type: {}, data: {}, crc: {:10} }}",
+            self.chunk.length(),
+            self.chunk.chunk_type(),
+            self.options.data(self.chunk),
+            self.chunk.crc()
+        )
+    }
+}
+
+/// Renders a [`Png`] according to some [`DisplayOptions`]
+pub struct PngDisplay<'a> {
+    chunks: Vec<&'a Chunk>,
+    options: &'a DisplayOptions,
+}
+
+impl Display for PngDisplay<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Chunks [")?;
+        for chunk in &self.chunks {
+            writeln!(f, "\t{}", self.options.chunk(chunk))?;
+        }
+        f.write_char(']')
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk_type::ChunkType;
+    use std::str::FromStr;
+
+    fn testing_chunk() -> Chunk {
+        Chunk::new(
+            ChunkType::from_str("RuSt").unwrap(),
+            b"This is where your secret message will be!".to_vec(),
+        )
+    }
+
+    #[test]
+    fn test_default_matches_display() {
+        let chunk = testing_chunk();
+        let options = DisplayOptions::default();
+        assert_eq!(options.chunk(&chunk).to_string(), chunk.to_string());
+    }
+
+    #[test]
+    fn test_human_size() {
+        assert_eq!(human_size(0), "0 B");
+        assert_eq!(human_size(1023), "1023 B");
+        assert_eq!(human_size(1536), "1.5 KiB");
+        assert_eq!(human_size(1024 * 1024 - 1), "1.0 MiB");
+        assert_eq!(human_size(5 * 1024 * 1024 * 1024), "5.0 GiB");
+    }
+
+    #[test]
+    fn test_columns() {
+        let rows = [
+            vec!["1".to_owned(), "IHDR".to_owned(), "13 B".to_owned()],
+            vec!["10".to_owned(), "RuSt".to_owned(), "1.5 KiB".to_owned()],
+            vec!["11".to_owned(), "x".to_owned(), "".to_owned()],
+        ];
+        assert_eq!(
+            columns(&rows, &[true, false, true]),
+            " 1  IHDR     13 B\n10  RuSt  1.5 KiB\n11  x"
+        );
+        assert_eq!(columns(&[], &[]), "");
+    }
+
+    #[test]
+    fn test_truncated() {
+        let chunk = testing_chunk();
+        let options = DisplayOptions {
+            max_data: Some(7),
+            ..Default::default()
+        };
+        assert!(options
+            .chunk(&chunk)
+            .to_string()
+            .contains("data: This is... (35 more bytes),"));
+    }
+
+    #[test]
+    fn test_hex() {
+        let chunk = Chunk::new(ChunkType::from_str("RuSt").unwrap(), vec![0, 1, 0xff]);
+        let options = DisplayOptions {
+            format: DataFormat::Hex,
+            ..Default::default()
+        };
+        assert!(options.chunk(&chunk).to_string().contains("data: 0001ff,"));
+    }
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(escape(b"plain text"), "plain text");
+        assert_eq!(escape("grün".as_bytes()), "grün");
+        assert_eq!(escape(b"\x1b[2J\tok\n"), "\\x1b[2J\\x09ok\\x0a");
+        assert_eq!(escape(b"a\\x41"), "a\\\\x41");
+        assert_eq!(escape(b"bad \xff\xc3"), "bad \\xff\\xc3");
+        assert_eq!(escape("\u{9b}".as_bytes()), "\\xc2\\x9b");
+    }
+
+    #[test]
+    fn test_hidden() {
+        let chunk = testing_chunk();
+        let options = DisplayOptions {
+            hide: vec!["rust".to_owned()],
+            ..Default::default()
+        };
+        assert!(options
+            .chunk(&chunk)
+            .to_string()
+            .contains("data: <hidden>,"));
+    }
+}
@@ -0,0 +1,225 @@
+use crate::{
+    cancel::CancellationToken,
+    chunk_type::ChunkType,
+    png::{Png, PngParseError},
+    select::TypePattern,
+    stream::{PngStreamParser, StreamEvent},
+};
+use std::io::{self, Read, Write};
+
+/// Ancillary chunk types which change how the image is shown, so
+/// [`StripFilter`] keeps them: transparency, color management and APNG
+/// animation
+pub const RENDERING_CHUNKS: &[&[u8; 4]] = &[
+    b"tRNS", b"gAMA", b"cHRM", b"sRGB", b"iCCP", b"sBIT", b"cICP", b"mDCV", b"cLLI", b"acTL",
+    b"fcTL", b"fdAT",
+];
+
+/// How many bytes are read from the input at a time
+const BUFFER_SIZE: usize = 64 * 1024;
+
+#[derive(Debug, thiserror::Error)]
+pub enum FilterError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+
+    #[error(transparent)]
+    Parse(#[from] PngParseError),
+}
+
+/// What a [`StripFilter`] did
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StripSummary {
+    pub kept: usize,
+    pub removed: usize,
+    /// The bytes of the removed chunks and the data after `IEND`
+    pub removed_bytes: u64,
+}
+
+/// Copies a PNG from a reader to a writer without its metadata: ancillary
+/// chunks which don't affect the pixels (see [`RENDERING_CHUNKS`]) and the
+/// data after `IEND` are dropped.
+///
+/// Chunks are passed through piece by piece as they arrive, so memory use
+/// doesn't depend on the size of the file and output starts right away.
+/// Checksums are verified as the chunks pass, which means a corrupted chunk
+/// is only noticed after the part of it before the error has been written.
+#[derive(Debug, Clone, Default)]
+pub struct StripFilter {
+    keep: Vec<TypePattern>,
+    cancel: CancellationToken,
+}
+
+impl StripFilter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Also keeps the ancillary chunks matching one of `patterns`
+    pub fn keep(mut self, patterns: Vec<TypePattern>) -> Self {
+        self.keep = patterns;
+        self
+    }
+
+    /// Fails with [`PngParseError::Cancelled`] once `cancel` is cancelled
+    pub fn with_cancellation(mut self, cancel: CancellationToken) -> Self {
+        self.cancel = cancel;
+        self
+    }
+
+    fn keeps(&self, chunk_type: &ChunkType) -> bool {
+        chunk_type.is_critical()
+            || RENDERING_CHUNKS.contains(&&chunk_type.bytes())
+            || self
+                .keep
+                .iter()
+                .any(|pattern| pattern.matches(&chunk_type.to_string()))
+    }
+
+    /// Filters all of `reader` into `writer`
+    pub fn run<R: Read, W: Write>(
+        &self,
+        mut reader: R,
+        mut writer: W,
+    ) -> Result<StripSummary, FilterError> {
+        let mut parser = PngStreamParser::with_cancellation(self.cancel.clone());
+        let mut buf = vec![0; BUFFER_SIZE];
+        let mut summary = StripSummary::default();
+        let mut keeping = false;
+
+        loop {
+            let n = match reader.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err.into()),
+            };
+            for event in parser.push(&buf[..n])? {
+                match event {
+                    StreamEvent::Signature => writer.write_all(Png::SIGNATURE)?,
+                    StreamEvent::ChunkStart { length, chunk_type } => {
+                        keeping = self.keeps(&chunk_type);
+                        if keeping {
+                            writer.write_all(&length.to_be_bytes())?;
+                            writer.write_all(&chunk_type.bytes())?;
+                        } else {
+                            summary.removed_bytes += 12 + u64::from(length);
+                        }
+                    }
+                    StreamEvent::ChunkData(data) if keeping => writer.write_all(data)?,
+                    StreamEvent::ChunkData(_) => {}
+                    StreamEvent::ChunkEnd { crc } if keeping => {
+                        writer.write_all(&crc.to_be_bytes())?;
+                        summary.kept += 1;
+                    }
+                    StreamEvent::ChunkEnd { .. } => summary.removed += 1,
+                    StreamEvent::TrailingData(data) => summary.removed_bytes += data.len() as u64,
+                }
+            }
+        }
+        parser.finish()?;
+        writer.flush()?;
+        Ok(summary)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{chunk::Chunk, png::tests::PNG_FILE};
+    use std::str::FromStr;
+
+    /// Hands out at most `step` bytes per read
+    struct Trickle<'a> {
+        bytes: &'a [u8],
+        step: usize,
+    }
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = self.step.min(buf.len()).min(self.bytes.len());
+            buf[..n].copy_from_slice(&self.bytes[..n]);
+            self.bytes = &self.bytes[n..];
+            Ok(n)
+        }
+    }
+
+    fn types(bytes: &[u8]) -> Vec<String> {
+        Png::try_from(bytes)
+            .unwrap()
+            .chunks()
+            .iter()
+            .map(|chunk| chunk.chunk_type().to_string())
+            .collect()
+    }
+
+    fn with_metadata() -> Vec<u8> {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        png.insert_before_end(Chunk::new(
+            ChunkType::from_str("tEXt").unwrap(),
+            b"Author\0someone".to_vec(),
+        ));
+        let mut bytes = png.as_bytes();
+        bytes.extend_from_slice(b"trailing");
+        bytes
+    }
+
+    #[test]
+    fn test_strip() {
+        let input = with_metadata();
+        let mut output = Vec::new();
+        let summary = StripFilter::new().run(&input[..], &mut output).unwrap();
+
+        assert_eq!(
+            types(&output),
+            ["IHDR", "sRGB", "gAMA", "IDAT", "RuSt", "IEND"]
+        );
+        assert_eq!(summary.kept, 6);
+        assert_eq!(summary.removed, 2);
+        assert_eq!(summary.removed_bytes, (input.len() - output.len()) as u64);
+    }
+
+    #[test]
+    fn test_strip_in_pieces() {
+        let input = with_metadata();
+        let mut whole = Vec::new();
+        StripFilter::new().run(&input[..], &mut whole).unwrap();
+
+        for step in [1, 7, 13] {
+            let mut output = Vec::new();
+            let reader = Trickle {
+                bytes: &input,
+                step,
+            };
+            StripFilter::new().run(reader, &mut output).unwrap();
+            assert_eq!(output, whole);
+        }
+    }
+
+    #[test]
+    fn test_keep() {
+        let mut output = Vec::new();
+        StripFilter::new()
+            .keep(vec![TypePattern::from_str("tEXt|pHYs").unwrap()])
+            .run(&with_metadata()[..], &mut output)
+            .unwrap();
+        assert_eq!(
+            types(&output),
+            ["IHDR", "sRGB", "gAMA", "pHYs", "IDAT", "RuSt", "tEXt", "IEND"]
+        );
+    }
+
+    #[test]
+    fn test_invalid_input() {
+        let filter = StripFilter::new();
+        assert!(matches!(
+            filter.run(&PNG_FILE[..PNG_FILE.len() - 3], io::sink()),
+            Err(FilterError::Parse(_))
+        ));
+        assert!(filter.run(&b"not a png"[..], io::sink()).is_err());
+
+        let mut corrupted = PNG_FILE.to_vec();
+        corrupted[20] ^= 1;
+        assert!(filter.run(&corrupted[..], io::sink()).is_err());
+    }
+}
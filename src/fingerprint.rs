@@ -0,0 +1,210 @@
+use crate::{
+    chunk::Chunk,
+    chunk_type::ChunkType,
+    png::Png,
+    watermark::{self, WatermarkError},
+};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+/// The chunk holding the marker of a copy
+pub const FINGERPRINT_CHUNK: &[u8; 4] = b"fpRt";
+
+/// The bytes of the HMAC kept as the marker
+pub const MARKER_LEN: usize = 16;
+
+/// The part of the marker embedded as a watermark, hex-encoded into its
+/// 8-byte identifier
+const WATERMARK_LEN: usize = watermark::MAX_IDENTIFIER_LEN / 2;
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum FingerprintError {
+    #[error("the recipient must not be empty")]
+    EmptyRecipient,
+
+    #[error(
+        "the image already has a watermark for another recipient, fingerprint the original instead"
+    )]
+    AlreadyMarked,
+
+    #[error(transparent)]
+    Watermark(#[from] WatermarkError),
+}
+
+/// The marker of the copy for `recipient`: the first bytes of
+/// HMAC-SHA256(`key`, `recipient`). Without the key, markers can't be
+/// linked to recipients or forged for another one.
+pub fn marker(key: &[u8], recipient: &str) -> [u8; MARKER_LEN] {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes keys of any length");
+    mac.update(recipient.as_bytes());
+    let mut marker = [0; MARKER_LEN];
+    marker.copy_from_slice(&mac.finalize().into_bytes()[..MARKER_LEN]);
+    marker
+}
+
+/// The watermark identifier of a marker
+fn watermark_identifier(marker: &[u8; MARKER_LEN]) -> String {
+    marker[..WATERMARK_LEN]
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+fn chunk_type() -> ChunkType {
+    ChunkType::try_from(*FINGERPRINT_CHUNK).unwrap()
+}
+
+/// Marks `png` as the copy for `recipient`, replacing an earlier marker:
+/// the marker is stored in a [`FINGERPRINT_CHUNK`] chunk and, if the image
+/// allows it, part of it as a watermark (see [`watermark::embed`]), which
+/// survives stripping the chunks and re-encoding. Returns whether the
+/// image has the watermark.
+///
+/// Watermarks can't be removed, so copies already watermarked for another
+/// recipient with the same key are rejected.
+pub fn embed(
+    png: &mut Png,
+    key: &[u8],
+    recipient: &str,
+    strength: u8,
+) -> Result<bool, FingerprintError> {
+    if recipient.is_empty() {
+        return Err(FingerprintError::EmptyRecipient);
+    }
+    let marker = marker(key, recipient);
+    let identifier = watermark_identifier(&marker);
+    let existing = watermark::detect(png, key)
+        .ok()
+        .and_then(|detection| detection.identifier);
+    let watermarked = match existing {
+        Some(existing) if existing == identifier => Ok(()),
+        Some(_) => return Err(FingerprintError::AlreadyMarked),
+        None => watermark::embed(png, &identifier, key, strength),
+    };
+    let watermarked = match watermarked {
+        Ok(()) => true,
+        Err(
+            WatermarkError::Indexed | WatermarkError::LowBitDepth(_) | WatermarkError::TooSmall,
+        ) => false,
+        Err(err) => return Err(err.into()),
+    };
+    png.retain(|chunk| &chunk.chunk_type().bytes() != FINGERPRINT_CHUNK);
+    png.insert_before_end(Chunk::new(chunk_type(), marker.to_vec()));
+    Ok(watermarked)
+}
+
+/// Whose copy a marker belongs to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Recipient {
+    Known(String),
+    /// A marker was found, but none of the recipients has it
+    Unknown,
+}
+
+/// The markers found by [`identify`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Identification {
+    pub chunk: Option<Recipient>,
+    pub watermark: Option<Recipient>,
+}
+
+/// Finds out which of `recipients` the copy `png` was made for, from the
+/// marker chunk and the watermark separately, so that a stripped chunk or
+/// an edit which destroyed the watermark still leaves the other.
+pub fn identify(png: &Png, key: &[u8], recipients: &[String]) -> Identification {
+    let markers: Vec<(&String, [u8; MARKER_LEN])> = recipients
+        .iter()
+        .map(|recipient| (recipient, marker(key, recipient)))
+        .collect();
+    let find = |matches: &dyn Fn(&[u8; MARKER_LEN]) -> bool| {
+        markers
+            .iter()
+            .find(|(_, marker)| matches(marker))
+            .map_or(Recipient::Unknown, |(recipient, _)| {
+                Recipient::Known(recipient.to_string())
+            })
+    };
+
+    let chunk = png
+        .chunk_by_type(&chunk_type())
+        .map(|chunk| find(&|marker| chunk.data() == marker));
+    // Images without room for a watermark simply have none
+    let watermark = watermark::detect(png, key)
+        .ok()
+        .and_then(|detection| detection.identifier)
+        .map(|identifier| find(&|marker| watermark_identifier(marker) == identifier));
+    Identification { chunk, watermark }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{png::tests::PNG_FILE, watermark::tests::image};
+
+    fn recipients() -> Vec<String> {
+        ["alice", "bob", "carol"].map(String::from).to_vec()
+    }
+
+    #[test]
+    fn test_marker() {
+        assert_eq!(marker(b"key", "alice"), marker(b"key", "alice"));
+        assert_ne!(marker(b"key", "alice"), marker(b"key", "bob"));
+        assert_ne!(marker(b"key", "alice"), marker(b"other", "alice"));
+        assert_eq!(watermark_identifier(&marker(b"key", "alice")).len(), 8);
+    }
+
+    #[test]
+    fn test_identify() {
+        let mut png = image();
+        assert!(embed(&mut png, b"key", "bob", watermark::DEFAULT_STRENGTH).unwrap());
+        let bob = Some(Recipient::Known("bob".to_owned()));
+        assert_eq!(
+            embed(&mut png.clone(), b"key", "alice", 3),
+            Err(FingerprintError::AlreadyMarked)
+        );
+        assert!(embed(&mut png, b"key", "bob", 3).unwrap());
+        assert_eq!(
+            identify(&png, b"key", &recipients()),
+            Identification {
+                chunk: bob.clone(),
+                watermark: bob.clone(),
+            }
+        );
+
+        // The watermark is left when the chunk is stripped
+        png.retain(|chunk| &chunk.chunk_type().bytes() != FINGERPRINT_CHUNK);
+        let identification = identify(&png, b"key", &recipients());
+        assert_eq!(identification.chunk, None);
+        assert_eq!(identification.watermark, bob);
+
+        let unknown = identify(&png, b"key", &["alice".to_owned()]);
+        assert_eq!(unknown.watermark, Some(Recipient::Unknown));
+        let other_key = identify(&png, b"other", &recipients());
+        assert_eq!(other_key.watermark, None);
+    }
+
+    #[test]
+    fn test_small_image() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        assert!(!embed(&mut png, b"key", "alice", 3).unwrap());
+        // Embedding again replaces the marker
+        assert!(!embed(&mut png, b"key", "carol", 3).unwrap());
+        let chunks = png
+            .chunks()
+            .iter()
+            .filter(|chunk| &chunk.chunk_type().bytes() == FINGERPRINT_CHUNK)
+            .count();
+        assert_eq!(chunks, 1);
+        assert_eq!(
+            identify(&png, b"key", &recipients()),
+            Identification {
+                chunk: Some(Recipient::Known("carol".to_owned())),
+                watermark: None,
+            }
+        );
+        assert_eq!(
+            embed(&mut png, b"key", "", 3),
+            Err(FingerprintError::EmptyRecipient)
+        );
+    }
+}
@@ -0,0 +1,394 @@
+use std::{fmt, str::FromStr};
+
+/// The identifier of an application extension: an 8 byte application name
+/// and a 3 byte authentication code, e.g. `NETSCAPE2.0`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ApplicationId {
+    pub identifier: [u8; 8],
+    /// If `None`, the id matches extensions with any authentication code
+    pub auth_code: Option<[u8; 3]>,
+}
+
+impl ApplicationId {
+    /// The authentication code written when none is given
+    pub const DEFAULT_AUTH_CODE: [u8; 3] = *b"1.0";
+
+    fn matches(&self, extension: &ApplicationExtension) -> bool {
+        self.identifier == extension.identifier
+            && self
+                .auth_code
+                .is_none_or(|auth_code| auth_code == extension.auth_code)
+    }
+}
+
+impl fmt::Display for ApplicationId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", String::from_utf8_lossy(&self.identifier))?;
+        if let Some(auth_code) = self.auth_code {
+            write!(f, "{}", String::from_utf8_lossy(&auth_code))?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+#[error("'{0}' is not an application id, expected 8 printable ASCII characters and optionally a 3 character authentication code")]
+pub struct ApplicationIdParseError(String);
+
+impl FromStr for ApplicationId {
+    type Err = ApplicationIdParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || ApplicationIdParseError(s.to_owned());
+        let bytes = s.as_bytes();
+        if !bytes.iter().all(|b| (b' '..=b'~').contains(b)) {
+            return Err(error());
+        }
+        match bytes.len() {
+            8 => Ok(Self {
+                identifier: bytes.try_into().unwrap(),
+                auth_code: None,
+            }),
+            11 => Ok(Self {
+                identifier: bytes[..8].try_into().unwrap(),
+                auth_code: Some(bytes[8..].try_into().unwrap()),
+            }),
+            _ => Err(error()),
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum GifError {
+    #[error("the file does not start with a GIF header")]
+    Format,
+
+    #[error("the block at byte {0} is truncated")]
+    Truncated(usize),
+
+    #[error("unknown block introducer 0x{1:02x} at byte {0}")]
+    UnknownBlock(usize, u8),
+}
+
+/// An application extension block with its data joined from the sub-blocks
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApplicationExtension {
+    identifier: [u8; 8],
+    auth_code: [u8; 3],
+    data: Vec<u8>,
+}
+
+impl ApplicationExtension {
+    pub fn new(id: ApplicationId, data: Vec<u8>) -> Self {
+        Self {
+            identifier: id.identifier,
+            auth_code: id.auth_code.unwrap_or(ApplicationId::DEFAULT_AUTH_CODE),
+            data,
+        }
+    }
+
+    pub fn id(&self) -> ApplicationId {
+        ApplicationId {
+            identifier: self.identifier,
+            auth_code: Some(self.auth_code),
+        }
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Serializes the block, splitting the data into sub-blocks of at most
+    /// 255 bytes
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![0x21, 0xff, 11];
+        bytes.extend_from_slice(&self.identifier);
+        bytes.extend_from_slice(&self.auth_code);
+        for sub_block in self.data.chunks(255) {
+            bytes.push(sub_block.len() as u8);
+            bytes.extend_from_slice(sub_block);
+        }
+        bytes.push(0);
+        bytes
+    }
+}
+
+/// A block of a GIF file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Block {
+    Application(ApplicationExtension),
+    /// Any other extension, e.g. a graphic control or comment extension,
+    /// kept as is
+    Extension {
+        label: u8,
+        bytes: Vec<u8>,
+    },
+    /// An image descriptor with its color table and image data, kept as is
+    Image(Vec<u8>),
+}
+
+impl Block {
+    pub fn name(&self) -> String {
+        match self {
+            Self::Application(extension) => extension.id().to_string(),
+            Self::Extension { label: 0x01, .. } => "PlainText".to_owned(),
+            Self::Extension { label: 0xf9, .. } => "GraphicControl".to_owned(),
+            Self::Extension { label: 0xfe, .. } => "Comment".to_owned(),
+            Self::Extension { label, .. } => format!("Extension 0x{:02x}", label),
+            Self::Image(_) => "Image".to_owned(),
+        }
+    }
+
+    /// The size of the block in the file
+    pub fn size(&self) -> usize {
+        match self {
+            Self::Application(extension) => extension.as_bytes().len(),
+            Self::Extension { bytes, .. } | Self::Image(bytes) => bytes.len(),
+        }
+    }
+
+    pub fn as_bytes(&self) -> Vec<u8> {
+        match self {
+            Self::Application(extension) => extension.as_bytes(),
+            Self::Extension { bytes, .. } | Self::Image(bytes) => bytes.clone(),
+        }
+    }
+}
+
+/// A GIF file, split into its blocks. The header, logical screen descriptor
+/// and global color table are kept as is, as is everything from the trailer
+/// on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Gif {
+    header: Vec<u8>,
+    blocks: Vec<Block>,
+    trailer: Vec<u8>,
+}
+
+impl Gif {
+    pub fn blocks(&self) -> &[Block] {
+        &self.blocks
+    }
+
+    /// Returns the blocks together with their offset in the file
+    pub fn blocks_with_offsets(&self) -> impl Iterator<Item = (usize, &Block)> {
+        self.blocks.iter().scan(self.header.len(), |offset, block| {
+            let start = *offset;
+            *offset += block.size();
+            Some((start, block))
+        })
+    }
+
+    /// Inserts an application extension before the first image (and its
+    /// graphic control extension), where players expect blocks like the
+    /// `NETSCAPE2.0` looping extension
+    pub fn insert_application(&mut self, extension: ApplicationExtension) {
+        let position = self
+            .blocks
+            .iter()
+            .position(|block| {
+                matches!(
+                    block,
+                    Block::Image(_) | Block::Extension { label: 0xf9, .. }
+                )
+            })
+            .unwrap_or(self.blocks.len());
+        self.blocks.insert(position, Block::Application(extension));
+        // Extensions were introduced with GIF89a
+        self.header[..6].copy_from_slice(b"GIF89a");
+    }
+
+    pub fn application_by_id(&self, id: ApplicationId) -> Option<&ApplicationExtension> {
+        self.blocks.iter().find_map(|block| match block {
+            Block::Application(extension) if id.matches(extension) => Some(extension),
+            _ => None,
+        })
+    }
+
+    /// Removes the first application extension matching `id`
+    pub fn remove_application(&mut self, id: ApplicationId) -> Option<ApplicationExtension> {
+        let position = self.blocks.iter().position(
+            |block| matches!(block, Block::Application(extension) if id.matches(extension)),
+        )?;
+        match self.blocks.remove(position) {
+            Block::Application(extension) => Some(extension),
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.header.clone();
+        for block in &self.blocks {
+            bytes.extend(block.as_bytes());
+        }
+        bytes.extend_from_slice(&self.trailer);
+        bytes
+    }
+}
+
+/// Reads the sub-blocks starting at `cursor`, returning their contents and
+/// the position after the terminating empty sub-block
+fn sub_blocks(bytes: &[u8], mut cursor: usize) -> Option<(Vec<&[u8]>, usize)> {
+    let mut blocks = Vec::new();
+    loop {
+        let length = *bytes.get(cursor)? as usize;
+        cursor += 1;
+        if length == 0 {
+            return Some((blocks, cursor));
+        }
+        blocks.push(bytes.get(cursor..cursor + length)?);
+        cursor += length;
+    }
+}
+
+/// The size of a color table, given the packed field it is described in
+fn color_table_size(packed: u8) -> usize {
+    match packed & 0x80 {
+        0 => 0,
+        _ => 3 << ((packed & 0x07) + 1),
+    }
+}
+
+impl TryFrom<&[u8]> for Gif {
+    type Error = GifError;
+
+    fn try_from(bytes: &[u8]) -> Result<Self, Self::Error> {
+        if !bytes.starts_with(b"GIF87a") && !bytes.starts_with(b"GIF89a") {
+            return Err(GifError::Format);
+        }
+        let packed = *bytes.get(10).ok_or(GifError::Truncated(6))?;
+        let header_len = 13 + color_table_size(packed);
+        let header = bytes.get(..header_len).ok_or(GifError::Truncated(6))?;
+
+        let mut blocks = Vec::new();
+        let mut cursor = header_len;
+        loop {
+            let start = cursor;
+            match bytes.get(cursor) {
+                Some(0x21) => {
+                    let label = *bytes.get(cursor + 1).ok_or(GifError::Truncated(start))?;
+                    let (sub_blocks, end) =
+                        sub_blocks(bytes, cursor + 2).ok_or(GifError::Truncated(start))?;
+                    cursor = end;
+
+                    blocks.push(match (label, sub_blocks.as_slice()) {
+                        (0xff, [id, data @ ..]) if id.len() == 11 => {
+                            Block::Application(ApplicationExtension {
+                                identifier: id[..8].try_into().unwrap(),
+                                auth_code: id[8..].try_into().unwrap(),
+                                data: data.concat(),
+                            })
+                        }
+                        _ => Block::Extension {
+                            label,
+                            bytes: bytes[start..end].to_vec(),
+                        },
+                    });
+                }
+                Some(0x2c) => {
+                    let packed = *bytes.get(cursor + 9).ok_or(GifError::Truncated(start))?;
+                    // Descriptor, color table and the LZW minimum code size
+                    let data_start = cursor + 10 + color_table_size(packed) + 1;
+                    let (_, end) =
+                        sub_blocks(bytes, data_start).ok_or(GifError::Truncated(start))?;
+                    cursor = end;
+                    blocks.push(Block::Image(bytes[start..end].to_vec()));
+                }
+                Some(0x3b) => {
+                    return Ok(Self {
+                        header: header.to_vec(),
+                        blocks,
+                        trailer: bytes[cursor..].to_vec(),
+                    })
+                }
+                Some(other) => return Err(GifError::UnknownBlock(cursor, *other)),
+                None => return Err(GifError::Truncated(start)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// A 1x1 GIF89a with a two color global table, a looping extension, a
+    /// graphic control extension and one image
+    pub(crate) fn testing_gif() -> Vec<u8> {
+        let mut bytes = b"GIF89a\x01\x00\x01\x00\x80\x00\x00".to_vec();
+        bytes.extend_from_slice(&[0, 0, 0, 255, 255, 255]);
+        bytes.extend_from_slice(b"\x21\xff\x0bNETSCAPE2.0\x03\x01\x00\x00\x00");
+        bytes.extend_from_slice(b"\x21\xf9\x04\x00\x00\x00\x00\x00");
+        bytes.extend_from_slice(b"\x2c\x00\x00\x00\x00\x01\x00\x01\x00\x00");
+        bytes.extend_from_slice(b"\x02\x02\x44\x01\x00");
+        bytes.push(0x3b);
+        bytes
+    }
+
+    fn id(s: &str) -> ApplicationId {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_application_id() {
+        assert_eq!(id("NETSCAPE2.0").to_string(), "NETSCAPE2.0");
+        assert_eq!(id("PNGMEPNG").auth_code, None);
+        assert!(ApplicationId::from_str("SHORT").is_err());
+        assert!(ApplicationId::from_str("NETSCAPE2.0!").is_err());
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let bytes = testing_gif();
+        let gif = Gif::try_from(&bytes[..]).unwrap();
+        let names: Vec<String> = gif.blocks().iter().map(Block::name).collect();
+        assert_eq!(names, ["NETSCAPE2.0", "GraphicControl", "Image"]);
+        assert_eq!(
+            gif.application_by_id(id("NETSCAPE")).unwrap().data(),
+            [1, 0, 0]
+        );
+        assert_eq!(gif.as_bytes(), bytes);
+    }
+
+    #[test]
+    fn test_large_payload_is_split() {
+        let bytes = testing_gif();
+        let mut gif = Gif::try_from(&bytes[..]).unwrap();
+        let payload: Vec<u8> = (0..600).map(|i| i as u8).collect();
+        gif.insert_application(ApplicationExtension::new(id("PNGMEPNG"), payload.clone()));
+        assert!(matches!(gif.blocks()[1], Block::Application(_)));
+
+        let written = gif.as_bytes();
+        let reparsed = Gif::try_from(&written[..]).unwrap();
+        let extension = reparsed.application_by_id(id("PNGMEPNG1.0")).unwrap();
+        assert_eq!(extension.data(), payload);
+        // 255 + 255 + 90 bytes, each with a length byte, and a terminator
+        assert_eq!(reparsed.blocks()[1].size(), 3 + 11 + 600 + 3 + 1);
+
+        let mut gif = reparsed;
+        assert!(gif.remove_application(id("PNGMEPNG")).is_some());
+        assert_eq!(gif.as_bytes(), bytes);
+    }
+
+    #[test]
+    fn test_auth_code_must_match() {
+        let bytes = testing_gif();
+        let gif = Gif::try_from(&bytes[..]).unwrap();
+        assert!(gif.application_by_id(id("NETSCAPE3.0")).is_none());
+    }
+
+    #[test]
+    fn test_invalid_files() {
+        assert_eq!(Gif::try_from(&b"\x89PNG"[..]), Err(GifError::Format));
+
+        let bytes = testing_gif();
+        assert_eq!(Gif::try_from(&bytes[..25]), Err(GifError::Truncated(19)));
+
+        let mut bytes = testing_gif();
+        bytes[19] = 0x42;
+        assert_eq!(
+            Gif::try_from(&bytes[..]),
+            Err(GifError::UnknownBlock(19, 0x42))
+        );
+    }
+}
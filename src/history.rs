@@ -0,0 +1,257 @@
+use crate::{chunk::Chunk, chunk_type::ChunkType, png::Png};
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::io::{Read, Write};
+
+/// The chunk type history entries are stored in (private, ancillary, safe
+/// to copy). Every entry has its own chunk, new ones are appended.
+pub const HISTORY_CHUNK_TYPE: [u8; 4] = *b"pmHs";
+
+#[derive(Debug, thiserror::Error)]
+pub enum HistoryError {
+    #[error("the file has no history")]
+    Empty,
+
+    #[error("history entry {index} is invalid: {reason}")]
+    Invalid { index: usize, reason: String },
+
+    #[error("the {0} chunk added by the last operation is gone, the file was changed without recording it")]
+    Diverged(String),
+
+    #[error("the recorded data of the removed {0} chunk is corrupted")]
+    Corrupted(String),
+}
+
+/// A chunk an operation added, identified by the SHA-256 of its data
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AddedChunk {
+    pub chunk_type: String,
+    #[serde(with = "serde_bytes")]
+    pub sha256: Vec<u8>,
+}
+
+/// A chunk an operation removed or changed, with its previous data
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RemovedChunk {
+    pub chunk_type: String,
+    /// The position of the chunk before the operation, not counting history
+    /// chunks
+    pub index: usize,
+    #[serde(with = "serde_bytes")]
+    pub sha256: Vec<u8>,
+    /// The previous data, zlib compressed
+    #[serde(with = "serde_bytes")]
+    pub data: Vec<u8>,
+}
+
+/// One operation on a PNG and the chunks it changed, stored as CBOR
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub operation: String,
+    /// Seconds since the unix epoch
+    pub timestamp: i64,
+    pub added: Vec<AddedChunk>,
+    pub removed: Vec<RemovedChunk>,
+}
+
+fn is_history(chunk: &Chunk) -> bool {
+    chunk.chunk_type().bytes() == HISTORY_CHUNK_TYPE
+}
+
+/// The chunks of `png` which aren't part of the history
+fn content(png: &Png) -> Vec<&Chunk> {
+    png.chunks()
+        .iter()
+        .filter(|chunk| !is_history(chunk))
+        .collect()
+}
+
+fn sha256(data: &[u8]) -> Vec<u8> {
+    Sha256::digest(data).to_vec()
+}
+
+impl HistoryEntry {
+    /// Describes how `after` differs from `before`, or returns `None` if
+    /// they hold the same chunks. Moving chunks around isn't recorded.
+    pub fn diff(operation: &str, timestamp: i64, before: &Png, after: &Png) -> Option<Self> {
+        let before = content(before);
+        let mut unmatched = content(after);
+
+        let mut removed = Vec::new();
+        for (index, chunk) in before.into_iter().enumerate() {
+            match unmatched.iter().position(|other| *other == chunk) {
+                Some(position) => {
+                    unmatched.remove(position);
+                }
+                None => removed.push(RemovedChunk {
+                    chunk_type: chunk.chunk_type().to_string(),
+                    index,
+                    sha256: sha256(chunk.data()),
+                    data: deflate(chunk.data()),
+                }),
+            }
+        }
+        let added: Vec<AddedChunk> = unmatched
+            .into_iter()
+            .map(|chunk| AddedChunk {
+                chunk_type: chunk.chunk_type().to_string(),
+                sha256: sha256(chunk.data()),
+            })
+            .collect();
+
+        if added.is_empty() && removed.is_empty() {
+            return None;
+        }
+        Some(Self {
+            operation: operation.to_owned(),
+            timestamp,
+            added,
+            removed,
+        })
+    }
+
+    fn to_chunk(&self) -> Chunk {
+        let mut data = Vec::new();
+        ciborium::ser::into_writer(self, &mut data).expect("writing to a Vec never fails");
+        Chunk::new(ChunkType::try_from(HISTORY_CHUNK_TYPE).unwrap(), data)
+    }
+}
+
+fn deflate(data: &[u8]) -> Vec<u8> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::best());
+    encoder
+        .write_all(data)
+        .expect("writing to a Vec never fails");
+    encoder.finish().expect("writing to a Vec never fails")
+}
+
+/// Appends `entry` to the history of `png`, before the `IEND` chunk
+pub fn record(png: &mut Png, entry: &HistoryEntry) {
+    png.insert_before_end(entry.to_chunk());
+}
+
+/// Reads the history of `png`, oldest entry first
+pub fn read(png: &Png) -> Result<Vec<HistoryEntry>, HistoryError> {
+    png.chunks()
+        .iter()
+        .filter(|chunk| is_history(chunk))
+        .enumerate()
+        .map(|(index, chunk)| {
+            ciborium::de::from_reader(chunk.data()).map_err(|err| HistoryError::Invalid {
+                index: index + 1,
+                reason: err.to_string(),
+            })
+        })
+        .collect()
+}
+
+/// Reverts the last recorded operation: removes the chunks it added and
+/// restores the ones it removed, then drops its history entry. Returns the
+/// reverted entry.
+pub fn undo(png: &mut Png) -> Result<HistoryEntry, HistoryError> {
+    let mut entries = read(png)?;
+    let entry = entries.pop().ok_or(HistoryError::Empty)?;
+
+    let mut chunks: Vec<Chunk> = content(png).into_iter().cloned().collect();
+    for added in entry.added.iter().rev() {
+        let position = chunks
+            .iter()
+            .rposition(|chunk| {
+                chunk.chunk_type().to_string() == added.chunk_type
+                    && sha256(chunk.data()) == added.sha256
+            })
+            .ok_or_else(|| HistoryError::Diverged(added.chunk_type.clone()))?;
+        chunks.remove(position);
+    }
+    for removed in &entry.removed {
+        let corrupted = || HistoryError::Corrupted(removed.chunk_type.clone());
+        let mut data = Vec::new();
+        ZlibDecoder::new(&removed.data[..])
+            .read_to_end(&mut data)
+            .map_err(|_| corrupted())?;
+        if sha256(&data) != removed.sha256 {
+            return Err(corrupted());
+        }
+        let chunk_type = removed.chunk_type.parse().map_err(|_| corrupted())?;
+        chunks.insert(
+            removed.index.min(chunks.len()),
+            Chunk::new(chunk_type, data),
+        );
+    }
+
+    let mut restored = Png::from_chunks(chunks);
+    for earlier in &entries {
+        record(&mut restored, earlier);
+    }
+    *png = restored;
+    Ok(entry)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::png::tests::PNG_FILE;
+    use std::str::FromStr;
+
+    fn chunk(chunk_type: &str, data: &[u8]) -> Chunk {
+        Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data.to_vec())
+    }
+
+    /// Applies `operation` to `png` and records it
+    fn recorded(png: &mut Png, name: &str, operation: impl FnOnce(&mut Png)) {
+        let before = png.clone();
+        operation(png);
+        let entry = HistoryEntry::diff(name, 0, &before, png).unwrap();
+        record(png, &entry);
+    }
+
+    #[test]
+    fn test_diff() {
+        let before = Png::try_from(&PNG_FILE[..]).unwrap();
+        assert!(HistoryEntry::diff("normalize", 0, &before, &before).is_none());
+
+        let mut after = before.clone();
+        after.insert_before_end(chunk("ruSt", b"secret"));
+        let removed = after.remove_chunk(&ChunkType::from_str("gAMA").unwrap());
+        assert!(removed.is_ok());
+
+        let entry = HistoryEntry::diff("encode", 0, &before, &after).unwrap();
+        assert_eq!(entry.added.len(), 1);
+        assert_eq!(entry.added[0].chunk_type, "ruSt");
+        assert_eq!(entry.removed.len(), 1);
+        assert_eq!(entry.removed[0].chunk_type, "gAMA");
+    }
+
+    #[test]
+    fn test_undo() {
+        let original = Png::try_from(&PNG_FILE[..]).unwrap();
+        let mut png = original.clone();
+        recorded(&mut png, "encode", |png| {
+            png.insert_before_end(chunk("ruSt", b"secret"))
+        });
+        let after_encode = png.clone();
+        recorded(&mut png, "remove", |png| {
+            png.remove_chunk(&ChunkType::from_str("gAMA").unwrap())
+                .unwrap();
+        });
+        assert_eq!(read(&png).unwrap().len(), 2);
+
+        assert_eq!(undo(&mut png).unwrap().operation, "remove");
+        assert_eq!(png, after_encode);
+        assert_eq!(undo(&mut png).unwrap().operation, "encode");
+        assert_eq!(png, original);
+        assert!(matches!(undo(&mut png), Err(HistoryError::Empty)));
+    }
+
+    #[test]
+    fn test_undo_diverged() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        recorded(&mut png, "encode", |png| {
+            png.insert_before_end(chunk("ruSt", b"secret"))
+        });
+        png.remove_chunk(&ChunkType::from_str("ruSt").unwrap())
+            .unwrap();
+        assert!(matches!(undo(&mut png), Err(HistoryError::Diverged(_))));
+    }
+}
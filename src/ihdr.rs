@@ -0,0 +1,227 @@
+use crate::png::Png;
+use core::fmt::{self, Display};
+
+/// How pixels are stored, from the `IHDR` chunk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorType {
+    Grayscale,
+    Rgb,
+    Indexed,
+    GrayscaleAlpha,
+    Rgba,
+}
+
+impl ColorType {
+    /// The number of samples per pixel
+    pub fn channels(self) -> u32 {
+        match self {
+            Self::Grayscale | Self::Indexed => 1,
+            Self::GrayscaleAlpha => 2,
+            Self::Rgb => 3,
+            Self::Rgba => 4,
+        }
+    }
+
+    /// Whether the last sample of each pixel is an alpha sample
+    pub fn has_alpha(self) -> bool {
+        matches!(self, Self::GrayscaleAlpha | Self::Rgba)
+    }
+
+    fn allows_bit_depth(self, bit_depth: u8) -> bool {
+        match self {
+            Self::Grayscale => matches!(bit_depth, 1 | 2 | 4 | 8 | 16),
+            Self::Indexed => matches!(bit_depth, 1 | 2 | 4 | 8),
+            Self::Rgb | Self::GrayscaleAlpha | Self::Rgba => matches!(bit_depth, 8 | 16),
+        }
+    }
+}
+
+impl TryFrom<u8> for ColorType {
+    type Error = HeaderError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Self::Grayscale),
+            2 => Ok(Self::Rgb),
+            3 => Ok(Self::Indexed),
+            4 => Ok(Self::GrayscaleAlpha),
+            6 => Ok(Self::Rgba),
+            _ => Err(HeaderError::UnknownColorType(value)),
+        }
+    }
+}
+
+impl Display for ColorType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Grayscale => "grayscale",
+            Self::Rgb => "RGB",
+            Self::Indexed => "indexed (palette)",
+            Self::GrayscaleAlpha => "grayscale with alpha",
+            Self::Rgba => "RGB with alpha",
+        })
+    }
+}
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum HeaderError {
+    #[error("the IHDR chunk is missing")]
+    Missing,
+
+    #[error("the IHDR chunk has {0} bytes of data instead of 13")]
+    InvalidLength(usize),
+
+    #[error("the image is {width}x{height}, but both sides must be between 1 and 2^31 - 1")]
+    InvalidDimensions { width: u32, height: u32 },
+
+    #[error("unknown color type {0}")]
+    UnknownColorType(u8),
+
+    #[error("a bit depth of {bit_depth} is not allowed for {color_type} images")]
+    InvalidBitDepth {
+        color_type: ColorType,
+        bit_depth: u8,
+    },
+
+    #[error("unknown compression, filter or interlace method")]
+    UnknownMethod,
+}
+
+/// Why the least significant bits of an image can't hide data
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum LsbError {
+    #[error("palette images can't carry data in their pixels, as changing an index picks an unrelated color; convert the image to RGB first")]
+    Indexed,
+
+    #[error("the image has {0} bits per sample, changing the lowest one would be visible")]
+    LowBitDepth(u8),
+}
+
+/// The contents of the `IHDR` chunk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImageHeader {
+    pub width: u32,
+    pub height: u32,
+    pub bit_depth: u8,
+    pub color_type: ColorType,
+    pub interlaced: bool,
+}
+
+impl ImageHeader {
+    /// Reads the header from the `IHDR` chunk of `png`
+    pub fn from_png(png: &Png) -> Result<Self, HeaderError> {
+        let chunk = png
+            .chunks()
+            .iter()
+            .find(|chunk| &chunk.chunk_type().bytes() == b"IHDR")
+            .ok_or(HeaderError::Missing)?;
+        Self::try_from(chunk.data())
+    }
+
+    /// The number of bytes that fit into the lowest bit of every color
+    /// sample. Alpha samples are left alone, as hidden data in fully
+    /// transparent pixels would stand out.
+    pub fn lsb_capacity(&self) -> Result<u64, LsbError> {
+        if self.color_type == ColorType::Indexed {
+            return Err(LsbError::Indexed);
+        }
+        if self.bit_depth < 8 {
+            return Err(LsbError::LowBitDepth(self.bit_depth));
+        }
+        let colors = self.color_type.channels() - self.color_type.has_alpha() as u32;
+        Ok(self.width as u64 * self.height as u64 * colors as u64 / 8)
+    }
+}
+
+impl TryFrom<&[u8]> for ImageHeader {
+    type Error = HeaderError;
+
+    fn try_from(data: &[u8]) -> Result<Self, Self::Error> {
+        let data: &[u8; 13] = data
+            .try_into()
+            .map_err(|_| HeaderError::InvalidLength(data.len()))?;
+
+        let width = u32::from_be_bytes(data[0..4].try_into().unwrap());
+        let height = u32::from_be_bytes(data[4..8].try_into().unwrap());
+        if !(1..1 << 31).contains(&width) || !(1..1 << 31).contains(&height) {
+            return Err(HeaderError::InvalidDimensions { width, height });
+        }
+
+        let bit_depth = data[8];
+        let color_type = ColorType::try_from(data[9])?;
+        if !color_type.allows_bit_depth(bit_depth) {
+            return Err(HeaderError::InvalidBitDepth {
+                color_type,
+                bit_depth,
+            });
+        }
+        if data[10] != 0 || data[11] != 0 || data[12] > 1 {
+            return Err(HeaderError::UnknownMethod);
+        }
+
+        Ok(Self {
+            width,
+            height,
+            bit_depth,
+            color_type,
+            interlaced: data[12] == 1,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::png::tests::PNG_FILE;
+
+    fn header(width: u32, height: u32, bit_depth: u8, color_type: u8) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend(width.to_be_bytes());
+        data.extend(height.to_be_bytes());
+        data.extend([bit_depth, color_type, 0, 0, 0]);
+        data
+    }
+
+    #[test]
+    fn test_from_png() {
+        let png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let header = ImageHeader::from_png(&png).unwrap();
+        assert!(header.width > 0 && header.height > 0);
+    }
+
+    #[test]
+    fn test_invalid_headers() {
+        assert_eq!(
+            ImageHeader::try_from(&[0; 12][..]),
+            Err(HeaderError::InvalidLength(12))
+        );
+        assert_eq!(
+            ImageHeader::try_from(&header(0, 1, 8, 2)[..]),
+            Err(HeaderError::InvalidDimensions {
+                width: 0,
+                height: 1
+            })
+        );
+        assert_eq!(
+            ImageHeader::try_from(&header(1, 1, 8, 5)[..]),
+            Err(HeaderError::UnknownColorType(5))
+        );
+        assert_eq!(
+            ImageHeader::try_from(&header(1, 1, 16, 3)[..]),
+            Err(HeaderError::InvalidBitDepth {
+                color_type: ColorType::Indexed,
+                bit_depth: 16
+            })
+        );
+    }
+
+    #[test]
+    fn test_lsb_capacity() {
+        let capacity = |data: Vec<u8>| ImageHeader::try_from(&data[..]).unwrap().lsb_capacity();
+        assert_eq!(capacity(header(10, 8, 8, 2)), Ok(30));
+        assert_eq!(capacity(header(10, 8, 16, 6)), Ok(30));
+        assert_eq!(capacity(header(10, 8, 8, 0)), Ok(10));
+        assert_eq!(capacity(header(10, 8, 8, 3)), Err(LsbError::Indexed));
+        assert_eq!(capacity(header(10, 8, 1, 0)), Err(LsbError::LowBitDepth(1)));
+    }
+}
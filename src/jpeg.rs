@@ -0,0 +1,344 @@
+use std::{fmt, str::FromStr};
+
+/// The marker of a JPEG segment, i.e. the byte following `0xFF`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Marker(pub u8);
+
+impl Marker {
+    pub const SOS: Marker = Marker(0xda);
+    pub const EOI: Marker = Marker(0xd9);
+    pub const COM: Marker = Marker(0xfe);
+
+    /// The `APPn` marker, `n` must be in `0..16`
+    pub fn app(n: u8) -> Self {
+        assert!(n < 16, "there are only 16 APPn markers");
+        Marker(0xe0 + n)
+    }
+
+    /// Whether segments with this marker hold application data or comments,
+    /// which decoders ignore if they don't understand them
+    pub fn is_metadata(&self) -> bool {
+        matches!(self.0, 0xe0..=0xef | 0xfe)
+    }
+
+    /// Whether the marker stands alone, without a length and data
+    pub fn is_standalone(&self) -> bool {
+        matches!(self.0, 0x01 | 0xd0..=0xd9)
+    }
+}
+
+impl fmt::Display for Marker {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            0xc4 => write!(f, "DHT"),
+            0xc8 => write!(f, "JPG"),
+            0xcc => write!(f, "DAC"),
+            n @ 0xc0..=0xcf => write!(f, "SOF{}", n - 0xc0),
+            n @ 0xd0..=0xd7 => write!(f, "RST{}", n - 0xd0),
+            0xd8 => write!(f, "SOI"),
+            0xd9 => write!(f, "EOI"),
+            0xda => write!(f, "SOS"),
+            0xdb => write!(f, "DQT"),
+            0xdc => write!(f, "DNL"),
+            0xdd => write!(f, "DRI"),
+            0xde => write!(f, "DHP"),
+            0xdf => write!(f, "EXP"),
+            n @ 0xe0..=0xef => write!(f, "APP{}", n - 0xe0),
+            0xfe => write!(f, "COM"),
+            n => write!(f, "0x{:02X}", n),
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+#[error("'{0}' is not a segment that can hold data, expected COM or APP0 to APP15")]
+pub struct MarkerParseError(String);
+
+/// Parses the markers of segments which can hold arbitrary data: `COM` and
+/// `APP0` to `APP15` (case-insensitive)
+impl FromStr for Marker {
+    type Err = MarkerParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let upper = s.to_ascii_uppercase();
+        if upper == "COM" {
+            return Ok(Self::COM);
+        }
+        upper
+            .strip_prefix("APP")
+            .and_then(|n| n.parse::<u8>().ok())
+            .filter(|n| *n < 16)
+            .map(Self::app)
+            .ok_or_else(|| MarkerParseError(s.to_owned()))
+    }
+}
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum JpegError {
+    #[error("the file does not start with a JPEG start of image marker")]
+    Format,
+
+    #[error("expected a marker at byte {0}")]
+    ExpectedMarker(usize),
+
+    #[error("the segment at byte {0} is truncated")]
+    Truncated(usize),
+
+    #[error("segments can hold at most 65533 bytes, got {0}")]
+    SegmentTooLarge(usize),
+}
+
+/// A marker segment of a JPEG file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Segment {
+    marker: Marker,
+    data: Vec<u8>,
+}
+
+impl Segment {
+    pub const MAX_DATA: usize = u16::MAX as usize - 2;
+
+    pub fn new(marker: Marker, data: Vec<u8>) -> Result<Self, JpegError> {
+        if data.len() > Self::MAX_DATA {
+            return Err(JpegError::SegmentTooLarge(data.len()));
+        }
+        Ok(Self { marker, data })
+    }
+
+    pub fn marker(&self) -> Marker {
+        self.marker
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// The value of the length field, which includes itself
+    pub fn length(&self) -> u16 {
+        self.data.len() as u16 + 2
+    }
+
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![0xff, self.marker.0];
+        if !self.marker.is_standalone() {
+            bytes.extend_from_slice(&self.length().to_be_bytes());
+            bytes.extend_from_slice(&self.data);
+        }
+        bytes
+    }
+}
+
+/// A JPEG file, split into the marker segments up to and including the
+/// start of scan header. The compressed image data and everything after it
+/// is kept as is.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Jpeg {
+    segments: Vec<Segment>,
+    image_data: Vec<u8>,
+}
+
+impl Jpeg {
+    pub const START_OF_IMAGE: &[u8; 2] = &[0xff, 0xd8];
+
+    pub fn segments(&self) -> &[Segment] {
+        &self.segments
+    }
+
+    /// Returns the segments together with their offset in the file
+    pub fn segments_with_offsets(&self) -> impl Iterator<Item = (usize, &Segment)> {
+        self.segments.iter().scan(2, |offset, segment| {
+            let start = *offset;
+            *offset += segment.as_bytes().len();
+            Some((start, segment))
+        })
+    }
+
+    /// Inserts a segment after the leading `APPn` and `COM` segments, so that
+    /// the `APP0` (JFIF) or `APP1` (Exif) segment stays first
+    pub fn insert_segment(&mut self, segment: Segment) {
+        let position = self
+            .segments
+            .iter()
+            .position(|segment| !segment.marker.is_metadata())
+            .unwrap_or(self.segments.len());
+        self.segments.insert(position, segment);
+    }
+
+    pub fn segment_by_marker(&self, marker: Marker) -> Option<&Segment> {
+        self.segments
+            .iter()
+            .find(|segment| segment.marker == marker)
+    }
+
+    /// Removes the first segment with the given marker
+    pub fn remove_segment(&mut self, marker: Marker) -> Option<Segment> {
+        let position = self
+            .segments
+            .iter()
+            .position(|segment| segment.marker == marker)?;
+        Some(self.segments.remove(position))
+    }
+
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut bytes = Self::START_OF_IMAGE.to_vec();
+        for segment in &self.segments {
+            bytes.extend(segment.as_bytes());
+        }
+        bytes.extend_from_slice(&self.image_data);
+        bytes
+    }
+}
+
+impl TryFrom<&[u8]> for Jpeg {
+    type Error = JpegError;
+
+    fn try_from(bytes: &[u8]) -> Result<Self, Self::Error> {
+        if !bytes.starts_with(Self::START_OF_IMAGE) {
+            return Err(JpegError::Format);
+        }
+
+        let mut segments = Vec::new();
+        let mut cursor = 2;
+        loop {
+            let start = cursor;
+            if bytes.get(cursor) != Some(&0xff) {
+                return Err(JpegError::ExpectedMarker(cursor));
+            }
+            // Any number of 0xFF fill bytes may precede a marker
+            while bytes.get(cursor) == Some(&0xff) {
+                cursor += 1;
+            }
+            let marker = Marker(*bytes.get(cursor).ok_or(JpegError::Truncated(start))?);
+            cursor += 1;
+
+            if marker == Marker::EOI {
+                return Ok(Self {
+                    segments,
+                    image_data: bytes[cursor - 2..].to_vec(),
+                });
+            }
+            if marker.is_standalone() {
+                segments.push(Segment {
+                    marker,
+                    data: Vec::new(),
+                });
+                continue;
+            }
+
+            let length = bytes
+                .get(cursor..cursor + 2)
+                .map(|length| u16::from_be_bytes(length.try_into().unwrap()) as usize)
+                .filter(|length| *length >= 2)
+                .ok_or(JpegError::Truncated(start))?;
+            let data = bytes
+                .get(cursor + 2..cursor + length)
+                .ok_or(JpegError::Truncated(start))?;
+            segments.push(Segment {
+                marker,
+                data: data.to_vec(),
+            });
+            cursor += length;
+
+            if marker == Marker::SOS {
+                return Ok(Self {
+                    segments,
+                    image_data: bytes[cursor..].to_vec(),
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// A minimal JPEG: JFIF header, a comment, a quantization table, the scan
+    /// header, some image data and the end of image marker
+    pub(crate) fn testing_jpeg() -> Vec<u8> {
+        let mut bytes = vec![0xff, 0xd8];
+        bytes.extend_from_slice(&[0xff, 0xe0, 0x00, 0x07, b'J', b'F', b'I', b'F', 0x00]);
+        bytes.extend_from_slice(&[0xff, 0xfe, 0x00, 0x05, b'h', b'e', b'y']);
+        bytes.extend_from_slice(&[0xff, 0xdb, 0x00, 0x04, 0x00, 0x01]);
+        bytes.extend_from_slice(&[0xff, 0xda, 0x00, 0x03, 0x01]);
+        bytes.extend_from_slice(&[0x12, 0xff, 0x00, 0x34, 0xff, 0xd9]);
+        bytes
+    }
+
+    #[test]
+    fn test_marker_from_str() {
+        assert_eq!(Marker::from_str("COM").unwrap(), Marker::COM);
+        assert_eq!(Marker::from_str("app11").unwrap(), Marker(0xeb));
+        assert!(Marker::from_str("APP16").is_err());
+        assert!(Marker::from_str("SOS").is_err());
+    }
+
+    #[test]
+    fn test_marker_display() {
+        assert_eq!(Marker(0xe1).to_string(), "APP1");
+        assert_eq!(Marker(0xc2).to_string(), "SOF2");
+        assert_eq!(Marker(0xc4).to_string(), "DHT");
+        assert_eq!(Marker(0x02).to_string(), "0x02");
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let bytes = testing_jpeg();
+        let jpeg = Jpeg::try_from(&bytes[..]).unwrap();
+        let markers: Vec<String> = jpeg
+            .segments()
+            .iter()
+            .map(|segment| segment.marker().to_string())
+            .collect();
+        assert_eq!(markers, ["APP0", "COM", "DQT", "SOS"]);
+        assert_eq!(jpeg.segment_by_marker(Marker::COM).unwrap().data(), b"hey");
+        assert_eq!(jpeg.as_bytes(), bytes);
+    }
+
+    #[test]
+    fn test_segments_with_offsets() {
+        let bytes = testing_jpeg();
+        let jpeg = Jpeg::try_from(&bytes[..]).unwrap();
+        let offsets: Vec<usize> = jpeg
+            .segments_with_offsets()
+            .map(|(offset, _)| offset)
+            .collect();
+        assert_eq!(offsets, [2, 11, 18, 24]);
+    }
+
+    #[test]
+    fn test_insert_and_remove() {
+        let bytes = testing_jpeg();
+        let mut jpeg = Jpeg::try_from(&bytes[..]).unwrap();
+        jpeg.insert_segment(Segment::new(Marker::app(11), b"secret".to_vec()).unwrap());
+        assert_eq!(jpeg.segments()[2].marker(), Marker::app(11));
+
+        let reparsed = Jpeg::try_from(&jpeg.as_bytes()[..]).unwrap();
+        assert_eq!(reparsed, jpeg);
+
+        let removed = jpeg.remove_segment(Marker::app(11)).unwrap();
+        assert_eq!(removed.data(), b"secret");
+        assert_eq!(jpeg.as_bytes(), bytes);
+        assert_eq!(jpeg.remove_segment(Marker::app(11)), None);
+    }
+
+    #[test]
+    fn test_segment_too_large() {
+        assert_eq!(
+            Segment::new(Marker::COM, vec![0; 70000]),
+            Err(JpegError::SegmentTooLarge(70000))
+        );
+    }
+
+    #[test]
+    fn test_invalid_files() {
+        assert_eq!(Jpeg::try_from(&b"\x89PNG"[..]), Err(JpegError::Format));
+
+        let bytes = testing_jpeg();
+        assert_eq!(Jpeg::try_from(&bytes[..14]), Err(JpegError::Truncated(11)));
+        assert_eq!(
+            Jpeg::try_from(&[0xff, 0xd8, 0x00][..]),
+            Err(JpegError::ExpectedMarker(2))
+        );
+    }
+}
@@ -0,0 +1,317 @@
+use serde::de::IgnoredAny;
+use serde_json::value::RawValue;
+use std::{collections::HashMap, fmt::Display, str::FromStr};
+
+/// One step of a [`Query`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Step {
+    /// The value of a key of an object. Keys made of digits also index
+    /// arrays, as they do in JSON pointers.
+    Key(String),
+    /// An element of an array, counted from the end if negative
+    Index(i64),
+}
+
+impl Display for Step {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Key(key) if is_identifier(key) => write!(f, ".{}", key),
+            Self::Key(key) => write!(f, "[{}]", serde_json::Value::from(key.as_str())),
+            Self::Index(index) => write!(f, "[{}]", index),
+        }
+    }
+}
+
+fn is_identifier(key: &str) -> bool {
+    !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '_' || c == '-')
+}
+
+/// A path into a JSON document, either jq-like (`.build.version`,
+/// `.tags[0]`, `.["a key"]`) or a JSON pointer (`/build/version`)
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Query(Vec<Step>);
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+#[error("invalid query '{query}': {reason}")]
+pub struct InvalidQueryError {
+    query: String,
+    reason: &'static str,
+}
+
+impl FromStr for Query {
+    type Err = InvalidQueryError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = |reason| InvalidQueryError {
+            query: s.to_owned(),
+            reason,
+        };
+        if let Some(pointer) = s.strip_prefix('/') {
+            // RFC 6901, where ~1 stands for / and ~0 for ~
+            let steps = pointer
+                .split('/')
+                .map(|token| Step::Key(token.replace("~1", "/").replace("~0", "~")))
+                .collect();
+            return Ok(Self(steps));
+        }
+        if !s.starts_with('.') && !s.starts_with('[') {
+            return Err(invalid("expected it to start with ., [ or /"));
+        }
+
+        let mut steps = Vec::new();
+        let mut rest = s;
+        while !rest.is_empty() {
+            if let Some(after) = rest.strip_prefix('[') {
+                let (inner, after) = after
+                    .split_once(']')
+                    .ok_or_else(|| invalid("a [ is not closed"))?;
+                steps.push(match inner.parse() {
+                    Ok(index) => Step::Index(index),
+                    Err(_) => Step::Key(
+                        serde_json::from_str(inner)
+                            .map_err(|_| invalid("expected a number or a quoted key in []"))?,
+                    ),
+                });
+                rest = after;
+            } else if let Some(after) = rest.strip_prefix('.') {
+                let end = after.find(['.', '[']).unwrap_or(after.len());
+                let key = &after[..end];
+                if key.starts_with('"') {
+                    return Err(invalid("quoted keys go in brackets, like .[\"a key\"]"));
+                }
+                // A lone . is the whole document, .[0] indexes it
+                if !key.is_empty() {
+                    steps.push(Step::Key(key.to_owned()));
+                } else if !(after.is_empty() || after.starts_with('[')) {
+                    return Err(invalid("expected a key after ."));
+                }
+                rest = &after[end..];
+            } else {
+                return Err(invalid("expected . or ["));
+            }
+        }
+        Ok(Self(steps))
+    }
+}
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum QueryError {
+    #[error("the payload is not valid JSON")]
+    InvalidJson,
+
+    #[error("{0} is not an object")]
+    NotAnObject(String),
+
+    #[error("{0} is not an array")]
+    NotAnArray(String),
+
+    #[error("{0} doesn't exist")]
+    Missing(String),
+}
+
+impl Query {
+    /// The part of `text` the query points to, exactly as written there
+    pub fn find<'a>(&self, text: &'a str) -> Result<&'a str, QueryError> {
+        let mut current: &RawValue =
+            serde_json::from_str(text).map_err(|_| QueryError::InvalidJson)?;
+        let mut path = String::from(".");
+        for step in &self.0 {
+            let parent = path.clone();
+            path = match path.as_str() {
+                "." => step.to_string(),
+                _ => format!("{}{}", path, step),
+            };
+            current = match step {
+                Step::Key(key) => {
+                    let value =
+                        match serde_json::from_str::<HashMap<String, &RawValue>>(current.get()) {
+                            Ok(object) => object.get(key).copied(),
+                            Err(_) => {
+                                let array = key.parse::<usize>().ok().and_then(|index| {
+                                    let array: Vec<&RawValue> =
+                                        serde_json::from_str(current.get()).ok()?;
+                                    Some(array.get(index).copied())
+                                });
+                                array.ok_or(QueryError::NotAnObject(parent))?
+                            }
+                        };
+                    value.ok_or(QueryError::Missing(path.clone()))?
+                }
+                Step::Index(index) => {
+                    let array: Vec<&RawValue> = serde_json::from_str(current.get())
+                        .map_err(|_| QueryError::NotAnArray(parent))?;
+                    let position = match *index {
+                        index if index < 0 => {
+                            array.len().checked_sub(index.unsigned_abs() as usize)
+                        }
+                        index => Some(index as usize),
+                    };
+                    *position
+                        .and_then(|position| array.get(position))
+                        .ok_or(QueryError::Missing(path.clone()))?
+                }
+            };
+        }
+        Ok(current.get())
+    }
+
+    /// Like [`Query::find`], but strings are unquoted and everything else is
+    /// pretty-printed, ready to be output
+    pub fn evaluate(&self, text: &str) -> Result<String, QueryError> {
+        let found = self.find(text)?;
+        Ok(match serde_json::from_str::<String>(found) {
+            Ok(string) => string,
+            Err(_) => pretty(found).unwrap_or_else(|| found.to_owned()),
+        })
+    }
+}
+
+/// Indents `text` by two spaces per level if it is valid JSON. Unlike
+/// parsing and serializing it again, the order of keys and the spelling of
+/// numbers and strings are kept.
+pub fn pretty(text: &str) -> Option<String> {
+    serde_json::from_str::<IgnoredAny>(text).ok()?;
+
+    // Everything outside of strings but whitespace, so the next token is
+    // always the next char
+    let mut tokens = Vec::with_capacity(text.len());
+    let (mut in_string, mut escaped) = (false, false);
+    for c in text.chars() {
+        if in_string {
+            match (escaped, c) {
+                (false, '\\') => escaped = true,
+                (false, '"') => in_string = false,
+                _ => escaped = false,
+            }
+        } else if c == '"' {
+            in_string = true;
+        } else if c.is_whitespace() {
+            continue;
+        }
+        tokens.push((c, in_string || c == '"'));
+    }
+
+    let mut pretty = String::with_capacity(text.len() * 2);
+    let mut depth = 0;
+    let newline = |pretty: &mut String, depth: usize| {
+        pretty.push('\n');
+        pretty.extend(std::iter::repeat_n("  ", depth));
+    };
+    let mut tokens = tokens.into_iter().peekable();
+    while let Some((c, quoted)) = tokens.next() {
+        if quoted {
+            pretty.push(c);
+            continue;
+        }
+        match c {
+            '{' | '[' => {
+                pretty.push(c);
+                let close = if c == '{' { '}' } else { ']' };
+                if tokens.peek() == Some(&(close, false)) {
+                    pretty.push(close);
+                    tokens.next();
+                } else {
+                    depth += 1;
+                    newline(&mut pretty, depth);
+                }
+            }
+            '}' | ']' => {
+                depth -= 1;
+                newline(&mut pretty, depth);
+                pretty.push(c);
+            }
+            ',' => {
+                pretty.push(c);
+                newline(&mut pretty, depth);
+            }
+            ':' => pretty.push_str(": "),
+            _ => pretty.push(c),
+        }
+    }
+    Some(pretty)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pretty() {
+        let text = r#" {"b":[1, 2.50,{}],"a" : {"s":"x, {y}: \"z\""}, "e":[]}"#;
+        assert_eq!(
+            pretty(text).unwrap(),
+            "{\n  \"b\": [\n    1,\n    2.50,\n    {}\n  ],\n  \"a\": {\n    \"s\": \"x, {y}: \\\"z\\\"\"\n  },\n  \"e\": []\n}"
+        );
+        assert_eq!(pretty("42").unwrap(), "42");
+        assert!(pretty("{\"a\":").is_none());
+    }
+
+    const DOCUMENT: &str =
+        r#"{"build": {"version": "1.2.3", "tags": ["a", "b"], "a/b": {"z": 1, "y": 2}}, "n": 7}"#;
+
+    #[test]
+    fn test_parse_query() {
+        let key = |key: &str| Step::Key(key.to_owned());
+        assert_eq!(".".parse(), Ok(Query(vec![])));
+        assert_eq!(
+            ".build.tags[-1]".parse(),
+            Ok(Query(vec![key("build"), key("tags"), Step::Index(-1)]))
+        );
+        assert_eq!(
+            r#".build["a/b"]"#.parse(),
+            Ok(Query(vec![key("build"), key("a/b")]))
+        );
+        assert_eq!(
+            "/build/a~1b/0".parse(),
+            Ok(Query(vec![key("build"), key("a/b"), key("0")]))
+        );
+        for invalid in [
+            "build",
+            ".build[0",
+            ".build[x]",
+            ".build..tags",
+            r#"."build""#,
+        ] {
+            assert!(invalid.parse::<Query>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let evaluate = |query: &str| query.parse::<Query>().unwrap().evaluate(DOCUMENT);
+        assert_eq!(evaluate(".build.version").unwrap(), "1.2.3");
+        assert_eq!(evaluate(".build.tags[-1]").unwrap(), "b");
+        assert_eq!(evaluate(".n").unwrap(), "7");
+        // Objects keep the order of their keys
+        assert_eq!(
+            evaluate(r#".build["a/b"]"#).unwrap(),
+            "{\n  \"z\": 1,\n  \"y\": 2\n}"
+        );
+        assert_eq!(evaluate("/build/a~1b/y").unwrap(), "2");
+        assert_eq!(evaluate("/build/tags/1").unwrap(), "b");
+
+        assert_eq!(
+            evaluate(".build.missing"),
+            Err(QueryError::Missing(".build.missing".to_owned()))
+        );
+        assert_eq!(
+            evaluate(".build.tags[2]"),
+            Err(QueryError::Missing(".build.tags[2]".to_owned()))
+        );
+        assert_eq!(
+            evaluate(".n.x"),
+            Err(QueryError::NotAnObject(".n".to_owned()))
+        );
+        assert_eq!(
+            evaluate(".build[0]"),
+            Err(QueryError::NotAnArray(".build".to_owned()))
+        );
+        assert_eq!(
+            ".a".parse::<Query>().unwrap().evaluate("not json"),
+            Err(QueryError::InvalidJson)
+        );
+    }
+}
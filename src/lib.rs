@@ -0,0 +1,123 @@
+//! Reading and writing PNG chunks, and hiding messages in them.
+//!
+//! Without the default `std` feature only [`chunk`], [`chunk_type`], [`ihdr`]
+//! and [`png`] are available, which need nothing but `alloc`.
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+#[cfg(feature = "std")]
+pub mod advice;
+#[cfg(feature = "std")]
+pub mod ancillary;
+#[cfg(feature = "std")]
+pub mod archive;
+#[cfg(feature = "std")]
+pub mod attest;
+#[cfg(feature = "std")]
+pub mod attributes;
+#[cfg(feature = "std")]
+pub mod audit;
+#[cfg(feature = "std")]
+pub mod audit_log;
+#[cfg(feature = "std")]
+pub mod batch;
+pub mod cancel;
+pub mod chunk;
+pub mod chunk_type;
+#[cfg(feature = "std")]
+pub mod clipboard;
+#[cfg(feature = "std")]
+pub mod color;
+#[cfg(feature = "std")]
+pub mod commands;
+#[cfg(feature = "std")]
+pub mod console;
+#[cfg(feature = "std")]
+pub mod container;
+#[cfg(feature = "std")]
+pub mod date;
+#[cfg(feature = "std")]
+pub mod decodable;
+#[cfg(feature = "std")]
+pub mod dedup;
+#[cfg(feature = "std")]
+pub mod detect;
+#[cfg(feature = "std")]
+pub mod display;
+#[cfg(feature = "std")]
+pub mod filter;
+#[cfg(feature = "std")]
+pub mod fingerprint;
+#[cfg(feature = "std")]
+pub mod gif;
+#[cfg(feature = "std")]
+pub mod history;
+pub mod ihdr;
+#[cfg(feature = "std")]
+pub mod jpeg;
+#[cfg(feature = "std")]
+pub mod json;
+#[cfg(feature = "std")]
+pub mod license;
+#[cfg(feature = "std")]
+pub mod lock;
+#[cfg(feature = "std")]
+pub mod magic;
+#[cfg(feature = "std")]
+pub mod manifest;
+#[cfg(feature = "std")]
+pub mod meta;
+#[cfg(feature = "std")]
+pub mod normalize;
+#[cfg(feature = "std")]
+pub mod optimize;
+#[cfg(feature = "std")]
+pub mod output;
+#[cfg(feature = "std")]
+pub mod padding;
+#[cfg(feature = "std")]
+pub mod palette;
+#[cfg(feature = "std")]
+pub mod payload;
+pub mod png;
+#[cfg(feature = "std")]
+pub mod profile;
+#[cfg(feature = "std")]
+pub mod provenance;
+#[cfg(feature = "std")]
+pub mod raster;
+#[cfg(feature = "std")]
+pub mod report;
+#[cfg(feature = "std")]
+pub mod riff;
+#[cfg(feature = "std")]
+pub mod scan;
+#[cfg(feature = "std")]
+pub mod scatter;
+#[cfg(feature = "std")]
+pub mod select;
+#[cfg(feature = "std")]
+pub mod server;
+#[cfg(feature = "std")]
+pub mod stamp;
+#[cfg(feature = "std")]
+pub mod stream;
+#[cfg(feature = "std")]
+pub mod suggest;
+#[cfg(feature = "std")]
+pub mod template;
+#[cfg(feature = "std")]
+pub mod text;
+#[cfg(feature = "std")]
+pub mod transport;
+#[cfg(feature = "std")]
+pub mod verify;
+#[cfg(feature = "std")]
+pub mod walk;
+#[cfg(feature = "std")]
+pub mod watermark;
+#[cfg(feature = "std")]
+pub mod writer;
+#[cfg(feature = "std")]
+pub mod xmp;
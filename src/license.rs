@@ -0,0 +1,171 @@
+use crate::{
+    png::Png,
+    text::{find_text, set_text, TextChunk, TextError},
+    xmp::{escape_xml, XMP_KEYWORD},
+};
+
+/// The text keyword the SPDX license expression is stored under
+pub const LICENSE_KEYWORD: &str = "License";
+
+/// Licensing information of an image
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct License {
+    /// An SPDX license expression, e.g. `CC-BY-4.0` or `MIT OR Apache-2.0`
+    pub spdx: String,
+    pub author: Option<String>,
+    /// The copyright notice, generated from the other fields if not set
+    pub copyright: Option<String>,
+}
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum LicenseError {
+    #[error("'{0}' is not a valid SPDX license expression")]
+    InvalidExpression(String),
+
+    #[error(transparent)]
+    Text(#[from] TextError),
+}
+
+/// Checks the syntax (not the license list) of an SPDX license expression
+fn is_valid_expression(expression: &str) -> bool {
+    let tokens: Vec<&str> = expression
+        .split(|c: char| c.is_whitespace() || c == '(' || c == ')')
+        .filter(|token| !token.is_empty())
+        .collect();
+
+    !tokens.is_empty()
+        && tokens.iter().all(|token| {
+            token
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "-.+:".contains(c))
+        })
+        && expression.matches('(').count() == expression.matches(')').count()
+}
+
+impl License {
+    pub fn new(spdx: &str, author: Option<&str>) -> Result<Self, LicenseError> {
+        if !is_valid_expression(spdx) {
+            return Err(LicenseError::InvalidExpression(spdx.to_owned()));
+        }
+        Ok(Self {
+            spdx: spdx.to_owned(),
+            author: author.map(str::to_owned),
+            copyright: None,
+        })
+    }
+
+    pub fn copyright_notice(&self) -> String {
+        match (&self.copyright, &self.author) {
+            (Some(copyright), _) => copyright.clone(),
+            (None, Some(author)) => format!("Copyright {}. Licensed under {}.", author, self.spdx),
+            (None, None) => format!("Licensed under {}.", self.spdx),
+        }
+    }
+
+    /// Stores the license in the `Author`, `Copyright` and `License` text
+    /// chunks, and optionally as an XMP packet
+    pub fn write(&self, png: &mut Png, xmp: bool) -> Result<(), LicenseError> {
+        if let Some(author) = &self.author {
+            set_text(png, &TextChunk::best_fit("Author", author))?;
+        }
+        set_text(
+            png,
+            &TextChunk::best_fit("Copyright", &self.copyright_notice()),
+        )?;
+        set_text(png, &TextChunk::best_fit(LICENSE_KEYWORD, &self.spdx))?;
+
+        if xmp {
+            set_text(
+                png,
+                &TextChunk::international(XMP_KEYWORD, &self.xmp_packet()),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Reads the license from the text chunks of a PNG
+    pub fn read(png: &Png) -> Option<Self> {
+        let spdx = find_text(png, LICENSE_KEYWORD)?.text;
+        Some(Self {
+            spdx,
+            author: find_text(png, "Author").map(|text| text.text),
+            copyright: find_text(png, "Copyright").map(|text| text.text),
+        })
+    }
+
+    /// Builds an XMP packet using the Dublin Core and XMP rights schemas
+    pub fn xmp_packet(&self) -> String {
+        let creator = match &self.author {
+            Some(author) => format!(
+                "\n   <dc:creator><rdf:Seq><rdf:li>{}</rdf:li></rdf:Seq></dc:creator>",
+                escape_xml(author)
+            ),
+            None => String::new(),
+        };
+
+        format!(
+            r#"<?xpacket begin="{bom}" id="W5M0MpCehiHzreSzNTczkc9d"?>
+<x:xmpmeta xmlns:x="adobe:ns:meta/">
+ <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+  <rdf:Description rdf:about=""
+    xmlns:dc="http://purl.org/dc/elements/1.1/"
+    xmlns:xmpRights="http://ns.adobe.com/xap/1.0/rights/">{creator}
+   <dc:rights><rdf:Alt><rdf:li xml:lang="x-default">{rights}</rdf:li></rdf:Alt></dc:rights>
+   <xmpRights:Marked>True</xmpRights:Marked>
+   <xmpRights:UsageTerms><rdf:Alt><rdf:li xml:lang="x-default">{license}</rdf:li></rdf:Alt></xmpRights:UsageTerms>
+  </rdf:Description>
+ </rdf:RDF>
+</x:xmpmeta>
+<?xpacket end="w"?>"#,
+            bom = '\u{feff}',
+            creator = creator,
+            rights = escape_xml(&self.copyright_notice()),
+            license = escape_xml(&self.spdx),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::png::tests::PNG_FILE;
+
+    #[test]
+    fn test_spdx_expressions() {
+        assert!(License::new("CC-BY-4.0", None).is_ok());
+        assert!(License::new("MIT OR (Apache-2.0 WITH LLVM-exception)", None).is_ok());
+        assert!(License::new("LicenseRef-my-license", None).is_ok());
+        assert_eq!(
+            License::new("not/a license", None),
+            Err(LicenseError::InvalidExpression("not/a license".to_owned()))
+        );
+        assert!(License::new("(MIT", None).is_err());
+        assert!(License::new("", None).is_err());
+    }
+
+    #[test]
+    fn test_write_and_read() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let license = License::new("CC-BY-4.0", Some("Jane Doe")).unwrap();
+        license.write(&mut png, true).unwrap();
+
+        let read = License::read(&png).unwrap();
+        assert_eq!(read.spdx, "CC-BY-4.0");
+        assert_eq!(read.author.as_deref(), Some("Jane Doe"));
+        assert_eq!(
+            read.copyright.as_deref(),
+            Some("Copyright Jane Doe. Licensed under CC-BY-4.0.")
+        );
+
+        let xmp = find_text(&png, XMP_KEYWORD).unwrap();
+        assert!(xmp.text.contains("<rdf:li>Jane Doe</rdf:li>"));
+    }
+
+    #[test]
+    fn test_xmp_is_escaped() {
+        let license = License::new("MIT", Some("Tom & Jerry <cartoons>")).unwrap();
+        assert!(license
+            .xmp_packet()
+            .contains("Tom &amp; Jerry &lt;cartoons&gt;"));
+    }
+}
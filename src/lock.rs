@@ -0,0 +1,105 @@
+use crate::cancel::{CancellationToken, Cancelled};
+use std::{fs::File, io, path::Path, thread, time::Duration};
+
+/// How long to wait between attempts while another process holds the lock
+const RETRY_INTERVAL: Duration = Duration::from_millis(50);
+
+#[derive(Debug, thiserror::Error)]
+pub enum LockError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+
+    #[error("gave up waiting for another process to release the file")]
+    Cancelled(#[from] Cancelled),
+}
+
+/// Whether other processes may hold the lock at the same time
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockMode {
+    /// For reading the file, which other readers can do at the same time
+    Shared,
+    /// For modifying the file, which nobody else may read or write meanwhile
+    Exclusive,
+}
+
+/// An advisory lock on a file, released when dropped. Only other pngme
+/// processes (and programs which lock the file as well) are kept out.
+#[derive(Debug)]
+pub struct FileLock {
+    _file: Option<File>,
+}
+
+impl FileLock {
+    /// Locks the file at `path`, waiting for other processes to release
+    /// their locks until `cancel` is cancelled. `on_wait` is called once if
+    /// the lock isn't available right away.
+    ///
+    /// Locks on Windows are mandatory and would keep pngme itself from
+    /// writing the file, so files are only locked on Unix.
+    pub fn acquire<P: AsRef<Path>>(
+        path: P,
+        mode: LockMode,
+        cancel: &CancellationToken,
+        on_wait: impl FnOnce(),
+    ) -> Result<Self, LockError> {
+        if cfg!(not(unix)) {
+            return Ok(Self { _file: None });
+        }
+        let file = File::open(path)?;
+        let mut on_wait = Some(on_wait);
+        loop {
+            let attempt = match mode {
+                LockMode::Shared => file.try_lock_shared(),
+                LockMode::Exclusive => file.try_lock(),
+            };
+            match attempt {
+                Ok(()) => return Ok(Self { _file: Some(file) }),
+                Err(std::fs::TryLockError::WouldBlock) => {}
+                Err(std::fs::TryLockError::Error(err)) => return Err(err.into()),
+            }
+            if let Some(on_wait) = on_wait.take() {
+                on_wait();
+            }
+            cancel.check()?;
+            thread::sleep(RETRY_INTERVAL);
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::{cell::Cell, fs};
+
+    #[test]
+    fn test_lock() {
+        let path = std::env::temp_dir().join(format!("pngme-lock-{}", std::process::id()));
+        fs::write(&path, b"data").unwrap();
+        let waited = Cell::new(false);
+        let wait = || waited.set(true);
+        let cancel = CancellationToken::new();
+
+        let shared = FileLock::acquire(&path, LockMode::Shared, &cancel, wait).unwrap();
+        FileLock::acquire(&path, LockMode::Shared, &cancel, wait).unwrap();
+        assert!(!waited.get());
+
+        // A writer waits for the readers until it gives up
+        let timeout = CancellationToken::with_timeout(Duration::from_millis(100));
+        let result = FileLock::acquire(&path, LockMode::Exclusive, &timeout, wait);
+        assert!(matches!(result, Err(LockError::Cancelled(_))));
+        assert!(waited.get());
+
+        drop(shared);
+        let exclusive = FileLock::acquire(&path, LockMode::Exclusive, &cancel, || {}).unwrap();
+        let result = FileLock::acquire(&path, LockMode::Shared, &timeout, || {});
+        assert!(matches!(result, Err(LockError::Cancelled(_))));
+        drop(exclusive);
+        FileLock::acquire(&path, LockMode::Shared, &cancel, || {}).unwrap();
+
+        fs::remove_file(&path).unwrap();
+        assert!(matches!(
+            FileLock::acquire(&path, LockMode::Shared, &cancel, || {}),
+            Err(LockError::Io(_))
+        ));
+    }
+}
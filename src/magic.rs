@@ -0,0 +1,113 @@
+use crate::{container::Container, jpeg::Jpeg, png::Png, riff::FourCc};
+use std::fmt::{self, Display};
+
+/// How far into a text file to look for the root element
+const TEXT_SNIFF_LEN: usize = 1024;
+
+/// A file format recognized from its first bytes: the formats pngme can read,
+/// and others commonly mistaken for PNGs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Png,
+    Jpeg,
+    Gif,
+    Webp,
+    Bmp,
+    Tiff,
+    Svg,
+    Html,
+}
+
+impl Format {
+    pub fn detect(bytes: &[u8]) -> Option<Self> {
+        if Png::is_png(bytes) {
+            Some(Self::Png)
+        } else if bytes.starts_with(Jpeg::START_OF_IMAGE) {
+            Some(Self::Jpeg)
+        } else if bytes.starts_with(b"GIF87a") || bytes.starts_with(b"GIF89a") {
+            Some(Self::Gif)
+        } else if bytes.starts_with(&FourCc::RIFF.0) && bytes.get(8..12) == Some(&FourCc::WEBP.0) {
+            Some(Self::Webp)
+        } else if bytes.starts_with(b"BM") {
+            Some(Self::Bmp)
+        } else if bytes.starts_with(b"II*\0") || bytes.starts_with(b"MM\0*") {
+            Some(Self::Tiff)
+        } else {
+            detect_markup(bytes)
+        }
+    }
+
+    /// The backend which can read files of this format
+    pub fn container(self) -> Option<Container> {
+        match self {
+            Self::Png => Some(Container::Png),
+            Self::Jpeg => Some(Container::Jpeg),
+            Self::Gif => Some(Container::Gif),
+            Self::Webp => Some(Container::Webp),
+            Self::Bmp | Self::Tiff | Self::Svg | Self::Html => None,
+        }
+    }
+}
+
+/// Recognizes SVG images and HTML pages, e.g. an error page saved instead of
+/// the image it was supposed to be
+fn detect_markup(bytes: &[u8]) -> Option<Format> {
+    let bytes = bytes.strip_prefix(b"\xef\xbb\xbf").unwrap_or(bytes);
+    let start = bytes.iter().position(|byte| !byte.is_ascii_whitespace())?;
+    let text = &bytes[start..bytes.len().min(start + TEXT_SNIFF_LEN)];
+    if text.first() != Some(&b'<') {
+        return None;
+    }
+
+    let text = text.to_ascii_lowercase();
+    let contains = |needle: &[u8]| text.windows(needle.len()).any(|window| window == needle);
+    if contains(b"<svg") {
+        Some(Format::Svg)
+    } else if contains(b"<!doctype html") || contains(b"<html") {
+        Some(Format::Html)
+    } else {
+        None
+    }
+}
+
+impl Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Png => "PNG",
+            Self::Jpeg => "JPEG",
+            Self::Gif => "GIF",
+            Self::Webp => "WebP",
+            Self::Bmp => "BMP",
+            Self::Tiff => "TIFF",
+            Self::Svg => "SVG",
+            Self::Html => "HTML",
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::png::tests::PNG_FILE;
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(Format::detect(&PNG_FILE), Some(Format::Png));
+        assert_eq!(Format::detect(b"\xff\xd8\xff\xe0"), Some(Format::Jpeg));
+        assert_eq!(Format::detect(b"GIF89a"), Some(Format::Gif));
+        assert_eq!(Format::detect(b"RIFF\0\0\0\0WEBPVP8 "), Some(Format::Webp));
+        assert_eq!(Format::detect(b"BM\x36\0\0\0"), Some(Format::Bmp));
+        assert_eq!(Format::detect(b"MM\0*\0\0\0\x08"), Some(Format::Tiff));
+        assert_eq!(
+            Format::detect(b"\xef\xbb\xbf<?xml version=\"1.0\"?>\n<svg xmlns=\"\">"),
+            Some(Format::Svg)
+        );
+        assert_eq!(
+            Format::detect(b"\n  <!DOCTYPE html><title>404</title>"),
+            Some(Format::Html)
+        );
+        assert_eq!(Format::detect(b"<?xml version=\"1.0\"?><feed/>"), None);
+        assert_eq!(Format::detect(b"hello"), None);
+        assert_eq!(Format::detect(b""), None);
+    }
+}
@@ -1,46 +1,1881 @@
-use std::path::PathBuf;
+use std::{
+    ffi::OsString,
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
 
-use chunk_type::ChunkType;
-use clap::{Parser, Subcommand};
-
-mod chunk;
-mod chunk_type;
-mod commands;
-mod png;
+use clap::{
+    ArgGroup, ArgMatches, Args as ClapArgs, Command, CommandFactory, FromArgMatches, Parser,
+    Subcommand,
+};
+use pngme::{
+    ancillary::{Ancillary, Calibration, Equation, OffsetUnit, ScaleUnit},
+    attest::{self, VerifyingKey},
+    attributes::Preserve,
+    audit::Rule,
+    cancel::CancellationToken,
+    chunk_type::{self, ChunkType},
+    color::RenderingIntent,
+    commands::{
+        self, BatchOptions, DecodeOptions, Destination, EnvelopeOptions, GlobalOptions, Message,
+        PayloadFormat,
+    },
+    console::{self, ColorChoice, Paging},
+    container::{self, Container},
+    date,
+    display::{DataFormat, DisplayOptions},
+    json::Query,
+    license::License,
+    lock::LockMode,
+    output::OutputFormat,
+    padding::Padding,
+    palette::PaletteFormat,
+    payload::ContentType,
+    report::ReportFormat,
+    select::{Selection, SortKey, TypePattern},
+    server::{self, ServeOptions},
+    stamp::Stamp,
+    text::TextChunk,
+    transport::Transport,
+    walk::{ScanOptions, SymlinkPolicy},
+    watermark,
+};
 
 #[derive(Parser)]
 struct Args {
     path: PathBuf,
+    /// Abort if the operation takes longer than this many seconds
+    #[arg(long, global = true, value_name = "SECONDS")]
+    timeout: Option<u64>,
+    /// Drop timestamps and sort chunks so written files only depend on
+    /// their content
+    #[arg(long, global = true)]
+    deterministic: bool,
+    /// The format of the file (png, jpeg, webp or gif), detected from its contents by
+    /// default
+    #[arg(long, global = true, value_name = "FORMAT")]
+    container: Option<Container>,
+    /// The image to operate on when PATH is a zip or tar archive (.tar.gz
+    /// included), needed if it contains more than one file
+    #[arg(long, global = true, value_name = "PATH")]
+    entry: Option<String>,
+    /// Decode PNGs with the png crate before writing them, and refuse to
+    /// write images it rejects (needs the check-decodable feature)
+    #[arg(long, global = true)]
+    check_decodable: bool,
+    /// Don't lock the file while the command runs. By default commands
+    /// wait for other pngme processes modifying the file to finish, and
+    /// keep them out while modifying it themselves.
+    #[arg(long, global = true)]
+    no_lock: bool,
+    /// Don't copy the extended attributes of the input file (e.g. Finder
+    /// tags) to the file written with --output
+    #[arg(long, global = true)]
+    no_xattrs: bool,
+    /// Don't copy the owner, group and permissions of the input file to the
+    /// file written with --output
+    #[arg(long, global = true)]
+    no_owner: bool,
+    /// Append a line of JSON to this file for every file a command
+    /// modifies, with the time, the operation, the chunks added and removed
+    /// and the SHA-256 of the file before and after
+    #[arg(long, global = true, value_name = "PATH")]
+    audit_log: Option<PathBuf>,
+    /// Sign a record of every edit with the Ed25519 key in this file and
+    /// store it in the PNG, see the attest command
+    #[arg(long, global = true, value_name = "KEYFILE")]
+    attest: Option<PathBuf>,
+    /// How the payload given as MESSAGE or on stdin, and the file read from
+    /// stdin when PATH is -, are encoded: raw or base64
+    #[arg(long, global = true, value_name = "FORMAT", default_value = "raw")]
+    stdin_format: Transport,
+    /// How a PNG written to stdout (-) and a printed payload are encoded:
+    /// raw or base64
+    #[arg(long, global = true, value_name = "FORMAT", default_value = "raw")]
+    stdout_format: Transport,
+    /// Record the chunks the command adds and removes in the PNG, so it can
+    /// be undone with the undo command
+    #[arg(long, global = true, conflicts_with = "deterministic")]
+    history: bool,
+    /// Print data from the file as is, including control characters which
+    /// the terminal would interpret. By default they are escaped as \xNN.
+    #[arg(long, global = true)]
+    raw: bool,
+    /// Don't print which file was written and what changed after commands
+    /// which modify a file
+    #[arg(short, long, global = true)]
+    quiet: bool,
+    /// Color the output: auto (if it goes to a terminal and NO_COLOR isn't
+    /// set), always or never
+    #[arg(long, global = true, value_name = "WHEN", default_value = "auto")]
+    color: ColorChoice,
+    #[command(flatten)]
+    batch: BatchArgs,
     #[command(subcommand)]
     command: PngMeCommand,
 }
 
-#[derive(Subcommand)]
+/// Options for batch mode, which is used when PATH is a directory
+#[derive(Clone, ClapArgs)]
+#[command(next_help_heading = "Batch mode (PATH is a directory)")]
+struct BatchArgs {
+    /// Stop at the first file the command fails on
+    #[arg(long, global = true)]
+    fail_fast: bool,
+    /// Process this many files at the same time
+    #[arg(long, global = true, value_name = "N", default_value = "1")]
+    jobs: NonZeroUsize,
+    /// Also process the files in subdirectories
+    #[arg(long, global = true)]
+    recursive: bool,
+    /// Don't look deeper than this many levels, 1 being the directory itself
+    #[arg(long, global = true, value_name = "N", requires = "recursive")]
+    max_depth: Option<usize>,
+    /// Leave out paths matching this .gitignore-style pattern, relative to
+    /// the directory. Files listed in .pngmeignore files are left out too.
+    #[arg(long, global = true, value_name = "PATTERN")]
+    exclude: Vec<String>,
+    /// What to do with symbolic links: skip or follow
+    #[arg(long, global = true, value_name = "POLICY", default_value = "skip")]
+    symlinks: SymlinkPolicy,
+    /// Also write the outcome of every file to this JSON file
+    #[arg(long, global = true, value_name = "PATH")]
+    batch_report: Option<PathBuf>,
+}
+
+#[derive(Clone, Subcommand)]
 enum PngMeCommand {
+    #[command(visible_alias = "add")]
+    #[command(group(ArgGroup::new("target").required(true).multiple(true).args(["output", "in_place", "spread"])))]
     Encode {
-        chunk_type: ChunkType,
-        message: String,
+        /// The chunk type, the segment marker (COM, APP0 to APP15) for JPEG
+        /// files, the FOURCC for WebP files or the application id (e.g.
+        /// PNGMEPNG1.0) for GIF files
+        #[arg(value_parser = parse_chunk_type)]
+        chunk_type: String,
+        /// The message to store, - to read it from stdin
+        #[arg(
+            required_unless_present_any = ["file", "from_clipboard"],
+            conflicts_with_all = ["file", "from_clipboard"]
+        )]
+        message: Option<String>,
+        /// Where to write the modified file, - for stdout
+        #[arg(short, long, value_name = "PATH")]
         output: Option<PathBuf>,
+        /// Modify PATH itself
+        #[arg(long, conflicts_with = "output")]
+        in_place: bool,
+        /// Store the contents of this file instead of a message, - for
+        /// stdin
+        #[arg(long, value_name = "PATH", conflicts_with = "from_clipboard")]
+        file: Option<PathBuf>,
+        /// Store the text on the clipboard instead of a message (needs the
+        /// clipboard feature)
+        #[arg(long)]
+        from_clipboard: bool,
+        /// Split the payload across PATH and these files, proportionally to
+        /// their size. The files are modified in place.
+        #[arg(long, num_args = 1.., value_name = "PATH")]
+        spread: Vec<PathBuf>,
+        /// Store the payload as is, without its SHA-256
+        #[arg(long)]
+        no_integrity: bool,
+        /// Record that the payload expires on this date (e.g. 2025-12-31)
+        /// or at this UTC time (e.g. 2025-12-31T18:00:00Z)
+        #[arg(long, value_name = "DATE", value_parser = parse_date, conflicts_with = "no_integrity")]
+        expires: Option<i64>,
+        /// Record what kind of data the payload is (e.g. application/json),
+        /// so decode can pretty-print or decompress it
+        #[arg(long, value_name = "TYPE", conflicts_with = "no_integrity")]
+        content_type: Option<ContentType>,
+        /// Record how the payload was stored (not its contents) in a
+        /// manifest chunk, see the info command
+        #[arg(long)]
+        manifest: bool,
+        /// Don't modify PATH in place if a chunk of that type already holds
+        /// the same payload, so it keeps its modification time. A file
+        /// given with --output is always written.
+        #[arg(long, conflicts_with = "spread")]
+        if_changed: bool,
+        /// Split the payload into small chunks of types other software
+        /// writes, placed by this key, instead of one chunk of CHUNK_TYPE
+        /// (PNG only)
+        #[arg(long, value_name = "KEY", conflicts_with_all = ["spread", "manifest", "if_changed"])]
+        scatter: Option<String>,
+        /// Pad the file to exactly this many bytes with a discardable chunk,
+        /// so carriers of different payloads have the same size (PNG only)
+        #[arg(long, value_name = "SIZE", conflicts_with_all = ["spread", "if_changed"])]
+        pad_to: Option<usize>,
+        /// Add a discardable chunk with a random number of bytes in this
+        /// range, e.g. 1000..5000 (PNG only)
+        #[arg(
+            long,
+            value_name = "N..M",
+            value_parser = Padding::random_range,
+            conflicts_with_all = ["spread", "if_changed", "pad_to"]
+        )]
+        pad_random: Option<Padding>,
+        /// Don't warn when the payload looks like a key, token, password or
+        /// card number
+        #[arg(long)]
+        no_advice: bool,
     },
+    #[command(visible_alias = "cat")]
     Decode {
-        chunk_type: ChunkType,
+        #[arg(value_parser = parse_chunk_type)]
+        chunk_type: String,
+        /// Reassemble a payload spread across PATH and these files
+        #[arg(long, num_args = 1.., value_name = "PATH")]
+        gather: Vec<PathBuf>,
+        /// Write the payload to this file instead of printing it
+        #[arg(short, long, value_name = "PATH")]
+        output: Option<PathBuf>,
+        /// Copy the payload to the clipboard instead of printing it (needs
+        /// the clipboard feature)
+        #[arg(long, conflicts_with = "output")]
+        to_clipboard: bool,
+        /// Output the payload as base64, for binary payloads
+        #[arg(long, conflicts_with = "output")]
+        base64: bool,
+        /// Output the payload as hex, for binary payloads
+        #[arg(long, conflicts_with_all = ["output", "base64"])]
+        hex: bool,
+        /// Print the payload as text with control characters and invalid
+        /// UTF-8 escaped as \xNN
+        #[arg(long, conflicts_with_all = ["output", "base64", "hex"])]
+        escape: bool,
+        /// Don't check the SHA-256 stored with the payload
+        #[arg(long)]
+        no_integrity: bool,
+        /// Refuse to output expired payloads instead of warning about them
+        #[arg(long)]
+        enforce_expiry: bool,
+        /// Only print the content type, expiry and size of the payload
+        #[arg(long)]
+        show_type: bool,
+        /// Output the payload as stored, without pretty-printing or
+        /// decompressing it according to its content type
+        #[arg(long)]
+        as_stored: bool,
+        /// Only output this part of a JSON payload, e.g. .build.version,
+        /// .tags[0] or the JSON pointer /build/version. Strings are output
+        /// without quotes.
+        #[arg(long, conflicts_with_all = ["show_type", "base64", "hex", "escape"])]
+        query: Option<Query>,
+        /// Reassemble a payload scattered with this key by encode --scatter
+        #[arg(long, value_name = "KEY", conflicts_with = "gather")]
+        scatter: Option<String>,
     },
+    #[command(visible_alias = "rm")]
+    #[command(group(ArgGroup::new("target").required(true).args(["output", "in_place"])))]
     Remove {
-        chunk_type: ChunkType,
+        #[arg(value_parser = parse_chunk_type)]
+        chunk_type: String,
+        /// Where to write the modified file, - for stdout
+        #[arg(short, long, value_name = "PATH")]
+        output: Option<PathBuf>,
+        /// Modify PATH itself
+        #[arg(long)]
+        in_place: bool,
+        /// Remove the fragments of a payload scattered with this key
+        #[arg(long, value_name = "KEY")]
+        scatter: Option<String>,
+    },
+    /// Show how the payloads recorded in the manifest were stored and how
+    /// to decode them
+    Info {
+        /// Also list every chunk with its contents decoded, e.g. the
+        /// background color or the significant bits
+        #[arg(long)]
+        all: bool,
+        /// Print sizes as exact byte counts instead of e.g. 1.5 KiB
+        #[arg(long)]
+        bytes: bool,
+    },
+    /// Compute the CRC of a chunk with this type and the contents of PATH
+    /// as data, or check the CRC of a chunk of the PNG at PATH
+    Crc {
+        #[arg(required_unless_present = "check")]
+        chunk_type: Option<String>,
+        /// Check the stored CRC of the chunk with this index, as shown by
+        /// list
+        #[arg(long, value_name = "INDEX", conflicts_with = "chunk_type")]
+        check: Option<usize>,
+    },
+    /// Show the operations recorded with --history
+    History {
+        #[command(subcommand)]
+        command: HistoryCommand,
+    },
+    /// Revert the last operation recorded with --history
+    #[command(group(ArgGroup::new("target").required(true).args(["output", "in_place"])))]
+    Undo {
+        /// Where to write the modified file, - for stdout
+        #[arg(short, long, value_name = "PATH")]
+        output: Option<PathBuf>,
+        /// Modify PATH itself
+        #[arg(long)]
+        in_place: bool,
+    },
+    /// Manage the keys of --attest and verify the signed records of edits
+    Attest {
+        #[command(subcommand)]
+        command: AttestCommand,
+    },
+    /// Remove a chunk and write its data to a file
+    #[command(group(ArgGroup::new("target").required(true).args(["output", "in_place"])))]
+    Extract {
+        #[arg(value_parser = parse_chunk_type)]
+        chunk_type: String,
+        /// Where to write the chunk's data
+        data_path: PathBuf,
+        /// Where to write the modified file, - for stdout
+        #[arg(short, long, value_name = "PATH")]
+        output: Option<PathBuf>,
+        /// Modify PATH itself
+        #[arg(long)]
+        in_place: bool,
+    },
+    Print {
+        #[command(flatten)]
+        display: DisplayArgs,
+        #[command(flatten)]
+        selection: SelectionArgs,
+        /// Print the chunks as json, csv, yaml or using a template instead,
+        /// see `list`
+        #[arg(long, value_name = "FORMAT")]
+        format: Option<OutputFormat>,
+    },
+    /// Print one line per chunk
+    #[command(visible_alias = "ls")]
+    List {
+        #[command(flatten)]
+        display: DisplayArgs,
+        #[command(flatten)]
+        selection: SelectionArgs,
+        /// Either table (the default), json, csv, yaml or a line template
+        /// with the placeholders {index}, {offset}, {type}, {length},
+        /// {crc}, {data} and {name}. Numbers can be printed as hex using
+        /// e.g. {crc:x} and as sizes like 1.5 KiB using {length:h}.
+        #[arg(long, value_name = "FORMAT")]
+        format: Option<OutputFormat>,
+    },
+    /// Put the chunks into an explicit order, given as a comma separated
+    /// list of their current indices, e.g. 0,2,1,3
+    #[command(group(ArgGroup::new("target").required(true).args(["output", "in_place"])))]
+    Reorder {
+        #[arg(value_name = "INDEX,...", value_parser = parse_order)]
+        order: ChunkOrder,
+        /// Where to write the modified file, - for stdout
+        #[arg(short, long, value_name = "PATH")]
+        output: Option<PathBuf>,
+        /// Modify PATH itself
+        #[arg(long)]
+        in_place: bool,
+    },
+    /// Reorder the chunks into a canonical order and drop duplicates
+    #[command(group(ArgGroup::new("target").required(true).args(["output", "in_place"])))]
+    Normalize {
+        /// Where to write the modified file, - for stdout
+        #[arg(short, long, value_name = "PATH")]
+        output: Option<PathBuf>,
+        /// Modify PATH itself
+        #[arg(long)]
+        in_place: bool,
+    },
+    /// Shrink the PNG without changing the image: drop redundant chunks and
+    /// trailing data and merge the IDAT chunks
+    #[command(group(ArgGroup::new("target").required(true).args(["output", "in_place"])))]
+    Optimize {
+        /// Also recompress the image data at the highest zlib level
+        #[arg(long)]
+        recompress: bool,
+        /// Where to write the modified file, - for stdout
+        #[arg(short, long, value_name = "PATH")]
+        output: Option<PathBuf>,
+        /// Modify PATH itself
+        #[arg(long)]
+        in_place: bool,
+    },
+    /// Rewrite the image data interlaced with Adam7, or without interlacing
+    /// with --off, keeping the pixels unchanged
+    #[command(group(ArgGroup::new("target").required(true).args(["output", "in_place"])))]
+    Interlace {
+        /// Store the image data without interlacing
+        #[arg(long)]
+        off: bool,
+        /// Where to write the modified file, - for stdout
+        #[arg(short, long, value_name = "PATH")]
+        output: Option<PathBuf>,
+        /// Modify PATH itself
+        #[arg(long)]
+        in_place: bool,
+    },
+    /// Show or optimize the filter types of the scanlines
+    Filters {
+        #[command(subcommand)]
+        command: FiltersCommand,
+    },
+    /// Embed or detect an invisible watermark which survives re-encoding
+    /// and mild edits
+    Watermark {
+        #[command(subcommand)]
+        command: WatermarkCommand,
+    },
+    /// Mark a copy with a per-recipient fingerprint, or find out whose copy
+    /// a leaked file is
+    Fingerprint {
+        #[command(subcommand)]
+        command: FingerprintCommand,
+    },
+    /// Declare the image as sRGB, replacing conflicting color management
+    /// chunks
+    #[command(group(ArgGroup::new("target").required(true).args(["output", "in_place"])))]
+    FixColor {
+        /// The rendering intent: perceptual, relative, saturation or absolute
+        #[arg(long, value_name = "INTENT", default_value = "perceptual")]
+        intent: RenderingIntent,
+        /// Where to write the modified file, - for stdout
+        #[arg(short, long, value_name = "PATH")]
+        output: Option<PathBuf>,
+        /// Modify PATH itself
+        #[arg(long)]
+        in_place: bool,
+    },
+    /// Compress large tEXt chunks into zTXt chunks where that saves space
+    #[command(group(ArgGroup::new("target").required(true).args(["output", "in_place"])))]
+    OptimizeText {
+        /// Only compress chunks with at least this many bytes of data
+        #[arg(long, value_name = "BYTES", default_value_t = 1024)]
+        min_size: usize,
+        /// Where to write the modified file, - for stdout
+        #[arg(short, long, value_name = "PATH")]
+        output: Option<PathBuf>,
+        /// Modify PATH itself
+        #[arg(long)]
+        in_place: bool,
+    },
+    /// Convert zTXt chunks into tEXt chunks, for tools that can't read
+    /// compressed text
+    #[command(group(ArgGroup::new("target").required(true).args(["output", "in_place"])))]
+    DecompressText {
+        /// Where to write the modified file, - for stdout
+        #[arg(short, long, value_name = "PATH")]
+        output: Option<PathBuf>,
+        /// Modify PATH itself
+        #[arg(long)]
+        in_place: bool,
+    },
+    /// Embed build metadata into the PNG, or show it
+    Stamp {
+        /// Print the stamp of the PNG instead of writing one
+        #[arg(long, conflicts_with_all = ["version", "commit", "timestamp", "now", "values", "from_json", "output", "in_place"])]
+        show: bool,
+        #[arg(long)]
+        version: Option<String>,
+        #[arg(long)]
+        commit: Option<String>,
+        #[arg(long)]
+        timestamp: Option<String>,
+        /// Use the current time (or SOURCE_DATE_EPOCH) as timestamp
+        #[arg(long, conflicts_with = "timestamp")]
+        now: bool,
+        /// Any other value to record
+        #[arg(long = "set", value_name = "KEY=VALUE", value_parser = parse_key_value)]
+        values: Vec<(String, String)>,
+        /// Read values from a flat JSON object, flags take precedence
+        #[arg(long, value_name = "FILE")]
+        from_json: Option<PathBuf>,
+        /// Where to write the modified file, - for stdout
+        #[arg(short, long, value_name = "PATH", required_unless_present_any = ["show", "in_place"])]
+        output: Option<PathBuf>,
+        /// Modify PATH itself
+        #[arg(long, conflicts_with = "output")]
+        in_place: bool,
+    },
+    /// Tag the PNG with license and copyright information
+    License {
+        #[command(subcommand)]
+        command: LicenseCommand,
+    },
+    /// Read and write tEXt, zTXt and iTXt chunks
+    Text {
+        #[command(subcommand)]
+        command: TextCommand,
+    },
+    /// Read and write metadata by keyword, whichever chunk holds it
+    Meta {
+        #[command(subcommand)]
+        command: MetaCommand,
+    },
+    /// Read and write the XMP metadata packet
+    Xmp {
+        #[command(subcommand)]
+        command: XmpCommand,
+    },
+    /// Read and write the oFFs, sCAL and pCAL chunks of scientific and
+    /// print tooling
+    Ext {
+        #[command(subcommand)]
+        command: ExtCommand,
+    },
+    /// Inspect or remove C2PA content credentials
+    Provenance {
+        #[command(subcommand)]
+        command: ProvenanceCommand,
+    },
+    /// Inspect the PLTE and tRNS chunks
+    Palette {
+        #[command(subcommand)]
+        command: PaletteCommand,
+    },
+    /// Find the chunks which probably carry a payload, for when the chunk
+    /// type was forgotten
+    Detect,
+    /// Help with choosing chunk types
+    ChunkType {
+        #[command(subcommand)]
+        command: ChunkTypeCommand,
+    },
+    /// Show the color type and bit depth, and how many bytes could be hidden
+    /// in the lowest bit of each color sample
+    Capacity,
+    /// Check the PNG, or every .png file in a directory tree, against a set
+    /// of rules and report violations
+    Audit {
+        /// Fail if any finding of this rule (id or name) is reported
+        #[arg(long, value_name = "RULE")]
+        deny: Vec<Rule>,
+        #[command(flatten)]
+        report: ReportArgs,
+    },
+    /// Check the signature, chunk types and checksums of the PNG
+    Verify {
+        /// Also run the checks of this profile: web, archive, strict or one
+        /// from the config file
+        #[arg(long, value_name = "NAME")]
+        profile: Option<String>,
+        /// A JSON file defining profiles
+        #[arg(long, value_name = "PATH", requires = "profile")]
+        config: Option<PathBuf>,
+        #[command(flatten)]
+        report: ReportArgs,
+    },
+    /// Check that the PNG at PATH has the same pixels as OTHER, ignoring
+    /// metadata, compression, filtering and interlacing
+    Pixequal { other: PathBuf },
+    /// Encode a random payload into a copy of the PNG, decode it, remove it
+    /// and check that the copy is byte-identical to the original, to see
+    /// whether pngme can be trusted with files like it
+    Selftest,
+    /// Find ancillary chunks with identical data across the PNGs in the
+    /// directory PATH, e.g. the same ICC profile or payload stored many times
+    DedupScan {
+        /// Ignore chunks with less data than this
+        #[arg(long, value_name = "BYTES", default_value_t = 64)]
+        min_size: u32,
+        /// Print the duplicates as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Serve an HTTP API on the address PATH, e.g. 127.0.0.1:8080, which
+    /// inspects, encodes, decodes and strips the PNGs POSTed to it
+    Serve {
+        /// Handle this many requests at the same time
+        #[arg(long, value_name = "N", default_value = "4")]
+        workers: NonZeroUsize,
+        /// Reject requests with a larger body
+        #[arg(long, value_name = "BYTES", default_value_t = server::DEFAULT_MAX_BODY)]
+        max_body: u64,
+    },
+    /// Filter the PNG at PATH, - for stdin, to stdout while it is read, with
+    /// memory use independent of its size
+    Stream {
+        #[command(subcommand)]
+        command: StreamCommand,
+    },
+}
+
+impl PngMeCommand {
+    /// Whether the command reads its payload from stdin
+    fn reads_stdin(&self) -> bool {
+        match self {
+            Self::Encode { message, file, .. } => {
+                message.as_deref() == Some("-") || file.as_deref() == Some(Path::new("-"))
+            }
+            _ => false,
+        }
+    }
+
+    /// Where the command writes the PNG it modified, --output or else
+    /// `path`. None for commands which don't modify the PNG or write it to
+    /// stdout.
+    fn written_png<'a>(&'a self, path: &'a Path) -> Option<&'a Path> {
+        let output = match self {
+            Self::Encode { output, .. }
+            | Self::Remove { output, .. }
+            | Self::Extract { output, .. }
+            | Self::Reorder { output, .. }
+            | Self::Normalize { output, .. }
+            | Self::Optimize { output, .. }
+            | Self::Interlace { output, .. }
+            | Self::Filters {
+                command: FiltersCommand::Optimize { output, .. },
+            }
+            | Self::Watermark {
+                command: WatermarkCommand::Embed { output, .. },
+            }
+            | Self::Fingerprint {
+                command: FingerprintCommand::Embed { output, .. },
+            }
+            | Self::FixColor { output, .. }
+            | Self::OptimizeText { output, .. }
+            | Self::DecompressText { output, .. }
+            | Self::Stamp {
+                show: false,
+                output,
+                ..
+            }
+            | Self::License {
+                command: LicenseCommand::Set { output, .. },
+            }
+            | Self::Text {
+                command: TextCommand::Set { output, .. },
+            }
+            | Self::Meta {
+                command: MetaCommand::Set { output, .. },
+            }
+            | Self::Ext {
+                command:
+                    ExtCommand::Offset { output, .. }
+                    | ExtCommand::Scale { output, .. }
+                    | ExtCommand::Calibration { output, .. },
+            }
+            | Self::Xmp {
+                command: XmpCommand::Embed { output, .. },
+            }
+            | Self::Provenance {
+                command: ProvenanceCommand::Strip { output, .. },
+            } => output,
+            _ => return None,
+        };
+        match output.as_deref() {
+            Some(output) if output == Path::new("-") => None,
+            output => Some(output.unwrap_or(path)),
+        }
+    }
+
+    /// Where the command writes the modified PNG, like
+    /// [`written_png`](Self::written_png) but including undo, which isn't
+    /// recorded in the history itself
+    fn modified_file<'a>(&'a self, path: &'a Path) -> Option<&'a Path> {
+        match self {
+            Self::Undo { output, .. } => match output.as_deref() {
+                Some(output) if output == Path::new("-") => None,
+                output => Some(output.unwrap_or(path)),
+            },
+            _ => self.written_png(path),
+        }
+    }
+
+    /// How the input file is locked while the command runs: exclusively if
+    /// the command writes it back, so no other process reads it in the
+    /// middle of the write or modifies it at the same time
+    fn lock_mode(&self, path: &Path) -> LockMode {
+        match self.modified_file(path) == Some(path) {
+            true => LockMode::Exclusive,
+            false => LockMode::Shared,
+        }
+    }
+
+    /// A path the command writes to besides the input file, which batch
+    /// mode would overwrite for every file
+    fn fixed_path(&self) -> Option<&Path> {
+        match self {
+            Self::Encode { output, spread, .. } => {
+                output.as_deref().or(spread.first().map(PathBuf::as_path))
+            }
+            Self::Decode { output, .. }
+            | Self::Reorder { output, .. }
+            | Self::Normalize { output, .. }
+            | Self::Optimize { output, .. }
+            | Self::Interlace { output, .. }
+            | Self::Filters {
+                command: FiltersCommand::Optimize { output, .. },
+            }
+            | Self::Watermark {
+                command: WatermarkCommand::Embed { output, .. },
+            }
+            | Self::Fingerprint {
+                command: FingerprintCommand::Embed { output, .. },
+            }
+            | Self::FixColor { output, .. }
+            | Self::OptimizeText { output, .. }
+            | Self::DecompressText { output, .. }
+            | Self::Undo { output, .. }
+            | Self::Stamp { output, .. }
+            | Self::License {
+                command: LicenseCommand::Set { output, .. },
+            }
+            | Self::Text {
+                command: TextCommand::Set { output, .. },
+            }
+            | Self::Meta {
+                command: MetaCommand::Set { output, .. },
+            }
+            | Self::Ext {
+                command:
+                    ExtCommand::Offset { output, .. }
+                    | ExtCommand::Scale { output, .. }
+                    | ExtCommand::Calibration { output, .. },
+            }
+            | Self::Xmp {
+                command: XmpCommand::Extract { output } | XmpCommand::Embed { output, .. },
+            }
+            | Self::Remove { output, .. }
+            | Self::Provenance {
+                command: ProvenanceCommand::Strip { output, .. },
+            } => output.as_deref(),
+            Self::Extract { data_path, .. }
+            | Self::Palette {
+                command:
+                    PaletteCommand::Export {
+                        output: data_path, ..
+                    },
+            } => Some(data_path),
+            Self::Audit { report, .. } | Self::Verify { report, .. } => report.report.as_deref(),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Subcommand)]
+enum HistoryCommand {
+    /// List the recorded operations, oldest first
+    Show,
+}
+
+#[derive(Clone, Subcommand)]
+enum AttestCommand {
+    /// Write a new secret key to PATH, which must not exist yet, and print
+    /// its public key
+    Keygen,
+    /// Print the public key of the key file at PATH
+    PublicKey,
+    /// List the attested edits and check that every one was signed by an
+    /// authorized key and the file hasn't changed since the last one
+    Verify {
+        /// An authorized public key, as printed by keygen and public-key
+        #[arg(long = "public-key", value_name = "KEY", required = true, value_parser = attest::parse_public_key)]
+        public_keys: Vec<VerifyingKey>,
+    },
+}
+
+#[derive(Clone, Subcommand)]
+enum LicenseCommand {
+    /// Write the Author, Copyright and License text chunks
+    #[command(group(ArgGroup::new("target").required(true).args(["output", "in_place"])))]
+    Set {
+        /// SPDX license expression, e.g. CC-BY-4.0
+        spdx: String,
+        #[arg(long)]
+        author: Option<String>,
+        /// Copyright notice, generated from author and license by default
+        #[arg(long)]
+        copyright: Option<String>,
+        /// Also write an XMP packet with the same information
+        #[arg(long)]
+        xmp: bool,
+        /// Where to write the modified file, - for stdout
+        #[arg(short, long, value_name = "PATH")]
+        output: Option<PathBuf>,
+        /// Modify PATH itself
+        #[arg(long)]
+        in_place: bool,
+    },
+    /// Print the license information of the PNG
+    Show,
+}
+
+#[derive(Clone, Subcommand)]
+enum TextCommand {
+    /// Store a text chunk, replacing those with the same keyword. Latin-1
+    /// text goes into tEXt, anything else or localized text into iTXt.
+    #[command(group(ArgGroup::new("target").required(true).args(["output", "in_place"])))]
+    Set {
+        /// 1 to 79 Latin-1 characters, e.g. Title, Author or Comment
+        keyword: String,
+        text: String,
+        /// Language tag of the text, e.g. de-DE
+        #[arg(long, value_name = "TAG")]
+        lang: Option<String>,
+        /// The keyword in the language of the text
+        #[arg(long, value_name = "KEYWORD")]
+        translated_keyword: Option<String>,
+        /// Store an iTXt chunk even for Latin-1 text
+        #[arg(long)]
+        international: bool,
+        /// Compress the text, as zTXt or a compressed iTXt chunk
+        #[arg(long)]
+        compress: bool,
+        /// Fail instead of warning if the keyword isn't one the PNG spec
+        /// defines
+        #[arg(long)]
+        keyword_strict: bool,
+        /// Where to write the modified file, - for stdout
+        #[arg(short, long, value_name = "PATH")]
+        output: Option<PathBuf>,
+        /// Modify PATH itself
+        #[arg(long)]
+        in_place: bool,
+    },
+    /// Print the text stored under a keyword
+    Get { keyword: String },
+}
+
+#[derive(Clone, Subcommand)]
+enum MetaCommand {
+    /// Print the value of a keyword, e.g. Author
+    Get { keyword: String },
+    /// Store a value, in tEXt if it is Latin-1 and in iTXt otherwise
+    #[command(group(ArgGroup::new("target").required(true).args(["output", "in_place"])))]
+    Set {
+        keyword: String,
+        value: String,
+        /// Where to write the modified file, - for stdout
+        #[arg(short, long, value_name = "PATH")]
+        output: Option<PathBuf>,
+        /// Modify PATH itself
+        #[arg(long)]
+        in_place: bool,
+    },
+    /// List all keywords with their value and the chunk holding it
+    List,
+}
+
+#[derive(Clone, Subcommand)]
+enum FiltersCommand {
+    /// Print the filter type of every scanline and how often each is used
+    Show,
+    /// Choose the filter of every scanline again and recompress the image
+    /// data, if that makes it smaller
+    #[command(group(ArgGroup::new("target").required(true).args(["output", "in_place"])))]
+    Optimize {
+        /// Where to write the modified file, - for stdout
+        #[arg(short, long, value_name = "PATH")]
+        output: Option<PathBuf>,
+        /// Modify PATH itself
+        #[arg(long)]
+        in_place: bool,
+    },
+}
+
+#[derive(Clone, Subcommand)]
+enum WatermarkCommand {
+    /// Embed a short identifier, e.g. the recipient of the copy, into the
+    /// pixels
+    #[command(group(ArgGroup::new("target").required(true).args(["output", "in_place"])))]
+    Embed {
+        /// Up to 8 bytes
+        identifier: String,
+        /// The secret the pattern is derived from, needed to detect it
+        #[arg(long)]
+        key: String,
+        /// How much the brightness changes, from 1 to 32; stronger marks
+        /// survive heavier edits but are easier to see
+        #[arg(long, default_value_t = watermark::DEFAULT_STRENGTH)]
+        strength: u8,
+        /// Where to write the modified file, - for stdout
+        #[arg(short, long, value_name = "PATH")]
+        output: Option<PathBuf>,
+        /// Modify PATH itself
+        #[arg(long)]
+        in_place: bool,
+    },
+    /// Read the identifier and report how confidently it was read
+    Detect {
+        #[arg(long)]
+        key: String,
+    },
+}
+
+#[derive(Clone, Subcommand)]
+enum FingerprintCommand {
+    /// Embed the marker of a recipient into a chunk and, if the image is
+    /// large enough, a watermark
+    #[command(group(ArgGroup::new("target").required(true).args(["output", "in_place"])))]
+    Embed {
+        #[arg(long)]
+        recipient: String,
+        /// The secret the markers are derived from
+        #[arg(long)]
+        key: String,
+        /// The strength of the watermark, see `watermark embed`
+        #[arg(long, default_value_t = watermark::DEFAULT_STRENGTH)]
+        strength: u8,
+        /// Where to write the modified file, - for stdout
+        #[arg(short, long, value_name = "PATH")]
+        output: Option<PathBuf>,
+        /// Modify PATH itself
+        #[arg(long)]
+        in_place: bool,
+    },
+    /// Report which recipient's copy the PNG is
+    Identify {
+        #[arg(long)]
+        key: String,
+        /// A recipient copies were made for, repeated for each of them
+        #[arg(long)]
+        recipient: Vec<String>,
+        /// A file with one recipient per line
+        #[arg(long, value_name = "FILE")]
+        recipients: Option<PathBuf>,
+    },
+}
+
+#[derive(Clone, Subcommand)]
+enum ExtCommand {
+    /// Print the offset, pixel size and calibration of the image
+    Show,
+    /// Set the position of the image on a page or in a larger image (oFFs)
+    #[command(allow_negative_numbers = true)]
+    #[command(group(ArgGroup::new("target").required(true).args(["output", "in_place"])))]
+    Offset {
+        x: i32,
+        y: i32,
+        /// pixel or micrometer
+        #[arg(long, default_value = "pixel")]
+        unit: OffsetUnit,
+        /// Where to write the modified file, - for stdout
+        #[arg(short, long, value_name = "PATH")]
+        output: Option<PathBuf>,
+        /// Modify PATH itself
+        #[arg(long)]
+        in_place: bool,
+    },
+    /// Set the physical size of a pixel (sCAL), e.g. 0.5 or 2.5E-3
+    #[command(group(ArgGroup::new("target").required(true).args(["output", "in_place"])))]
+    Scale {
+        width: String,
+        height: String,
+        /// meter or radian
+        #[arg(long, default_value = "meter")]
+        unit: ScaleUnit,
+        /// Where to write the modified file, - for stdout
+        #[arg(short, long, value_name = "PATH")]
+        output: Option<PathBuf>,
+        /// Modify PATH itself
+        #[arg(long)]
+        in_place: bool,
+    },
+    /// Set how sample values map to physical values (pCAL), e.g. elevation
+    /// in meters
+    #[command(allow_negative_numbers = true)]
+    #[command(group(ArgGroup::new("target").required(true).args(["output", "in_place"])))]
+    Calibration {
+        /// What the samples measure, e.g. elevation
+        name: String,
+        /// The sample values mapped to the start and the end of the
+        /// equation's range
+        x0: i32,
+        x1: i32,
+        /// linear, exponential, arbitrary-exponential or hyperbolic
+        #[arg(long, default_value = "linear")]
+        equation: Equation,
+        /// The physical unit, e.g. m
+        #[arg(long, default_value = "")]
+        unit: String,
+        /// A parameter of the equation, repeated for each of them in order
+        #[arg(
+            long = "param",
+            value_name = "VALUE",
+            required = true,
+            allow_hyphen_values = true
+        )]
+        parameters: Vec<String>,
+        /// Where to write the modified file, - for stdout
+        #[arg(short, long, value_name = "PATH")]
+        output: Option<PathBuf>,
+        /// Modify PATH itself
+        #[arg(long)]
+        in_place: bool,
     },
-    Print,
+}
+
+#[derive(Clone, Subcommand)]
+enum XmpCommand {
+    /// Print the XMP packet or write it to a file
+    Extract {
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+    },
+    /// Store the XMP packet from a file, replacing any existing one
+    #[command(group(ArgGroup::new("target").required(true).args(["output", "in_place"])))]
+    Embed {
+        packet: PathBuf,
+        /// Where to write the modified file, - for stdout
+        #[arg(short, long, value_name = "PATH")]
+        output: Option<PathBuf>,
+        /// Modify PATH itself
+        #[arg(long)]
+        in_place: bool,
+    },
+    /// Print a property, e.g. dc:creator or dc:creator[1]
+    Get { key: String },
+}
+
+#[derive(Clone, Subcommand)]
+enum StreamCommand {
+    /// Drop the ancillary chunks which don't change how the image looks and
+    /// everything after IEND
+    Strip {
+        /// Also keep chunks whose type matches this regular expression, e.g.
+        /// pHYs or 'tEXt|iTXt', can be repeated
+        #[arg(long, value_name = "PATTERN")]
+        keep: Vec<TypePattern>,
+    },
+}
+
+#[derive(Clone, Subcommand)]
+enum ProvenanceCommand {
+    /// List the manifests with their claims and signers
+    Show,
+    /// Remove all embedded manifest stores
+    #[command(group(ArgGroup::new("target").required(true).args(["output", "in_place"])))]
+    Strip {
+        /// Where to write the modified file, - for stdout
+        #[arg(short, long, value_name = "PATH")]
+        output: Option<PathBuf>,
+        /// Modify PATH itself
+        #[arg(long)]
+        in_place: bool,
+    },
+}
+
+#[derive(Clone, Subcommand)]
+enum PaletteCommand {
+    /// List the palette entries with their index, RGB color and alpha
+    Show,
+    /// Write the palette to a file
+    Export {
+        /// gpl for a GIMP palette, png for an image with a square per color
+        #[arg(long, value_name = "FORMAT", default_value = "gpl")]
+        format: PaletteFormat,
+        output: PathBuf,
+    },
+}
+
+#[derive(Clone, Subcommand)]
+enum ChunkTypeCommand {
+    /// Suggest valid private, ancillary, safe-to-copy chunk types which
+    /// aren't used by the specification, well-known software or the PNG
+    Suggest {
+        /// Derive the types from the letters of this name
+        #[arg(long, value_name = "NAME")]
+        project: Option<String>,
+        /// How many types to suggest
+        #[arg(long, value_name = "N", default_value_t = 5)]
+        count: usize,
+    },
+}
+
+#[derive(Clone, ClapArgs)]
+struct ReportArgs {
+    /// Also write the findings to this file
+    #[arg(long, value_name = "PATH")]
+    report: Option<PathBuf>,
+    /// Format of the report file: sarif or json
+    #[arg(long, value_name = "FORMAT", default_value = "sarif")]
+    report_format: ReportFormat,
+}
+
+impl ReportArgs {
+    fn target(&self) -> Option<(&Path, ReportFormat)> {
+        self.report
+            .as_deref()
+            .map(|path| (path, self.report_format))
+    }
+}
+
+#[derive(Clone, ClapArgs)]
+struct DisplayArgs {
+    /// Print the data of every chunk in full
+    #[arg(long, conflicts_with = "max_data")]
+    full: bool,
+    /// Only print this many bytes of each chunk's data
+    #[arg(long, value_name = "BYTES", default_value_t = 64)]
+    max_data: usize,
+    /// Print chunk data as hex instead of text
+    #[arg(long)]
+    hex: bool,
+    /// Escape control characters and invalid UTF-8 in chunk data as \xNN
+    #[arg(long, conflicts_with = "hex")]
+    escape: bool,
+    /// Print sizes as exact byte counts instead of e.g. 1.5 KiB
+    #[arg(long)]
+    bytes: bool,
+    /// Don't print the data of chunks with this type (case-insensitive)
+    #[arg(long, value_name = "TYPE")]
+    hide: Vec<String>,
+}
+
+#[derive(Clone, ClapArgs)]
+struct SelectionArgs {
+    /// Only show chunks whose type matches this regular expression, e.g.
+    /// tEXt or 'te?Xt', can be repeated
+    #[arg(long = "type", value_name = "PATTERN")]
+    types: Vec<TypePattern>,
+    /// Only show ancillary chunks
+    #[arg(long, conflicts_with = "critical")]
+    ancillary: bool,
+    /// Only show critical chunks
+    #[arg(long)]
+    critical: bool,
+    /// Only show chunks which are unsafe to copy
+    #[arg(long)]
+    unsafe_to_copy: bool,
+    /// Only show chunks whose type isn't defined by the PNG specification
+    #[arg(long)]
+    nonstandard: bool,
+    /// Sort the chunks by size, type or offset (file order)
+    #[arg(long, value_name = "KEY", default_value = "offset")]
+    sort: SortKey,
+    /// Sort in descending order
+    #[arg(long)]
+    desc: bool,
+    /// Leave out the first N chunks (after filtering and sorting)
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    skip: usize,
+    /// Show at most N chunks
+    #[arg(long, value_name = "N")]
+    limit: Option<usize>,
+    /// Show the output through $PAGER: never, auto (if it doesn't fit on
+    /// the screen) or always
+    #[arg(long, value_name = "WHEN", default_value = "never")]
+    paging: Paging,
+}
+
+impl From<SelectionArgs> for Selection {
+    fn from(args: SelectionArgs) -> Self {
+        Selection {
+            types: args.types,
+            ancillary: args.ancillary,
+            critical: args.critical,
+            unsafe_to_copy: args.unsafe_to_copy,
+            nonstandard: args.nonstandard,
+            sort: args.sort,
+            descending: args.desc,
+            skip: args.skip,
+            limit: args.limit,
+        }
+    }
+}
+
+impl From<DisplayArgs> for DisplayOptions {
+    fn from(args: DisplayArgs) -> Self {
+        DisplayOptions {
+            max_data: (!args.full).then_some(args.max_data),
+            format: if args.hex {
+                DataFormat::Hex
+            } else if args.escape {
+                DataFormat::Escaped
+            } else {
+                DataFormat::Utf8Lossy
+            },
+            hide: args.hide,
+            exact_sizes: args.bytes,
+        }
+    }
+}
+
+fn parse_key_value(s: &str) -> Result<(String, String), String> {
+    s.split_once('=')
+        .map(|(key, value)| (key.to_owned(), value.to_owned()))
+        .ok_or_else(|| format!("expected KEY=VALUE but got '{}'", s))
+}
+
+/// Accepts chunk types and the keys of the other formats, explaining what
+/// makes a chunk type if the key fits no format
+fn parse_chunk_type(s: &str) -> Result<String, String> {
+    match s.parse::<ChunkType>() {
+        Ok(_) => Ok(s.to_owned()),
+        Err(_) if container::is_key(s) => Ok(s.to_owned()),
+        Err(err) => Err(format!("{}\n\n{}", err, chunk_type::SYNTAX_HELP)),
+    }
+}
+
+/// Chunk indices in their new order, see `reorder`
+#[derive(Clone)]
+struct ChunkOrder(Vec<usize>);
+
+fn parse_order(s: &str) -> Result<ChunkOrder, String> {
+    s.split(',')
+        .map(|index| index.trim().parse())
+        .collect::<Result<_, _>>()
+        .map(ChunkOrder)
+        .map_err(|_| format!("expected comma separated chunk indices but got '{}'", s))
+}
+
+fn parse_date(s: &str) -> Result<i64, String> {
+    date::parse_utc(s).ok_or_else(|| {
+        format!(
+            "expected a date like 2025-12-31 or 2025-12-31T18:00:00Z but got '{}'",
+            s
+        )
+    })
+}
+
+/// Moves PATH in front of the command if the command comes first, as in
+/// `pngme encode file.png ruSt hello` or `pngme --quiet history show
+/// file.png`. PATH is the first argument after the command and its nested
+/// subcommands, and goes where the command started, after any global
+/// options. Arguments starting with a file named like a command are left
+/// alone.
+fn path_first(mut args: Vec<OsString>, command: &Command) -> Vec<OsString> {
+    let mut index = 1;
+    while let Some(arg) = args.get(index).and_then(|arg| arg.to_str()) {
+        if arg == "-" || arg == "--" || !arg.starts_with('-') {
+            break;
+        }
+        index += match option_takes_value(command, arg) {
+            true => 2,
+            false => 1,
+        };
+    }
+    let start = index;
+    if args.get(start).is_some_and(|arg| Path::new(arg).exists()) {
+        return args;
+    }
+
+    let mut current = command;
+    while let Some(subcommand) = args
+        .get(index)
+        .and_then(|arg| arg.to_str())
+        .and_then(|name| current.find_subcommand(name))
+    {
+        current = subcommand;
+        index += 1;
+    }
+    let path_follows = args.get(index).is_some_and(|arg| {
+        let arg = arg.to_string_lossy();
+        arg == "-" || !arg.starts_with('-')
+    });
+    if index > start && path_follows {
+        let path = args.remove(index);
+        args.insert(start, path);
+    }
+    args
+}
+
+/// Whether `arg`, e.g. `--timeout` or `-q`, is an option of `command` whose
+/// value is the next argument
+fn option_takes_value(command: &Command, arg: &str) -> bool {
+    if arg.contains('=') {
+        return false;
+    }
+    command.get_arguments().any(|option| {
+        let named = match arg.strip_prefix("--") {
+            Some(long) => option.get_long() == Some(long),
+            None => arg.len() == 2 && option.get_short() == arg.chars().nth(1),
+        };
+        named && option.get_action().takes_values()
+    })
+}
+
+/// The names of the subcommands given, e.g. `license set`
+fn operation(matches: &ArgMatches) -> String {
+    let mut names = Vec::new();
+    let mut matches = matches;
+    while let Some((name, subcommand)) = matches.subcommand() {
+        names.push(name);
+        matches = subcommand;
+    }
+    names.join(" ")
 }
 
 fn main() -> anyhow::Result<()> {
-    let args = Args::parse();
-    match args.command {
+    let command = Args::command();
+    let args = path_first(std::env::args_os().collect(), &command);
+    let matches = command.get_matches_from(args);
+    let args = Args::from_arg_matches(&matches).unwrap_or_else(|err| err.exit());
+    let operation = operation(&matches);
+    console::set_raw(args.raw);
+    console::set_color(args.color);
+    let options = GlobalOptions {
+        cancel: match args.timeout {
+            Some(secs) => CancellationToken::with_timeout(Duration::from_secs(secs)),
+            None => CancellationToken::new(),
+        },
+        deterministic: args.deterministic,
+        container: args.container,
+        entry: args.entry,
+        check_decodable: args.check_decodable,
+        lock: !args.no_lock,
+        preserve: Preserve {
+            xattrs: !args.no_xattrs,
+            owner: !args.no_owner,
+        },
+        audit_log: args.audit_log,
+        attest: args
+            .attest
+            .as_deref()
+            .map(commands::read_signing_key)
+            .transpose()?,
+        stdin_format: args.stdin_format,
+        stdout_format: args.stdout_format,
+        quiet: args.quiet,
+    };
+
+    let scan = ScanOptions {
+        recursive: args.batch.recursive,
+        max_depth: args.batch.max_depth,
+        exclude: args.batch.exclude,
+        symlinks: args.batch.symlinks,
+    };
+    // dedup-scan is the only command using the scan options for itself
+    if let PngMeCommand::DedupScan { min_size, json } = args.command {
+        return commands::dedup_scan(&args.path, &scan, min_size, json, &options);
+    }
+    // serve listens on PATH instead of reading it
+    if let PngMeCommand::Serve { workers, max_body } = args.command {
+        let options = ServeOptions {
+            workers: workers.get(),
+            max_body,
+        };
+        return Ok(server::serve(&args.path.to_string_lossy(), &options)?);
+    }
+    // stream reads PATH piece by piece, so it can't unpack or batch
+    if let PngMeCommand::Stream {
+        command: StreamCommand::Strip { keep },
+    } = args.command
+    {
+        return commands::stream_strip(&args.path, keep, &options);
+    }
+
+    if commands::is_stdout(&args.path) && args.command.reads_stdin() {
+        anyhow::bail!("stdin can only be read once, it can't hold both the file and the payload");
+    }
+
+    // A directory means batch mode, except for audit which handles
+    // directories itself
+    if args.path.is_dir() && !matches!(args.command, PngMeCommand::Audit { .. }) {
+        if let Some(path) = args.command.fixed_path() {
+            anyhow::bail!(
+                "{} would be written for every file, batch mode modifies files in place",
+                path.display()
+            );
+        }
+        if args.command.reads_stdin() {
+            anyhow::bail!(
+                "stdin can only be read once, store the payload in a file for batch mode"
+            );
+        }
+        let batch = BatchOptions {
+            fail_fast: args.batch.fail_fast,
+            jobs: args.batch.jobs.get(),
+            report: args.batch.batch_report,
+            scan,
+        };
+        return commands::batch(&args.path, &batch, |file| {
+            run_recorded(
+                file,
+                args.command.clone(),
+                &options,
+                &operation,
+                args.history,
+            )
+        });
+    }
+    run_recorded(&args.path, args.command, &options, &operation, args.history)
+}
+
+/// Runs the command with the file locked, records how it changed the PNG in
+/// the history and attests it if asked to, and logs it to the audit log. A PNG written to
+/// another file gets the attributes of the input file.
+fn run_recorded(
+    path: &Path,
+    command: PngMeCommand,
+    options: &GlobalOptions,
+    operation: &str,
+    history: bool,
+) -> anyhow::Result<()> {
+    // Paths which aren't files, like - for stdin, can't be locked
+    let _lock = match path.is_file() {
+        true => commands::lock_file(path, command.lock_mode(path), options)?,
+        false => None,
+    };
+    let recorded = command.written_png(path).is_some();
+    // A file read from stdin can't be read again to see what changed
+    let written = command
+        .modified_file(path)
+        .filter(|_| !commands::is_stdout(path));
+    let Some(written) = written.map(Path::to_path_buf) else {
+        return run(path.to_path_buf(), command, options);
+    };
+    let recording = history || options.attest.is_some() || options.audit_log.is_some();
+    let before = match recording || !options.quiet {
+        true => Some(std::fs::read(path)?),
+        false => None,
+    };
+    let output_modified = modified(&written);
+    run(path.to_path_buf(), command, options)?;
+    // Only a file written by the command is read back. PATH itself is
+    // always checked, so leaving it alone is reported as unchanged.
+    if written != path && modified(&written) == output_modified {
+        return Ok(());
+    }
+    if let Some(before) = before {
+        if history && recorded {
+            commands::record_history(before.clone(), &written, operation, options)?;
+        }
+        commands::attest_edit(before.clone(), &written, operation, options)?;
+        commands::log_operation(operation, path, &written, &before, options)?;
+        commands::confirm(operation, path, &written, &before, options)?;
+    }
+    if written != path {
+        commands::carry_over_attributes(path, &written, options);
+    }
+    Ok(())
+}
+
+/// When the file at `path` was last modified, None if it doesn't exist
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+fn run(path: PathBuf, command: PngMeCommand, options: &GlobalOptions) -> anyhow::Result<()> {
+    match command {
         PngMeCommand::Encode {
             chunk_type,
             message,
             output,
-        } => commands::encode(args.path, chunk_type, message, output),
-        PngMeCommand::Decode { chunk_type } => commands::decode(args.path, &chunk_type),
-        PngMeCommand::Remove { chunk_type } => commands::remove(args.path, &chunk_type),
-        PngMeCommand::Print => commands::print(args.path),
+            file,
+            from_clipboard,
+            spread,
+            no_integrity,
+            expires,
+            content_type,
+            manifest,
+            if_changed,
+            scatter,
+            pad_to,
+            pad_random,
+            no_advice,
+            ..
+        } => {
+            let message = match (message, file.as_deref()) {
+                _ if from_clipboard => Message::Clipboard,
+                (_, Some(file)) if file == Path::new("-") => Message::Stdin,
+                (_, Some(file)) => Message::File(file),
+                (Some(text), None) if text == "-" => Message::Stdin,
+                (Some(text), None) => Message::Text(text),
+                (None, None) => unreachable!("clap requires a message, a file or the clipboard"),
+            };
+            commands::encode(
+                path,
+                &chunk_type,
+                message,
+                output,
+                &spread,
+                &EnvelopeOptions {
+                    integrity: !no_integrity,
+                    expires,
+                    content_type,
+                    manifest,
+                    if_changed,
+                    scatter,
+                    padding: pad_to.map(Padding::To).or(pad_random),
+                    advice: !no_advice,
+                },
+                options,
+            )
+        }
+        PngMeCommand::Decode {
+            chunk_type,
+            gather,
+            output,
+            to_clipboard,
+            base64,
+            hex,
+            escape,
+            no_integrity,
+            enforce_expiry,
+            show_type,
+            as_stored,
+            query,
+            scatter,
+        } => {
+            let format = if base64 || options.stdout_format == Transport::Base64 {
+                PayloadFormat::Base64
+            } else if hex {
+                PayloadFormat::Hex
+            } else if escape {
+                PayloadFormat::Escaped
+            } else {
+                PayloadFormat::Raw
+            };
+            commands::decode(
+                path,
+                &chunk_type,
+                &gather,
+                match (&output, to_clipboard) {
+                    (Some(output), _) => Destination::File(output),
+                    (None, true) => Destination::Clipboard(format),
+                    (None, false) => Destination::Print(format),
+                },
+                &DecodeOptions {
+                    integrity: !no_integrity,
+                    enforce_expiry,
+                    show_type,
+                    as_stored,
+                    query,
+                    scatter,
+                },
+                options,
+            )
+        }
+        PngMeCommand::Remove {
+            chunk_type,
+            output,
+            scatter,
+            ..
+        } => commands::remove(path, &chunk_type, scatter.as_deref(), output, options),
+        PngMeCommand::Info { all, bytes } => commands::info(path, all, bytes, options),
+        PngMeCommand::Crc { chunk_type, check } => match (chunk_type, check) {
+            (_, Some(index)) => commands::check_crc(path, index, options),
+            (Some(chunk_type), None) => commands::crc(path, &chunk_type),
+            (None, None) => unreachable!("clap requires a chunk type or --check"),
+        },
+        PngMeCommand::History {
+            command: HistoryCommand::Show,
+        } => commands::show_history(path, options),
+        PngMeCommand::Undo { output, .. } => commands::undo(path, output, options),
+        PngMeCommand::Attest { command } => match command {
+            AttestCommand::Keygen => commands::generate_attestation_key(path),
+            AttestCommand::PublicKey => commands::print_public_key(path),
+            AttestCommand::Verify { public_keys } => {
+                commands::verify_attestations(path, &public_keys, options)
+            }
+        },
+        PngMeCommand::Extract {
+            chunk_type,
+            data_path,
+            output,
+            ..
+        } => commands::extract(path, &chunk_type, data_path, output, options),
+        PngMeCommand::Print {
+            display,
+            selection,
+            format,
+        } => commands::print(
+            path,
+            &display.into(),
+            &selection.clone().into(),
+            format.as_ref(),
+            selection.paging,
+            options,
+        ),
+        PngMeCommand::List {
+            display,
+            selection,
+            format,
+        } => commands::list(
+            path,
+            &display.into(),
+            &selection.clone().into(),
+            format.as_ref(),
+            selection.paging,
+            options,
+        ),
+        PngMeCommand::Reorder { order, output, .. } => {
+            commands::reorder(path, &order.0, output, options)
+        }
+        PngMeCommand::Normalize { output, .. } => commands::normalize(path, output, options),
+        PngMeCommand::Optimize {
+            recompress, output, ..
+        } => commands::optimize(path, recompress, output, options),
+        PngMeCommand::Interlace { off, output, .. } => {
+            commands::interlace(path, !off, output, options)
+        }
+        PngMeCommand::Filters { command } => match command {
+            FiltersCommand::Show => commands::show_filters(path, options),
+            FiltersCommand::Optimize { output, .. } => commands::refilter(path, output, options),
+        },
+        PngMeCommand::Watermark { command } => match command {
+            WatermarkCommand::Embed {
+                identifier,
+                key,
+                strength,
+                output,
+                ..
+            } => commands::embed_watermark(path, &identifier, &key, strength, output, options),
+            WatermarkCommand::Detect { key } => commands::detect_watermark(path, &key, options),
+        },
+        PngMeCommand::Fingerprint { command } => match command {
+            FingerprintCommand::Embed {
+                recipient,
+                key,
+                strength,
+                output,
+                ..
+            } => commands::fingerprint(path, &recipient, &key, strength, output, options),
+            FingerprintCommand::Identify {
+                key,
+                recipient,
+                recipients,
+            } => commands::identify_fingerprint(path, &key, recipient, recipients, options),
+        },
+        PngMeCommand::FixColor { intent, output, .. } => {
+            commands::fix_color(path, intent, output, options)
+        }
+        PngMeCommand::OptimizeText {
+            min_size, output, ..
+        } => commands::optimize_text(path, min_size, output, options),
+        PngMeCommand::DecompressText { output, .. } => {
+            commands::decompress_text(path, output, options)
+        }
+        PngMeCommand::Stamp { show: true, .. } => commands::show_stamp(path, options),
+        PngMeCommand::Stamp {
+            version,
+            commit,
+            timestamp,
+            now,
+            values,
+            from_json,
+            output,
+            ..
+        } => {
+            let mut stamp = Stamp {
+                version,
+                commit,
+                timestamp: match now {
+                    true => Some(date::format_utc(date::now())),
+                    false => timestamp,
+                },
+                ..Default::default()
+            };
+            for (key, value) in values {
+                stamp.set(key, value);
+            }
+            commands::stamp(path, stamp, from_json, output, options)
+        }
+        PngMeCommand::License {
+            command:
+                LicenseCommand::Set {
+                    spdx,
+                    author,
+                    copyright,
+                    xmp,
+                    output,
+                    ..
+                },
+        } => {
+            let mut license = License::new(&spdx, author.as_deref())?;
+            license.copyright = copyright;
+            commands::set_license(path, &license, xmp, output, options)
+        }
+        PngMeCommand::License {
+            command: LicenseCommand::Show,
+        } => commands::show_license(path, options),
+        PngMeCommand::Text { command } => match command {
+            TextCommand::Set {
+                keyword,
+                text,
+                lang,
+                translated_keyword,
+                international,
+                compress,
+                keyword_strict,
+                output,
+                ..
+            } => {
+                let text = match (lang, translated_keyword) {
+                    (None, None) if !international => TextChunk::best_fit(&keyword, &text),
+                    (lang, translated_keyword) => TextChunk::localized(
+                        &keyword,
+                        &text,
+                        lang.as_deref().unwrap_or_default(),
+                        translated_keyword.as_deref().unwrap_or_default(),
+                    ),
+                };
+                let text = if compress { text.compressed() } else { text };
+                commands::set_text(path, &text, keyword_strict, output, options)
+            }
+            TextCommand::Get { keyword } => commands::get_text(path, &keyword, options),
+        },
+        PngMeCommand::Meta { command } => match command {
+            MetaCommand::Get { keyword } => commands::get_meta(path, &keyword, options),
+            MetaCommand::Set {
+                keyword,
+                value,
+                output,
+                ..
+            } => {
+                let text = TextChunk::best_fit(&keyword, &value);
+                commands::set_text(path, &text, false, output, options)
+            }
+            MetaCommand::List => commands::list_meta(path, options),
+        },
+        PngMeCommand::Ext { command } => {
+            let (ancillary, output) = match command {
+                ExtCommand::Show => return commands::show_extensions(path, options),
+                ExtCommand::Offset {
+                    x, y, unit, output, ..
+                } => (Ancillary::Offset { x, y, unit }, output),
+                ExtCommand::Scale {
+                    width,
+                    height,
+                    unit,
+                    output,
+                    ..
+                } => (
+                    Ancillary::Scale {
+                        width,
+                        height,
+                        unit,
+                    },
+                    output,
+                ),
+                ExtCommand::Calibration {
+                    name,
+                    x0,
+                    x1,
+                    equation,
+                    unit,
+                    parameters,
+                    output,
+                    ..
+                } => (
+                    Ancillary::Calibration(Calibration {
+                        name,
+                        x0,
+                        x1,
+                        equation,
+                        unit,
+                        parameters,
+                    }),
+                    output,
+                ),
+            };
+            commands::set_ancillary(path, &ancillary, output, options)
+        }
+        PngMeCommand::Xmp { command } => match command {
+            XmpCommand::Extract { output, .. } => commands::extract_xmp(path, output, options),
+            XmpCommand::Embed { packet, output, .. } => {
+                commands::embed_xmp(path, packet, output, options)
+            }
+            XmpCommand::Get { key } => commands::get_xmp(path, &key, options),
+        },
+        PngMeCommand::Provenance { command } => match command {
+            ProvenanceCommand::Show => commands::show_provenance(path, options),
+            ProvenanceCommand::Strip { output, .. } => {
+                commands::strip_provenance(path, output, options)
+            }
+        },
+        PngMeCommand::Palette { command } => match command {
+            PaletteCommand::Show => commands::show_palette(path, options),
+            PaletteCommand::Export { format, output } => {
+                commands::export_palette(path, format, output, options)
+            }
+        },
+        PngMeCommand::Detect => commands::detect(path, options),
+        PngMeCommand::ChunkType { command } => match command {
+            ChunkTypeCommand::Suggest { project, count } => {
+                commands::suggest_chunk_types(path, project.as_deref(), count, options)
+            }
+        },
+        PngMeCommand::Capacity => commands::capacity(path, options),
+        PngMeCommand::Audit { deny, report } => {
+            commands::audit(path, &deny, report.target(), options)
+        }
+        PngMeCommand::Verify {
+            profile,
+            config,
+            report,
+        } => commands::verify(path, profile.as_deref(), config, report.target(), options),
+        PngMeCommand::Pixequal { other } => commands::pixequal(path, other, options),
+        PngMeCommand::Selftest => commands::selftest(path, options),
+        PngMeCommand::DedupScan { .. } => {
+            unreachable!("main runs dedup-scan with the scan options")
+        }
+        PngMeCommand::Serve { .. } => unreachable!("main runs serve before reading PATH"),
+        PngMeCommand::Stream { .. } => unreachable!("main runs stream before reading PATH"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reorder(args: &[&str]) -> Vec<String> {
+        let args = args.iter().map(OsString::from).collect();
+        path_first(args, &Args::command())
+            .into_iter()
+            .map(|arg| arg.into_string().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_path_first() {
+        assert_eq!(
+            reorder(&["pngme", "encode", "a.png", "ruSt", "hi", "-o", "b.png"]),
+            ["pngme", "a.png", "encode", "ruSt", "hi", "-o", "b.png"]
+        );
+    }
+
+    #[test]
+    fn test_path_first_path_already_first() {
+        let args = ["pngme", "a.png", "encode", "ruSt", "hi", "--in-place"];
+        assert_eq!(reorder(&args), args);
+    }
+
+    #[test]
+    fn test_path_first_nested_command() {
+        assert_eq!(
+            reorder(&["pngme", "history", "show", "a.png"]),
+            ["pngme", "a.png", "history", "show"]
+        );
+        assert_eq!(
+            reorder(&["pngme", "license", "set", "a.png", "MIT", "--in-place"]),
+            ["pngme", "a.png", "license", "set", "MIT", "--in-place"]
+        );
+    }
+
+    #[test]
+    fn test_path_first_global_options() {
+        assert_eq!(
+            reorder(&["pngme", "--quiet", "encode", "a.png", "ruSt", "hi"]),
+            ["pngme", "--quiet", "a.png", "encode", "ruSt", "hi"]
+        );
+        assert_eq!(
+            reorder(&["pngme", "-q", "--timeout", "5", "history", "show", "a.png"]),
+            ["pngme", "-q", "--timeout", "5", "a.png", "history", "show"]
+        );
+        assert_eq!(
+            reorder(&["pngme", "--timeout=5", "decode", "a.png", "ruSt"]),
+            ["pngme", "--timeout=5", "a.png", "decode", "ruSt"]
+        );
+        // The value of an option is never taken for the command
+        assert_eq!(
+            reorder(&["pngme", "--entry", "list", "decode", "a.zip", "ruSt"]),
+            ["pngme", "--entry", "list", "a.zip", "decode", "ruSt"]
+        );
+    }
+
+    #[test]
+    fn test_path_first_stdin() {
+        assert_eq!(
+            reorder(&["pngme", "decode", "-", "ruSt"]),
+            ["pngme", "-", "decode", "ruSt"]
+        );
+    }
+
+    #[test]
+    fn test_path_first_option_after_command() {
+        let args = ["pngme", "list", "--bytes"];
+        assert_eq!(reorder(&args), args);
     }
 }
@@ -0,0 +1,152 @@
+use crate::{chunk::Chunk, chunk_type::ChunkType, png::Png};
+use serde::{Deserialize, Serialize};
+use std::fmt::{self, Display};
+
+/// The chunk type manifests are stored in (private, ancillary, safe to copy)
+pub const MANIFEST_CHUNK_TYPE: [u8; 4] = *b"pmMf";
+
+/// The version of the payload layout written by this version of pngme
+pub const PAYLOAD_VERSION: u32 = 1;
+
+/// A step applied to a payload before it was stored
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Transform {
+    /// Sealed in an envelope together with its SHA-256
+    Integrity,
+    /// The envelope records an expiry date
+    Expiry,
+    /// The envelope records the content type
+    ContentType,
+    /// Split across several files, this chunk holding part `part` (starting
+    /// at 1) of `parts`
+    Spread { part: u16, parts: u16 },
+}
+
+impl Display for Transform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Integrity => f.write_str("integrity envelope (SHA-256)"),
+            Self::Expiry => f.write_str("expiry date"),
+            Self::ContentType => f.write_str("content type"),
+            Self::Spread { part, parts } => write!(f, "part {} of {}", part, parts),
+        }
+    }
+}
+
+/// How one payload was stored, without anything about its contents
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    pub chunk_type: String,
+    /// The [`PAYLOAD_VERSION`] of the pngme which stored the payload
+    pub version: u32,
+    #[serde(default)]
+    pub transforms: Vec<Transform>,
+    /// The pngme version which stored the payload
+    pub written_by: String,
+}
+
+impl ManifestEntry {
+    /// An entry for a payload stored by this version of pngme
+    pub fn new(chunk_type: &str, transforms: Vec<Transform>) -> Self {
+        Self {
+            chunk_type: chunk_type.to_owned(),
+            version: PAYLOAD_VERSION,
+            transforms,
+            written_by: env!("CARGO_PKG_VERSION").to_owned(),
+        }
+    }
+}
+
+/// An unencrypted record of the payloads pngme stored in a PNG, so they can
+/// be decoded long after the fact. Stored as a JSON object in a single
+/// `pmMf` chunk.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub entries: Vec<ManifestEntry>,
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("manifest is not valid JSON: {0}")]
+pub struct ManifestError(#[from] serde_json::Error);
+
+impl Manifest {
+    fn chunk_type() -> ChunkType {
+        ChunkType::try_from(MANIFEST_CHUNK_TYPE).unwrap()
+    }
+
+    pub fn to_chunk(&self) -> Chunk {
+        let data = serde_json::to_vec(self).expect("manifests are always serializable");
+        Chunk::new(Self::chunk_type(), data)
+    }
+
+    pub fn from_chunk(chunk: &Chunk) -> Result<Self, ManifestError> {
+        Ok(serde_json::from_slice(chunk.data())?)
+    }
+
+    /// Reads the manifest of a PNG, if it has one
+    pub fn read(png: &Png) -> Option<Result<Self, ManifestError>> {
+        png.chunk_by_type(&Self::chunk_type()).map(Self::from_chunk)
+    }
+
+    /// Stores the manifest in a PNG, replacing an existing manifest. New
+    /// manifests are inserted before the `IEND` chunk.
+    pub fn write(&self, png: &mut Png) {
+        let chunk_type = Self::chunk_type();
+        let position = png
+            .chunks()
+            .iter()
+            .position(|chunk| *chunk.chunk_type() == chunk_type);
+
+        if let Some(position) = position {
+            png.remove_chunk(&chunk_type).unwrap();
+            png.insert_chunk(position, self.to_chunk());
+        } else {
+            png.insert_before_end(self.to_chunk());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::png::tests::PNG_FILE;
+
+    #[test]
+    fn test_round_trip() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        assert!(Manifest::read(&png).is_none());
+
+        let mut manifest = Manifest {
+            entries: vec![ManifestEntry::new("ruSt", vec![Transform::Integrity])],
+        };
+        manifest.write(&mut png);
+        manifest.entries.push(ManifestEntry::new(
+            "ruSt",
+            vec![Transform::Spread { part: 2, parts: 3 }],
+        ));
+        manifest.write(&mut png);
+
+        let chunks = png.chunks();
+        assert_eq!(&chunks[chunks.len() - 1].chunk_type().bytes(), b"IEND");
+        let manifests = chunks
+            .iter()
+            .filter(|chunk| chunk.chunk_type().bytes() == MANIFEST_CHUNK_TYPE)
+            .count();
+        assert_eq!(manifests, 1);
+        assert_eq!(Manifest::read(&png).unwrap().unwrap(), manifest);
+    }
+
+    #[test]
+    fn test_json() {
+        let entry = ManifestEntry::new(
+            "ruSt",
+            vec![Transform::Expiry, Transform::Spread { part: 1, parts: 2 }],
+        );
+        let json = serde_json::to_value(&entry).unwrap();
+        assert_eq!(
+            json["transforms"],
+            serde_json::json!(["expiry", {"spread": {"part": 1, "parts": 2}}])
+        );
+    }
+}
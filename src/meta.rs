@@ -0,0 +1,169 @@
+use crate::{
+    png::Png,
+    text::{self, TextChunk},
+    xmp::{self, XMP_KEYWORD},
+};
+use std::fmt::Display;
+
+/// The XMP properties holding the same information as a text keyword
+const XMP_PROPERTIES: &[(&str, &str)] = &[
+    ("Title", "dc:title"),
+    ("Author", "dc:creator"),
+    ("Description", "dc:description"),
+    ("Copyright", "dc:rights"),
+    ("Creation Time", "xmp:CreateDate"),
+    ("Software", "xmp:CreatorTool"),
+];
+
+/// Where a value was found
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Source {
+    /// A text chunk of this type
+    Chunk(&'static str),
+    /// This property of the XMP packet
+    Xmp(&'static str),
+}
+
+impl Display for Source {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Chunk(chunk_type) => f.write_str(chunk_type),
+            Self::Xmp(property) => write!(f, "XMP {}", property),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub keyword: String,
+    pub value: String,
+    pub source: Source,
+}
+
+impl Entry {
+    fn from_text(text: TextChunk) -> Self {
+        Self {
+            source: Source::Chunk(text.kind.chunk_type()),
+            keyword: text.keyword,
+            value: text.text,
+        }
+    }
+}
+
+/// Looks up the XMP property for `keyword`. List values are joined with
+/// `; `.
+fn from_xmp(packet: &str, keyword: &str) -> Option<Entry> {
+    let (keyword, property) = XMP_PROPERTIES.iter().find(|(known, _)| *known == keyword)?;
+    let values = xmp::get(packet, property).ok()?;
+    if values.is_empty() {
+        return None;
+    }
+    Some(Entry {
+        keyword: keyword.to_string(),
+        value: values.join("; "),
+        source: Source::Xmp(property),
+    })
+}
+
+/// The value of `keyword`, whichever chunk holds it: the first `tEXt`,
+/// `zTXt` or `iTXt` chunk with the keyword, or else the matching property of
+/// the XMP packet. Values are written with [`text::set_text`] and
+/// [`TextChunk::best_fit`].
+pub fn get(png: &Png, keyword: &str) -> Option<Entry> {
+    if keyword != XMP_KEYWORD {
+        if let Some(text) = text::find_text(png, keyword) {
+            return Some(Entry::from_text(text));
+        }
+    }
+    from_xmp(&xmp::read(png)?, keyword)
+}
+
+/// All values in the PNG, one per keyword: the text chunks in file order,
+/// then the XMP properties whose keyword no text chunk has. The XMP packet
+/// itself isn't listed.
+pub fn list(png: &Png) -> Vec<Entry> {
+    let mut entries: Vec<Entry> = Vec::new();
+    let texts = png
+        .chunks()
+        .iter()
+        .filter_map(TextChunk::from_chunk)
+        .filter_map(Result::ok)
+        .filter(|text| text.keyword != XMP_KEYWORD);
+    for text in texts {
+        if !entries.iter().any(|entry| entry.keyword == text.keyword) {
+            entries.push(Entry::from_text(text));
+        }
+    }
+
+    if let Some(packet) = xmp::read(png) {
+        for (keyword, _) in XMP_PROPERTIES {
+            if entries.iter().any(|entry| entry.keyword == *keyword) {
+                continue;
+            }
+            entries.extend(from_xmp(&packet, keyword));
+        }
+    }
+    entries
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::png::tests::PNG_FILE;
+
+    const PACKET: &str = r#"<x:xmpmeta xmlns:x="adobe:ns:meta/"><rdf:RDF><rdf:Description>
+        <dc:creator><rdf:Seq><rdf:li>Alice</rdf:li><rdf:li>Bob</rdf:li></rdf:Seq></dc:creator>
+        <dc:title><rdf:Alt><rdf:li xml:lang="x-default">Dice</rdf:li></rdf:Alt></dc:title>
+        </rdf:Description></rdf:RDF></x:xmpmeta>"#;
+
+    fn png() -> Png {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        text::set_text(&mut png, &TextChunk::text("Title", "Sunset")).unwrap();
+        text::set_text(
+            &mut png,
+            &TextChunk::localized("Comment", "Schön", "de", "Kommentar").compressed(),
+        )
+        .unwrap();
+        xmp::write(&mut png, PACKET).unwrap();
+        png
+    }
+
+    #[test]
+    fn test_get() {
+        let png = png();
+        let title = get(&png, "Title").unwrap();
+        assert_eq!(title.value, "Sunset");
+        assert_eq!(title.source, Source::Chunk("tEXt"));
+        assert_eq!(get(&png, "Comment").unwrap().source.to_string(), "iTXt");
+
+        let author = get(&png, "Author").unwrap();
+        assert_eq!(author.value, "Alice; Bob");
+        assert_eq!(author.source.to_string(), "XMP dc:creator");
+
+        assert_eq!(get(&png, "Description"), None);
+        assert_eq!(get(&png, XMP_KEYWORD), None);
+    }
+
+    #[test]
+    fn test_list() {
+        let mut png = png();
+        // Only the first chunk with a keyword counts, like in get
+        png.insert_before_end(TextChunk::text("Comment", "later").to_chunk().unwrap());
+        let keywords: Vec<(String, String)> = list(&png)
+            .into_iter()
+            .map(|entry| (entry.keyword, entry.source.to_string()))
+            .collect();
+        let expected = [
+            ("Title", "tEXt"),
+            ("Comment", "iTXt"),
+            ("Author", "XMP dc:creator"),
+        ];
+        assert_eq!(
+            keywords,
+            expected.map(|(keyword, source)| (keyword.to_owned(), source.to_owned()))
+        );
+
+        let empty = Png::try_from(&PNG_FILE[..]).unwrap();
+        assert!(list(&empty).is_empty());
+    }
+}
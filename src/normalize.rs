@@ -0,0 +1,241 @@
+use crate::{
+    audit::{is_one_of, AFTER_PLTE, BEFORE_IDAT, BEFORE_PLTE, TEXT_CHUNKS},
+    chunk::Chunk,
+    png::Png,
+};
+use std::collections::HashSet;
+
+/// Keyword of the text chunk which conventionally holds the creation time
+const CREATION_TIME_KEYWORD: &[u8] = b"Creation Time";
+
+/// The result of [`normalize`] and [`deterministic`]
+#[derive(Debug)]
+pub struct Normalized {
+    pub png: Png,
+    /// Number of chunks that ended up in a different position
+    pub moved: usize,
+    /// Number of duplicate chunks that were dropped
+    pub merged: usize,
+    /// Number of timestamp chunks that were dropped
+    pub dropped: usize,
+}
+
+/// Brings the chunks of a PNG into a canonical order:
+///
+/// 1. `IHDR`
+/// 2. Color management chunks (`cHRM`, `gAMA`, `iCCP`, `sBIT`, `sRGB`)
+/// 3. `PLTE`, followed by `bKGD`, `hIST` and `tRNS`
+/// 4. Other chunks that must precede the image data (`pHYs`, `sPLT`, ...)
+/// 5. Text chunks, then `tIME`
+/// 6. Any other chunks found before the image data
+/// 7. `IDAT`
+/// 8. Any other chunks found after the image data
+/// 9. `IEND`
+///
+/// Chunks within a group keep their relative order. Of chunks that may only
+/// appear once, only the first one is kept.
+pub fn normalize(png: Png) -> Normalized {
+    normalize_chunks(png.chunks().to_vec(), false)
+}
+
+/// Makes the output only depend on the logical content of a PNG, for
+/// reproducible builds: timestamps (`tIME` and `Creation Time` text chunks)
+/// are dropped, the chunks are [`normalize`]d and within each group the
+/// ancillary, safe-to-copy chunks are sorted by type and data, after the
+/// chunks which have to stay in their relative order.
+pub fn deterministic(png: Png) -> Normalized {
+    let (chunks, timestamps): (Vec<Chunk>, Vec<Chunk>) = png
+        .chunks()
+        .iter()
+        .cloned()
+        .partition(|chunk| !is_timestamp(chunk));
+
+    Normalized {
+        dropped: timestamps.len(),
+        ..normalize_chunks(chunks, true)
+    }
+}
+
+fn is_timestamp(chunk: &Chunk) -> bool {
+    let chunk_type = chunk.chunk_type();
+    &chunk_type.bytes() == b"tIME"
+        || (is_one_of(chunk_type, TEXT_CHUNKS)
+            && chunk.data().split(|b| *b == 0).next() == Some(CREATION_TIME_KEYWORD))
+}
+
+fn normalize_chunks(chunks: Vec<Chunk>, sort_copyable: bool) -> Normalized {
+    let first_idat = chunks
+        .iter()
+        .position(|chunk| &chunk.chunk_type().bytes() == b"IDAT");
+
+    let mut seen = HashSet::new();
+    let mut merged = 0;
+    let mut ordered: Vec<(u8, usize, Chunk)> = Vec::with_capacity(chunks.len());
+
+    for (index, chunk) in chunks.into_iter().enumerate() {
+        let chunk_type = chunk.chunk_type();
+        if chunk_type.is_single() && !seen.insert(chunk_type.bytes()) {
+            merged += 1;
+            continue;
+        }
+
+        let bytes = &chunk_type.bytes();
+        let group = if bytes == b"IHDR" {
+            0
+        } else if is_one_of(chunk_type, BEFORE_PLTE) {
+            1
+        } else if bytes == b"PLTE" {
+            2
+        } else if is_one_of(chunk_type, AFTER_PLTE) {
+            3
+        } else if is_one_of(chunk_type, BEFORE_IDAT) {
+            4
+        } else if is_one_of(chunk_type, TEXT_CHUNKS) {
+            5
+        } else if bytes == b"tIME" {
+            6
+        } else if bytes == b"IDAT" {
+            8
+        } else if bytes == b"IEND" {
+            10
+        } else if first_idat.is_none_or(|idat| index < idat) {
+            7
+        } else {
+            9
+        };
+        ordered.push((group, index, chunk));
+    }
+
+    // The sort is stable, so chunks of the same group keep their order
+    ordered.sort_by_key(|(group, _, _)| *group);
+    if sort_copyable {
+        ordered.sort_by(|(group_a, _, a), (group_b, _, b)| {
+            let sortable = |chunk: &Chunk| {
+                let chunk_type = chunk.chunk_type();
+                !chunk_type.is_critical() && chunk_type.is_safe_to_copy()
+            };
+            let key = |chunk: &Chunk| match sortable(chunk) {
+                true => Some((chunk.chunk_type().bytes(), chunk.data().to_vec())),
+                false => None,
+            };
+
+            // Chunks which can't be sorted compare as equal, keeping their order
+            group_a.cmp(group_b).then_with(|| match (key(a), key(b)) {
+                (Some(a), Some(b)) => a.cmp(&b),
+                (a, b) => a.is_some().cmp(&b.is_some()),
+            })
+        });
+    }
+
+    let moved = ordered
+        .iter()
+        .enumerate()
+        .filter(|(position, (_, index, _))| position != index)
+        .count();
+
+    Normalized {
+        png: Png::from_chunks(ordered.into_iter().map(|(_, _, chunk)| chunk).collect()),
+        moved,
+        merged,
+        dropped: 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk_type::ChunkType;
+    use std::str::FromStr;
+
+    fn png_with(types: &[&str]) -> Png {
+        Png::from_chunks(
+            types
+                .iter()
+                .enumerate()
+                .map(|(i, name)| Chunk::new(ChunkType::from_str(name).unwrap(), vec![i as u8]))
+                .collect(),
+        )
+    }
+
+    fn types(png: &Png) -> Vec<String> {
+        png.chunks()
+            .iter()
+            .map(|chunk| chunk.chunk_type().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_normalize_order() {
+        let png = png_with(&[
+            "IHDR", "tEXt", "pHYs", "PLTE", "sRGB", "ruSt", "tRNS", "IDAT", "tIME", "zTXt", "IDAT",
+            "prIv", "IEND",
+        ]);
+        let normalized = normalize(png);
+        assert_eq!(
+            types(&normalized.png),
+            vec![
+                "IHDR", "sRGB", "PLTE", "tRNS", "pHYs", "tEXt", "zTXt", "tIME", "ruSt", "IDAT",
+                "IDAT", "prIv", "IEND"
+            ]
+        );
+        assert_eq!(normalized.merged, 0);
+    }
+
+    #[test]
+    fn test_normalize_merges_duplicates() {
+        let png = png_with(&["IHDR", "gAMA", "gAMA", "IDAT", "tEXt", "tEXt", "IEND"]);
+        let normalized = normalize(png);
+        assert_eq!(
+            types(&normalized.png),
+            vec!["IHDR", "gAMA", "tEXt", "tEXt", "IDAT", "IEND"]
+        );
+        assert_eq!(normalized.merged, 1);
+        // The first gAMA is kept
+        assert_eq!(normalized.png.chunks()[1].data(), &[1]);
+    }
+
+    #[test]
+    fn test_deterministic() {
+        let text = |keyword: &str, text: &str| {
+            Chunk::new(
+                ChunkType::from_str("tEXt").unwrap(),
+                format!("{}\0{}", keyword, text).into_bytes(),
+            )
+        };
+        let chunk = |name: &str| Chunk::new(ChunkType::from_str(name).unwrap(), vec![]);
+
+        let a = Png::from_chunks(vec![
+            chunk("IHDR"),
+            text("Title", "b"),
+            chunk("tIME"),
+            text("Creation Time", "yesterday"),
+            text("Author", "a"),
+            chunk("IDAT"),
+            chunk("IEND"),
+        ]);
+        let b = Png::from_chunks(vec![
+            chunk("IHDR"),
+            text("Author", "a"),
+            chunk("IDAT"),
+            text("Title", "b"),
+            text("Creation Time", "today"),
+            chunk("IEND"),
+        ]);
+
+        let a = deterministic(a);
+        let b = deterministic(b);
+        assert_eq!(a.dropped, 2);
+        assert_eq!(b.dropped, 1);
+        assert_eq!(a.png.as_bytes(), b.png.as_bytes());
+        assert_eq!(a.png.chunks()[1].data(), b"Author\0a",);
+    }
+
+    #[test]
+    fn test_normalize_is_idempotent() {
+        let png = png_with(&["IHDR", "IDAT", "tEXt", "gAMA", "IEND"]);
+        let once = normalize(png).png;
+        let twice = normalize(once.clone());
+        assert_eq!(once, twice.png);
+        assert_eq!(twice.moved, 0);
+    }
+}
@@ -0,0 +1,287 @@
+use crate::{
+    chunk::Chunk,
+    chunk_type::ChunkType,
+    png::Png,
+    raster::{Raster, RasterError},
+};
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
+use std::{
+    collections::HashSet,
+    io::{Read, Write},
+};
+
+/// The largest chunk length allowed by the PNG specification
+const MAX_CHUNK_LENGTH: usize = (1 << 31) - 1;
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum OptimizeError {
+    #[error("the image data is not a valid zlib stream")]
+    CorruptImageData,
+
+    #[error(transparent)]
+    Pixels(#[from] RasterError),
+}
+
+/// Bytes saved by each technique of [`optimize`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Savings {
+    /// Repeated chunks which may only appear once, and ancillary chunks
+    /// identical to an earlier one
+    pub redundant: usize,
+    /// Chunk headers saved by merging the `IDAT` chunks into one
+    pub merged: usize,
+    /// Recompressing the image data with the strongest zlib setting
+    pub recompressed: usize,
+    /// Chunks after `IEND`
+    pub trailing: usize,
+}
+
+impl Savings {
+    pub fn total(&self) -> usize {
+        self.redundant + self.merged + self.recompressed + self.trailing
+    }
+}
+
+/// The size a chunk takes up in the file
+fn size(chunk: &Chunk) -> usize {
+    chunk.length() as usize + 12
+}
+
+fn is_idat(chunk: &Chunk) -> bool {
+    &chunk.chunk_type().bytes() == b"IDAT"
+}
+
+/// Shrinks a PNG without touching its pixels: redundant ancillary chunks and
+/// everything after `IEND` are dropped and the `IDAT` chunks are merged. With
+/// `recompress`, the image data is also deflated again at the highest
+/// compression level, if that makes it smaller.
+pub fn optimize(png: Png, recompress: bool) -> Result<(Png, Savings), OptimizeError> {
+    let mut savings = Savings::default();
+    let mut chunks = png.chunks().to_vec();
+
+    if let Some(end) = chunks
+        .iter()
+        .position(|chunk| &chunk.chunk_type().bytes() == b"IEND")
+    {
+        savings.trailing = chunks.drain(end + 1..).map(|chunk| size(&chunk)).sum();
+    }
+
+    let mut single = HashSet::new();
+    let mut ancillary = HashSet::new();
+    chunks.retain(|chunk| {
+        let chunk_type = chunk.chunk_type();
+        let redundant = (chunk_type.is_single() && !single.insert(chunk_type.bytes()))
+            || (!chunk_type.is_critical()
+                && !ancillary.insert((chunk_type.bytes(), chunk.data().to_vec())));
+        if redundant {
+            savings.redundant += size(chunk);
+        }
+        !redundant
+    });
+
+    let Some(first_idat) = chunks.iter().position(is_idat) else {
+        return Ok((Png::from_chunks(chunks), savings));
+    };
+    let idats: Vec<Chunk> = chunks
+        .iter()
+        .skip(first_idat)
+        .take_while(|chunk| is_idat(chunk))
+        .cloned()
+        .collect();
+    let data: Vec<u8> = idats
+        .iter()
+        .flat_map(|chunk| chunk.data().to_vec())
+        .collect();
+
+    let mut replacement = idats.clone();
+    if data.len() <= MAX_CHUNK_LENGTH {
+        replacement = vec![Chunk::new(idat_type(), data.clone())];
+        savings.merged = (idats.len() - 1) * 12;
+    }
+    if recompress {
+        let recompressed = deflate(&inflate(&data)?);
+        if recompressed.len() < data.len() && recompressed.len() <= MAX_CHUNK_LENGTH {
+            savings.recompressed = data.len() - recompressed.len();
+            savings.merged = (idats.len() - 1) * 12;
+            replacement = vec![Chunk::new(idat_type(), recompressed)];
+        }
+    }
+
+    chunks.splice(first_idat..first_idat + idats.len(), replacement);
+    Ok((Png::from_chunks(chunks), savings))
+}
+
+/// The bytes of image data in all `IDAT` chunks
+pub fn idat_size(png: &Png) -> usize {
+    png.chunks()
+        .iter()
+        .filter(|chunk| is_idat(chunk))
+        .map(|chunk| chunk.data().len())
+        .sum()
+}
+
+/// The size of the image data before and after [`refilter`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Refiltered {
+    pub before: usize,
+    pub after: usize,
+}
+
+/// Chooses the filter of every scanline again, with the heuristic
+/// recommended by the specification, and deflates the image data at the
+/// highest compression level. Encoders often use a single filter for the
+/// whole image, so this tends to save more than recompressing alone. The
+/// PNG is only changed if its image data gets smaller.
+pub fn refilter(png: &mut Png) -> Result<Refiltered, OptimizeError> {
+    let raster = Raster::decode(png)?;
+    let mut refiltered = png.clone();
+    raster.store(&mut refiltered, raster.header.interlaced)?;
+
+    let (before, after) = (idat_size(png), idat_size(&refiltered));
+    if after < before {
+        *png = refiltered;
+        Ok(Refiltered { before, after })
+    } else {
+        Ok(Refiltered {
+            before,
+            after: before,
+        })
+    }
+}
+
+fn idat_type() -> ChunkType {
+    ChunkType::try_from(*b"IDAT").unwrap()
+}
+
+fn inflate(data: &[u8]) -> Result<Vec<u8>, OptimizeError> {
+    let mut inflated = Vec::new();
+    ZlibDecoder::new(data)
+        .read_to_end(&mut inflated)
+        .map_err(|_| OptimizeError::CorruptImageData)?;
+    Ok(inflated)
+}
+
+fn deflate(data: &[u8]) -> Vec<u8> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::best());
+    encoder
+        .write_all(data)
+        .expect("writing to a Vec never fails");
+    encoder.finish().expect("writing to a Vec never fails")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn chunk(chunk_type: &str, data: &[u8]) -> Chunk {
+        Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data.to_vec())
+    }
+
+    /// A PNG whose image data is stored without compression and split into
+    /// three chunks, with redundant chunks and a chunk after IEND
+    fn testing_png() -> Png {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::none());
+        encoder.write_all(&[0; 3000]).unwrap();
+        let data = encoder.finish().unwrap();
+        let third = data.len() / 3;
+
+        Png::from_chunks(vec![
+            chunk("IHDR", &[0; 13]),
+            chunk("gAMA", &[0, 0, 177, 143]),
+            chunk("gAMA", &[0, 0, 177, 143]),
+            chunk("tEXt", b"Title\0Dice"),
+            chunk("tEXt", b"Title\0Dice"),
+            chunk("tEXt", b"Title\0Other"),
+            chunk("IDAT", &data[..third]),
+            chunk("IDAT", &data[third..2 * third]),
+            chunk("IDAT", &data[2 * third..]),
+            chunk("IEND", &[]),
+            chunk("ruSt", b"appended"),
+        ])
+    }
+
+    fn types(png: &Png) -> Vec<String> {
+        png.chunks()
+            .iter()
+            .map(|chunk| chunk.chunk_type().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_optimize() {
+        let png = testing_png();
+        let original = png.as_bytes().len();
+        let (optimized, savings) = optimize(png, false).unwrap();
+
+        assert_eq!(
+            types(&optimized),
+            ["IHDR", "gAMA", "tEXt", "tEXt", "IDAT", "IEND"]
+        );
+        assert_eq!(
+            savings,
+            Savings {
+                redundant: 16 + 22,
+                merged: 24,
+                recompressed: 0,
+                trailing: 20,
+            }
+        );
+        assert_eq!(optimized.as_bytes().len(), original - savings.total());
+    }
+
+    #[test]
+    fn test_recompress() {
+        let png = testing_png();
+        let pixels = inflate(
+            &png.chunks()
+                .iter()
+                .filter(|chunk| is_idat(chunk))
+                .flat_map(|chunk| chunk.data().to_vec())
+                .collect::<Vec<u8>>(),
+        )
+        .unwrap();
+        let original = png.as_bytes().len();
+
+        let (optimized, savings) = optimize(png, true).unwrap();
+        assert!(savings.recompressed > 2000);
+        assert_eq!(optimized.as_bytes().len(), original - savings.total());
+        let idat = optimized.chunk_by_type(&idat_type()).unwrap();
+        assert_eq!(inflate(idat.data()).unwrap(), pixels);
+    }
+
+    #[test]
+    fn test_refilter() {
+        let mut png = Png::try_from(&crate::png::tests::PNG_FILE[..]).unwrap();
+        let original = Raster::decode(&png).unwrap();
+        let refiltered = refilter(&mut png).unwrap();
+        assert!(refiltered.after < refiltered.before);
+
+        let raster = Raster::decode(&png).unwrap();
+        assert_eq!(
+            raster.compare(&original),
+            crate::raster::PixelComparison::Identical
+        );
+        assert_eq!(
+            types(&png),
+            ["IHDR", "sRGB", "gAMA", "pHYs", "IDAT", "RuSt", "IEND"]
+        );
+
+        // Nothing is left to gain the second time
+        let again = refilter(&mut png).unwrap();
+        assert_eq!(again.after, again.before);
+    }
+
+    #[test]
+    fn test_corrupt_image_data() {
+        let png = Png::from_chunks(vec![
+            chunk("IHDR", &[0; 13]),
+            chunk("IDAT", b"not zlib"),
+            chunk("IEND", &[]),
+        ]);
+        assert_eq!(
+            optimize(png, true).unwrap_err(),
+            OptimizeError::CorruptImageData
+        );
+    }
+}
@@ -0,0 +1,303 @@
+use crate::{
+    chunk::Chunk,
+    console::{self, Style, StyledText},
+    display::{self, DisplayOptions},
+    select::Selected,
+    template::{Template, TemplateParseError},
+};
+use serde::Serialize;
+use std::{fmt::Write, str::FromStr};
+
+/// The output format of chunk listings
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum OutputFormat {
+    /// Aligned columns with the index, type, size, CRC and the name of
+    /// registered types of each chunk. Critical and ancillary types are
+    /// [`console::paint`]ed differently.
+    #[default]
+    Table,
+
+    /// One line per chunk rendered from a [`Template`]
+    Template(Template),
+
+    /// A JSON array with one object per chunk
+    Json,
+
+    /// A header line followed by one row per chunk
+    Csv,
+
+    /// A YAML sequence with one mapping per chunk
+    Yaml,
+}
+
+/// Anything that isn't one of the format names is parsed as a [`Template`]
+impl FromStr for OutputFormat {
+    type Err = TemplateParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(Self::Json),
+            "csv" => Ok(Self::Csv),
+            "yaml" => Ok(Self::Yaml),
+            "table" => Ok(Self::Table),
+            template => Ok(Self::Template(template.parse()?)),
+        }
+    }
+}
+
+/// The properties of a chunk which are included in structured output
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ChunkRecord {
+    pub index: usize,
+    pub offset: usize,
+    #[serde(rename = "type")]
+    pub chunk_type: String,
+    pub length: u32,
+    pub crc: u32,
+    pub data: String,
+    /// The name of a registered type, see
+    /// [`ChunkType::registered_info`](crate::chunk_type::ChunkType::registered_info)
+    pub name: Option<String>,
+}
+
+impl ChunkRecord {
+    pub fn new(index: usize, offset: usize, chunk: &Chunk, options: &DisplayOptions) -> Self {
+        Self {
+            index,
+            offset,
+            chunk_type: chunk.chunk_type().to_string(),
+            length: chunk.length(),
+            crc: chunk.crc(),
+            data: options.data(chunk).to_string(),
+            name: chunk
+                .chunk_type()
+                .registered_info()
+                .map(|info| info.name.to_owned()),
+        }
+    }
+
+    /// Creates the records of the selected chunks
+    pub fn all(chunks: &[Selected], options: &DisplayOptions) -> Vec<Self> {
+        chunks
+            .iter()
+            .map(|selected| Self::new(selected.index, selected.offset, selected.chunk, options))
+            .collect()
+    }
+}
+
+impl OutputFormat {
+    /// Renders a listing of the selected chunks, without a trailing newline
+    pub fn render(&self, chunks: &[Selected], options: &DisplayOptions) -> String {
+        match self {
+            Self::Table => render_table(chunks, options),
+            Self::Template(template) => chunks
+                .iter()
+                .map(|selected| {
+                    template.render(selected.index, selected.offset, selected.chunk, options)
+                })
+                .collect::<Vec<_>>()
+                .join("\n"),
+            Self::Json => serde_json::to_string_pretty(&ChunkRecord::all(chunks, options))
+                .expect("chunk records are always serializable"),
+            Self::Csv => render_csv(&ChunkRecord::all(chunks, options)),
+            Self::Yaml => render_yaml(&ChunkRecord::all(chunks, options)),
+        }
+    }
+
+    /// Like [`render`](Self::render), keeping the colors of the table. The
+    /// other formats hold data from the file, so they are never styled.
+    pub fn render_styled(&self, chunks: &[Selected], options: &DisplayOptions) -> StyledText {
+        let text = self.render(chunks, options);
+        match self {
+            Self::Table => StyledText::from_painted(&text),
+            _ => StyledText::plain(text),
+        }
+    }
+}
+
+fn render_table(chunks: &[Selected], options: &DisplayOptions) -> String {
+    let rows: Vec<Vec<String>> = chunks
+        .iter()
+        .map(|selected| {
+            let chunk = selected.chunk;
+            let style = match chunk.chunk_type().is_critical() {
+                true => Style::Critical,
+                false => Style::Ancillary,
+            };
+            vec![
+                selected.index.to_string(),
+                console::paint(chunk.chunk_type(), style).to_string(),
+                options.size(chunk.length() as u64),
+                format!("{:08x}", chunk.crc()),
+                chunk
+                    .chunk_type()
+                    .registered_info()
+                    .map(|info| info.name.to_owned())
+                    .unwrap_or_default(),
+            ]
+        })
+        .collect();
+    display::columns(&rows, &[true, false, true])
+}
+
+fn render_csv(records: &[ChunkRecord]) -> String {
+    let mut out = String::from("index,offset,type,length,crc,data,name");
+    for record in records {
+        write!(
+            out,
+            "\n{},{},{},{},{},{},{}",
+            record.index,
+            record.offset,
+            csv_field(&record.chunk_type),
+            record.length,
+            record.crc,
+            csv_field(&record.data),
+            csv_field(record.name.as_deref().unwrap_or_default())
+        )
+        .unwrap();
+    }
+    out
+}
+
+/// Quotes a field as described in RFC 4180 if necessary
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_owned()
+    }
+}
+
+fn render_yaml(records: &[ChunkRecord]) -> String {
+    if records.is_empty() {
+        return "[]".to_owned();
+    }
+
+    records
+        .iter()
+        .map(|record| {
+            // Strings are written as double quoted scalars, whose escaping
+            // rules are a superset of JSON's.
+            format!(
+                "- index: {}\n  offset: {}\n  type: {}\n  length: {}\n  crc: {}\n  data: {}\n  name: {}",
+                record.index,
+                record.offset,
+                serde_json::to_string(&record.chunk_type).unwrap(),
+                record.length,
+                record.crc,
+                serde_json::to_string(&record.data).unwrap(),
+                serde_json::to_string(&record.name).unwrap(),
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{chunk_type::ChunkType, png::Png, select::Selection};
+
+    fn render(format: OutputFormat, png: &Png) -> String {
+        format.render(
+            &Selection::default().select(png),
+            &DisplayOptions::default(),
+        )
+    }
+
+    fn testing_png() -> Png {
+        Png::from_chunks(vec![
+            Chunk::new(ChunkType::from_str("FrSt").unwrap(), b"plain".to_vec()),
+            Chunk::new(
+                ChunkType::from_str("miDl").unwrap(),
+                b"a, \"quoted\"\n".to_vec(),
+            ),
+        ])
+    }
+
+    #[test]
+    fn test_parse_format() {
+        assert_eq!(OutputFormat::from_str("json"), Ok(OutputFormat::Json));
+        assert_eq!(OutputFormat::from_str("csv"), Ok(OutputFormat::Csv));
+        assert_eq!(OutputFormat::from_str("yaml"), Ok(OutputFormat::Yaml));
+        assert_eq!(OutputFormat::from_str("table"), Ok(OutputFormat::Table));
+        assert!(matches!(
+            OutputFormat::from_str("{type}"),
+            Ok(OutputFormat::Template(_))
+        ));
+        assert!(OutputFormat::from_str("{nope}").is_err());
+    }
+
+    #[test]
+    fn test_render_table() {
+        let mut png = testing_png();
+        png.append_chunk(Chunk::new(
+            ChunkType::from_str("IDAT").unwrap(),
+            vec![0; 2048],
+        ));
+        let table = render(OutputFormat::Table, &png);
+        let lines: Vec<&str> = table.lines().collect();
+        assert_eq!(
+            lines[0],
+            format!(
+                "0  {}      5 B  {:08x}",
+                console::paint("FrSt", Style::Critical),
+                png.chunks()[0].crc()
+            )
+        );
+        let idat = console::paint("IDAT", Style::Critical);
+        assert!(lines[2].starts_with(&format!("2  {}  2.0 KiB  ", idat)));
+        assert!(lines[2].ends_with("  Image data"));
+
+        let exact = OutputFormat::Table.render(
+            &Selection::default().select(&png),
+            &DisplayOptions {
+                exact_sizes: true,
+                ..Default::default()
+            },
+        );
+        assert!(exact
+            .lines()
+            .nth(2)
+            .unwrap()
+            .starts_with(&format!("2  {}  2048  ", idat)));
+    }
+
+    #[test]
+    fn test_render_csv() {
+        let csv = render(OutputFormat::Csv, &testing_png());
+        let expected = format!(
+            "index,offset,type,length,crc,data,name\n0,8,FrSt,5,{},plain,\n1,25,miDl,12,{},\"a, \"\"quoted\"\"\n\",",
+            testing_png().chunks()[0].crc(),
+            testing_png().chunks()[1].crc(),
+        );
+        assert_eq!(csv, expected);
+    }
+
+    #[test]
+    fn test_render_json() {
+        let json = render(OutputFormat::Json, &testing_png());
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(value[1]["type"], "miDl");
+        assert_eq!(value[1]["data"], "a, \"quoted\"\n");
+        assert_eq!(value[1]["name"], serde_json::Value::Null);
+
+        let gamma = Png::from_chunks(vec![Chunk::new(
+            ChunkType::from_str("gAMA").unwrap(),
+            vec![0, 0, 177, 143],
+        )]);
+        let json = render(OutputFormat::Json, &gamma);
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(value[0]["name"], "Image gamma");
+    }
+
+    #[test]
+    fn test_render_yaml() {
+        let yaml = render(OutputFormat::Yaml, &testing_png());
+        assert!(yaml.starts_with("- index: 0\n  offset: 8\n  type: \"FrSt\"\n"));
+        assert!(yaml.contains("\n  data: \"a, \\\"quoted\\\"\\n\""));
+
+        let empty = render(OutputFormat::Yaml, &Png::from_chunks(vec![]));
+        assert_eq!(empty, "[]");
+    }
+}
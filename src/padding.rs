@@ -0,0 +1,157 @@
+use crate::{chunk::Chunk, chunk_type::ChunkType, png::Png};
+use std::{collections::hash_map::RandomState, hash::BuildHasher, str::FromStr};
+
+/// The chunk holding the padding, which readers can discard
+pub const PADDING_CHUNK: &[u8; 4] = b"paDd";
+
+/// The bytes a chunk takes besides its data: length, type and CRC
+const OVERHEAD: usize = 12;
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum PaddingError {
+    #[error("the file is already {size} bytes, it can't be padded to {target}")]
+    TooLarge { size: usize, target: usize },
+
+    #[error("the file is {size} bytes, padding it to {target} needs room for a chunk of at least {OVERHEAD} bytes")]
+    TooClose { size: usize, target: usize },
+
+    #[error("the range must look like 100..200, with the start not above the end")]
+    InvalidRange,
+}
+
+/// How much padding [`pad`] adds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Padding {
+    /// Makes the file exactly this many bytes long
+    To(usize),
+    /// A padding chunk with between `min` and `max` bytes of data, both
+    /// included
+    Random { min: usize, max: usize },
+}
+
+impl Padding {
+    /// Parses the range of [`Padding::Random`], e.g. `100..200`
+    pub fn random_range(s: &str) -> Result<Self, PaddingError> {
+        let (min, max) = s.split_once("..").ok_or(PaddingError::InvalidRange)?;
+        let parse = |n: &str| usize::from_str(n.trim()).map_err(|_| PaddingError::InvalidRange);
+        let (min, max) = (parse(min)?, parse(max)?);
+        if min > max {
+            return Err(PaddingError::InvalidRange);
+        }
+        Ok(Self::Random { min, max })
+    }
+}
+
+/// Bytes which are different on every run
+pub fn random_bytes(len: usize) -> Vec<u8> {
+    let state = RandomState::new();
+    (0..len).map(|i| state.hash_one(i) as u8).collect()
+}
+
+/// Removes the padding chunks, returning how many bytes they took up
+pub fn strip(png: &mut Png) -> usize {
+    let mut removed = 0;
+    png.retain(|chunk| {
+        let padding = &chunk.chunk_type().bytes() == PADDING_CHUNK;
+        if padding {
+            removed += chunk.length() as usize + OVERHEAD;
+        }
+        !padding
+    });
+    removed
+}
+
+/// Pads `png` with random bytes in a [`PADDING_CHUNK`] chunk before `IEND`,
+/// replacing earlier padding, so that the size of the file doesn't tell how
+/// large the payload is. Returns the bytes the padding chunk takes up.
+pub fn pad(png: &mut Png, padding: Padding) -> Result<usize, PaddingError> {
+    strip(png);
+    let len = match padding {
+        Padding::To(target) => {
+            let size = png.total_size();
+            if size == target {
+                return Ok(0);
+            }
+            if size > target {
+                return Err(PaddingError::TooLarge { size, target });
+            }
+            if target - size < OVERHEAD {
+                return Err(PaddingError::TooClose { size, target });
+            }
+            target - size - OVERHEAD
+        }
+        Padding::Random { min, max } => {
+            let spread = u64::try_from(max - min).unwrap_or(u64::MAX);
+            let random = u64::from_be_bytes(random_bytes(8).try_into().unwrap());
+            min + (random % spread.saturating_add(1)) as usize
+        }
+    };
+    let chunk_type = ChunkType::try_from(*PADDING_CHUNK).unwrap();
+    png.insert_before_end(Chunk::new(chunk_type, random_bytes(len)));
+    Ok(len + OVERHEAD)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::png::tests::PNG_FILE;
+
+    #[test]
+    fn test_pad_to() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let size = png.total_size();
+        assert_eq!(pad(&mut png, Padding::To(10_000)).unwrap(), 10_000 - size);
+        assert_eq!(png.as_bytes().len(), 10_000);
+        assert_eq!(
+            png.chunks().last().unwrap().chunk_type().to_string(),
+            "IEND"
+        );
+
+        // Padding again replaces the padding
+        pad(&mut png, Padding::To(8_000)).unwrap();
+        assert_eq!(png.as_bytes().len(), 8_000);
+        assert_eq!(strip(&mut png), 8_000 - size);
+        assert_eq!(png.as_bytes(), PNG_FILE);
+
+        assert_eq!(pad(&mut png, Padding::To(size)), Ok(0));
+        assert_eq!(
+            pad(&mut png, Padding::To(size - 1)),
+            Err(PaddingError::TooLarge {
+                size,
+                target: size - 1
+            })
+        );
+        assert_eq!(
+            pad(&mut png, Padding::To(size + 11)),
+            Err(PaddingError::TooClose {
+                size,
+                target: size + 11
+            })
+        );
+    }
+
+    #[test]
+    fn test_pad_random() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        for _ in 0..20 {
+            let added = pad(&mut png, Padding::Random { min: 10, max: 20 }).unwrap();
+            assert!((22..=32).contains(&added));
+            assert_eq!(png.as_bytes().len(), PNG_FILE.len() + added);
+        }
+        assert_eq!(pad(&mut png, Padding::Random { min: 0, max: 0 }), Ok(12));
+    }
+
+    #[test]
+    fn test_random_range() {
+        assert_eq!(
+            Padding::random_range("100..200"),
+            Ok(Padding::Random { min: 100, max: 200 })
+        );
+        for invalid in ["200..100", "100", "a..b", "..5"] {
+            assert_eq!(
+                Padding::random_range(invalid),
+                Err(PaddingError::InvalidRange)
+            );
+        }
+    }
+}
@@ -0,0 +1,237 @@
+use crate::{chunk::Chunk, chunk_type::ChunkType, ihdr::ColorType, png::Png};
+use flate2::{write::ZlibEncoder, Compression};
+use std::{fmt::Write as _, io::Write, str::FromStr};
+
+/// The side of a color square in exported swatch images, in pixels
+const SWATCH_SIZE: usize = 16;
+
+/// The number of colors per row in exported palettes
+const COLUMNS: usize = 16;
+
+/// A palette entry, with its alpha from the `tRNS` chunk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Color {
+    pub red: u8,
+    pub green: u8,
+    pub blue: u8,
+    pub alpha: u8,
+}
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum PaletteError {
+    #[error("the image has no palette")]
+    Missing,
+
+    #[error("the PLTE chunk has {0} bytes of data, which is not a multiple of 3")]
+    InvalidLength(usize),
+
+    #[error("the PLTE chunk has {0} entries, but at most 256 are allowed")]
+    TooManyEntries(usize),
+
+    #[error("the tRNS chunk has {alphas} alpha values for {entries} palette entries")]
+    TooManyAlphas { alphas: usize, entries: usize },
+}
+
+/// The file format of an exported palette
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PaletteFormat {
+    /// A GIMP palette, also read by Inkscape, Krita and Aseprite
+    Gpl,
+
+    /// An image with a square of each color
+    Png,
+}
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+#[error("unknown palette format '{0}', expected gpl or png")]
+pub struct UnknownPaletteFormatError(String);
+
+impl FromStr for PaletteFormat {
+    type Err = UnknownPaletteFormatError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "gpl" => Ok(Self::Gpl),
+            "png" => Ok(Self::Png),
+            other => Err(UnknownPaletteFormatError(other.to_owned())),
+        }
+    }
+}
+
+fn find<'a>(png: &'a Png, chunk_type: &[u8; 4]) -> Option<&'a Chunk> {
+    png.chunks()
+        .iter()
+        .find(|chunk| &chunk.chunk_type().bytes() == chunk_type)
+}
+
+/// Reads the palette of `png`. Entries are opaque unless the image is an
+/// indexed one with a `tRNS` chunk, as `tRNS` holds a single transparent
+/// color for the other color types.
+pub fn read(png: &Png) -> Result<Vec<Color>, PaletteError> {
+    let data = find(png, b"PLTE").ok_or(PaletteError::Missing)?.data();
+    if data.len() % 3 != 0 {
+        return Err(PaletteError::InvalidLength(data.len()));
+    }
+    let entries = data.len() / 3;
+    if entries > 256 {
+        return Err(PaletteError::TooManyEntries(entries));
+    }
+
+    let indexed = png.color_type() == Ok(ColorType::Indexed);
+    let alphas = match find(png, b"tRNS") {
+        Some(chunk) if indexed => chunk.data(),
+        _ => &[],
+    };
+    if alphas.len() > entries {
+        return Err(PaletteError::TooManyAlphas {
+            alphas: alphas.len(),
+            entries,
+        });
+    }
+
+    Ok(data
+        .chunks_exact(3)
+        .enumerate()
+        .map(|(index, rgb)| Color {
+            red: rgb[0],
+            green: rgb[1],
+            blue: rgb[2],
+            alpha: alphas.get(index).copied().unwrap_or(255),
+        })
+        .collect())
+}
+
+/// Writes the palette in the GIMP palette format. The format has no alpha
+/// channel, so transparency is lost.
+pub fn to_gpl(colors: &[Color], name: &str) -> String {
+    let mut gpl = format!("GIMP Palette\nName: {}\nColumns: {}\n#\n", name, COLUMNS);
+    for (index, color) in colors.iter().enumerate() {
+        writeln!(
+            gpl,
+            "{:3} {:3} {:3}\tIndex {}",
+            color.red, color.green, color.blue, index
+        )
+        .unwrap();
+    }
+    gpl
+}
+
+/// Draws a square for every color, in rows of 16, as an RGBA image
+pub fn to_png(colors: &[Color]) -> Png {
+    let columns = colors.len().clamp(1, COLUMNS);
+    let rows = colors.len().div_ceil(COLUMNS).max(1);
+    let width = columns * SWATCH_SIZE;
+    let height = rows * SWATCH_SIZE;
+
+    let mut pixels = Vec::with_capacity(height * (width * 4 + 1));
+    for y in 0..height {
+        // Every scanline starts with its filter type, 0 for none
+        pixels.push(0);
+        for x in 0..width {
+            let index = y / SWATCH_SIZE * COLUMNS + x / SWATCH_SIZE;
+            pixels.extend(match colors.get(index) {
+                Some(color) => [color.red, color.green, color.blue, color.alpha],
+                None => [0; 4],
+            });
+        }
+    }
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder
+        .write_all(&pixels)
+        .expect("writing to a Vec never fails");
+    let image_data = encoder.finish().expect("writing to a Vec never fails");
+
+    let mut header = Vec::with_capacity(13);
+    header.extend((width as u32).to_be_bytes());
+    header.extend((height as u32).to_be_bytes());
+    // 8 bits per sample, RGBA, default compression, filtering and no interlacing
+    header.extend([8, 6, 0, 0, 0]);
+
+    let chunk =
+        |chunk_type: &[u8; 4], data| Chunk::new(ChunkType::try_from(*chunk_type).unwrap(), data);
+    Png::from_chunks(vec![
+        chunk(b"IHDR", header),
+        chunk(b"IDAT", image_data),
+        chunk(b"IEND", Vec::new()),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(chunk_type: &[u8; 4], data: &[u8]) -> Chunk {
+        Chunk::new(ChunkType::try_from(*chunk_type).unwrap(), data.to_vec())
+    }
+
+    fn indexed_png(palette: &[u8], alphas: Option<&[u8]>) -> Png {
+        let mut chunks = vec![
+            chunk(b"IHDR", &[0, 0, 0, 4, 0, 0, 0, 4, 8, 3, 0, 0, 0]),
+            chunk(b"PLTE", palette),
+        ];
+        if let Some(alphas) = alphas {
+            chunks.push(chunk(b"tRNS", alphas));
+        }
+        chunks.push(chunk(b"IEND", &[]));
+        Png::from_chunks(chunks)
+    }
+
+    #[test]
+    fn test_read() {
+        let png = indexed_png(&[255, 0, 0, 0, 255, 0, 0, 0, 255], Some(&[0]));
+        let colors = read(&png).unwrap();
+        assert_eq!(
+            colors,
+            [
+                Color {
+                    red: 255,
+                    green: 0,
+                    blue: 0,
+                    alpha: 0
+                },
+                Color {
+                    red: 0,
+                    green: 255,
+                    blue: 0,
+                    alpha: 255
+                },
+                Color {
+                    red: 0,
+                    green: 0,
+                    blue: 255,
+                    alpha: 255
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_read_errors() {
+        let png = Png::from_chunks(vec![chunk(b"IEND", &[])]);
+        assert_eq!(read(&png), Err(PaletteError::Missing));
+        assert_eq!(
+            read(&indexed_png(&[1, 2], None)),
+            Err(PaletteError::InvalidLength(2))
+        );
+        assert_eq!(
+            read(&indexed_png(&[1, 2, 3], Some(&[0, 0]))),
+            Err(PaletteError::TooManyAlphas {
+                alphas: 2,
+                entries: 1
+            })
+        );
+    }
+
+    #[test]
+    fn test_export() {
+        let colors = read(&indexed_png(&[255, 0, 0, 0, 128, 255], None)).unwrap();
+        assert_eq!(
+            to_gpl(&colors, "sprites"),
+            "GIMP Palette\nName: sprites\nColumns: 16\n#\n255   0   0\tIndex 0\n  0 128 255\tIndex 1\n"
+        );
+
+        let swatches = to_png(&colors);
+        assert_eq!((swatches.width(), swatches.height()), (Ok(32), Ok(16)));
+        assert!(Png::try_from(&swatches.as_bytes()[..]).is_ok());
+    }
+}
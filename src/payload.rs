@@ -0,0 +1,569 @@
+use sha2::{Digest, Sha256};
+use std::{fmt::Display, str::FromStr};
+
+/// Marks data as a payload prefixed with its SHA-256
+pub const ENVELOPE_MAGIC: &[u8; 4] = b"PMie";
+
+/// Marks data as one part of a payload spread across several files
+pub const PART_MAGIC: &[u8; 4] = b"PMpt";
+
+/// The magic, the payload id, the index and the number of parts
+const PART_HEADER_LEN: usize = 4 + 8 + 2 + 2;
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum PayloadError {
+    #[error("the data is not part of a spread payload")]
+    NotAPart,
+
+    #[error("the part header is invalid: part {index} of {count}")]
+    InvalidPart { index: u16, count: u16 },
+
+    #[error("a payload can be spread across at most 65535 files, got {0}")]
+    TooManyParts(usize),
+
+    #[error("part {} is missing", .0 + 1)]
+    MissingPart(usize),
+
+    #[error("part {} was given more than once", .0 + 1)]
+    DuplicatePart(usize),
+
+    #[error("the parts belong to different payloads")]
+    MixedPayloads,
+
+    #[error("the data has no integrity envelope")]
+    NotAnEnvelope,
+
+    #[error(
+        "the envelope uses unknown features (flags {0:#04x}), it was written by a newer version"
+    )]
+    UnknownFlags(u8),
+
+    #[error("the payload is corrupted, its SHA-256 is {actual} instead of {expected}")]
+    Corrupted { expected: String, actual: String },
+}
+
+pub(crate) fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// The envelope records when the payload expires
+const FLAG_EXPIRES: u8 = 1;
+
+/// The envelope records the content type of the payload
+const FLAG_CONTENT_TYPE: u8 = 2;
+
+/// The media type of a payload, e.g. `application/json`. At most 255
+/// printable ASCII characters, with a `/` before any parameters.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContentType(String);
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+#[error("invalid content type '{0}', expected a media type like application/json")]
+pub struct InvalidContentTypeError(String);
+
+impl FromStr for ContentType {
+    type Err = InvalidContentTypeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let essence = s.split(';').next().unwrap_or_default().trim();
+        let valid = s.len() <= 255
+            && s.bytes().all(|byte| (b' '..=b'~').contains(&byte))
+            && essence
+                .split_once('/')
+                .is_some_and(|(kind, subtype)| !kind.is_empty() && !subtype.is_empty());
+        match valid {
+            true => Ok(Self(s.to_owned())),
+            false => Err(InvalidContentTypeError(s.to_owned())),
+        }
+    }
+}
+
+impl Display for ContentType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl ContentType {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// The type and subtype in lowercase, without parameters
+    pub fn essence(&self) -> String {
+        self.0
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase()
+    }
+
+    pub fn is_json(&self) -> bool {
+        let essence = self.essence();
+        essence == "application/json" || essence.ends_with("+json")
+    }
+
+    pub fn is_gzip(&self) -> bool {
+        matches!(
+            self.essence().as_str(),
+            "application/gzip" | "application/x-gzip"
+        )
+    }
+
+    /// Whether the payload is meant to be read as text
+    pub fn is_text(&self) -> bool {
+        let essence = self.essence();
+        self.is_json()
+            || essence.starts_with("text/")
+            || essence.ends_with("+xml")
+            || matches!(
+                essence.as_str(),
+                "application/xml" | "application/javascript" | "application/toml"
+            )
+    }
+}
+
+/// A payload together with the SHA-256 of its original contents, so damage
+/// can be detected independently of the checksums of the carrier format.
+///
+/// Its header consists of [`ENVELOPE_MAGIC`], a flags byte, the fields
+/// announced by the flags and the digest.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Envelope {
+    /// Seconds since the unix epoch
+    expires: Option<i64>,
+    content_type: Option<ContentType>,
+    digest: [u8; 32],
+    payload: Vec<u8>,
+}
+
+impl Envelope {
+    /// The number of bytes [`Envelope::peek_expiry`] looks at
+    pub const PEEK_LEN: usize = 13;
+
+    pub fn seal(payload: Vec<u8>) -> Self {
+        Self {
+            expires: None,
+            content_type: None,
+            digest: Sha256::digest(&payload).into(),
+            payload,
+        }
+    }
+
+    /// Records that the payload expires at `secs` since the unix epoch
+    pub fn with_expiry(mut self, secs: i64) -> Self {
+        self.expires = Some(secs);
+        self
+    }
+
+    pub fn expires(&self) -> Option<i64> {
+        self.expires
+    }
+
+    /// Records what kind of data the payload is
+    pub fn with_content_type(mut self, content_type: ContentType) -> Self {
+        self.content_type = Some(content_type);
+        self
+    }
+
+    pub fn content_type(&self) -> Option<&ContentType> {
+        self.content_type.as_ref()
+    }
+
+    /// Whether the expiry has been reached at `now` seconds since the unix
+    /// epoch
+    pub fn is_expired(&self, now: i64) -> bool {
+        self.expires.is_some_and(|expires| expires <= now)
+    }
+
+    pub fn payload(&self) -> &[u8] {
+        &self.payload
+    }
+
+    /// Returns the payload without checking it
+    pub fn into_payload(self) -> Vec<u8> {
+        self.payload
+    }
+
+    /// Returns the payload if it still matches its digest
+    pub fn open(self) -> Result<Vec<u8>, PayloadError> {
+        let actual: [u8; 32] = Sha256::digest(&self.payload).into();
+        if actual != self.digest {
+            return Err(PayloadError::Corrupted {
+                expected: hex(&self.digest),
+                actual: hex(&actual),
+            });
+        }
+        Ok(self.payload)
+    }
+
+    /// Reads the expiry from the start of an envelope, without the need for
+    /// the rest of it. Returns `None` if `bytes` is not an envelope or it
+    /// doesn't expire.
+    pub fn peek_expiry(bytes: &[u8]) -> Option<i64> {
+        if !bytes.starts_with(ENVELOPE_MAGIC) || bytes.get(4)? & FLAG_EXPIRES == 0 {
+            return None;
+        }
+        Some(i64::from_be_bytes(
+            bytes.get(5..Self::PEEK_LEN)?.try_into().unwrap(),
+        ))
+    }
+
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut bytes = ENVELOPE_MAGIC.to_vec();
+        let mut flags = 0;
+        if self.expires.is_some() {
+            flags |= FLAG_EXPIRES;
+        }
+        if self.content_type.is_some() {
+            flags |= FLAG_CONTENT_TYPE;
+        }
+        bytes.push(flags);
+        // The expiry comes first, so it can be peeked at a fixed offset
+        if let Some(expires) = self.expires {
+            bytes.extend_from_slice(&expires.to_be_bytes());
+        }
+        if let Some(content_type) = &self.content_type {
+            // At most 255 bytes, checked when parsing it
+            bytes.push(content_type.0.len() as u8);
+            bytes.extend_from_slice(content_type.0.as_bytes());
+        }
+        bytes.extend_from_slice(&self.digest);
+        bytes.extend_from_slice(&self.payload);
+        bytes
+    }
+}
+
+impl TryFrom<&[u8]> for Envelope {
+    type Error = PayloadError;
+
+    fn try_from(bytes: &[u8]) -> Result<Self, Self::Error> {
+        if !bytes.starts_with(ENVELOPE_MAGIC) || bytes.len() < 5 {
+            return Err(PayloadError::NotAnEnvelope);
+        }
+        let flags = bytes[4];
+        if flags & !(FLAG_EXPIRES | FLAG_CONTENT_TYPE) != 0 {
+            return Err(PayloadError::UnknownFlags(flags));
+        }
+
+        let mut cursor = 5;
+        let mut take = |len: usize| {
+            let field = bytes
+                .get(cursor..cursor + len)
+                .ok_or(PayloadError::NotAnEnvelope)?;
+            cursor += len;
+            Ok(field)
+        };
+        let expires = match flags & FLAG_EXPIRES {
+            0 => None,
+            _ => Some(i64::from_be_bytes(take(8)?.try_into().unwrap())),
+        };
+        let content_type = match flags & FLAG_CONTENT_TYPE {
+            0 => None,
+            _ => {
+                let len = take(1)?[0] as usize;
+                let content_type = std::str::from_utf8(take(len)?)
+                    .ok()
+                    .and_then(|content_type| content_type.parse().ok())
+                    .ok_or(PayloadError::NotAnEnvelope)?;
+                Some(content_type)
+            }
+        };
+        let digest = take(32)?.try_into().unwrap();
+
+        Ok(Self {
+            expires,
+            content_type,
+            digest,
+            payload: bytes[cursor..].to_vec(),
+        })
+    }
+}
+
+/// One part of a payload, prefixed with a header telling its position so
+/// the parts can be gathered in any order
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Part {
+    /// Identifies the payload the part belongs to
+    id: [u8; 8],
+    index: u16,
+    count: u16,
+    data: Vec<u8>,
+}
+
+impl Part {
+    /// The position of the part, starting at 0
+    pub fn index(&self) -> u16 {
+        self.index
+    }
+
+    /// The number of parts the payload was split into
+    pub fn count(&self) -> u16 {
+        self.count
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut bytes = PART_MAGIC.to_vec();
+        bytes.extend_from_slice(&self.id);
+        bytes.extend_from_slice(&self.index.to_be_bytes());
+        bytes.extend_from_slice(&self.count.to_be_bytes());
+        bytes.extend_from_slice(&self.data);
+        bytes
+    }
+}
+
+impl TryFrom<&[u8]> for Part {
+    type Error = PayloadError;
+
+    fn try_from(bytes: &[u8]) -> Result<Self, Self::Error> {
+        if bytes.len() < PART_HEADER_LEN || !bytes.starts_with(PART_MAGIC) {
+            return Err(PayloadError::NotAPart);
+        }
+        let index = u16::from_be_bytes(bytes[12..14].try_into().unwrap());
+        let count = u16::from_be_bytes(bytes[14..16].try_into().unwrap());
+        if index >= count {
+            return Err(PayloadError::InvalidPart { index, count });
+        }
+
+        Ok(Self {
+            id: bytes[4..12].try_into().unwrap(),
+            index,
+            count,
+            data: bytes[PART_HEADER_LEN..].to_vec(),
+        })
+    }
+}
+
+/// Splits `payload` into one part per weight, sized proportionally to the
+/// weights (e.g. the sizes of the carrier files)
+pub fn split(payload: &[u8], weights: &[u64]) -> Result<Vec<Part>, PayloadError> {
+    let count =
+        u16::try_from(weights.len()).map_err(|_| PayloadError::TooManyParts(weights.len()))?;
+    let id = Sha256::digest(payload)[..8].try_into().unwrap();
+    let total: u128 = weights.iter().map(|weight| *weight as u128).sum();
+
+    let mut parts = Vec::with_capacity(weights.len());
+    let mut start = 0;
+    let mut cumulative = 0;
+    for (index, weight) in weights.iter().enumerate() {
+        cumulative += *weight as u128;
+        let end = match total {
+            0 => payload.len() * (index + 1) / weights.len(),
+            total => (payload.len() as u128 * cumulative / total) as usize,
+        };
+        parts.push(Part {
+            id,
+            index: index as u16,
+            count,
+            data: payload[start..end].to_vec(),
+        });
+        start = end;
+    }
+    Ok(parts)
+}
+
+/// Reassembles a payload from all of its parts, given in any order
+pub fn join(mut parts: Vec<Part>) -> Result<Vec<u8>, PayloadError> {
+    let first = parts.first().ok_or(PayloadError::MissingPart(0))?;
+    let (id, count) = (first.id, first.count);
+    if parts
+        .iter()
+        .any(|part| part.id != id || part.count != count)
+    {
+        return Err(PayloadError::MixedPayloads);
+    }
+
+    parts.sort_by_key(|part| part.index);
+    for (expected, part) in parts.iter().enumerate() {
+        match (part.index as usize).cmp(&expected) {
+            std::cmp::Ordering::Less => return Err(PayloadError::DuplicatePart(expected - 1)),
+            std::cmp::Ordering::Greater => return Err(PayloadError::MissingPart(expected)),
+            std::cmp::Ordering::Equal => {}
+        }
+    }
+    if parts.len() < count as usize {
+        return Err(PayloadError::MissingPart(parts.len()));
+    }
+
+    Ok(parts.into_iter().flat_map(|part| part.data).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PAYLOAD: &[u8] = b"This payload is spread across several files";
+
+    #[test]
+    fn test_envelope() {
+        let bytes = Envelope::seal(PAYLOAD.to_vec()).as_bytes();
+        assert!(bytes.starts_with(ENVELOPE_MAGIC));
+        let envelope = Envelope::try_from(&bytes[..]).unwrap();
+        assert_eq!(envelope.payload(), PAYLOAD);
+        assert_eq!(envelope.open().unwrap(), PAYLOAD);
+
+        assert_eq!(
+            Envelope::try_from(PAYLOAD),
+            Err(PayloadError::NotAnEnvelope)
+        );
+    }
+
+    #[test]
+    fn test_corrupted_envelope() {
+        let mut bytes = Envelope::seal(PAYLOAD.to_vec()).as_bytes();
+        *bytes.last_mut().unwrap() ^= 1;
+        let envelope = Envelope::try_from(&bytes[..]).unwrap();
+        assert!(matches!(
+            envelope.clone().open(),
+            Err(PayloadError::Corrupted { .. })
+        ));
+        assert_eq!(envelope.into_payload(), &bytes[37..]);
+    }
+
+    #[test]
+    fn test_expiry() {
+        let envelope = Envelope::seal(PAYLOAD.to_vec()).with_expiry(1000);
+        assert!(!envelope.is_expired(999));
+        assert!(envelope.is_expired(1000));
+
+        let bytes = envelope.as_bytes();
+        assert_eq!(Envelope::peek_expiry(&bytes[..13]), Some(1000));
+        assert_eq!(Envelope::try_from(&bytes[..]).unwrap(), envelope);
+
+        let bytes = Envelope::seal(PAYLOAD.to_vec()).as_bytes();
+        assert_eq!(Envelope::peek_expiry(&bytes), None);
+        assert!(!Envelope::try_from(&bytes[..]).unwrap().is_expired(i64::MAX));
+    }
+
+    #[test]
+    fn test_content_type() {
+        let content_type: ContentType = "application/json; charset=utf-8".parse().unwrap();
+        assert_eq!(content_type.essence(), "application/json");
+        assert!(content_type.is_json() && content_type.is_text());
+        assert!("application/x-gzip"
+            .parse::<ContentType>()
+            .unwrap()
+            .is_gzip());
+        assert!(!"image/png".parse::<ContentType>().unwrap().is_text());
+        for invalid in ["json", "/json", "application/", "text/plain\n", ""] {
+            assert!(invalid.parse::<ContentType>().is_err(), "{}", invalid);
+        }
+
+        let envelope = Envelope::seal(PAYLOAD.to_vec())
+            .with_expiry(1000)
+            .with_content_type(content_type.clone());
+        let bytes = envelope.as_bytes();
+        assert_eq!(Envelope::peek_expiry(&bytes), Some(1000));
+        let parsed = Envelope::try_from(&bytes[..]).unwrap();
+        assert_eq!(parsed.content_type(), Some(&content_type));
+        assert_eq!(parsed.open().unwrap(), PAYLOAD);
+
+        // The content type claims more bytes than there are
+        assert_eq!(
+            Envelope::try_from(&bytes[..13 + content_type.as_str().len()]),
+            Err(PayloadError::NotAnEnvelope)
+        );
+    }
+
+    #[test]
+    fn test_unknown_flags() {
+        let mut bytes = Envelope::seal(PAYLOAD.to_vec()).as_bytes();
+        bytes[4] = 0x80;
+        assert_eq!(
+            Envelope::try_from(&bytes[..]),
+            Err(PayloadError::UnknownFlags(0x80))
+        );
+    }
+
+    #[test]
+    fn test_split_proportionally() {
+        let parts = split(PAYLOAD, &[100, 300, 0]).unwrap();
+        let lengths: Vec<usize> = parts.iter().map(|part| part.data().len()).collect();
+        assert_eq!(lengths, [10, 33, 0]);
+        assert!(parts.iter().all(|part| part.count() == 3));
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let parts = split(PAYLOAD, &[1, 2, 3]).unwrap();
+        let mut parsed: Vec<Part> = parts
+            .iter()
+            .map(|part| Part::try_from(&part.as_bytes()[..]).unwrap())
+            .collect();
+        assert_eq!(parsed, parts);
+
+        parsed.reverse();
+        assert_eq!(join(parsed).unwrap(), PAYLOAD);
+    }
+
+    #[test]
+    fn test_invalid_parts() {
+        assert_eq!(Part::try_from(&b"hello"[..]), Err(PayloadError::NotAPart));
+
+        let mut bytes = split(PAYLOAD, &[1]).unwrap()[0].as_bytes();
+        bytes[13] = 1;
+        assert_eq!(
+            Part::try_from(&bytes[..]),
+            Err(PayloadError::InvalidPart { index: 1, count: 1 })
+        );
+    }
+
+    #[test]
+    fn test_join_errors() {
+        let parts = split(PAYLOAD, &[1, 1, 1]).unwrap();
+        assert_eq!(
+            join(vec![parts[0].clone(), parts[2].clone()]),
+            Err(PayloadError::MissingPart(1))
+        );
+        assert_eq!(
+            join(vec![parts[0].clone(), parts[1].clone()]),
+            Err(PayloadError::MissingPart(2))
+        );
+        assert_eq!(
+            join(vec![parts[0].clone(), parts[0].clone(), parts[1].clone()]),
+            Err(PayloadError::DuplicatePart(0))
+        );
+
+        let other = split(b"another payload", &[1, 1, 1]).unwrap();
+        assert_eq!(
+            join(vec![parts[0].clone(), other[1].clone(), parts[2].clone()]),
+            Err(PayloadError::MixedPayloads)
+        );
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn prop_envelope_roundtrip(
+            payload in proptest::collection::vec(proptest::num::u8::ANY, 0..512),
+            expires in proptest::option::of(proptest::num::i64::ANY),
+        ) {
+            let mut envelope = Envelope::seal(payload.clone());
+            if let Some(expires) = expires {
+                envelope = envelope.with_expiry(expires);
+            }
+            let bytes = envelope.as_bytes();
+            proptest::prop_assert_eq!(Envelope::peek_expiry(&bytes), expires);
+            let opened = Envelope::try_from(bytes.as_ref()).unwrap().open();
+            proptest::prop_assert_eq!(opened, Ok(payload));
+        }
+
+        #[test]
+        fn prop_split_join(
+            payload in proptest::collection::vec(proptest::num::u8::ANY, 1..512),
+            weights in proptest::collection::vec(1..100u64, 1..8),
+        ) {
+            let parts = split(&payload, &weights).unwrap();
+            let parts = parts
+                .iter()
+                .map(|part| Part::try_from(part.as_bytes().as_ref()).unwrap())
+                .rev()
+                .collect();
+            proptest::prop_assert_eq!(join(parts), Ok(payload));
+        }
+    }
+}
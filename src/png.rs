@@ -1,13 +1,46 @@
+#[cfg(feature = "std")]
+use crate::magic;
 use crate::{
-    chunk::{Chunk, ChunkParseError},
+    cancel::{CancellationToken, Cancelled},
+    chunk::{Chunk, ChunkParseError, MAX_CHUNK_LENGTH},
     chunk_type::ChunkType,
+    ihdr::{ColorType, HeaderError, ImageHeader},
+};
+use alloc::{sync::Arc, vec::Vec};
+use core::{
+    fmt::{Debug, Display},
+    ops::{Index, IndexMut},
+};
+#[cfg(feature = "std")]
+use std::{
+    fs::File,
+    io::{self, Read},
+    path::Path,
 };
-use std::fmt::Display;
 
+/// The chunks of a PNG file. A parsed `Png` is `Send + Sync`, so it can be
+/// shared between threads, see [`Png::parse_shared`].
+#[derive(Clone, PartialEq, Eq)]
 pub struct Png(Vec<Chunk>);
 
+// Checked at compile time, like for `Chunk` and `ChunkType`
+const _: () = {
+    const fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Png>();
+};
+
 impl Png {
-    pub const STANDARD_HEADER: &[u8; 8] = &[137, 80, 78, 71, 13, 10, 26, 10];
+    /// The 8 bytes every PNG file starts with
+    pub const SIGNATURE: &[u8; 8] = &[137, 80, 78, 71, 13, 10, 26, 10];
+
+    /// The old name of [`Png::SIGNATURE`]
+    pub const STANDARD_HEADER: &[u8; 8] = Self::SIGNATURE;
+
+    /// Whether `bytes` start with the PNG signature. Nothing else is checked,
+    /// so this is cheap enough to filter large numbers of files.
+    pub fn is_png(bytes: &[u8]) -> bool {
+        bytes.starts_with(Self::SIGNATURE)
+    }
 
     pub fn from_chunks(chunks: Vec<Chunk>) -> Png {
         Self(chunks)
@@ -17,6 +50,50 @@ impl Png {
         self.0.push(chunk);
     }
 
+    /// Inserts a chunk at `index`, shifting all chunks after it.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is greater than the number of chunks.
+    pub fn insert_chunk(&mut self, index: usize, chunk: Chunk) {
+        self.0.insert(index, chunk);
+    }
+
+    /// Replaces the chunk at `index` and returns the old one, keeping the
+    /// position of every other chunk.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is out of bounds.
+    pub fn replace_chunk_at(&mut self, index: usize, chunk: Chunk) -> Chunk {
+        core::mem::replace(&mut self.0[index], chunk)
+    }
+
+    /// Swaps the chunks at `a` and `b`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `a` or `b` is out of bounds.
+    pub fn swap_chunks(&mut self, a: usize, b: usize) {
+        self.0.swap(a, b);
+    }
+
+    /// Inserts a chunk right before the `IEND` chunk, or at the end if there
+    /// is none
+    pub fn insert_before_end(&mut self, chunk: Chunk) {
+        let end = self
+            .0
+            .iter()
+            .position(|chunk| &chunk.chunk_type().bytes() == b"IEND")
+            .unwrap_or(self.0.len());
+        self.0.insert(end, chunk);
+    }
+
+    /// Only keeps the chunks for which `f` returns true
+    pub fn retain<F: FnMut(&Chunk) -> bool>(&mut self, f: F) {
+        self.0.retain(f);
+    }
+
     pub fn remove_chunk(&mut self, chunk_type: &ChunkType) -> anyhow::Result<Chunk> {
         match self
             .0
@@ -28,20 +105,93 @@ impl Png {
         }
     }
 
+    /// Removes every chunk of `chunk_type` and returns them in file order
+    pub fn take_chunks_of_type(&mut self, chunk_type: &ChunkType) -> Vec<Chunk> {
+        let (taken, kept) = core::mem::take(&mut self.0)
+            .into_iter()
+            .partition(|chunk| chunk.chunk_type() == chunk_type);
+        self.0 = kept;
+        taken
+    }
+
     pub fn header(&self) -> &[u8; 8] {
-        Self::STANDARD_HEADER
+        Self::SIGNATURE
+    }
+
+    /// The contents of the `IHDR` chunk. It is parsed on every call, so the
+    /// result always matches the current chunks.
+    pub fn image_header(&self) -> Result<ImageHeader, HeaderError> {
+        ImageHeader::from_png(self)
+    }
+
+    /// The width of the image in pixels
+    pub fn width(&self) -> Result<u32, HeaderError> {
+        Ok(self.image_header()?.width)
+    }
+
+    /// The height of the image in pixels
+    pub fn height(&self) -> Result<u32, HeaderError> {
+        Ok(self.image_header()?.height)
+    }
+
+    pub fn color_type(&self) -> Result<ColorType, HeaderError> {
+        Ok(self.image_header()?.color_type)
+    }
+
+    /// The number of bits per sample, or per palette index
+    pub fn bit_depth(&self) -> Result<u8, HeaderError> {
+        Ok(self.image_header()?.bit_depth)
     }
 
     pub fn chunks(&self) -> &[Chunk] {
         &self.0
     }
 
+    /// The number of chunks
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// The size of the serialized file in bytes, the signature included
+    pub fn total_size(&self) -> usize {
+        Self::SIGNATURE.len()
+            + self
+                .0
+                .iter()
+                .map(|chunk| chunk.length() as usize + 12)
+                .sum::<usize>()
+    }
+
+    /// The bytes the ancillary chunks take up in the serialized file, i.e.
+    /// what could be stripped without losing the image
+    pub fn ancillary_size(&self) -> usize {
+        self.0
+            .iter()
+            .filter(|chunk| !chunk.chunk_type().is_critical())
+            .map(|chunk| chunk.length() as usize + 12)
+            .sum()
+    }
+
+    /// Returns every chunk together with the byte offset it starts at in the
+    /// serialized file
+    pub fn chunks_with_offsets(&self) -> impl Iterator<Item = (usize, &Chunk)> {
+        self.0.iter().scan(Self::SIGNATURE.len(), |offset, chunk| {
+            let start = *offset;
+            *offset += chunk.length() as usize + 12;
+            Some((start, chunk))
+        })
+    }
+
     pub fn chunk_by_type(&self, chunk_type: &ChunkType) -> Option<&Chunk> {
         self.0.iter().find(|chunk| chunk.chunk_type() == chunk_type)
     }
 
     pub fn as_bytes(&self) -> Vec<u8> {
-        Self::STANDARD_HEADER
+        Self::SIGNATURE
             .iter()
             .copied()
             .chain(self.0.iter().flat_map(|chunk| chunk.as_bytes()))
@@ -49,27 +199,153 @@ impl Png {
     }
 }
 
+/// Generates any sequence of valid chunks. The chunk order, `IHDR` and
+/// `IEND` are not enforced, like they aren't when parsing.
+#[cfg(feature = "arbitrary")]
+impl<'a> arbitrary::Arbitrary<'a> for Png {
+    fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
+        Ok(Self::from_chunks(Vec::arbitrary(u)?))
+    }
+}
+
+#[cfg(any(test, feature = "test-util"))]
+impl Png {
+    /// Generates PNGs of up to `max_chunks` random chunks, followed by an
+    /// `IEND` chunk. The chunk order is not checked, like it isn't when
+    /// parsing.
+    pub fn arbitrary_valid(max_chunks: usize) -> impl proptest::strategy::Strategy<Value = Self> {
+        use proptest::prelude::*;
+        let chunk = Chunk::arbitrary_valid(256).prop_filter("IEND ends the PNG", |chunk| {
+            &chunk.chunk_type().bytes() != b"IEND"
+        });
+        proptest::collection::vec(chunk, 0..=max_chunks).prop_map(|mut chunks| {
+            let end = ChunkType::try_from(*b"IEND").unwrap();
+            chunks.push(Chunk::new(end, Vec::new()));
+            Self(chunks)
+        })
+    }
+}
+
+/// Checks whether the file at `path` is a PNG, reading only its first 8 bytes
+#[cfg(feature = "std")]
+pub fn sniff_file<P: AsRef<Path>>(path: P) -> io::Result<bool> {
+    let mut signature = Vec::with_capacity(Png::SIGNATURE.len());
+    File::open(path)?
+        .take(Png::SIGNATURE.len() as u64)
+        .read_to_end(&mut signature)?;
+    Ok(Png::is_png(&signature))
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum PngParseError {
     #[error("not a valid PNG")]
     Format,
 
+    #[cfg(feature = "std")]
+    #[error("not a PNG (looks like {0})")]
+    OtherFormat(magic::Format),
+
     #[error(transparent)]
     InvalidChunk(#[from] ChunkParseError),
-}
 
-impl TryFrom<&[u8]> for Png {
-    type Error = PngParseError;
+    #[error(transparent)]
+    Cancelled(#[from] Cancelled),
+}
 
-    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
-        // If we don't do this then we might panic when checking the header
-        if value.len() < 8 {
-            return Err(PngParseError::Format);
+impl PngParseError {
+    /// The error for a file which doesn't start with the PNG signature,
+    /// naming the format it has instead if it is recognized
+    #[cfg(feature = "std")]
+    pub(crate) fn from_signature(bytes: &[u8]) -> Self {
+        match magic::Format::detect(bytes) {
+            Some(format) => Self::OtherFormat(format),
+            None => Self::Format,
         }
+    }
+
+    /// Other formats are only recognized with `std`
+    #[cfg(not(feature = "std"))]
+    pub(crate) fn from_signature(_bytes: &[u8]) -> Self {
+        Self::Format
+    }
+}
+
+/// What [`Png::parse_with`] should do with a chunk, as decided by a [`ChunkVisitor`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Visit {
+    /// Keep the chunk in the resulting [`Png`]
+    Keep,
+
+    /// Leave the chunk out. Its data is neither copied nor checksummed.
+    Skip,
 
-        let header = &value[..8];
-        if header != Self::STANDARD_HEADER {
-            return Err(PngParseError::Format);
+    /// Stop parsing, leaving out this and all following chunks
+    Stop,
+}
+
+/// Callbacks invoked by [`Png::parse_with`] for every chunk it encounters
+pub trait ChunkVisitor {
+    /// Called once the length and type of a chunk are known, before its data
+    /// is looked at.
+    fn visit_header(&mut self, _length: u32, _chunk_type: &ChunkType) -> Visit {
+        Visit::Keep
+    }
+
+    /// Called with the data of every chunk that was kept by
+    /// [`ChunkVisitor::visit_header`], before it is copied.
+    fn visit_data(&mut self, _chunk_type: &ChunkType, _data: &[u8]) -> Visit {
+        Visit::Keep
+    }
+}
+
+/// Keeps every chunk, which is what a plain parse does
+pub struct KeepAll;
+
+impl ChunkVisitor for KeepAll {}
+
+impl Png {
+    /// Parses a PNG while letting `visitor` decide which chunks end up in the
+    /// result. Useful to look for a single chunk or collect statistics
+    /// without allocating every chunk of a large file.
+    pub fn parse_with<V: ChunkVisitor>(
+        value: &[u8],
+        visitor: &mut V,
+    ) -> Result<Png, PngParseError> {
+        Self::parse_cancellable(value, visitor, &CancellationToken::new())
+    }
+
+    /// Like [`Png::parse_with`], but gives up with [`PngParseError::Cancelled`]
+    /// as soon as `cancel` is cancelled.
+    pub fn parse_cancellable<V: ChunkVisitor>(
+        value: &[u8],
+        visitor: &mut V,
+        cancel: &CancellationToken,
+    ) -> Result<Png, PngParseError> {
+        Self::parse_chunks(value, visitor, cancel, None)
+    }
+
+    /// Parses a PNG whose chunks keep their data in `bytes` instead of
+    /// copying it, so the result and its chunks are cheap to clone. Useful to
+    /// hand one parsed file to many worker threads.
+    pub fn parse_shared(bytes: Arc<[u8]>) -> Result<Png, PngParseError> {
+        Self::parse_chunks(
+            &bytes,
+            &mut KeepAll,
+            &CancellationToken::new(),
+            Some(&bytes),
+        )
+    }
+
+    /// Parses the chunks of `value`, which is `shared` if the chunks should
+    /// point into it
+    fn parse_chunks<V: ChunkVisitor>(
+        value: &[u8],
+        visitor: &mut V,
+        cancel: &CancellationToken,
+        shared: Option<&Arc<[u8]>>,
+    ) -> Result<Png, PngParseError> {
+        if !Self::is_png(value) {
+            return Err(PngParseError::from_signature(value));
         }
 
         // I made this number up lol.
@@ -82,25 +358,120 @@ impl TryFrom<&[u8]> for Png {
         // Make sure at least a zero-sized chunk can still be read
         // length (4) + type (4) + data (0) + crc(4)
         while cursor + 12 <= value.len() {
-            // length field + 12 for length, type and crc
-            let chunk_size =
-                (u32::from_be_bytes(value[cursor..cursor + 4].try_into().unwrap()) + 12) as usize;
+            cancel.check()?;
 
-            // Incomplete chunk
-            if cursor + chunk_size > value.len() {
-                return Err(PngParseError::InvalidChunk(ChunkParseError::Incomplete));
+            let length = u32::from_be_bytes(value[cursor..cursor + 4].try_into().unwrap());
+            if length > MAX_CHUNK_LENGTH {
+                return Err(ChunkParseError::LengthOutOfRange(length).into());
             }
+            // length field + 12 for length, type and crc, which can't
+            // overflow in 64 bits
+            let needed = length as u64 + 12;
+            let available = (value.len() - cursor) as u64;
+            if needed > available {
+                return Err(ChunkParseError::Truncated { needed, available }.into());
+            }
+            let chunk_size = needed as usize;
 
-            chunks.push(Chunk::try_from(&value[cursor..cursor + chunk_size])?);
+            let range = cursor..cursor + chunk_size;
+            let chunk_bytes = &value[range.clone()];
             cursor += chunk_size;
+
+            let type_bytes: [u8; 4] = chunk_bytes[4..8].try_into().unwrap();
+            let chunk_type = ChunkType::try_from(type_bytes).map_err(ChunkParseError::from)?;
+
+            let visit = match visitor.visit_header(length, &chunk_type) {
+                Visit::Keep => visitor.visit_data(&chunk_type, &chunk_bytes[8..chunk_size - 4]),
+                visit => visit,
+            };
+            match visit {
+                Visit::Keep => chunks.push(match shared {
+                    Some(bytes) => Chunk::from_shared(bytes.clone(), range)?,
+                    None => Chunk::try_from(chunk_bytes)?,
+                }),
+                Visit::Skip => continue,
+                Visit::Stop => break,
+            }
         }
 
         Ok(Self(chunks))
     }
 }
 
+impl TryFrom<&[u8]> for Png {
+    type Error = PngParseError;
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        Self::parse_with(value, &mut KeepAll)
+    }
+}
+
+impl Index<usize> for Png {
+    type Output = Chunk;
+
+    fn index(&self, index: usize) -> &Chunk {
+        &self.0[index]
+    }
+}
+
+/// Chunks can be edited in place, their length and crc are kept up to date
+/// by [`Chunk`] itself
+impl IndexMut<usize> for Png {
+    fn index_mut(&mut self, index: usize) -> &mut Chunk {
+        &mut self.0[index]
+    }
+}
+
+impl IntoIterator for Png {
+    type Item = Chunk;
+    type IntoIter = alloc::vec::IntoIter<Chunk>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a Png {
+    type Item = &'a Chunk;
+    type IntoIter = core::slice::Iter<'a, Chunk>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.iter()
+    }
+}
+
+/// Appends the chunks like [`Png::append_chunk`], so they end up after any
+/// `IEND` chunk
+impl Extend<Chunk> for Png {
+    fn extend<T: IntoIterator<Item = Chunk>>(&mut self, iter: T) {
+        self.0.extend(iter);
+    }
+}
+
+/// Only shows a summary of each chunk, chunk data can easily be megabytes
+/// in size.
+impl Debug for Png {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        struct Summary<'a>(&'a Chunk);
+
+        impl Debug for Summary<'_> {
+            fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+                f.debug_struct("Chunk")
+                    .field("chunk_type", &format_args!("{}", self.0.chunk_type()))
+                    .field("length", &self.0.length())
+                    .field("crc", &self.0.crc())
+                    .finish_non_exhaustive()
+            }
+        }
+
+        f.debug_tuple("Png")
+            .field(&self.0.iter().map(Summary).collect::<Vec<_>>())
+            .finish()
+    }
+}
+
 impl Display for Png {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         write!(f, "Chunks [")?;
         for chunk in self.0.iter() {
             writeln!(f, "\t{}", chunk)?;
@@ -111,7 +482,7 @@ impl Display for Png {
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use super::*;
     use crate::chunk::Chunk;
     use crate::chunk_type::ChunkType;
@@ -185,6 +556,41 @@ mod tests {
         assert!(png.is_err());
     }
 
+    #[test]
+    fn test_signature() {
+        assert_eq!(Png::SIGNATURE, &PNG_FILE[..8]);
+        assert_eq!(Png::SIGNATURE, Png::STANDARD_HEADER);
+    }
+
+    #[test]
+    fn test_is_png() {
+        assert!(Png::is_png(&PNG_FILE));
+        assert!(!Png::is_png(&PNG_FILE[..7]));
+        assert!(!Png::is_png(b"GIF89a"));
+    }
+
+    #[test]
+    fn test_other_format() {
+        assert!(matches!(
+            Png::try_from(&b"GIF89a\x01\0\x01\0"[..]),
+            Err(PngParseError::OtherFormat(magic::Format::Gif))
+        ));
+        assert!(matches!(
+            Png::try_from(&b"not an image"[..]),
+            Err(PngParseError::Format)
+        ));
+    }
+
+    #[test]
+    fn test_sniff_file() {
+        let path = std::env::temp_dir().join(format!("pngme-sniff-{}.png", std::process::id()));
+        std::fs::write(&path, PNG_FILE).unwrap();
+        let sniffed = sniff_file(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert!(sniffed.unwrap());
+        assert!(!sniff_file("Cargo.toml").unwrap());
+    }
+
     #[test]
     fn test_invalid_chunk() {
         let mut chunk_bytes: Vec<u8> = testing_chunks()
@@ -207,6 +613,63 @@ mod tests {
         assert!(png.is_err());
     }
 
+    #[test]
+    fn test_declared_length_bounds() {
+        let with_length = |length: u32| {
+            let mut bytes = PNG_FILE[..Png::SIGNATURE.len()].to_vec();
+            bytes.extend_from_slice(&length.to_be_bytes());
+            bytes.extend_from_slice(b"RuSt0123456789");
+            Png::try_from(bytes.as_ref())
+        };
+
+        assert!(matches!(
+            with_length(MAX_CHUNK_LENGTH),
+            Err(PngParseError::InvalidChunk(ChunkParseError::Truncated {
+                needed,
+                available: 18,
+            })) if needed == (1 << 31) + 11
+        ));
+        for length in [1 << 31, u32::MAX - 11, u32::MAX] {
+            assert!(matches!(
+                with_length(length),
+                Err(PngParseError::InvalidChunk(ChunkParseError::LengthOutOfRange(found)))
+                    if found == length
+            ));
+        }
+    }
+
+    /// The inputs of a fuzz target's corpus, see `fuzz/`
+    pub(crate) fn fuzz_corpus(target: &str) -> Vec<(String, Vec<u8>)> {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("fuzz/corpus")
+            .join(target);
+        let mut inputs: Vec<_> = std::fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| {
+                let path = entry.unwrap().path();
+                let name = path.file_name().unwrap().to_string_lossy().into_owned();
+                (name, std::fs::read(path).unwrap())
+            })
+            .collect();
+        inputs.sort();
+        assert!(!inputs.is_empty());
+        inputs
+    }
+
+    #[test]
+    fn test_fuzz_corpus() {
+        for (name, input) in fuzz_corpus("parse_png") {
+            if let Ok(png) = Png::try_from(input.as_ref()) {
+                let reparsed = Png::try_from(png.as_bytes().as_ref());
+                assert!(reparsed.is_ok_and(|reparsed| reparsed == png), "{}", name);
+            }
+            let mut parser = crate::stream::PngStreamParser::new();
+            if input.chunks(7).all(|piece| parser.push(piece).is_ok()) {
+                let _ = parser.finish();
+            }
+        }
+    }
+
     #[test]
     fn test_list_chunks() {
         let png = testing_png();
@@ -214,6 +677,16 @@ mod tests {
         assert_eq!(chunks.len(), 3);
     }
 
+    #[test]
+    fn test_chunks_with_offsets() {
+        let png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let offsets: Vec<usize> = png
+            .chunks_with_offsets()
+            .map(|(offset, _)| offset)
+            .collect();
+        assert_eq!(offsets, vec![8, 33, 46, 62, 83, 4776, 4791]);
+    }
+
     #[test]
     fn test_chunk_by_type() {
         let png = testing_png();
@@ -235,6 +708,54 @@ mod tests {
         assert_eq!(&chunk.data_as_string().unwrap(), "Message");
     }
 
+    #[test]
+    fn test_insert_chunk() {
+        let mut png = testing_png();
+        png.insert_chunk(1, chunk_from_strings("TeSt", "Message").unwrap());
+        assert_eq!(&png.chunks()[1].chunk_type().to_string(), "TeSt");
+        assert_eq!(png.chunks().len(), 4);
+    }
+
+    #[test]
+    fn test_replace_chunk_at() {
+        let mut png = testing_png();
+        let old = png.replace_chunk_at(1, chunk_from_strings("TeSt", "Message").unwrap());
+        assert_eq!(&old.chunk_type().to_string(), "miDl");
+        assert_eq!(&png.chunks()[1].chunk_type().to_string(), "TeSt");
+        assert_eq!(png.chunks().len(), 3);
+    }
+
+    #[test]
+    fn test_swap_chunks() {
+        let mut png = testing_png();
+        png.swap_chunks(0, 2);
+        let types: Vec<String> = png
+            .chunks()
+            .iter()
+            .map(|chunk| chunk.chunk_type().to_string())
+            .collect();
+        assert_eq!(types, ["LASt", "miDl", "FrSt"]);
+    }
+
+    #[test]
+    fn test_insert_before_end() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        png.insert_before_end(chunk_from_strings("TeSt", "Message").unwrap());
+        let chunks = png.chunks();
+        assert_eq!(&chunks[chunks.len() - 2].chunk_type().to_string(), "TeSt");
+
+        let mut png = testing_png();
+        png.insert_before_end(chunk_from_strings("TeSt", "Message").unwrap());
+        assert_eq!(&png.chunks()[3].chunk_type().to_string(), "TeSt");
+    }
+
+    #[test]
+    fn test_retain() {
+        let mut png = testing_png();
+        png.retain(|chunk| chunk.chunk_type().is_critical());
+        assert_eq!(png.chunks().len(), 2);
+    }
+
     #[test]
     fn test_remove_chunk() {
         let mut png = testing_png();
@@ -245,12 +766,131 @@ mod tests {
         assert!(chunk.is_none());
     }
 
+    #[test]
+    fn test_remove_chunk_returns_it() {
+        let mut png = testing_png();
+        png.append_chunk(chunk_from_strings("TeSt", "Message").unwrap());
+        let removed = png
+            .remove_chunk(&ChunkType::from_str("TeSt").unwrap())
+            .unwrap();
+        assert_eq!(removed.data_as_string().unwrap(), "Message");
+    }
+
+    #[test]
+    fn test_take_chunks_of_type() {
+        let mut png = testing_png();
+        png.append_chunk(chunk_from_strings("TeSt", "first").unwrap());
+        png.append_chunk(chunk_from_strings("MiNe", "other").unwrap());
+        png.append_chunk(chunk_from_strings("TeSt", "second").unwrap());
+        let before = png.chunks().len();
+
+        let taken = png.take_chunks_of_type(&ChunkType::from_str("TeSt").unwrap());
+        let data: Vec<String> = taken
+            .iter()
+            .map(|chunk| chunk.data_as_string().unwrap())
+            .collect();
+        assert_eq!(data, ["first", "second"]);
+        assert_eq!(png.chunks().len(), before - 2);
+        assert_eq!(
+            png.chunks().last().unwrap().data_as_string().unwrap(),
+            "other"
+        );
+    }
+
     #[test]
     fn test_png_from_image_file() {
         let png = Png::try_from(&PNG_FILE[..]);
         assert!(png.is_ok());
     }
 
+    #[test]
+    fn test_parse_shared() {
+        let bytes: Arc<[u8]> = Arc::from(&PNG_FILE[..]);
+        let png = Png::parse_shared(bytes.clone()).unwrap();
+        assert_eq!(png, Png::try_from(&PNG_FILE[..]).unwrap());
+        assert_eq!(png.as_bytes(), PNG_FILE);
+
+        // The data points into the shared buffer, also in clones
+        let file = bytes.as_ptr_range();
+        let clone = png.clone();
+        assert!(clone.chunks().iter().all(|chunk| {
+            let data = chunk.data().as_ptr_range();
+            file.start <= data.start && data.end <= file.end
+        }));
+
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| assert_eq!(png.width(), Ok(50)));
+            }
+        });
+
+        let mut corrupted = PNG_FILE.to_vec();
+        corrupted[20] ^= 1;
+        assert!(Png::parse_shared(corrupted.into()).is_err());
+    }
+
+    #[test]
+    fn test_collection() {
+        let mut png = testing_png();
+        assert_eq!(png[1].chunk_type().to_string(), "miDl");
+        png[1].set_data(b"I was edited".to_vec()).unwrap();
+        assert_eq!(
+            Png::try_from(&png.as_bytes()[..]).unwrap()[1].data(),
+            b"I was edited"
+        );
+
+        let borrowed: Vec<String> = (&png)
+            .into_iter()
+            .map(|c| c.chunk_type().to_string())
+            .collect();
+        assert_eq!(borrowed, ["FrSt", "miDl", "LASt"]);
+
+        png.extend(testing_chunks());
+        assert_eq!(png.len(), 6);
+        assert_eq!(png[3].chunk_type().to_string(), "FrSt");
+
+        let owned: Vec<Chunk> = png.clone().into_iter().collect();
+        assert_eq!(owned.as_slice(), png.chunks());
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_index_out_of_bounds() {
+        let _ = &testing_png()[3];
+    }
+
+    #[test]
+    fn test_sizes() {
+        let png = Png::try_from(&PNG_FILE[..]).unwrap();
+        assert_eq!(png.len(), png.chunks().len());
+        assert!(!png.is_empty());
+        assert_eq!(png.total_size(), PNG_FILE.len());
+
+        let mut critical = png.clone();
+        critical.retain(|chunk| chunk.chunk_type().is_critical());
+        assert_eq!(
+            png.ancillary_size(),
+            png.total_size() - critical.total_size()
+        );
+        assert_eq!(critical.ancillary_size(), 0);
+
+        let empty = Png::from_chunks(Vec::new());
+        assert!(empty.is_empty());
+        assert_eq!(empty.total_size(), 8);
+    }
+
+    #[test]
+    fn test_image_header() {
+        let png = Png::try_from(&PNG_FILE[..]).unwrap();
+        assert_eq!(png.width(), Ok(50));
+        assert_eq!(png.height(), Ok(50));
+        assert_eq!(png.color_type(), Ok(ColorType::Rgba));
+        assert_eq!(png.bit_depth(), Ok(8));
+        assert!(!png.image_header().unwrap().interlaced);
+
+        assert_eq!(testing_png().width(), Err(HeaderError::Missing));
+    }
+
     #[test]
     fn test_as_bytes() {
         let png = Png::try_from(&PNG_FILE[..]).unwrap();
@@ -277,8 +917,105 @@ mod tests {
         let _png_string = format!("{}", png);
     }
 
+    /// Counts chunks without keeping any of them
+    struct Counter(usize);
+
+    impl ChunkVisitor for Counter {
+        fn visit_header(&mut self, _length: u32, _chunk_type: &ChunkType) -> Visit {
+            self.0 += 1;
+            Visit::Skip
+        }
+    }
+
+    /// Keeps the first chunk of a given type and stops afterwards
+    struct FindFirst {
+        chunk_type: ChunkType,
+        found: bool,
+    }
+
+    impl ChunkVisitor for FindFirst {
+        fn visit_header(&mut self, _length: u32, chunk_type: &ChunkType) -> Visit {
+            if self.found {
+                Visit::Stop
+            } else if *chunk_type == self.chunk_type {
+                self.found = true;
+                Visit::Keep
+            } else {
+                Visit::Skip
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_with_counter() {
+        let mut counter = Counter(0);
+        let png = Png::parse_with(&PNG_FILE, &mut counter).unwrap();
+        assert_eq!(counter.0, 7);
+        assert!(png.chunks().is_empty());
+    }
+
+    #[test]
+    fn test_parse_with_find_first() {
+        let mut visitor = FindFirst {
+            chunk_type: ChunkType::from_str("IDAT").unwrap(),
+            found: false,
+        };
+        let png = Png::parse_with(&PNG_FILE, &mut visitor).unwrap();
+        assert_eq!(png.chunks().len(), 1);
+        assert_eq!(png.chunks()[0].chunk_type().to_string(), "IDAT");
+    }
+
+    #[test]
+    fn test_parse_with_data_filter() {
+        struct TextOnly;
+
+        impl ChunkVisitor for TextOnly {
+            fn visit_data(&mut self, _chunk_type: &ChunkType, data: &[u8]) -> Visit {
+                match std::str::from_utf8(data) {
+                    Ok(_) => Visit::Keep,
+                    Err(_) => Visit::Skip,
+                }
+            }
+        }
+
+        let png = Png::parse_with(&PNG_FILE, &mut TextOnly).unwrap();
+        let types: Vec<String> = png
+            .chunks()
+            .iter()
+            .map(|chunk| chunk.chunk_type().to_string())
+            .collect();
+        assert!(types.contains(&"RuSt".to_owned()));
+        assert!(!types.contains(&"IDAT".to_owned()));
+    }
+
+    #[test]
+    fn test_parse_cancelled() {
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+        let png = Png::parse_cancellable(&PNG_FILE, &mut KeepAll, &cancel);
+        assert!(matches!(png, Err(PngParseError::Cancelled(_))));
+    }
+
+    #[test]
+    fn test_png_clone_eq() {
+        let png = testing_png();
+        let mut clone = png.clone();
+        assert_eq!(png, clone);
+
+        clone.append_chunk(chunk_from_strings("TeSt", "Message").unwrap());
+        assert_ne!(png, clone);
+    }
+
+    #[test]
+    fn test_png_debug_omits_data() {
+        let png = testing_png();
+        let debug = format!("{:?}", png);
+        assert!(debug.contains("FrSt"));
+        assert!(!debug.contains("data"));
+    }
+
     // This is the raw bytes for a shrunken version of the `dice.png` image on Wikipedia
-    const PNG_FILE: [u8; 4803] = [
+    pub(crate) const PNG_FILE: [u8; 4803] = [
         137, 80, 78, 71, 13, 10, 26, 10, 0, 0, 0, 13, 73, 72, 68, 82, 0, 0, 0, 50, 0, 0, 0, 50, 8,
         6, 0, 0, 0, 30, 63, 136, 177, 0, 0, 0, 1, 115, 82, 71, 66, 0, 174, 206, 28, 233, 0, 0, 0,
         4, 103, 65, 77, 65, 0, 0, 177, 143, 11, 252, 97, 5, 0, 0, 0, 9, 112, 72, 89, 115, 0, 0, 14,
@@ -523,4 +1260,18 @@ mod tests {
         202, 28, 31, 66, 176, 235, 16, 0, 0, 0, 3, 82, 117, 83, 116, 104, 101, 121, 158, 176, 245,
         160, 0, 0, 0, 0, 73, 69, 78, 68, 174, 66, 96, 130,
     ];
+
+    proptest::proptest! {
+        #[test]
+        fn prop_roundtrip(png in Png::arbitrary_valid(8)) {
+            let bytes = png.as_bytes();
+            let parsed = Png::try_from(bytes.as_ref()).unwrap();
+            proptest::prop_assert!(parsed == png);
+
+            for (offset, chunk) in png.chunks_with_offsets() {
+                let end = offset + chunk.length() as usize + 12;
+                proptest::prop_assert_eq!(bytes[offset..end].to_vec(), chunk.as_bytes());
+            }
+        }
+    }
 }
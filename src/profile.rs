@@ -0,0 +1,326 @@
+use crate::{
+    audit::{self, Finding, Location, Rule, Severity, UnknownRuleError},
+    cancel::{CancellationToken, Cancelled},
+    chunk_type::{ChunkType, ChunkTypeParseError},
+    payload::Envelope,
+    png::Png,
+    verify,
+};
+use serde::Deserialize;
+use std::{collections::BTreeMap, str::FromStr};
+
+/// The names of the profiles which don't need to be configured
+pub const BUILTIN_PROFILES: [&str; 3] = ["web", "archive", "strict"];
+
+/// What the `web` profile allows the ancillary chunks to hold
+const WEB_MAX_ANCILLARY_BYTES: u64 = 1024 * 1024;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ProfileError {
+    #[error("unknown profile '{0}', expected one of web, archive, strict or a profile from the config file")]
+    Unknown(String),
+
+    #[error("the config file is invalid: {0}")]
+    Config(#[from] serde_json::Error),
+
+    #[error("profile '{0}': {1}")]
+    Rule(String, UnknownRuleError),
+
+    #[error("profile '{0}': invalid chunk type '{1}': {2}")]
+    ChunkType(String, String, ChunkTypeParseError),
+}
+
+/// The checks [`check`] runs on top of [`verify::verify`], and which
+/// findings make verification fail. The default only fails on errors.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Profile {
+    /// Also check the [`audit`] rules
+    pub audit: bool,
+    /// Fail on warnings, not only on errors
+    pub fail_on_warnings: bool,
+    /// Leave out the findings of these rules
+    pub allow: Vec<Rule>,
+    /// The most data the ancillary chunks may hold together
+    pub max_ancillary_bytes: Option<u64>,
+    /// Chunk types the PNG must have
+    pub required_chunks: Vec<ChunkType>,
+    /// Check payloads sealed by `encode` against their SHA-256
+    pub check_payloads: bool,
+}
+
+impl Profile {
+    /// One of the [`BUILTIN_PROFILES`]:
+    ///
+    /// - `web` forbids more than 1 MiB of ancillary data, which slows down
+    ///   page loads
+    /// - `archive` requires a `tIME` chunk and intact sealed payloads
+    /// - `strict` checks full conformance: the audit rules too, with
+    ///   warnings failing as well
+    pub fn builtin(name: &str) -> Option<Self> {
+        match name {
+            "web" => Some(Self {
+                max_ancillary_bytes: Some(WEB_MAX_ANCILLARY_BYTES),
+                ..Self::default()
+            }),
+            "archive" => Some(Self {
+                required_chunks: vec![ChunkType::from_str("tIME").unwrap()],
+                check_payloads: true,
+                ..Self::default()
+            }),
+            "strict" => Some(Self {
+                audit: true,
+                fail_on_warnings: true,
+                check_payloads: true,
+                ..Self::default()
+            }),
+            _ => None,
+        }
+    }
+
+    /// Whether `finding` makes verification fail
+    pub fn fails(&self, finding: &Finding) -> bool {
+        match finding.rule.severity() {
+            Severity::Error => true,
+            Severity::Warning => self.fail_on_warnings,
+            Severity::Info => false,
+        }
+    }
+}
+
+/// A profile in the config file. Unset fields are taken from the profile it
+/// `extends`, or from the default.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+struct ProfileEntry {
+    extends: Option<String>,
+    audit: Option<bool>,
+    fail_on_warnings: Option<bool>,
+    allow: Option<Vec<String>>,
+    max_ancillary_bytes: Option<u64>,
+    required_chunks: Option<Vec<String>>,
+    check_payloads: Option<bool>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct Config {
+    profiles: BTreeMap<String, ProfileEntry>,
+}
+
+impl ProfileEntry {
+    fn resolve(self, name: &str) -> Result<Profile, ProfileError> {
+        let mut profile = match &self.extends {
+            Some(base) => {
+                Profile::builtin(base).ok_or_else(|| ProfileError::Unknown(base.clone()))?
+            }
+            None => Profile::default(),
+        };
+        if let Some(audit) = self.audit {
+            profile.audit = audit;
+        }
+        if let Some(fail_on_warnings) = self.fail_on_warnings {
+            profile.fail_on_warnings = fail_on_warnings;
+        }
+        if let Some(allow) = self.allow {
+            profile.allow = allow
+                .iter()
+                .map(|rule| Rule::from_str(rule))
+                .collect::<Result<_, _>>()
+                .map_err(|err| ProfileError::Rule(name.to_owned(), err))?;
+        }
+        if self.max_ancillary_bytes.is_some() {
+            profile.max_ancillary_bytes = self.max_ancillary_bytes;
+        }
+        if let Some(required) = self.required_chunks {
+            profile.required_chunks = required
+                .into_iter()
+                .map(|chunk_type| {
+                    ChunkType::from_str(&chunk_type)
+                        .map_err(|err| ProfileError::ChunkType(name.to_owned(), chunk_type, err))
+                })
+                .collect::<Result<_, _>>()?;
+        }
+        if let Some(check_payloads) = self.check_payloads {
+            profile.check_payloads = check_payloads;
+        }
+        Ok(profile)
+    }
+}
+
+/// Looks up the profile `name` in `config`, a JSON object with a `profiles`
+/// object mapping names to profiles, e.g.
+/// `{"profiles": {"web": {"max-ancillary-bytes": 65536}}}`, and then among
+/// the [`BUILTIN_PROFILES`]. Profiles in the config file replace built-in
+/// ones of the same name.
+pub fn load(name: &str, config: Option<&str>) -> Result<Profile, ProfileError> {
+    let mut config: Config = match config {
+        Some(config) => serde_json::from_str(config)?,
+        None => Config::default(),
+    };
+    match config.profiles.remove(name) {
+        Some(entry) => entry.resolve(name),
+        None => Profile::builtin(name).ok_or_else(|| ProfileError::Unknown(name.to_owned())),
+    }
+}
+
+/// Verifies the file (see [`verify::verify`]) and runs the checks of
+/// `profile`, leaving out the findings of the rules it allows
+pub fn check(
+    bytes: &[u8],
+    profile: &Profile,
+    cancel: &CancellationToken,
+) -> Result<Vec<Finding>, Cancelled> {
+    let mut findings = verify::verify(bytes, cancel)?;
+    if profile.audit {
+        // Files which can't be parsed are already reported by the
+        // verification
+        if let Ok(audited) = audit::audit(bytes, cancel) {
+            findings.extend(audited);
+        }
+        cancel.check()?;
+    }
+    if let Ok(png) = Png::try_from(bytes) {
+        check_png(&png, profile, &mut findings);
+    }
+    findings.retain(|finding| !profile.allow.contains(&finding.rule));
+    Ok(findings)
+}
+
+fn check_png(png: &Png, profile: &Profile, findings: &mut Vec<Finding>) {
+    if let Some(max) = profile.max_ancillary_bytes {
+        let ancillary: u64 = png
+            .chunks()
+            .iter()
+            .filter(|chunk| !chunk.chunk_type().is_critical())
+            .map(|chunk| chunk.length() as u64)
+            .sum();
+        if ancillary > max {
+            findings.push(Finding {
+                rule: Rule::OversizedAncillary,
+                location: None,
+                message: format!(
+                    "the ancillary chunks hold {} bytes, more than the allowed {}",
+                    ancillary, max
+                ),
+            });
+        }
+    }
+
+    for required in &profile.required_chunks {
+        if png.chunk_by_type(required).is_none() {
+            findings.push(Finding {
+                rule: Rule::MissingRequiredChunk,
+                location: None,
+                message: format!("the file has no {} chunk", required),
+            });
+        }
+    }
+
+    if profile.check_payloads {
+        for (index, (offset, chunk)) in png.chunks_with_offsets().enumerate() {
+            let Ok(envelope) = Envelope::try_from(chunk.data()) else {
+                continue;
+            };
+            if let Err(err) = envelope.open() {
+                findings.push(Finding {
+                    rule: Rule::PayloadChecksum,
+                    location: Some(Location {
+                        chunk: Some(index),
+                        offset,
+                        length: chunk.length() as usize + 12,
+                    }),
+                    message: format!(
+                        "the payload in {} chunk {}: {}",
+                        chunk.chunk_type(),
+                        index,
+                        err
+                    ),
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{chunk::Chunk, png::tests::PNG_FILE};
+
+    fn rules(bytes: &[u8], profile: &Profile) -> Vec<Rule> {
+        check(bytes, profile, &CancellationToken::new())
+            .unwrap()
+            .into_iter()
+            .map(|finding| finding.rule)
+            .collect()
+    }
+
+    fn with_chunk(chunk_type: &str, data: Vec<u8>) -> Vec<u8> {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        png.insert_before_end(Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data));
+        png.as_bytes()
+    }
+
+    #[test]
+    fn test_builtin_profiles() {
+        let web = Profile::builtin("web").unwrap();
+        assert!(rules(&PNG_FILE, &web).is_empty());
+        let large = with_chunk("zzZz", vec![0; 2 * 1024 * 1024]);
+        assert_eq!(rules(&large, &web), vec![Rule::OversizedAncillary]);
+        assert!(rules(&large, &Profile::default()).is_empty());
+
+        let archive = Profile::builtin("archive").unwrap();
+        assert_eq!(rules(&PNG_FILE, &archive), vec![Rule::MissingRequiredChunk]);
+        let time = with_chunk("tIME", vec![7, 232, 1, 1, 0, 0, 0]);
+        assert!(rules(&time, &archive).is_empty());
+
+        let strict = Profile::builtin("strict").unwrap();
+        let findings = check(&PNG_FILE, &strict, &CancellationToken::new()).unwrap();
+        // The audit notes the missing color space, which is only info
+        assert_eq!(findings.len(), 0);
+        assert!(Profile::builtin("lenient").is_none());
+    }
+
+    #[test]
+    fn test_payload_checksum() {
+        let mut sealed = Envelope::seal(b"secret".to_vec()).as_bytes();
+        let last = sealed.len() - 1;
+        sealed[last] ^= 1;
+        let bytes = with_chunk("ruSt", sealed);
+        let archive = Profile::builtin("archive").unwrap();
+        assert!(rules(&bytes, &archive).contains(&Rule::PayloadChecksum));
+        assert!(!rules(&bytes, &Profile::default()).contains(&Rule::PayloadChecksum));
+    }
+
+    #[test]
+    fn test_config() {
+        let config = r#"{"profiles": {
+            "web": {"max-ancillary-bytes": 10},
+            "team": {"extends": "archive", "allow": ["missing-required-chunk"], "required-chunks": ["eXIf"]}
+        }}"#;
+        let web = load("web", Some(config)).unwrap();
+        assert_eq!(web.max_ancillary_bytes, Some(10));
+        assert_eq!(rules(&PNG_FILE, &web), vec![Rule::OversizedAncillary]);
+
+        let team = load("team", Some(config)).unwrap();
+        assert!(team.check_payloads);
+        assert_eq!(team.required_chunks, [ChunkType::from_str("eXIf").unwrap()]);
+        assert!(rules(&PNG_FILE, &team).is_empty());
+
+        assert_eq!(
+            load("strict", Some(config)).unwrap(),
+            Profile::builtin("strict").unwrap()
+        );
+        assert!(matches!(
+            load("nope", Some(config)),
+            Err(ProfileError::Unknown(_))
+        ));
+        assert!(matches!(
+            load("bad", Some(r#"{"profiles": {"bad": {"allow": ["X999"]}}}"#)),
+            Err(ProfileError::Rule(..))
+        ));
+        assert!(matches!(
+            load("web", Some(r#"{"profiles": {"web": {"typo": 1}}}"#)),
+            Err(ProfileError::Config(_))
+        ));
+    }
+}
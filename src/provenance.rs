@@ -0,0 +1,622 @@
+use crate::{chunk::Chunk, png::Png};
+use ciborium::Value;
+use sha2::{Digest, Sha256, Sha384, Sha512};
+use std::ops::Range;
+
+/// The chunk type C2PA manifest stores are embedded in
+pub const C2PA_CHUNK_TYPE: [u8; 4] = *b"caBX";
+
+/// A C2PA manifest, i.e. one signed claim about the image and the assertions
+/// it refers to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Manifest {
+    pub label: String,
+    /// The software which created the claim
+    pub claim_generator: Option<String>,
+    pub title: Option<String>,
+    /// The common name (or organization) of the signing certificate
+    pub signer: Option<String>,
+    /// Labels of all assertions, e.g. `c2pa.actions`
+    pub assertions: Vec<String>,
+    pub data_hash: Option<DataHash>,
+}
+
+/// The `c2pa.hash.data` assertion: a hash over the whole file except the
+/// excluded byte ranges (which hold the manifest itself)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DataHash {
+    pub alg: String,
+    pub hash: Vec<u8>,
+    pub exclusions: Vec<Range<usize>>,
+}
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum ProvenanceError {
+    #[error("a JUMBF box is truncated")]
+    TruncatedBox,
+
+    #[error("a JUMBF superbox has no description box")]
+    MissingDescription,
+
+    #[error("the chunk does not contain a C2PA manifest store")]
+    NotManifestStore,
+
+    #[error("the {0} box contains invalid CBOR")]
+    InvalidCbor(String),
+}
+
+/// A box of the JPEG universal metadata box format (JUMBF), the container
+/// C2PA manifests are stored in
+#[derive(Debug)]
+enum JumbfBox<'a> {
+    Super {
+        label: Option<String>,
+        children: Vec<JumbfBox<'a>>,
+    },
+    Content {
+        box_type: [u8; 4],
+        data: &'a [u8],
+    },
+}
+
+impl<'a> JumbfBox<'a> {
+    fn label(&self) -> Option<&str> {
+        match self {
+            Self::Super { label, .. } => label.as_deref(),
+            Self::Content { .. } => None,
+        }
+    }
+
+    fn children(&self) -> &[JumbfBox<'a>] {
+        match self {
+            Self::Super { children, .. } => children,
+            Self::Content { .. } => &[],
+        }
+    }
+
+    /// Finds the child superbox with the given label. Repeated labels are
+    /// made unique with a `__N` suffix, which is ignored.
+    fn child(&self, label: &str) -> Option<&JumbfBox<'a>> {
+        self.children()
+            .iter()
+            .find(|child| child.label().is_some_and(|l| base_label(l) == label))
+    }
+
+    /// Decodes the first `cbor` content box
+    fn cbor(&self) -> Result<Option<Value>, ProvenanceError> {
+        let data = self.children().iter().find_map(|child| match child {
+            Self::Content {
+                box_type: [b'c', b'b', b'o', b'r'],
+                data,
+            } => Some(*data),
+            _ => None,
+        });
+        data.map(|data| {
+            ciborium::de::from_reader(data).map_err(|_| {
+                ProvenanceError::InvalidCbor(self.label().unwrap_or_default().to_owned())
+            })
+        })
+        .transpose()
+    }
+}
+
+fn base_label(label: &str) -> &str {
+    label.split("__").next().unwrap_or(label)
+}
+
+fn parse_boxes(mut data: &[u8]) -> Result<Vec<JumbfBox<'_>>, ProvenanceError> {
+    let mut boxes = Vec::new();
+
+    while !data.is_empty() {
+        if data.len() < 8 {
+            return Err(ProvenanceError::TruncatedBox);
+        }
+        let box_type: [u8; 4] = data[4..8].try_into().unwrap();
+        let (header, length) = match u32::from_be_bytes(data[..4].try_into().unwrap()) {
+            0 => (8, data.len()),
+            1 => {
+                let length = data.get(8..16).ok_or(ProvenanceError::TruncatedBox)?;
+                let length = u64::from_be_bytes(length.try_into().unwrap());
+                (16, usize::try_from(length).unwrap_or(usize::MAX))
+            }
+            length => (8, length as usize),
+        };
+        if length < header || length > data.len() {
+            return Err(ProvenanceError::TruncatedBox);
+        }
+
+        let payload = &data[header..length];
+        boxes.push(match &box_type {
+            b"jumb" => parse_superbox(payload)?,
+            _ => JumbfBox::Content {
+                box_type,
+                data: payload,
+            },
+        });
+        data = &data[length..];
+    }
+
+    Ok(boxes)
+}
+
+fn parse_superbox(payload: &[u8]) -> Result<JumbfBox<'_>, ProvenanceError> {
+    let mut children = parse_boxes(payload)?.into_iter();
+
+    // The description box holds a 16 byte content type, a toggles byte and,
+    // if the second toggle is set, a null-terminated label
+    let Some(JumbfBox::Content {
+        box_type: [b'j', b'u', b'm', b'd'],
+        data: description,
+    }) = children.next()
+    else {
+        return Err(ProvenanceError::MissingDescription);
+    };
+    let label = match description.get(16..) {
+        Some([toggles, rest @ ..]) if toggles & 0x02 != 0 => {
+            let end = rest.iter().position(|b| *b == 0).unwrap_or(rest.len());
+            Some(String::from_utf8_lossy(&rest[..end]).into_owned())
+        }
+        _ => None,
+    };
+
+    Ok(JumbfBox::Super {
+        label,
+        children: children.collect(),
+    })
+}
+
+fn get<'v>(map: &'v Value, key: &str) -> Option<&'v Value> {
+    map.as_map()?
+        .iter()
+        .find(|(k, _)| k.as_text() == Some(key))
+        .map(|(_, value)| value)
+}
+
+fn get_text(map: &Value, key: &str) -> Option<String> {
+    get(map, key)?.as_text().map(str::to_owned)
+}
+
+/// Parses the contents of a `caBX` chunk. Manifests are returned in the
+/// order they are stored in, the last one is the active manifest.
+pub fn parse_manifest_store(data: &[u8]) -> Result<Vec<Manifest>, ProvenanceError> {
+    let boxes = parse_boxes(data)?;
+    let store = match boxes.first() {
+        Some(store) if store.label() == Some("c2pa") => store,
+        _ => return Err(ProvenanceError::NotManifestStore),
+    };
+
+    store
+        .children()
+        .iter()
+        .filter_map(|manifest| Some((manifest.label()?, manifest)))
+        .map(|(label, manifest)| parse_manifest(label, manifest))
+        .collect()
+}
+
+fn parse_manifest(label: &str, manifest: &JumbfBox) -> Result<Manifest, ProvenanceError> {
+    let claim = match manifest
+        .child("c2pa.claim")
+        .or_else(|| manifest.child("c2pa.claim.v2"))
+    {
+        Some(claim) => claim.cbor()?,
+        None => None,
+    };
+    let claim = claim.as_ref();
+
+    let claim_generator = claim.and_then(|claim| {
+        get_text(claim, "claim_generator").or_else(|| {
+            let info = get(claim, "claim_generator_info")?.as_array()?.first()?;
+            let name = get_text(info, "name")?;
+            Some(match get_text(info, "version") {
+                Some(version) => format!("{} {}", name, version),
+                None => name,
+            })
+        })
+    });
+    let title = claim.and_then(|claim| get_text(claim, "dc:title"));
+
+    let assertion_store = manifest.child("c2pa.assertions");
+    let assertions = assertion_store
+        .map(|store| {
+            store
+                .children()
+                .iter()
+                .filter_map(|assertion| assertion.label().map(str::to_owned))
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let data_hash = match assertion_store.and_then(|store| store.child("c2pa.hash.data")) {
+        Some(assertion) => assertion.cbor()?.and_then(|hash| {
+            let default_alg = claim.and_then(|claim| get_text(claim, "alg"));
+            parse_data_hash(&hash, default_alg)
+        }),
+        None => None,
+    };
+
+    let signer = match manifest.child("c2pa.signature") {
+        Some(signature) => signature.cbor()?.as_ref().and_then(signer),
+        None => None,
+    };
+
+    Ok(Manifest {
+        label: label.to_owned(),
+        claim_generator,
+        title,
+        signer,
+        assertions,
+        data_hash,
+    })
+}
+
+fn parse_data_hash(assertion: &Value, default_alg: Option<String>) -> Option<DataHash> {
+    let exclusions = match get(assertion, "exclusions") {
+        Some(exclusions) => exclusions
+            .as_array()?
+            .iter()
+            .map(|exclusion| {
+                let start = u64::try_from(get(exclusion, "start")?.as_integer()?).ok()?;
+                let length = u64::try_from(get(exclusion, "length")?.as_integer()?).ok()?;
+                Some(start as usize..(start + length) as usize)
+            })
+            .collect::<Option<_>>()?,
+        None => Vec::new(),
+    };
+
+    Some(DataHash {
+        alg: get_text(assertion, "alg")
+            .or(default_alg)
+            .unwrap_or_else(|| "sha256".to_owned()),
+        hash: get(assertion, "hash")?.as_bytes()?.clone(),
+        exclusions,
+    })
+}
+
+/// Finds the signing certificate in a `COSE_Sign1` structure and returns its
+/// subject name
+fn signer(signature: &Value) -> Option<String> {
+    let signature = match signature.as_tag() {
+        Some((_, value)) => value,
+        None => signature,
+    };
+    let [protected, unprotected, ..] = signature.as_array()?.as_slice() else {
+        return None;
+    };
+
+    // The x5chain header (label 33) holds a certificate or a chain of them,
+    // starting with the signer's
+    let protected: Option<Value> = protected
+        .as_bytes()
+        .and_then(|bytes| ciborium::de::from_reader(bytes.as_slice()).ok());
+    let x5chain = [protected.as_ref(), Some(unprotected)]
+        .into_iter()
+        .flatten()
+        .filter_map(Value::as_map)
+        .flatten()
+        .find(|(key, _)| key.as_integer() == Some(33.into()))
+        .map(|(_, value)| value)?;
+
+    let certificate = match x5chain {
+        Value::Bytes(certificate) => certificate,
+        Value::Array(chain) => chain.first()?.as_bytes()?,
+        _ => return None,
+    };
+    certificate_subject(certificate)
+}
+
+/// Reads a DER encoded value, returning its tag, its contents and the rest
+/// of the input
+fn der(data: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, rest) = data.split_first()?;
+    let (&first, rest) = rest.split_first()?;
+    let (length, rest) = match first {
+        0..=0x7f => (first as usize, rest),
+        _ => {
+            let n = (first & 0x7f) as usize;
+            if n == 0 || n > 4 || rest.len() < n {
+                return None;
+            }
+            let length = rest[..n]
+                .iter()
+                .fold(0, |length, b| length << 8 | *b as usize);
+            (length, &rest[n..])
+        }
+    };
+    if rest.len() < length {
+        return None;
+    }
+    Some((tag, &rest[..length], &rest[length..]))
+}
+
+/// Returns the common name, or else the organization, of an X.509
+/// certificate's subject
+fn certificate_subject(certificate: &[u8]) -> Option<String> {
+    let (_, certificate, _) = der(certificate)?;
+    let (_, mut tbs, _) = der(certificate)?;
+
+    // The version is optional and explicitly tagged
+    if der(tbs)?.0 == 0xa0 {
+        tbs = der(tbs)?.2;
+    }
+    // Skip the serial number, signature algorithm, issuer and validity
+    for _ in 0..4 {
+        tbs = der(tbs)?.2;
+    }
+    let (_, mut subject, _) = der(tbs)?;
+
+    let mut common_name = None;
+    let mut organization = None;
+    while !subject.is_empty() {
+        let (_, mut set, rest) = der(subject)?;
+        subject = rest;
+        while !set.is_empty() {
+            let (_, attribute, rest) = der(set)?;
+            set = rest;
+            let (_, oid, value) = der(attribute)?;
+            let (_, value, _) = der(value)?;
+            let value = String::from_utf8_lossy(value).into_owned();
+            match oid {
+                [0x55, 0x04, 0x03] => common_name = Some(value),
+                [0x55, 0x04, 0x0a] => organization = Some(value),
+                _ => {}
+            }
+        }
+    }
+
+    common_name.or(organization)
+}
+
+fn is_manifest_store(chunk: &Chunk) -> bool {
+    chunk.chunk_type().bytes() == C2PA_CHUNK_TYPE
+}
+
+/// Returns the manifests of all manifest stores in the PNG
+pub fn manifests(png: &Png) -> Result<Vec<Manifest>, ProvenanceError> {
+    let mut manifests = Vec::new();
+    for chunk in png.chunks().iter().filter(|chunk| is_manifest_store(chunk)) {
+        manifests.extend(parse_manifest_store(chunk.data())?);
+    }
+    Ok(manifests)
+}
+
+/// Removes all manifest stores, returning how many were removed
+pub fn strip(png: &mut Png) -> usize {
+    let before = png.len();
+    png.retain(|chunk| !is_manifest_store(chunk));
+    before - png.len()
+}
+
+/// Checks a data hash against the file it was found in. Returns `None` if the
+/// hash algorithm isn't supported.
+pub fn check_data_hash(bytes: &[u8], data_hash: &DataHash) -> Option<bool> {
+    let mut exclusions = data_hash.exclusions.clone();
+    exclusions.sort_by_key(|exclusion| exclusion.start);
+
+    let mut included = Vec::new();
+    let mut cursor = 0;
+    for exclusion in exclusions {
+        if exclusion.end > bytes.len() || exclusion.start < cursor {
+            return Some(false);
+        }
+        included.push(&bytes[cursor..exclusion.start]);
+        cursor = exclusion.end;
+    }
+    included.push(&bytes[cursor..]);
+
+    let hash = match data_hash.alg.as_str() {
+        "sha256" => digest::<Sha256>(&included),
+        "sha384" => digest::<Sha384>(&included),
+        "sha512" => digest::<Sha512>(&included),
+        _ => return None,
+    };
+    Some(hash == data_hash.hash)
+}
+
+fn digest<D: Digest>(parts: &[&[u8]]) -> Vec<u8> {
+    let mut digest = D::new();
+    for part in parts {
+        digest.update(part);
+    }
+    digest.finalize().to_vec()
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::{chunk_type::ChunkType, png::tests::PNG_FILE};
+
+    fn jumbf_box(box_type: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+        let mut bytes = ((payload.len() + 8) as u32).to_be_bytes().to_vec();
+        bytes.extend_from_slice(box_type);
+        bytes.extend_from_slice(payload);
+        bytes
+    }
+
+    fn superbox(label: &str, children: &[Vec<u8>]) -> Vec<u8> {
+        let mut description = vec![0; 16];
+        description.push(0x03);
+        description.extend_from_slice(label.as_bytes());
+        description.push(0);
+
+        let mut payload = jumbf_box(b"jumd", &description);
+        for child in children {
+            payload.extend_from_slice(child);
+        }
+        jumbf_box(b"jumb", &payload)
+    }
+
+    fn cbor(value: &Value) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        ciborium::ser::into_writer(value, &mut bytes).unwrap();
+        jumbf_box(b"cbor", &bytes)
+    }
+
+    fn text_map(entries: Vec<(&str, Value)>) -> Value {
+        Value::Map(
+            entries
+                .into_iter()
+                .map(|(key, value)| (Value::Text(key.to_owned()), value))
+                .collect(),
+        )
+    }
+
+    fn der_value(tag: u8, contents: &[u8]) -> Vec<u8> {
+        let mut bytes = vec![tag];
+        match contents.len() {
+            length @ 0..=0x7f => bytes.push(length as u8),
+            length => {
+                bytes.push(0x82);
+                bytes.extend_from_slice(&(length as u16).to_be_bytes());
+            }
+        }
+        bytes.extend_from_slice(contents);
+        bytes
+    }
+
+    fn name(common_name: &str) -> Vec<u8> {
+        let attribute = [
+            der_value(0x06, &[0x55, 0x04, 0x03]),
+            der_value(0x0c, common_name.as_bytes()),
+        ]
+        .concat();
+        der_value(0x30, &der_value(0x31, &der_value(0x30, &attribute)))
+    }
+
+    fn certificate(issuer: &str, subject: &str) -> Vec<u8> {
+        let tbs = [
+            der_value(0xa0, &der_value(0x02, &[2])),
+            der_value(0x02, &[1]),
+            der_value(0x30, &der_value(0x06, &[0x2a, 0x86, 0x48])),
+            name(issuer),
+            der_value(0x30, &[]),
+            name(subject),
+        ]
+        .concat();
+        der_value(0x30, &der_value(0x30, &tbs))
+    }
+
+    /// Builds a manifest store whose data hash excludes a `caBX` chunk at
+    /// `offset` with the given total length
+    fn manifest_store(hash: &[u8], offset: usize, length: usize) -> Vec<u8> {
+        let claim = text_map(vec![
+            ("claim_generator", Value::Text("pngme-test/1.0".to_owned())),
+            ("dc:title", Value::Text("dice.png".to_owned())),
+            ("alg", Value::Text("sha256".to_owned())),
+        ]);
+        let data_hash = text_map(vec![
+            (
+                "exclusions",
+                Value::Array(vec![text_map(vec![
+                    ("start", Value::Integer((offset as u64).into())),
+                    ("length", Value::Integer((length as u64).into())),
+                ])]),
+            ),
+            ("name", Value::Text("jumbf manifest".to_owned())),
+            ("hash", Value::Bytes(hash.to_vec())),
+        ]);
+        let protected = {
+            let mut bytes = Vec::new();
+            let header = Value::Map(vec![(
+                Value::Integer(33.into()),
+                Value::Bytes(certificate("Test CA", "Jane Doe")),
+            )]);
+            ciborium::ser::into_writer(&header, &mut bytes).unwrap();
+            bytes
+        };
+        let signature = Value::Tag(
+            18,
+            Box::new(Value::Array(vec![
+                Value::Bytes(protected),
+                Value::Map(Vec::new()),
+                Value::Null,
+                Value::Bytes(vec![0; 64]),
+            ])),
+        );
+
+        superbox(
+            "c2pa",
+            &[superbox(
+                "urn:uuid:0a1b2c3d",
+                &[
+                    superbox(
+                        "c2pa.assertions",
+                        &[
+                            superbox("c2pa.actions", &[cbor(&text_map(vec![]))]),
+                            superbox("c2pa.hash.data", &[cbor(&data_hash)]),
+                        ],
+                    ),
+                    superbox("c2pa.claim", &[cbor(&claim)]),
+                    superbox("c2pa.signature", &[cbor(&signature)]),
+                ],
+            )],
+        )
+    }
+
+    /// Returns the test PNG with a valid manifest store after `IHDR`
+    pub(crate) fn signed_png() -> Png {
+        let unsigned = Png::try_from(&PNG_FILE[..]).unwrap();
+        let hash = Sha256::digest(unsigned.as_bytes()).to_vec();
+        let offset = 8 + 25;
+
+        // The exclusion must cover the chunk it is stored in, so repeat until
+        // the length doesn't change the chunk's size anymore
+        let mut length = 0;
+        loop {
+            let store = manifest_store(&hash, offset, length);
+            if store.len() + 12 == length {
+                let mut png = unsigned;
+                let chunk_type = ChunkType::try_from(C2PA_CHUNK_TYPE).unwrap();
+                png.insert_chunk(1, Chunk::new(chunk_type, store));
+                return png;
+            }
+            length = store.len() + 12;
+        }
+    }
+
+    #[test]
+    fn test_manifests() {
+        let png = signed_png();
+        let manifests = manifests(&png).unwrap();
+        assert_eq!(manifests.len(), 1);
+
+        let manifest = &manifests[0];
+        assert_eq!(manifest.label, "urn:uuid:0a1b2c3d");
+        assert_eq!(manifest.claim_generator.as_deref(), Some("pngme-test/1.0"));
+        assert_eq!(manifest.title.as_deref(), Some("dice.png"));
+        assert_eq!(manifest.signer.as_deref(), Some("Jane Doe"));
+        assert_eq!(manifest.assertions, vec!["c2pa.actions", "c2pa.hash.data"]);
+    }
+
+    #[test]
+    fn test_data_hash() {
+        let png = signed_png();
+        let data_hash = manifests(&png).unwrap()[0].data_hash.clone().unwrap();
+        assert_eq!(data_hash.alg, "sha256");
+        assert_eq!(check_data_hash(&png.as_bytes(), &data_hash), Some(true));
+
+        let mut bytes = png.as_bytes();
+        let last = bytes.len() - 20;
+        bytes[last] ^= 0xff;
+        assert_eq!(check_data_hash(&bytes, &data_hash), Some(false));
+    }
+
+    #[test]
+    fn test_strip() {
+        let mut png = signed_png();
+        assert_eq!(strip(&mut png), 1);
+        assert_eq!(png, Png::try_from(&PNG_FILE[..]).unwrap());
+        assert!(manifests(&png).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_not_a_manifest_store() {
+        assert_eq!(
+            parse_manifest_store(&superbox("other", &[])),
+            Err(ProvenanceError::NotManifestStore)
+        );
+        assert_eq!(
+            parse_manifest_store(&[0, 0, 0, 20, b'j', b'u', b'm', b'b']),
+            Err(ProvenanceError::TruncatedBox)
+        );
+    }
+}
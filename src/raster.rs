@@ -0,0 +1,757 @@
+use crate::{
+    chunk::Chunk,
+    chunk_type::ChunkType,
+    ihdr::{ColorType, HeaderError, ImageHeader},
+    png::Png,
+};
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
+use std::io::{Read, Write};
+
+/// The passes of Adam7 interlacing as (x offset, y offset, x step, y step)
+const ADAM7: [(u64, u64, u64, u64); 7] = [
+    (0, 0, 8, 8),
+    (4, 0, 8, 8),
+    (0, 4, 4, 8),
+    (2, 0, 4, 4),
+    (0, 2, 2, 4),
+    (1, 0, 2, 2),
+    (0, 1, 1, 2),
+];
+
+/// The names of the filter types, by number
+pub const FILTER_NAMES: [&str; 5] = ["None", "Sub", "Up", "Average", "Paeth"];
+
+/// The largest `IDAT` chunk [`Raster::store`] writes
+const IDAT_SIZE: usize = 1 << 20;
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum RasterError {
+    #[error(transparent)]
+    Header(#[from] HeaderError),
+
+    #[error("the PNG has no IDAT chunk")]
+    NoImageData,
+
+    #[error("the image data is not a valid zlib stream")]
+    Corrupted,
+
+    #[error("the image data holds {actual} bytes, but the image needs {expected}")]
+    Truncated { expected: u64, actual: u64 },
+
+    #[error("scanline {row} uses the unknown filter type {filter}")]
+    UnknownFilter { row: usize, filter: u8 },
+
+    #[error("the pixels have a different size, color type or bit depth than the PNG")]
+    FormatMismatch,
+}
+
+/// The pixels of a PNG as unfiltered scanlines of the full image, with
+/// interlaced images already put together
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Raster {
+    pub header: ImageHeader,
+    row_len: usize,
+    data: Vec<u8>,
+    filters: Vec<Vec<u8>>,
+}
+
+/// How the pixels of two PNGs compare
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PixelComparison {
+    Identical,
+    /// Size, color type or bit depth differ, so the pixels can't be compared
+    DifferentFormat,
+    /// The first scanline (starting at 0) which differs
+    DifferentRow(usize),
+}
+
+fn bits_per_pixel(header: &ImageHeader) -> u64 {
+    header.color_type.channels() as u64 * header.bit_depth as u64
+}
+
+/// The bytes a scanline of `width` pixels takes, without the filter byte
+fn scanline_len(header: &ImageHeader, width: u64) -> u64 {
+    (width * bits_per_pixel(header)).div_ceil(8)
+}
+
+/// The size of a pass of `length` pixels starting at `offset` with `step`
+fn pass_size(length: u32, offset: u64, step: u64) -> u64 {
+    (length as u64).saturating_sub(offset).div_ceil(step)
+}
+
+/// The passes the image data is split into
+fn passes(interlaced: bool) -> Vec<(u64, u64, u64, u64)> {
+    if interlaced {
+        ADAM7.to_vec()
+    } else {
+        vec![(0, 0, 1, 1)]
+    }
+}
+
+/// Copies pixel `from` of `source` to pixel `to` of `target`, both
+/// scanlines of pixels with `bits` bits
+fn copy_pixel(bits: u64, source: &[u8], from: u64, target: &mut [u8], to: u64) {
+    if bits >= 8 {
+        let bytes = (bits / 8) as usize;
+        let (from, to) = (from as usize * bytes, to as usize * bytes);
+        target[to..to + bytes].copy_from_slice(&source[from..from + bytes]);
+        return;
+    }
+    // Pixels of less than a byte are packed starting at the highest bit
+    let mask = (1u8 << bits) - 1;
+    let (from, to) = (from * bits, to * bits);
+    let value = (source[(from / 8) as usize] >> (8 - bits - from % 8)) & mask;
+    let shift = 8 - bits - to % 8;
+    let byte = &mut target[(to / 8) as usize];
+    *byte = (*byte & !(mask << shift)) | (value << shift);
+}
+
+impl Raster {
+    /// An image of `header`'s format with all samples 0
+    pub fn new(header: ImageHeader) -> Self {
+        let row_len = scanline_len(&header, header.width as u64) as usize;
+        Self {
+            header,
+            row_len,
+            data: vec![0; row_len * header.height as usize],
+            filters: Vec::new(),
+        }
+    }
+
+    /// Decompresses and unfilters the `IDAT` chunks of `png`
+    pub fn decode(png: &Png) -> Result<Self, RasterError> {
+        let header = png.image_header()?;
+        let mut compressed = Vec::new();
+        for chunk in png.chunks() {
+            if &chunk.chunk_type().bytes() == b"IDAT" {
+                compressed.extend_from_slice(chunk.data());
+            }
+        }
+        if compressed.is_empty() {
+            return Err(RasterError::NoImageData);
+        }
+
+        let passes = passes(header.interlaced);
+        // Every non-empty scanline starts with a filter byte
+        let expected: u64 = passes
+            .iter()
+            .map(|&(x0, y0, dx, dy)| {
+                let width = pass_size(header.width, x0, dx);
+                let height = pass_size(header.height, y0, dy);
+                if width == 0 {
+                    0
+                } else {
+                    height * (scanline_len(&header, width) + 1)
+                }
+            })
+            .sum();
+
+        // Reading one byte more than needed is enough to tell that there
+        // is extra data, without inflating all of it
+        let mut filtered = Vec::new();
+        ZlibDecoder::new(&compressed[..])
+            .take(expected + 1)
+            .read_to_end(&mut filtered)
+            .map_err(|_| RasterError::Corrupted)?;
+        if (filtered.len() as u64) < expected {
+            return Err(RasterError::Truncated {
+                expected,
+                actual: filtered.len() as u64,
+            });
+        }
+
+        let mut raster = Self::new(header);
+        let mut filtered = &filtered[..];
+        let mut row = 0;
+        for (x0, y0, dx, dy) in passes {
+            let width = pass_size(header.width, x0, dx);
+            let height = pass_size(header.height, y0, dy);
+            if width == 0 || height == 0 {
+                raster.filters.push(Vec::new());
+                continue;
+            }
+            let pass_row_len = scanline_len(&header, width) as usize;
+            let (pass, rest) = filtered.split_at(height as usize * (pass_row_len + 1));
+            filtered = rest;
+            raster
+                .filters
+                .push(pass.iter().step_by(pass_row_len + 1).copied().collect());
+            let pass = unfilter(&header, pass, pass_row_len, row)?;
+            row += height as usize;
+
+            let bits = bits_per_pixel(&header);
+            for (y, line) in pass.chunks(pass_row_len).enumerate() {
+                let target = raster.row_mut((y0 + y as u64 * dy) as usize);
+                for x in 0..width {
+                    copy_pixel(bits, line, x, target, x0 + x * dx);
+                }
+            }
+        }
+        Ok(raster)
+    }
+
+    /// Filters and compresses the pixels into the data of `IDAT` chunks,
+    /// split into the seven passes of Adam7 if `interlaced`
+    pub fn encode(&self, interlaced: bool) -> Vec<u8> {
+        let bits = bits_per_pixel(&self.header);
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::best());
+        for (x0, y0, dx, dy) in passes(interlaced) {
+            let width = pass_size(self.header.width, x0, dx);
+            let height = pass_size(self.header.height, y0, dy);
+            if width == 0 || height == 0 {
+                continue;
+            }
+            let pass_row_len = scanline_len(&self.header, width) as usize;
+            let mut pass = vec![0; height as usize * pass_row_len];
+            for (y, line) in pass.chunks_mut(pass_row_len).enumerate() {
+                let source = self.row((y0 + y as u64 * dy) as usize);
+                for x in 0..width {
+                    copy_pixel(bits, source, x0 + x * dx, line, x);
+                }
+            }
+            encoder
+                .write_all(&filter(&self.header, &pass, pass_row_len))
+                .expect("writing to a Vec never fails");
+        }
+        encoder.finish().expect("writing to a Vec never fails")
+    }
+
+    /// Replaces the image data of `png` with these pixels, interlaced with
+    /// Adam7 or not, and sets the interlace method in `IHDR` to match. The
+    /// `IDAT` chunks are written where the first one was.
+    pub fn store(&self, png: &mut Png, interlaced: bool) -> Result<(), RasterError> {
+        let header = png.image_header()?;
+        if (
+            header.width,
+            header.height,
+            header.color_type,
+            header.bit_depth,
+        ) != (
+            self.header.width,
+            self.header.height,
+            self.header.color_type,
+            self.header.bit_depth,
+        ) {
+            return Err(RasterError::FormatMismatch);
+        }
+        let is_type =
+            |chunk: &Chunk, chunk_type: &[u8; 4]| &chunk.chunk_type().bytes() == chunk_type;
+        let first_idat = png
+            .chunks()
+            .iter()
+            .position(|chunk| is_type(chunk, b"IDAT"))
+            .ok_or(RasterError::NoImageData)?;
+
+        let data = self.encode(interlaced);
+        png.retain(|chunk| !is_type(chunk, b"IDAT"));
+        for (i, data) in data.chunks(IDAT_SIZE).enumerate() {
+            let idat = Chunk::new(ChunkType::try_from(*b"IDAT").unwrap(), data.to_vec());
+            png.insert_chunk(first_idat + i, idat);
+        }
+
+        let ihdr = png
+            .chunks()
+            .iter()
+            .position(|chunk| is_type(chunk, b"IHDR"))
+            .expect("image_header found IHDR");
+        let mut data = png[ihdr].data().to_vec();
+        data[12] = interlaced as u8;
+        png.replace_chunk_at(
+            ihdr,
+            Chunk::new(ChunkType::try_from(*b"IHDR").unwrap(), data),
+        );
+        Ok(())
+    }
+
+    /// The filter types of the scanlines as they were stored, one list per
+    /// pass: a single one, or seven for interlaced images. Passes without
+    /// pixels are empty.
+    pub fn filters(&self) -> &[Vec<u8>] {
+        &self.filters
+    }
+
+    /// Sample `channel` of the pixel at `x`, `y`: a palette index for
+    /// palette images, else a gray, red, green, blue or alpha value with the
+    /// bit depth of the image. Panics if the pixel or channel is outside the
+    /// image.
+    pub fn sample(&self, x: u32, y: u32, channel: u32) -> u16 {
+        assert!(x < self.header.width && channel < self.header.color_type.channels());
+        let depth = self.header.bit_depth as u64;
+        let bit = (x as u64 * self.header.color_type.channels() as u64 + channel as u64) * depth;
+        let (row, byte) = (self.row(y as usize), (bit / 8) as usize);
+        match depth {
+            16 => u16::from_be_bytes([row[byte], row[byte + 1]]),
+            8 => row[byte] as u16,
+            _ => ((row[byte] >> (8 - depth - bit % 8)) & ((1 << depth) - 1)) as u16,
+        }
+    }
+
+    /// Sets sample `channel` of the pixel at `x`, `y`, see [`Self::sample`].
+    /// Bits above the bit depth of the image are ignored.
+    pub fn set_sample(&mut self, x: u32, y: u32, channel: u32, value: u16) {
+        assert!(x < self.header.width && channel < self.header.color_type.channels());
+        let depth = self.header.bit_depth as u64;
+        let bit = (x as u64 * self.header.color_type.channels() as u64 + channel as u64) * depth;
+        let (row, byte) = (self.row_mut(y as usize), (bit / 8) as usize);
+        match depth {
+            16 => row[byte..byte + 2].copy_from_slice(&value.to_be_bytes()),
+            8 => row[byte] = value as u8,
+            _ => {
+                let mask = ((1u16 << depth) - 1) as u8;
+                let shift = 8 - depth - bit % 8;
+                row[byte] = (row[byte] & !(mask << shift)) | ((value as u8 & mask) << shift);
+            }
+        }
+    }
+
+    fn row(&self, row: usize) -> &[u8] {
+        &self.data[row * self.row_len..(row + 1) * self.row_len]
+    }
+
+    fn row_mut(&mut self, row: usize) -> &mut [u8] {
+        &mut self.data[row * self.row_len..(row + 1) * self.row_len]
+    }
+
+    /// The unfiltered scanlines, top to bottom
+    pub fn rows(&self) -> impl Iterator<Item = &[u8]> {
+        self.data.chunks(self.row_len)
+    }
+
+    /// Compares the pixels of two images, regardless of how they were
+    /// filtered, compressed or interlaced
+    pub fn compare(&self, other: &Raster) -> PixelComparison {
+        let format = |header: &ImageHeader| {
+            (
+                header.width,
+                header.height,
+                header.color_type,
+                header.bit_depth,
+            )
+        };
+        if format(&self.header) != format(&other.header) {
+            return PixelComparison::DifferentFormat;
+        }
+        match self.rows().zip(other.rows()).position(|(a, b)| a != b) {
+            Some(row) => PixelComparison::DifferentRow(row),
+            None => PixelComparison::Identical,
+        }
+    }
+}
+
+/// Reverses the filters of the scanlines in `data`, each of which is
+/// `row_len` bytes long after its filter byte. `first_row` is only used to
+/// report errors.
+fn unfilter(
+    header: &ImageHeader,
+    data: &[u8],
+    row_len: usize,
+    first_row: usize,
+) -> Result<Vec<u8>, RasterError> {
+    // Filters work on bytes, using the byte of the previous pixel
+    let distance = (bits_per_pixel(header) as usize).div_ceil(8);
+    let mut rows = vec![0; data.len() / (row_len + 1) * row_len];
+    for (y, line) in data.chunks(row_len + 1).enumerate() {
+        let (filter, line) = (line[0], &line[1..]);
+        let (previous, current) = rows.split_at_mut(y * row_len);
+        let above = if y == 0 {
+            None
+        } else {
+            Some(&previous[(y - 1) * row_len..])
+        };
+        let current = &mut current[..row_len];
+        for x in 0..row_len {
+            let predicted =
+                predict(filter, current, above, x, distance).ok_or(RasterError::UnknownFilter {
+                    row: first_row + y,
+                    filter,
+                })?;
+            current[x] = line[x].wrapping_add(predicted);
+        }
+    }
+    Ok(rows)
+}
+
+/// Filters the scanlines in `rows`, each of which is `row_len` bytes long.
+/// Every scanline gets the filter with the smallest sum of absolute
+/// differences, except for palette images and bit depths below 8, which
+/// compress best unfiltered, as the specification recommends.
+fn filter(header: &ImageHeader, rows: &[u8], row_len: usize) -> Vec<u8> {
+    let distance = (bits_per_pixel(header) as usize).div_ceil(8);
+    let filters = if header.color_type == ColorType::Indexed || header.bit_depth < 8 {
+        0..1
+    } else {
+        0..5
+    };
+    let mut data = Vec::with_capacity(rows.len() / row_len * (row_len + 1));
+    let (mut best, mut candidate) = (vec![0; row_len], vec![0; row_len]);
+    for (y, line) in rows.chunks(row_len).enumerate() {
+        let above = (y > 0).then(|| &rows[(y - 1) * row_len..y * row_len]);
+        let (mut best_filter, mut best_sum) = (0, u64::MAX);
+        for filter in filters.clone() {
+            for x in 0..row_len {
+                let predicted = predict(filter, line, above, x, distance).unwrap();
+                candidate[x] = line[x].wrapping_sub(predicted);
+            }
+            let sum = candidate
+                .iter()
+                .map(|&byte| (byte as i8).unsigned_abs() as u64)
+                .sum();
+            if sum < best_sum {
+                (best_filter, best_sum) = (filter, sum);
+                std::mem::swap(&mut best, &mut candidate);
+            }
+        }
+        data.push(best_filter);
+        data.extend_from_slice(&best);
+    }
+    data
+}
+
+/// The value `filter` predicts for byte `x` of `line` from the bytes before
+/// it in `line` and the bytes of the scanline `above`, `distance` bytes
+/// being one pixel. None for unknown filters.
+fn predict(filter: u8, line: &[u8], above: Option<&[u8]>, x: usize, distance: usize) -> Option<u8> {
+    let a = if x >= distance { line[x - distance] } else { 0 };
+    let b = above.map_or(0, |above| above[x]);
+    let c = match above {
+        Some(above) if x >= distance => above[x - distance],
+        _ => 0,
+    };
+    Some(match filter {
+        0 => 0,
+        1 => a,
+        2 => b,
+        3 => ((a as u16 + b as u16) / 2) as u8,
+        4 => paeth(a, b, c),
+        _ => return None,
+    })
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let (pa, pb, pc) = (
+        (p - a as i16).abs(),
+        (p - b as i16).abs(),
+        (p - c as i16).abs(),
+    );
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{chunk::Chunk, chunk_type::ChunkType, png::tests::PNG_FILE};
+    use flate2::{write::ZlibEncoder, Compression};
+    use std::io::Write;
+
+    const WIDTH: usize = 10;
+    const HEIGHT: usize = 9;
+
+    /// Every combination of color type and bit depth the specification
+    /// allows
+    const FORMATS: [(u8, u8); 15] = [
+        (0, 1),
+        (0, 2),
+        (0, 4),
+        (0, 8),
+        (0, 16),
+        (2, 8),
+        (2, 16),
+        (3, 1),
+        (3, 2),
+        (3, 4),
+        (3, 8),
+        (4, 8),
+        (4, 16),
+        (6, 8),
+        (6, 16),
+    ];
+
+    /// An 8-bit grayscale gradient
+    fn pixels() -> Vec<Vec<u8>> {
+        (0..HEIGHT)
+            .map(|y| (0..WIDTH).map(|x| (x * 25 + y * 7) as u8).collect())
+            .collect()
+    }
+
+    /// Filters every scanline with `filter` as written in the
+    /// specification, for pixels of one byte
+    fn filtered(rows: &[Vec<u8>], filter: u8) -> Vec<u8> {
+        filtered_with_distance(rows, filter, 1)
+    }
+
+    /// Filters every scanline with `filter`, `distance` being the bytes
+    /// per pixel rounded up
+    fn filtered_with_distance(rows: &[Vec<u8>], filter: u8, distance: usize) -> Vec<u8> {
+        let mut data = Vec::new();
+        for (y, row) in rows.iter().enumerate() {
+            data.push(filter);
+            for x in 0..row.len() {
+                let a = if x >= distance { row[x - distance] } else { 0 } as i16;
+                let b = if y > 0 { rows[y - 1][x] } else { 0 } as i16;
+                let c = if x >= distance && y > 0 {
+                    rows[y - 1][x - distance]
+                } else {
+                    0
+                } as i16;
+                let predicted = match filter {
+                    0 => 0,
+                    1 => a,
+                    2 => b,
+                    3 => (a + b) / 2,
+                    _ => {
+                        let p = a + b - c;
+                        let (pa, pb, pc) = ((p - a).abs(), (p - b).abs(), (p - c).abs());
+                        if pa <= pb && pa <= pc {
+                            a
+                        } else if pb <= pc {
+                            b
+                        } else {
+                            c
+                        }
+                    }
+                };
+                data.push(row[x].wrapping_sub(predicted as u8));
+            }
+        }
+        data
+    }
+
+    fn png(filtered: &[u8], interlaced: bool) -> Png {
+        image(0, 8, filtered, interlaced)
+    }
+
+    fn image(color_type: u8, bit_depth: u8, filtered: &[u8], interlaced: bool) -> Png {
+        let mut header = Vec::new();
+        header.extend((WIDTH as u32).to_be_bytes());
+        header.extend((HEIGHT as u32).to_be_bytes());
+        header.extend([bit_depth, color_type, 0, 0, interlaced as u8]);
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(filtered).unwrap();
+
+        let chunk = |chunk_type: &[u8; 4], data| {
+            Chunk::new(ChunkType::try_from(*chunk_type).unwrap(), data)
+        };
+        Png::from_chunks(vec![
+            chunk(b"IHDR", header),
+            chunk(b"IDAT", encoder.finish().unwrap()),
+            chunk(b"IEND", Vec::new()),
+        ])
+    }
+
+    #[test]
+    fn test_filters() {
+        let plain = Raster::decode(&png(&filtered(&pixels(), 0), false)).unwrap();
+        assert_eq!(plain.rows().collect::<Vec<_>>(), pixels());
+        for filter in [1, 2] {
+            let other = Raster::decode(&png(&filtered(&pixels(), filter), false)).unwrap();
+            assert_eq!(plain.compare(&other), PixelComparison::Identical);
+        }
+
+        let mut changed = pixels();
+        changed[4][7] ^= 1;
+        let changed = Raster::decode(&png(&filtered(&changed, 0), false)).unwrap();
+        assert_eq!(plain.compare(&changed), PixelComparison::DifferentRow(4));
+    }
+
+    /// Sample values covering the whole range of `bit_depth`, for every
+    /// scanline
+    fn samples(channels: usize, bit_depth: u8) -> Vec<Vec<u16>> {
+        (0..HEIGHT)
+            .map(|y| {
+                (0..WIDTH * channels)
+                    .map(|i| ((i * 40503 + y * 9973) % (1 << bit_depth)) as u16)
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Packs samples into scanlines, big-endian and highest bits first
+    fn pack(samples: &[u16], bit_depth: u8) -> Vec<u8> {
+        if bit_depth == 16 {
+            return samples
+                .iter()
+                .flat_map(|sample| sample.to_be_bytes())
+                .collect();
+        }
+        let mut row = vec![0; (samples.len() * bit_depth as usize).div_ceil(8)];
+        for (i, &sample) in samples.iter().enumerate() {
+            let bit = i * bit_depth as usize;
+            row[bit / 8] |= (sample as u8) << (8 - bit_depth as usize - bit % 8);
+        }
+        row
+    }
+
+    #[test]
+    fn test_formats() {
+        for (color_type, bit_depth) in FORMATS {
+            let channels = ColorType::try_from(color_type).unwrap().channels() as usize;
+            let samples = samples(channels, bit_depth);
+            let rows: Vec<Vec<u8>> = samples.iter().map(|row| pack(row, bit_depth)).collect();
+            let distance = (channels * bit_depth as usize).div_ceil(8);
+
+            for filter in 0..5 {
+                let data = filtered_with_distance(&rows, filter, distance);
+                let raster = Raster::decode(&image(color_type, bit_depth, &data, false)).unwrap();
+                for (y, row) in samples.iter().enumerate() {
+                    for (i, &expected) in row.iter().enumerate() {
+                        let (x, channel) = (i / channels, i % channels);
+                        assert_eq!(
+                            raster.sample(x as u32, y as u32, channel as u32),
+                            expected,
+                            "color type {}, bit depth {}, filter {}, pixel {}x{}",
+                            color_type,
+                            bit_depth,
+                            filter,
+                            x,
+                            y
+                        );
+                    }
+                }
+
+                let mut rebuilt = Raster::new(raster.header);
+                for (y, row) in samples.iter().enumerate() {
+                    for (i, &sample) in row.iter().enumerate() {
+                        let (x, channel) = ((i / channels) as u32, (i % channels) as u32);
+                        rebuilt.set_sample(x, y as u32, channel, sample);
+                    }
+                }
+                assert_eq!(rebuilt.compare(&raster), PixelComparison::Identical);
+
+                let mut interlaced = image(color_type, bit_depth, &data, false);
+                raster.store(&mut interlaced, true).unwrap();
+                let stored = Raster::decode(&interlaced).unwrap();
+                assert_eq!(stored.compare(&raster), PixelComparison::Identical);
+            }
+        }
+    }
+
+    #[test]
+    fn test_interlaced() {
+        let mut data = Vec::new();
+        for (x0, y0, dx, dy) in ADAM7 {
+            let rows: Vec<Vec<u8>> = pixels()
+                .iter()
+                .skip(y0 as usize)
+                .step_by(dy as usize)
+                .map(|row| {
+                    row.iter()
+                        .skip(x0 as usize)
+                        .step_by(dx as usize)
+                        .copied()
+                        .collect()
+                })
+                .filter(|row: &Vec<u8>| !row.is_empty())
+                .collect();
+            data.extend(filtered(&rows, 2));
+        }
+        let interlaced = Raster::decode(&png(&data, true)).unwrap();
+        let plain = Raster::decode(&png(&filtered(&pixels(), 0), false)).unwrap();
+        // A 10x9 image has pixels in every pass
+        let pass_heights: Vec<usize> = interlaced.filters().iter().map(Vec::len).collect();
+        assert_eq!(pass_heights, [2, 2, 1, 3, 2, 5, 4]);
+        assert!(interlaced
+            .filters()
+            .iter()
+            .flatten()
+            .all(|&filter| filter == 2));
+        assert_eq!(plain.filters(), [vec![0; HEIGHT]]);
+        assert_eq!(interlaced.compare(&plain), PixelComparison::Identical);
+    }
+
+    #[test]
+    fn test_errors() {
+        let data = filtered(&pixels(), 0);
+        assert!(matches!(
+            Raster::decode(&png(&data[..data.len() - 1], false)),
+            Err(RasterError::Truncated { .. })
+        ));
+
+        let mut data = data;
+        data[(WIDTH + 1) * 3] = 9;
+        assert!(matches!(
+            Raster::decode(&png(&data, false)),
+            Err(RasterError::UnknownFilter { row: 3, filter: 9 })
+        ));
+    }
+
+    #[test]
+    fn test_store() {
+        let plain = Raster::decode(&png(&filtered(&pixels(), 0), false)).unwrap();
+        for interlaced in [true, false] {
+            let mut png = png(&filtered(&pixels(), 1), !interlaced);
+            plain.store(&mut png, interlaced).unwrap();
+            assert_eq!(png.image_header().unwrap().interlaced, interlaced);
+            let stored = Raster::decode(&png).unwrap();
+            assert_eq!(stored.compare(&plain), PixelComparison::Identical);
+        }
+
+        let mut dice = Png::try_from(&PNG_FILE[..]).unwrap();
+        assert!(matches!(
+            plain.store(&mut dice, true),
+            Err(RasterError::FormatMismatch)
+        ));
+    }
+
+    #[test]
+    fn test_store_packed_pixels() {
+        // 2-bit grayscale, so that the pixels of a pass are spread across
+        // bytes differently than in the full scanlines
+        let mut header = Vec::new();
+        header.extend((WIDTH as u32).to_be_bytes());
+        header.extend((HEIGHT as u32).to_be_bytes());
+        header.extend([2, 0, 0, 0, 0]);
+        let raster = Raster {
+            header: ImageHeader::try_from(&header[..]).unwrap(),
+            row_len: 3,
+            data: (0..3 * HEIGHT as u8).map(|i| i.wrapping_mul(97)).collect(),
+            filters: Vec::new(),
+        };
+        let chunk = |chunk_type: &[u8; 4], data| {
+            Chunk::new(ChunkType::try_from(*chunk_type).unwrap(), data)
+        };
+        let mut png = Png::from_chunks(vec![
+            chunk(b"IHDR", header),
+            chunk(b"IDAT", vec![0]),
+            chunk(b"IEND", Vec::new()),
+        ]);
+
+        raster.store(&mut png, true).unwrap();
+        let stored = Raster::decode(&png).unwrap();
+        // The padding bits at the end of the scanlines aren't pixels
+        let mask = |rows: &Raster| -> Vec<u8> {
+            rows.rows()
+                .flat_map(|row| [row[0], row[1], row[2] & 0xf0])
+                .collect()
+        };
+        assert_eq!(mask(&stored), mask(&raster));
+    }
+
+    #[test]
+    fn test_png_file() {
+        let png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let raster = Raster::decode(&png).unwrap();
+        assert_eq!(raster.rows().count(), raster.header.height as usize);
+        assert_eq!(raster.compare(&raster.clone()), PixelComparison::Identical);
+        let mut stored = png.clone();
+        raster.store(&mut stored, true).unwrap();
+        let types: Vec<String> = stored
+            .chunks()
+            .iter()
+            .map(|chunk| chunk.chunk_type().to_string())
+            .collect();
+        assert_eq!(
+            types,
+            ["IHDR", "sRGB", "gAMA", "pHYs", "IDAT", "RuSt", "IEND"]
+        );
+        let interlaced = Raster::decode(&stored).unwrap();
+        assert!(interlaced.header.interlaced);
+        assert_eq!(raster.compare(&interlaced), PixelComparison::Identical);
+    }
+}
@@ -0,0 +1,214 @@
+use crate::audit::{Finding, Rule, Severity};
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::str::FromStr;
+
+/// The file format of a findings report
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportFormat {
+    /// The Static Analysis Results Interchange Format 2.1.0, understood by
+    /// most code scanning integrations
+    Sarif,
+
+    /// A plain JSON document listing the findings per file
+    Json,
+}
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+#[error("unknown report format '{0}', expected sarif or json")]
+pub struct UnknownReportFormatError(String);
+
+impl FromStr for ReportFormat {
+    type Err = UnknownReportFormatError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sarif" => Ok(Self::Sarif),
+            "json" => Ok(Self::Json),
+            other => Err(UnknownReportFormatError(other.to_owned())),
+        }
+    }
+}
+
+/// The findings for a single file
+pub struct FileFindings<'a> {
+    /// The path of the file, as it should appear in the report
+    pub path: &'a str,
+    pub findings: &'a [Finding],
+}
+
+impl ReportFormat {
+    /// Renders a report of all `files` as a pretty-printed JSON document
+    pub fn render(&self, files: &[FileFindings]) -> String {
+        let value = match self {
+            Self::Sarif => sarif(files),
+            Self::Json => structured(files),
+        };
+        serde_json::to_string_pretty(&value).expect("reports are always serializable")
+    }
+}
+
+fn sarif_level(severity: Severity) -> &'static str {
+    match severity {
+        Severity::Info => "note",
+        Severity::Warning => "warning",
+        Severity::Error => "error",
+    }
+}
+
+fn sarif(files: &[FileFindings]) -> Value {
+    let rules: Vec<Value> = Rule::ALL
+        .iter()
+        .map(|rule| {
+            json!({
+                "id": rule.id(),
+                "name": rule.name(),
+                "shortDescription": { "text": rule.description() },
+                "defaultConfiguration": { "level": sarif_level(rule.severity()) },
+            })
+        })
+        .collect();
+
+    let results: Vec<Value> = files
+        .iter()
+        .flat_map(|file| {
+            file.findings.iter().map(|finding| {
+                let mut location = json!({ "artifactLocation": { "uri": file.path } });
+                if let Some(region) = finding.location {
+                    location["region"] = json!({
+                        "byteOffset": region.offset,
+                        "byteLength": region.length,
+                    });
+                }
+
+                json!({
+                    "ruleId": finding.rule.id(),
+                    "ruleIndex": Rule::ALL.iter().position(|rule| *rule == finding.rule),
+                    "level": sarif_level(finding.rule.severity()),
+                    "message": { "text": finding.message },
+                    "locations": [{ "physicalLocation": location }],
+                })
+            })
+        })
+        .collect();
+
+    json!({
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": env!("CARGO_PKG_NAME"),
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "rules": rules,
+                }
+            },
+            "results": results,
+        }],
+    })
+}
+
+#[derive(Serialize)]
+struct JsonFinding<'a> {
+    rule_id: &'static str,
+    rule: &'static str,
+    severity: String,
+    message: &'a str,
+    chunk: Option<usize>,
+    offset: Option<usize>,
+    length: Option<usize>,
+}
+
+fn structured(files: &[FileFindings]) -> Value {
+    let files: Vec<Value> = files
+        .iter()
+        .map(|file| {
+            let findings: Vec<JsonFinding> = file
+                .findings
+                .iter()
+                .map(|finding| JsonFinding {
+                    rule_id: finding.rule.id(),
+                    rule: finding.rule.name(),
+                    severity: finding.rule.severity().to_string(),
+                    message: &finding.message,
+                    chunk: finding.location.and_then(|location| location.chunk),
+                    offset: finding.location.map(|location| location.offset),
+                    length: finding.location.map(|location| location.length),
+                })
+                .collect();
+            json!({ "path": file.path, "findings": findings })
+        })
+        .collect();
+
+    json!({
+        "tool": env!("CARGO_PKG_NAME"),
+        "version": env!("CARGO_PKG_VERSION"),
+        "files": files,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audit::Location;
+
+    fn testing_findings() -> Vec<Finding> {
+        vec![
+            Finding {
+                rule: Rule::TrailingData,
+                location: Some(Location {
+                    chunk: None,
+                    offset: 4803,
+                    length: 4,
+                }),
+                message: "found 4 bytes after the IEND chunk".to_owned(),
+            },
+            Finding {
+                rule: Rule::MissingColorSpace,
+                location: None,
+                message: "no color space".to_owned(),
+            },
+        ]
+    }
+
+    #[test]
+    fn test_sarif() {
+        let findings = testing_findings();
+        let report = ReportFormat::Sarif.render(&[FileFindings {
+            path: "dice.png",
+            findings: &findings,
+        }]);
+        let value: Value = serde_json::from_str(&report).unwrap();
+
+        assert_eq!(value["version"], "2.1.0");
+        let results = &value["runs"][0]["results"];
+        assert_eq!(results[0]["ruleId"], "A004");
+        assert_eq!(results[0]["level"], "warning");
+        let location = &results[0]["locations"][0]["physicalLocation"];
+        assert_eq!(location["artifactLocation"]["uri"], "dice.png");
+        assert_eq!(location["region"]["byteOffset"], 4803);
+        assert_eq!(results[1]["level"], "note");
+        assert!(results[1]["locations"][0]["physicalLocation"]["region"].is_null());
+    }
+
+    #[test]
+    fn test_json() {
+        let findings = testing_findings();
+        let report = ReportFormat::Json.render(&[FileFindings {
+            path: "dice.png",
+            findings: &findings,
+        }]);
+        let value: Value = serde_json::from_str(&report).unwrap();
+
+        let finding = &value["files"][0]["findings"][0];
+        assert_eq!(finding["rule"], "trailing-data");
+        assert_eq!(finding["severity"], "warning");
+        assert_eq!(finding["offset"], 4803);
+    }
+
+    #[test]
+    fn test_parse_format() {
+        assert_eq!(ReportFormat::from_str("sarif"), Ok(ReportFormat::Sarif));
+        assert!(ReportFormat::from_str("xml").is_err());
+    }
+}
@@ -0,0 +1,260 @@
+use std::{fmt, str::FromStr};
+
+/// A four character code identifying a RIFF chunk or form type
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FourCc(pub [u8; 4]);
+
+impl FourCc {
+    pub const RIFF: FourCc = FourCc(*b"RIFF");
+    pub const WEBP: FourCc = FourCc(*b"WEBP");
+}
+
+impl fmt::Display for FourCc {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", String::from_utf8_lossy(&self.0))
+    }
+}
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+#[error("'{0}' is not a FOURCC, expected 4 printable ASCII characters")]
+pub struct FourCcParseError(String);
+
+impl FromStr for FourCc {
+    type Err = FourCcParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.as_bytes()
+            .try_into()
+            .ok()
+            .filter(|bytes: &[u8; 4]| bytes.iter().all(|b| (b' '..=b'~').contains(b)))
+            .map(FourCc)
+            .ok_or_else(|| FourCcParseError(s.to_owned()))
+    }
+}
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum RiffError {
+    #[error("the file does not start with a RIFF header")]
+    Format,
+
+    #[error("the chunk at byte {0} is truncated")]
+    Truncated(usize),
+
+    #[error("the data of a chunk must be smaller than 4 GiB, got {0} bytes")]
+    ChunkTooLarge(usize),
+}
+
+/// A chunk of a RIFF file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RiffChunk {
+    id: FourCc,
+    data: Vec<u8>,
+}
+
+impl RiffChunk {
+    pub fn new(id: FourCc, data: Vec<u8>) -> Result<Self, RiffError> {
+        // The size must fit into the chunk header and, with padding, into the
+        // size of the whole file
+        if data.len() > u32::MAX as usize - 16 {
+            return Err(RiffError::ChunkTooLarge(data.len()));
+        }
+        Ok(Self { id, data })
+    }
+
+    pub fn id(&self) -> FourCc {
+        self.id
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// The value of the size field, which doesn't include the padding byte
+    pub fn size(&self) -> u32 {
+        self.data.len() as u32
+    }
+
+    /// Chunks are padded to an even length with a zero byte
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.id.0.to_vec();
+        bytes.extend_from_slice(&self.size().to_le_bytes());
+        bytes.extend_from_slice(&self.data);
+        if self.data.len() % 2 == 1 {
+            bytes.push(0);
+        }
+        bytes
+    }
+}
+
+/// A RIFF container such as a WebP file: a form type followed by a list of
+/// chunks. Bytes after the size given in the RIFF header are not part of the
+/// container and are dropped.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Riff {
+    form_type: FourCc,
+    chunks: Vec<RiffChunk>,
+}
+
+impl Riff {
+    pub fn new(form_type: FourCc, chunks: Vec<RiffChunk>) -> Self {
+        Self { form_type, chunks }
+    }
+
+    pub fn form_type(&self) -> FourCc {
+        self.form_type
+    }
+
+    pub fn chunks(&self) -> &[RiffChunk] {
+        &self.chunks
+    }
+
+    /// Returns the chunks together with their offset in the file
+    pub fn chunks_with_offsets(&self) -> impl Iterator<Item = (usize, &RiffChunk)> {
+        self.chunks.iter().scan(12, |offset, chunk| {
+            let start = *offset;
+            *offset += chunk.as_bytes().len();
+            Some((start, chunk))
+        })
+    }
+
+    pub fn append_chunk(&mut self, chunk: RiffChunk) {
+        self.chunks.push(chunk);
+    }
+
+    pub fn chunk_by_id(&self, id: FourCc) -> Option<&RiffChunk> {
+        self.chunks.iter().find(|chunk| chunk.id == id)
+    }
+
+    /// Removes the first chunk with the given id
+    pub fn remove_chunk(&mut self, id: FourCc) -> Option<RiffChunk> {
+        let position = self.chunks.iter().position(|chunk| chunk.id == id)?;
+        Some(self.chunks.remove(position))
+    }
+
+    /// Serializes the container, updating the size in the RIFF header
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut body = self.form_type.0.to_vec();
+        for chunk in &self.chunks {
+            body.extend(chunk.as_bytes());
+        }
+
+        let mut bytes = FourCc::RIFF.0.to_vec();
+        bytes.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        bytes.extend(body);
+        bytes
+    }
+}
+
+impl TryFrom<&[u8]> for Riff {
+    type Error = RiffError;
+
+    fn try_from(bytes: &[u8]) -> Result<Self, Self::Error> {
+        if bytes.len() < 12 || &bytes[..4] != b"RIFF" {
+            return Err(RiffError::Format);
+        }
+        let size = u32::from_le_bytes(bytes[4..8].try_into().unwrap()) as usize;
+        let end = size.checked_add(8).ok_or(RiffError::Truncated(0))?;
+        let bytes = bytes.get(..end).ok_or(RiffError::Truncated(0))?;
+        let form_type = FourCc(bytes[8..12].try_into().unwrap());
+
+        let mut chunks = Vec::new();
+        let mut cursor = 12;
+        while cursor < bytes.len() {
+            let header = bytes
+                .get(cursor..cursor + 8)
+                .ok_or(RiffError::Truncated(cursor))?;
+            let id = FourCc(header[..4].try_into().unwrap());
+            let size = u32::from_le_bytes(header[4..].try_into().unwrap()) as usize;
+            let data = bytes
+                .get(cursor + 8..cursor + 8 + size)
+                .ok_or(RiffError::Truncated(cursor))?;
+
+            chunks.push(RiffChunk {
+                id,
+                data: data.to_vec(),
+            });
+            // Some writers leave out the padding byte of the last chunk
+            cursor = (cursor + 8 + size + size % 2).min(bytes.len());
+        }
+
+        Ok(Self { form_type, chunks })
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// A simple lossless WebP with a fake bitstream and an odd-sized EXIF
+    /// chunk
+    pub(crate) fn testing_webp() -> Vec<u8> {
+        let mut bytes = b"RIFF\x1c\x00\x00\x00WEBP".to_vec();
+        bytes.extend_from_slice(b"VP8L\x04\x00\x00\x00\x2f\x00\x00\x00");
+        bytes.extend_from_slice(b"EXIF\x03\x00\x00\x00abc\x00");
+        bytes
+    }
+
+    #[test]
+    fn test_fourcc_from_str() {
+        assert_eq!(FourCc::from_str("ICCP").unwrap(), FourCc(*b"ICCP"));
+        assert_eq!(FourCc::from_str("ab c").unwrap(), FourCc(*b"ab c"));
+        assert!(FourCc::from_str("abc").is_err());
+        assert!(FourCc::from_str("abcde").is_err());
+        assert!(FourCc::from_str("ab\tc").is_err());
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let bytes = testing_webp();
+        let riff = Riff::try_from(&bytes[..]).unwrap();
+        assert_eq!(riff.form_type(), FourCc::WEBP);
+        let ids: Vec<String> = riff.chunks().iter().map(|c| c.id().to_string()).collect();
+        assert_eq!(ids, ["VP8L", "EXIF"]);
+        assert_eq!(riff.chunk_by_id(FourCc(*b"EXIF")).unwrap().data(), b"abc");
+        assert_eq!(riff.as_bytes(), bytes);
+    }
+
+    #[test]
+    fn test_chunks_with_offsets() {
+        let bytes = testing_webp();
+        let riff = Riff::try_from(&bytes[..]).unwrap();
+        let offsets: Vec<usize> = riff.chunks_with_offsets().map(|(o, _)| o).collect();
+        assert_eq!(offsets, [12, 24]);
+    }
+
+    #[test]
+    fn test_append_updates_sizes() {
+        let bytes = testing_webp();
+        let mut riff = Riff::try_from(&bytes[..]).unwrap();
+        riff.append_chunk(RiffChunk::new(FourCc(*b"RuSt"), b"hidden!".to_vec()).unwrap());
+
+        let written = riff.as_bytes();
+        assert_eq!(&written[4..8], &(written.len() as u32 - 8).to_le_bytes());
+        assert_eq!(written.len() % 2, 0);
+        assert_eq!(Riff::try_from(&written[..]).unwrap(), riff);
+
+        riff.remove_chunk(FourCc(*b"RuSt")).unwrap();
+        assert_eq!(riff.as_bytes(), bytes);
+    }
+
+    #[test]
+    fn test_missing_padding() {
+        let mut bytes = testing_webp();
+        bytes.pop();
+        bytes[4] -= 1;
+        let riff = Riff::try_from(&bytes[..]).unwrap();
+        assert_eq!(riff.chunks().len(), 2);
+    }
+
+    #[test]
+    fn test_invalid_files() {
+        assert_eq!(Riff::try_from(&b"\x89PNG"[..]), Err(RiffError::Format));
+
+        let bytes = testing_webp();
+        assert_eq!(Riff::try_from(&bytes[..20]), Err(RiffError::Truncated(0)));
+
+        let mut bytes = testing_webp();
+        bytes[28] = 0x10;
+        assert_eq!(Riff::try_from(&bytes[..]), Err(RiffError::Truncated(24)));
+    }
+}
@@ -0,0 +1,740 @@
+use crate::{
+    cancel::CancellationToken,
+    chunk::{Chunk, CrcHasher, MAX_CHUNK_LENGTH},
+    chunk_type::{ChunkType, STANDARD_TYPES},
+    detect::{self, Candidate},
+    png::{Png, PngParseError},
+    stream::{PngStreamParser, StreamEvent},
+};
+use std::{
+    fmt::{self, Display},
+    io::{self, Read},
+    time::{Duration, Instant},
+};
+
+/// How many bytes are read from the input at a time, which is also how often
+/// the time budget is checked
+const READ_SIZE: usize = 8 * 1024;
+
+/// How much of the data of a chunk, and of the bytes after `IEND` which
+/// aren't chunks, is kept to look for a payload in. The rest is only
+/// counted, so a scan holds at most this much per chunk whatever the
+/// length fields claim.
+const DETECT_PREFIX: usize = 64 * 1024;
+
+/// The resources a [`Scanner`] may use on one file, `None` meaning unlimited
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Limits {
+    /// Stop after reading this many bytes
+    pub max_bytes: Option<u64>,
+    /// Stop after this many chunks
+    pub max_chunks: Option<usize>,
+    /// Stop once the scan has taken this long
+    pub time_budget: Option<Duration>,
+}
+
+/// The limit that made a scan stop before the end of the file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Quota {
+    Bytes,
+    Chunks,
+    Time,
+    /// The scanner's cancellation token was cancelled
+    Cancelled,
+}
+
+impl Display for Quota {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Bytes => "the byte limit was reached",
+            Self::Chunks => "the chunk limit was reached",
+            Self::Time => "the time budget ran out",
+            Self::Cancelled => "the scan was cancelled",
+        })
+    }
+}
+
+/// A chunk found by a [`Scanner`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScannedChunk {
+    pub chunk_type: ChunkType,
+    /// Offset in bytes from the start of the file
+    pub offset: u64,
+    pub length: u32,
+    /// Whether the chunk was read to the end and its crc is valid, which is
+    /// only not the case for the last chunk of an incomplete scan
+    pub complete: bool,
+    /// Whether the chunk comes after `IEND`, where `encode` puts payloads
+    pub after_end: bool,
+}
+
+/// Something wrong or suspicious about a scanned file
+#[derive(Debug)]
+pub enum Anomaly {
+    /// The file isn't a PNG, or is broken in a way that stops parsing
+    Malformed(PngParseError),
+    /// The file ends in the middle of a chunk
+    Truncated,
+    /// The file has no `IEND` chunk
+    MissingEnd,
+    /// The first chunk isn't `IHDR`
+    MisplacedHeader,
+    /// A critical chunk viewers don't know and may refuse to show the image for
+    UnknownCritical { chunk_type: ChunkType, offset: u64 },
+    /// Bytes after `IEND` which aren't chunks, with what they look like if
+    /// they start like a known file, see [`detect::appended_signature`]
+    TrailingData {
+        offset: u64,
+        length: u64,
+        looks_like: Option<&'static str>,
+    },
+}
+
+impl Display for Anomaly {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Malformed(err) => write!(f, "the file can't be parsed: {}", err),
+            Self::Truncated => write!(f, "the file ends in the middle of a chunk"),
+            Self::MissingEnd => write!(f, "the IEND chunk is missing"),
+            Self::MisplacedHeader => write!(f, "the first chunk isn't IHDR"),
+            Self::UnknownCritical { chunk_type, offset } => write!(
+                f,
+                "unknown critical chunk {} at offset {}",
+                chunk_type, offset
+            ),
+            Self::TrailingData {
+                offset,
+                length,
+                looks_like,
+            } => {
+                write!(
+                    f,
+                    "{} bytes at offset {} after the IEND chunk aren't chunks",
+                    length, offset
+                )?;
+                match looks_like {
+                    Some(what) => write!(f, ", they look like {} appended to the image", what),
+                    None => Ok(()),
+                }
+            }
+        }
+    }
+}
+
+/// What a [`Scanner`] found out about a file, as far as it got
+#[derive(Debug, Default)]
+pub struct ScanReport {
+    /// The chunks in file order
+    pub chunks: Vec<ScannedChunk>,
+    pub anomalies: Vec<Anomaly>,
+    /// Chunks which probably carry a hidden payload, see [`detect::detect`]
+    pub payloads: Vec<Candidate>,
+    /// The number of bytes of the file that were looked at
+    pub bytes_read: u64,
+    /// Why the scan stopped before the end of the file, if it did
+    pub stopped: Option<Quota>,
+}
+
+impl ScanReport {
+    /// Whether the whole file was scanned, so the report covers all of it
+    pub fn is_complete(&self) -> bool {
+        self.stopped.is_none()
+    }
+}
+
+/// Inspects untrusted PNGs, e.g. uploads to a server, in bounded time and
+/// memory.
+///
+/// The file is streamed through a [`PngStreamParser`], and only the first
+/// [`DETECT_PREFIX`] bytes of the data of ancillary chunks, and of whatever
+/// comes after `IEND`, are held in memory to look for payloads in. So zlib
+/// streams longer than that are judged by their entropy. When a [`Limits`]
+/// quota is hit, the scan stops and reports what it found so far.
+#[derive(Debug, Clone, Default)]
+pub struct Scanner {
+    limits: Limits,
+    cancel: CancellationToken,
+}
+
+impl Scanner {
+    pub fn new(limits: Limits) -> Self {
+        Self {
+            limits,
+            cancel: CancellationToken::new(),
+        }
+    }
+
+    /// Also stops scans once `cancel` is cancelled, e.g. because the client
+    /// went away
+    pub fn with_cancellation(self, cancel: CancellationToken) -> Self {
+        Self { cancel, ..self }
+    }
+
+    /// Scans the PNG read from `reader`. Only errors reading it are returned,
+    /// problems with the file end up in the report.
+    pub fn scan<R: Read>(&self, reader: R) -> io::Result<ScanReport> {
+        let deadline = self
+            .limits
+            .time_budget
+            .and_then(|budget| Instant::now().checked_add(budget));
+        // One byte more than allowed tells whether the file is any longer
+        let mut reader = reader.take(self.limits.max_bytes.map_or(u64::MAX, |max| max + 1));
+        let mut scan = Scan::new(self.limits.max_chunks);
+        let mut buf = vec![0; READ_SIZE];
+
+        loop {
+            if self.cancel.is_cancelled() {
+                scan.stop(Quota::Cancelled);
+            } else if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                scan.stop(Quota::Time);
+            }
+            if scan.done() {
+                break;
+            }
+
+            let mut n = match reader.read(&mut buf) {
+                Ok(0) => {
+                    scan.finish();
+                    return Ok(scan.report);
+                }
+                Ok(n) => n,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            };
+            if let Some(max) = self.limits.max_bytes {
+                let allowed = max - scan.report.bytes_read;
+                if n as u64 > allowed {
+                    n = allowed as usize;
+                    scan.push(&buf[..n]);
+                    scan.stop(Quota::Bytes);
+                    continue;
+                }
+            }
+            scan.push(&buf[..n]);
+        }
+
+        Ok(scan.report)
+    }
+}
+
+/// The state of one scan
+struct Scan {
+    report: ScanReport,
+    max_chunks: Option<usize>,
+    parser: PngStreamParser,
+    /// Where the next chunk starts
+    offset: u64,
+    seen_end: bool,
+    /// The bytes after `IEND`
+    trailer: Trailer,
+    /// The start of the current chunk's data, if it is kept for payload
+    /// detection
+    data: Option<Vec<u8>>,
+    failed: bool,
+}
+
+impl Scan {
+    fn new(max_chunks: Option<usize>) -> Self {
+        Self {
+            report: ScanReport::default(),
+            max_chunks,
+            parser: PngStreamParser::new(),
+            offset: Png::SIGNATURE.len() as u64,
+            seen_end: false,
+            trailer: Trailer::default(),
+            data: None,
+            failed: false,
+        }
+    }
+
+    fn done(&self) -> bool {
+        self.failed || self.report.stopped.is_some()
+    }
+
+    fn stop(&mut self, quota: Quota) {
+        if !self.done() {
+            self.report.stopped = Some(quota);
+        }
+    }
+
+    fn push(&mut self, input: &[u8]) {
+        if self.done() {
+            return;
+        }
+        self.report.bytes_read += input.len() as u64;
+        let events = match self.parser.push(input) {
+            Ok(events) => events,
+            Err(err) => {
+                self.report.anomalies.push(Anomaly::Malformed(err));
+                self.failed = true;
+                return;
+            }
+        };
+        for event in events {
+            match event {
+                StreamEvent::ChunkStart { length, chunk_type } => {
+                    if !self.start_chunk(chunk_type, length, false) {
+                        return;
+                    }
+                }
+                StreamEvent::ChunkData(data) => {
+                    if let Some(buf) = &mut self.data {
+                        extend_prefix(buf, data);
+                    }
+                }
+                StreamEvent::ChunkEnd { .. } => {
+                    let data = self.data.take();
+                    self.end_chunk(data);
+                }
+                StreamEvent::TrailingData(data) => {
+                    for (chunk_type, length, data) in self.trailer.push(data) {
+                        if !self.start_chunk(chunk_type, length, true) {
+                            return;
+                        }
+                        self.end_chunk(data);
+                    }
+                }
+                StreamEvent::Signature => {}
+            }
+        }
+    }
+
+    /// Records the start of a chunk, unless the chunk limit is reached.
+    /// Returns whether the scan goes on.
+    fn start_chunk(&mut self, chunk_type: ChunkType, length: u32, after_end: bool) -> bool {
+        if self
+            .max_chunks
+            .is_some_and(|max| self.report.chunks.len() >= max)
+        {
+            self.stop(Quota::Chunks);
+            return false;
+        }
+        if self.report.chunks.is_empty() && &chunk_type.bytes() != b"IHDR" {
+            self.report.anomalies.push(Anomaly::MisplacedHeader);
+        }
+        if chunk_type.is_critical() && !STANDARD_TYPES.contains(&&chunk_type.bytes()) {
+            self.report.anomalies.push(Anomaly::UnknownCritical {
+                chunk_type: chunk_type.clone(),
+                offset: self.offset,
+            });
+        }
+        self.data = is_kept(&chunk_type).then(Vec::new);
+        self.report.chunks.push(ScannedChunk {
+            chunk_type,
+            offset: self.offset,
+            length,
+            complete: false,
+            after_end,
+        });
+        self.offset += length as u64 + 12;
+        true
+    }
+
+    /// Marks the current chunk as complete and looks for a payload in the
+    /// start of its data
+    fn end_chunk(&mut self, data: Option<Vec<u8>>) {
+        let Some(scanned) = self.report.chunks.last_mut() else {
+            return;
+        };
+        scanned.complete = true;
+        if &scanned.chunk_type.bytes() == b"IEND" {
+            self.seen_end = true;
+        }
+        let Some(data) = data else {
+            return;
+        };
+        let chunk = Chunk::new(scanned.chunk_type.clone(), data);
+        if let Some(evidence) = detect::evidence(&chunk) {
+            self.report.payloads.push(Candidate {
+                chunk_type: scanned.chunk_type.to_string(),
+                offset: scanned.offset as usize,
+                length: scanned.length as usize,
+                evidence,
+            });
+        }
+    }
+
+    /// Checks the end of a file that was read completely
+    fn finish(&mut self) {
+        if self.done() {
+            return;
+        }
+        if self.parser.finish().is_err() {
+            let anomaly = match self.report.bytes_read {
+                // An empty file or a partial signature
+                0..=7 => Anomaly::Malformed(PngParseError::Format),
+                _ => Anomaly::Truncated,
+            };
+            self.report.anomalies.push(anomaly);
+            return;
+        }
+        if !self.seen_end {
+            self.report.anomalies.push(Anomaly::MissingEnd);
+        }
+        let trailer = &self.trailer;
+        if trailer.chunks_end < trailer.len {
+            let start = self.report.bytes_read - trailer.len;
+            self.report.anomalies.push(Anomaly::TrailingData {
+                offset: start + trailer.chunks_end,
+                length: trailer.len - trailer.chunks_end,
+                looks_like: detect::appended_signature(&trailer.head),
+            });
+        }
+    }
+}
+
+/// Whether the data of chunks of `chunk_type` is looked at for a payload.
+/// Critical chunks only are if their type is a signature.
+fn is_kept(chunk_type: &ChunkType) -> bool {
+    !chunk_type.is_critical() || detect::signature(chunk_type).is_some()
+}
+
+/// Appends as much of `input` to `buf` as fits into [`DETECT_PREFIX`]
+fn extend_prefix(buf: &mut Vec<u8>, input: &[u8]) {
+    let room = DETECT_PREFIX.saturating_sub(buf.len());
+    buf.extend_from_slice(&input[..room.min(input.len())]);
+}
+
+/// Reads the bytes after `IEND` as they arrive, as the well-formed chunks
+/// `encode` appends there, up to the first bytes which aren't one
+#[derive(Default)]
+struct Trailer {
+    /// How many bytes came after `IEND`
+    len: u64,
+    /// Where the well-formed chunks end, counted from `IEND`
+    chunks_end: u64,
+    /// The first bytes from `chunks_end` on: the length, type and start of
+    /// the data of the current chunk, or of whatever isn't a chunk
+    head: Vec<u8>,
+    /// The current chunk, once its length and type were read
+    chunk: Option<TrailerChunk>,
+    /// The stored CRC of the current chunk
+    stored_crc: [u8; 4],
+    /// Whether the bytes from `chunks_end` on aren't a chunk
+    broken: bool,
+}
+
+struct TrailerChunk {
+    chunk_type: ChunkType,
+    length: u32,
+    /// The CRC of the type and the data read so far
+    crc: CrcHasher,
+}
+
+impl Trailer {
+    /// Reads the next bytes after `IEND`. Returns the chunks they complete,
+    /// with the start of their data if it is kept for payload detection.
+    fn push(&mut self, mut input: &[u8]) -> Vec<(ChunkType, u32, Option<Vec<u8>>)> {
+        let mut chunks = Vec::new();
+        while !input.is_empty() && !self.broken {
+            let position = self.len - self.chunks_end;
+            let n = match &mut self.chunk {
+                None => input.len().min(8 - position as usize),
+                Some(chunk) if position < 8 + chunk.length as u64 => {
+                    let n = input
+                        .len()
+                        .min((8 + chunk.length as u64 - position) as usize);
+                    chunk.crc.update(&input[..n]);
+                    n
+                }
+                Some(chunk) => {
+                    let start = (position - 8 - chunk.length as u64) as usize;
+                    let n = input.len().min(4 - start);
+                    self.stored_crc[start..start + n].copy_from_slice(&input[..n]);
+                    n
+                }
+            };
+            extend_prefix(&mut self.head, &input[..n]);
+            self.len += n as u64;
+            input = &input[n..];
+
+            let position = self.len - self.chunks_end;
+            match &self.chunk {
+                None if position == 8 => self.start_chunk(),
+                Some(chunk) if position == 12 + chunk.length as u64 => {
+                    if let Some(chunk) = self.end_chunk() {
+                        chunks.push(chunk);
+                    }
+                }
+                _ => {}
+            }
+        }
+        // What isn't a chunk is only counted, with its first bytes kept to
+        // tell what it is
+        extend_prefix(&mut self.head, input);
+        self.len += input.len() as u64;
+        chunks
+    }
+
+    /// Checks the length and type of the current chunk
+    fn start_chunk(&mut self) {
+        let length = u32::from_be_bytes(self.head[..4].try_into().unwrap());
+        let chunk_type: [u8; 4] = self.head[4..8].try_into().unwrap();
+        match ChunkType::try_from(chunk_type) {
+            Ok(chunk_type) if length <= MAX_CHUNK_LENGTH => {
+                self.chunk = Some(TrailerChunk {
+                    crc: CrcHasher::new(&chunk_type),
+                    chunk_type,
+                    length,
+                });
+            }
+            _ => self.broken = true,
+        }
+    }
+
+    /// Checks the CRC of the current chunk, which was read to the end
+    fn end_chunk(&mut self) -> Option<(ChunkType, u32, Option<Vec<u8>>)> {
+        let chunk = self.chunk.take()?;
+        if chunk.crc.finalize() != u32::from_be_bytes(self.stored_crc) {
+            self.broken = true;
+            return None;
+        }
+        let mut data = self.head.split_off(8);
+        data.truncate(chunk.length as usize);
+        self.head.clear();
+        self.chunks_end = self.len;
+        let data = is_kept(&chunk.chunk_type).then_some(data);
+        Some((chunk.chunk_type, chunk.length, data))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{detect::Evidence, payload::Envelope, png::tests::PNG_FILE};
+    use std::str::FromStr;
+
+    fn scan(limits: Limits, bytes: &[u8]) -> ScanReport {
+        Scanner::new(limits).scan(bytes).unwrap()
+    }
+
+    /// A reader returning a single byte per read
+    struct Trickle<'a>(&'a [u8]);
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.0.take(1).read(buf).inspect(|n| self.0 = &self.0[*n..])
+        }
+    }
+
+    #[test]
+    fn test_scan() {
+        let report = scan(Limits::default(), &PNG_FILE);
+        assert!(report.is_complete());
+        assert_eq!(report.bytes_read, PNG_FILE.len() as u64);
+
+        let png = Png::try_from(&PNG_FILE[..]).unwrap();
+        assert_eq!(report.chunks.len(), png.len());
+        for (scanned, (offset, chunk)) in report.chunks.iter().zip(png.chunks_with_offsets()) {
+            assert_eq!(&scanned.chunk_type, chunk.chunk_type());
+            assert_eq!(scanned.offset, offset as u64);
+            assert_eq!(scanned.length, chunk.length());
+            assert!(scanned.complete && !scanned.after_end);
+        }
+
+        // RuSt is a critical chunk no viewer knows
+        assert!(matches!(
+            &report.anomalies[..],
+            [Anomaly::UnknownCritical { chunk_type, .. }] if chunk_type.to_string() == "RuSt"
+        ));
+        // Which is the chunk type of the PNGme tutorial
+        assert_eq!(report.payloads.len(), 1);
+        assert_eq!(report.payloads[0].chunk_type, "RuSt");
+    }
+
+    #[test]
+    fn test_scan_in_pieces() {
+        let report = Scanner::default().scan(Trickle(&PNG_FILE)).unwrap();
+        assert_eq!(report.chunks, scan(Limits::default(), &PNG_FILE).chunks);
+    }
+
+    #[test]
+    fn test_payload_after_end() {
+        let chunk_type = ChunkType::from_str("ruSt").unwrap();
+        let payload = Chunk::new(
+            chunk_type.clone(),
+            Envelope::seal(b"hidden".to_vec()).as_bytes(),
+        );
+        let mut bytes = PNG_FILE.to_vec();
+        bytes.extend(payload.as_bytes());
+        bytes.extend(b"junk");
+
+        let report = scan(Limits::default(), &bytes);
+        let trickled = Scanner::default().scan(Trickle(&bytes)).unwrap();
+        assert_eq!(trickled.chunks, report.chunks);
+        assert_eq!(trickled.payloads, report.payloads);
+        let last = report.chunks.last().unwrap();
+        assert_eq!(last.chunk_type, chunk_type);
+        assert_eq!(last.offset, PNG_FILE.len() as u64);
+        assert!(last.complete && last.after_end);
+
+        // After the fixture's RuSt chunk
+        assert_eq!(report.payloads.len(), 2);
+        assert_eq!(report.payloads[1].offset, PNG_FILE.len());
+        assert_eq!(report.payloads[1].evidence, Evidence::Envelope);
+
+        let junk = bytes.len() as u64 - 4;
+        assert!(matches!(
+            report.anomalies.last(),
+            Some(Anomaly::TrailingData { offset, length: 4, looks_like: None }) if *offset == junk
+        ));
+    }
+
+    #[test]
+    fn test_large_chunks() {
+        let sealed = |len| Envelope::seal(vec![b'x'; len]).as_bytes();
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let large = Chunk::new(ChunkType::from_str("laRg").unwrap(), sealed(300_000));
+        png.insert_chunk(png.len() - 1, large);
+        let mut bytes = png.as_bytes();
+        let appended = Chunk::new(ChunkType::from_str("ruSt").unwrap(), sealed(300_000));
+        bytes.extend(appended.as_bytes());
+        let junk = bytes.len() as u64;
+        bytes.extend(b"PK\x03\x04");
+        bytes.extend(vec![0; 300_000]);
+
+        // Only the start of every chunk and of the junk is held
+        let mut scan = Scan::new(None);
+        for piece in bytes.chunks(READ_SIZE) {
+            scan.push(piece);
+            assert!(scan.data.as_ref().map_or(0, Vec::len) <= DETECT_PREFIX);
+            assert!(scan.trailer.head.len() <= DETECT_PREFIX);
+        }
+        scan.finish();
+        let report = scan.report;
+
+        // which is enough to find both payloads
+        let payloads: Vec<_> = report.payloads[1..]
+            .iter()
+            .map(|payload| (payload.chunk_type.as_str(), &payload.evidence))
+            .collect();
+        assert_eq!(
+            payloads,
+            [("laRg", &Evidence::Envelope), ("ruSt", &Evidence::Envelope)]
+        );
+        let last = report.chunks.last().unwrap();
+        assert!(last.complete && last.after_end);
+        assert!(matches!(
+            report.anomalies.last(),
+            Some(Anomaly::TrailingData {
+                offset,
+                length: 300_004,
+                looks_like: Some("a ZIP archive"),
+            }) if *offset == junk
+        ));
+    }
+
+    #[test]
+    fn test_corrupted_chunk_after_end() {
+        let chunk = Chunk::new(ChunkType::from_str("ruSt").unwrap(), vec![1; 100]);
+        let mut bytes = PNG_FILE.to_vec();
+        bytes.extend(chunk.as_bytes());
+        // The CRC no longer matches
+        let last = bytes.len() - 1;
+        bytes[last] ^= 1;
+
+        let report = scan(Limits::default(), &bytes);
+        assert!(report.chunks.iter().all(|chunk| !chunk.after_end));
+        assert!(matches!(
+            report.anomalies.last(),
+            Some(Anomaly::TrailingData { offset, length: 112, .. })
+                if *offset == PNG_FILE.len() as u64
+        ));
+    }
+
+    #[test]
+    fn test_signatures() {
+        let mut bytes = PNG_FILE.to_vec();
+        bytes.extend(b"PK\x03\x04 the rest of a zip file");
+        let report = scan(Limits::default(), &bytes);
+        let anomaly = report.anomalies.last().unwrap();
+        assert!(matches!(
+            anomaly,
+            Anomaly::TrailingData {
+                looks_like: Some("a ZIP archive"),
+                ..
+            }
+        ));
+        assert!(anomaly
+            .to_string()
+            .ends_with("they look like a ZIP archive appended to the image"));
+
+        // The fixture's RuSt chunk is the PNGme tutorial's
+        assert!(matches!(
+            &report.payloads[..],
+            [Candidate {
+                evidence: Evidence::Signature(_),
+                ..
+            }]
+        ));
+    }
+
+    #[test]
+    fn test_max_bytes() {
+        let limits = Limits {
+            max_bytes: Some(100),
+            ..Limits::default()
+        };
+        let report = scan(limits, &PNG_FILE);
+        assert_eq!(report.stopped, Some(Quota::Bytes));
+        assert_eq!(report.bytes_read, 100);
+        // Stopped in the middle of IDAT
+        let last = report.chunks.last().unwrap();
+        assert_eq!(last.chunk_type.to_string(), "IDAT");
+        assert!(!last.complete);
+
+        let limits = Limits {
+            max_bytes: Some(PNG_FILE.len() as u64),
+            ..Limits::default()
+        };
+        assert!(scan(limits, &PNG_FILE).is_complete());
+    }
+
+    #[test]
+    fn test_max_chunks() {
+        let limits = Limits {
+            max_chunks: Some(3),
+            ..Limits::default()
+        };
+        let report = scan(limits, &PNG_FILE);
+        assert_eq!(report.stopped, Some(Quota::Chunks));
+        assert_eq!(report.chunks.len(), 3);
+        assert!(report.chunks.iter().all(|chunk| chunk.complete));
+    }
+
+    #[test]
+    fn test_time_budget_and_cancellation() {
+        let limits = Limits {
+            time_budget: Some(Duration::ZERO),
+            ..Limits::default()
+        };
+        let report = scan(limits, &PNG_FILE);
+        assert_eq!(report.stopped, Some(Quota::Time));
+        assert_eq!(report.bytes_read, 0);
+
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+        let scanner = Scanner::default().with_cancellation(cancel);
+        let report = scanner.scan(&PNG_FILE[..]).unwrap();
+        assert_eq!(report.stopped, Some(Quota::Cancelled));
+    }
+
+    #[test]
+    fn test_broken_files() {
+        let report = scan(Limits::default(), &PNG_FILE[..100]);
+        assert!(report.is_complete());
+        assert!(matches!(report.anomalies.last(), Some(Anomaly::Truncated)));
+
+        let report = scan(Limits::default(), b"GIF89a");
+        assert!(matches!(
+            &report.anomalies[..],
+            [Anomaly::Malformed(PngParseError::Format)]
+        ));
+
+        let mut corrupted = PNG_FILE.to_vec();
+        corrupted[20] ^= 1;
+        let report = scan(Limits::default(), &corrupted);
+        assert!(matches!(&report.anomalies[..], [Anomaly::Malformed(_)]));
+        assert!(report.chunks.is_empty() || !report.chunks[0].complete);
+    }
+}
@@ -0,0 +1,308 @@
+use crate::{chunk::Chunk, chunk_type::ChunkType, png::Png};
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+
+/// Private chunk types written by common image software, which the
+/// fragments of a scattered payload pose as
+pub const COVER_TYPES: [&[u8; 4]; 10] = [
+    b"mkBF", b"mkBS", b"mkBT", b"mkTS", b"prVW", b"npTc", b"npLb", b"vpAg", b"caNv", b"tpNG",
+];
+
+/// The bytes identifying a fragment and its place in the payload
+const TAG_LEN: usize = 4;
+
+/// The data of a fragment, without its tag, is at least this long unless it
+/// is the last one, and shorter than `MIN_FRAGMENT + FRAGMENT_RANGE`
+const MIN_FRAGMENT: usize = 24;
+const FRAGMENT_RANGE: usize = 200;
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum ScatterError {
+    #[error("the payload is too large to be scattered")]
+    TooLarge,
+
+    #[error("the PNG doesn't start with an IHDR chunk to place the fragments after")]
+    NoPlace,
+
+    #[error("fragment {0} of the scattered payload is missing")]
+    MissingFragment(usize),
+}
+
+/// An endless stream of bytes derived from a seed
+struct KeyStream {
+    seed: [u8; 32],
+    purpose: &'static [u8],
+    counter: u64,
+    block: [u8; 32],
+    used: usize,
+}
+
+impl KeyStream {
+    fn new(seed: [u8; 32], purpose: &'static [u8]) -> Self {
+        Self {
+            seed,
+            purpose,
+            counter: 0,
+            block: [0; 32],
+            used: 32,
+        }
+    }
+
+    fn byte(&mut self) -> u8 {
+        if self.used == self.block.len() {
+            let mut hasher = Sha256::new();
+            hasher.update(self.seed);
+            hasher.update(self.purpose);
+            hasher.update(self.counter.to_le_bytes());
+            self.block = hasher.finalize().into();
+            self.counter += 1;
+            self.used = 0;
+        }
+        self.used += 1;
+        self.block[self.used - 1]
+    }
+
+    fn below(&mut self, bound: usize) -> usize {
+        let value = u32::from_le_bytes([self.byte(), self.byte(), self.byte(), self.byte()]);
+        value as usize % bound
+    }
+}
+
+/// Everything about a scattered payload follows from the key and the label,
+/// so several payloads can be scattered with the same key
+fn seed(key: &[u8], label: &str) -> [u8; 32] {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes keys of any length");
+    mac.update(label.as_bytes());
+    mac.finalize().into_bytes().into()
+}
+
+fn tag(seed: &[u8; 32], index: usize) -> [u8; TAG_LEN] {
+    let mut mac = Hmac::<Sha256>::new_from_slice(seed).expect("HMAC takes keys of any length");
+    mac.update(&(index as u64).to_be_bytes());
+    let mut tag = [0; TAG_LEN];
+    tag.copy_from_slice(&mac.finalize().into_bytes()[..TAG_LEN]);
+    tag
+}
+
+fn is_cover(chunk: &Chunk) -> bool {
+    COVER_TYPES.contains(&&chunk.chunk_type().bytes())
+}
+
+/// The fragments of the payload of `seed` in `png`, as (chunk index,
+/// fragment index)
+fn fragments(png: &Png, seed: &[u8; 32]) -> Vec<(usize, usize)> {
+    let candidates = png.chunks().iter().filter(|chunk| is_cover(chunk)).count();
+    let tags: HashMap<[u8; TAG_LEN], usize> = (0..candidates)
+        .map(|index| (tag(seed, index), index))
+        .collect();
+    png.chunks()
+        .iter()
+        .enumerate()
+        .filter(|(_, chunk)| is_cover(chunk) && chunk.data().len() >= TAG_LEN)
+        .filter_map(|(position, chunk)| {
+            let tag: [u8; TAG_LEN] = chunk.data()[..TAG_LEN].try_into().unwrap();
+            tags.get(&tag).map(|&index| (position, index))
+        })
+        .collect()
+}
+
+/// The indices a chunk can be inserted at: anywhere after `IHDR` and before
+/// `IEND`, except between two `IDAT` chunks
+fn positions(png: &Png) -> Vec<usize> {
+    let chunks = png.chunks();
+    let is_type = |index: usize, chunk_type: &[u8; 4]| {
+        chunks
+            .get(index)
+            .is_some_and(|chunk| &chunk.chunk_type().bytes() == chunk_type)
+    };
+    let end = chunks
+        .iter()
+        .position(|chunk| &chunk.chunk_type().bytes() == b"IEND")
+        .unwrap_or(chunks.len());
+    (1..=end)
+        .filter(|&index| !(is_type(index - 1, b"IDAT") && is_type(index, b"IDAT")))
+        .collect()
+}
+
+/// Splits `payload` into small chunks of the [`COVER_TYPES`] and inserts
+/// them at scattered positions, replacing a payload scattered earlier with
+/// the same key and label. The sizes, types and positions of the fragments
+/// are derived from the key and the label, and their data is masked with a
+/// key-derived stream, so it looks random.
+///
+/// This only keeps the payload from standing out to casual inspection. The
+/// masking is not meant as encryption, and the number and size of the
+/// fragments still show in a chunk listing. Returns the number of chunks
+/// written.
+pub fn scatter(
+    png: &mut Png,
+    label: &str,
+    key: &[u8],
+    payload: &[u8],
+) -> Result<usize, ScatterError> {
+    let len = u32::try_from(payload.len()).map_err(|_| ScatterError::TooLarge)?;
+    let first_chunk = png.chunks().first().map(|chunk| chunk.chunk_type().bytes());
+    if first_chunk != Some(*b"IHDR") {
+        return Err(ScatterError::NoPlace);
+    }
+    remove(png, label, key);
+
+    let seed = seed(key, label);
+    let mut mask = KeyStream::new(seed, b"mask");
+    let mut layout = KeyStream::new(seed, b"layout");
+    let stream: Vec<u8> = len
+        .to_be_bytes()
+        .iter()
+        .chain(payload)
+        .map(|byte| byte ^ mask.byte())
+        .collect();
+
+    let mut rest = &stream[..];
+    let mut index = 0;
+    while !rest.is_empty() {
+        let size = (MIN_FRAGMENT + layout.below(FRAGMENT_RANGE)).min(rest.len());
+        let (data, remaining) = rest.split_at(size);
+        rest = remaining;
+
+        let chunk_type = COVER_TYPES[layout.below(COVER_TYPES.len())];
+        let mut fragment = tag(&seed, index).to_vec();
+        fragment.extend_from_slice(data);
+        let positions = positions(png);
+        let position = positions[layout.below(positions.len())];
+        png.insert_chunk(
+            position,
+            Chunk::new(ChunkType::try_from(*chunk_type).unwrap(), fragment),
+        );
+        index += 1;
+    }
+    Ok(index)
+}
+
+/// Reassembles the payload scattered with `key` and `label`, None if there
+/// is none
+pub fn gather(png: &Png, label: &str, key: &[u8]) -> Result<Option<Vec<u8>>, ScatterError> {
+    let seed = seed(key, label);
+    let mut fragments = fragments(png, &seed);
+    if fragments.is_empty() {
+        return Ok(None);
+    }
+    fragments.sort_by_key(|&(_, index)| index);
+
+    let mut mask = KeyStream::new(seed, b"mask");
+    let mut stream = Vec::new();
+    for (expected, &(position, index)) in fragments.iter().enumerate() {
+        if index != expected {
+            return Err(ScatterError::MissingFragment(expected));
+        }
+        let data = &png[position].data()[TAG_LEN..];
+        stream.extend(data.iter().map(|byte| byte ^ mask.byte()));
+    }
+    let missing = || ScatterError::MissingFragment(fragments.len());
+    let len: [u8; 4] = stream.get(..4).ok_or_else(missing)?.try_into().unwrap();
+    let len = u32::from_be_bytes(len) as usize;
+    match stream.get(4..4 + len) {
+        Some(payload) => Ok(Some(payload.to_vec())),
+        None => Err(missing()),
+    }
+}
+
+/// Removes the fragments scattered with `key` and `label`, returning how many
+/// there were
+pub fn remove(png: &mut Png, label: &str, key: &[u8]) -> usize {
+    let mut positions: Vec<usize> = fragments(png, &seed(key, label))
+        .into_iter()
+        .map(|(position, _)| position)
+        .collect();
+    positions.sort_unstable();
+    let mut position = 0;
+    png.retain(|_| {
+        position += 1;
+        positions.binary_search(&(position - 1)).is_err()
+    });
+    positions.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::png::tests::PNG_FILE;
+
+    fn payload() -> Vec<u8> {
+        (0..1000u32).map(|i| (i * 7 % 251) as u8).collect()
+    }
+
+    fn types(png: &Png) -> Vec<String> {
+        png.chunks()
+            .iter()
+            .map(|chunk| chunk.chunk_type().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_scatter_gather() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let original = png.clone();
+        let count = scatter(&mut png, "ruSt", b"key", &payload()).unwrap();
+        assert!(count > 1);
+        assert_eq!(png.len(), original.len() + count);
+        assert_eq!(gather(&png, "ruSt", b"key").unwrap(), Some(payload()));
+
+        // Nothing is found with another key or label
+        assert_eq!(gather(&png, "ruSt", b"other").unwrap(), None);
+        assert_eq!(gather(&png, "other", b"key").unwrap(), None);
+
+        // Fragments stay between IHDR and IEND and off the IDAT sequence,
+        // and the payload doesn't show
+        let types = types(&png);
+        assert_eq!(types.first().unwrap(), "IHDR");
+        assert_eq!(types.last().unwrap(), "IEND");
+        let bytes = png.as_bytes();
+        assert!(!bytes
+            .windows(16)
+            .any(|window| payload().windows(16).any(|p| p == window)));
+
+        // The layout only depends on the key, the label and the payload
+        let mut again = original.clone();
+        scatter(&mut again, "ruSt", b"key", &payload()).unwrap();
+        assert_eq!(again.as_bytes(), bytes);
+        let mut other = original.clone();
+        scatter(&mut other, "ruSt", b"other", &payload()).unwrap();
+        assert_ne!(other.as_bytes(), bytes);
+    }
+
+    #[test]
+    fn test_replace_and_remove() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let original = png.clone();
+        scatter(&mut png, "ruSt", b"key", &payload()).unwrap();
+        scatter(&mut png, "ruSt", b"key", b"short").unwrap();
+        assert_eq!(
+            gather(&png, "ruSt", b"key").unwrap().as_deref(),
+            Some(&b"short"[..])
+        );
+        assert_eq!(remove(&mut png, "ruSt", b"key"), 1);
+        assert_eq!(png, original);
+
+        scatter(&mut png, "ruSt", b"key", b"").unwrap();
+        assert_eq!(gather(&png, "ruSt", b"key").unwrap(), Some(Vec::new()));
+    }
+
+    #[test]
+    fn test_missing_fragment() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        scatter(&mut png, "ruSt", b"key", &payload()).unwrap();
+        let fragments = fragments(&png, &seed(b"key", "ruSt"));
+        let (position, _) = fragments.iter().find(|(_, index)| *index == 1).unwrap();
+        let position = *position;
+        let mut index = 0;
+        png.retain(|_| {
+            index += 1;
+            index - 1 != position
+        });
+        assert_eq!(
+            gather(&png, "ruSt", b"key"),
+            Err(ScatterError::MissingFragment(1))
+        );
+    }
+}
@@ -0,0 +1,294 @@
+use crate::{chunk::Chunk, png::Png};
+use regex::Regex;
+use std::{cmp::Ordering, str::FromStr};
+
+/// A chunk picked by a [`Selection`], with its position in the PNG
+#[derive(Debug, Clone, Copy)]
+pub struct Selected<'a> {
+    pub index: usize,
+    /// The byte offset the chunk starts at in the serialized file
+    pub offset: usize,
+    pub chunk: &'a Chunk,
+}
+
+/// A regular expression matched against the whole chunk type, so a plain
+/// type like `tEXt` only matches itself and `te?Xt` matches `tEXt` and `tXt`
+#[derive(Debug, Clone)]
+pub struct TypePattern(Regex);
+
+impl FromStr for TypePattern {
+    type Err = regex::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // Checked on its own first, so errors point into the pattern as given
+        Regex::new(s)?;
+        Ok(Self(Regex::new(&format!("^(?:{})$", s))?))
+    }
+}
+
+impl TypePattern {
+    pub fn matches(&self, chunk_type: &str) -> bool {
+        self.0.is_match(chunk_type)
+    }
+}
+
+/// What a listing is sorted by
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SortKey {
+    /// The length of the data
+    Size,
+    /// The chunk type, compared byte by byte
+    Type,
+    /// The position in the file
+    #[default]
+    Offset,
+}
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+#[error("unknown sort key '{0}', expected size, type or offset")]
+pub struct UnknownSortKeyError(String);
+
+impl FromStr for SortKey {
+    type Err = UnknownSortKeyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "size" => Ok(Self::Size),
+            "type" => Ok(Self::Type),
+            "offset" => Ok(Self::Offset),
+            other => Err(UnknownSortKeyError(other.to_owned())),
+        }
+    }
+}
+
+impl SortKey {
+    fn compare(self, a: &Selected, b: &Selected) -> Ordering {
+        match self {
+            Self::Size => a.chunk.length().cmp(&b.chunk.length()),
+            Self::Type => a
+                .chunk
+                .chunk_type()
+                .bytes()
+                .cmp(&b.chunk.chunk_type().bytes()),
+            Self::Offset => a.offset.cmp(&b.offset),
+        }
+    }
+}
+
+/// Which chunks a listing shows. The default selects all of them.
+#[derive(Debug, Clone, Default)]
+pub struct Selection {
+    /// Only chunks whose type matches one of these, any type if empty
+    pub types: Vec<TypePattern>,
+    /// Only ancillary chunks
+    pub ancillary: bool,
+    /// Only critical chunks
+    pub critical: bool,
+    /// Only chunks which are unsafe to copy
+    pub unsafe_to_copy: bool,
+    /// Only chunks whose type isn't defined by the PNG specification
+    pub nonstandard: bool,
+    pub sort: SortKey,
+    /// Sort in descending order
+    pub descending: bool,
+    /// Leave out this many of the matching chunks, after sorting
+    pub skip: usize,
+    /// Show at most this many of the matching chunks
+    pub limit: Option<usize>,
+}
+
+impl Selection {
+    pub fn matches_type(&self, chunk_type: &str) -> bool {
+        self.types.is_empty() || self.types.iter().any(|pattern| pattern.matches(chunk_type))
+    }
+
+    /// Whether chunks are filtered by the properties of their type, which
+    /// only PNG chunks have
+    pub fn filters_properties(&self) -> bool {
+        self.ancillary || self.critical || self.unsafe_to_copy || self.nonstandard
+    }
+
+    /// Whether `chunk` passes all filters
+    pub fn matches(&self, chunk: &Chunk) -> bool {
+        let chunk_type = chunk.chunk_type();
+        let critical = chunk_type.is_critical();
+        self.matches_type(&chunk_type.to_string())
+            && (!self.ancillary || !critical)
+            && (!self.critical || critical)
+            && (!self.unsafe_to_copy || !chunk_type.is_safe_to_copy())
+            && (!self.nonstandard || !chunk_type.is_standard())
+    }
+
+    /// Applies `skip` and `limit` to items which already passed the filters
+    pub fn window<T>(&self, items: impl IntoIterator<Item = T>) -> impl Iterator<Item = T> {
+        items
+            .into_iter()
+            .skip(self.skip)
+            .take(self.limit.unwrap_or(usize::MAX))
+    }
+
+    /// Whether the listing is in another order than the file
+    pub fn reorders(&self) -> bool {
+        self.sort != SortKey::Offset || self.descending
+    }
+
+    /// The selected chunks of `png`, in the order asked for. Chunks which
+    /// compare equal stay in file order.
+    pub fn select<'a>(&self, png: &'a Png) -> Vec<Selected<'a>> {
+        let mut matching: Vec<_> = png
+            .chunks_with_offsets()
+            .enumerate()
+            .map(|(index, (offset, chunk))| Selected {
+                index,
+                offset,
+                chunk,
+            })
+            .filter(|selected| self.matches(selected.chunk))
+            .collect();
+        matching.sort_by(|a, b| {
+            let ordering = self.sort.compare(a, b);
+            if self.descending {
+                ordering.reverse()
+            } else {
+                ordering
+            }
+        });
+        self.window(matching).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::png::tests::PNG_FILE;
+
+    fn selected_types(png: &Png, selection: &Selection) -> Vec<(usize, String)> {
+        selection
+            .select(png)
+            .iter()
+            .map(|selected| (selected.index, selected.chunk.chunk_type().to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_select() {
+        let png = Png::try_from(&PNG_FILE[..]).unwrap();
+        assert_eq!(Selection::default().select(&png).len(), png.chunks().len());
+
+        let selection = Selection {
+            skip: 1,
+            limit: Some(2),
+            ..Selection::default()
+        };
+        let expected: Vec<_> = png.chunks()[1..3]
+            .iter()
+            .enumerate()
+            .map(|(index, chunk)| (index + 1, chunk.chunk_type().to_string()))
+            .collect();
+        assert_eq!(selected_types(&png, &selection), expected);
+    }
+
+    #[test]
+    fn test_types() {
+        let png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let selection = Selection {
+            types: vec!["gAMA".parse().unwrap(), "IEND".parse().unwrap()],
+            ..Selection::default()
+        };
+        let selected = selection.select(&png);
+        assert_eq!(selected.len(), 2);
+        assert_eq!(selected[1].index, png.chunks().len() - 1);
+        let (offset, _) = png.chunks_with_offsets().last().unwrap();
+        assert_eq!(selected[1].offset, offset);
+
+        let selection = Selection {
+            types: vec!["gama".parse().unwrap(), "AMA".parse().unwrap()],
+            ..Selection::default()
+        };
+        assert!(selection.select(&png).is_empty());
+
+        let selection = Selection {
+            types: vec!["[a-z]..[A-Z]|IE.D".parse().unwrap()],
+            ..Selection::default()
+        };
+        assert_eq!(
+            selected_types(&png, &selection)
+                .into_iter()
+                .map(|(_, chunk_type)| chunk_type)
+                .collect::<Vec<_>>(),
+            ["sRGB", "gAMA", "IEND"]
+        );
+    }
+
+    #[test]
+    fn test_sort() {
+        let png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let selection = Selection {
+            sort: SortKey::Size,
+            descending: true,
+            limit: Some(3),
+            ..Selection::default()
+        };
+        let selected = selected_types(&png, &selection);
+        assert_eq!(selected[0], (4, "IDAT".to_owned()));
+        assert_eq!(selected[1], (0, "IHDR".to_owned()));
+
+        let selection = Selection {
+            sort: SortKey::Type,
+            ..Selection::default()
+        };
+        let types: Vec<_> = selected_types(&png, &selection)
+            .into_iter()
+            .map(|(_, chunk_type)| chunk_type)
+            .collect();
+        assert_eq!(
+            types,
+            ["IDAT", "IEND", "IHDR", "RuSt", "gAMA", "pHYs", "sRGB"]
+        );
+
+        let selection = Selection {
+            descending: true,
+            ..Selection::default()
+        };
+        assert_eq!(selection.select(&png)[0].index, png.chunks().len() - 1);
+        assert!(selection.reorders());
+        assert!(!Selection::default().reorders());
+    }
+
+    #[test]
+    fn test_properties() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        png.insert_before_end(Chunk::new("ruST".parse().unwrap(), b"copy".to_vec()));
+        png.insert_before_end(Chunk::new("ruSt".parse().unwrap(), b"keep".to_vec()));
+
+        let types = |selection: Selection| -> Vec<String> {
+            selected_types(&png, &selection)
+                .into_iter()
+                .map(|(_, chunk_type)| chunk_type)
+                .collect()
+        };
+        assert_eq!(
+            types(Selection {
+                critical: true,
+                ..Selection::default()
+            }),
+            ["IHDR", "IDAT", "RuSt", "IEND"]
+        );
+        assert_eq!(
+            types(Selection {
+                ancillary: true,
+                nonstandard: true,
+                ..Selection::default()
+            }),
+            ["ruST", "ruSt"]
+        );
+        assert_eq!(
+            types(Selection {
+                nonstandard: true,
+                unsafe_to_copy: true,
+                ..Selection::default()
+            }),
+            ["ruST"]
+        );
+    }
+}
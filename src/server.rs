@@ -0,0 +1,373 @@
+use crate::{
+    chunk_type::ChunkType,
+    container::StegoContainer,
+    payload::{Envelope, Part},
+    png::Png,
+    scan::Scanner,
+};
+use base64::prelude::{Engine, BASE64_STANDARD};
+use serde_json::{json, Value};
+use std::{collections::HashMap, fmt::Display, str::FromStr};
+
+/// The largest request body `serve` accepts unless told otherwise, 32 MiB
+pub const DEFAULT_MAX_BODY: u64 = 32 * 1024 * 1024;
+
+/// Why the server couldn't be started
+#[derive(Debug, thiserror::Error)]
+pub enum ServerError {
+    #[error("pngme was built without the HTTP server, rebuild it with --features server")]
+    Unsupported,
+
+    #[error("failed to listen on {address}: {message}")]
+    Listen { address: String, message: String },
+}
+
+/// How [`serve`] handles requests
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ServeOptions {
+    /// The number of requests handled at the same time
+    pub workers: usize,
+    /// Requests with a larger body are rejected with status 413
+    pub max_body: u64,
+}
+
+impl Default for ServeOptions {
+    fn default() -> Self {
+        Self {
+            workers: 4,
+            max_body: DEFAULT_MAX_BODY,
+        }
+    }
+}
+
+/// The answer to a request, independent of the HTTP library
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Response {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    fn json(status: u16, value: Value) -> Self {
+        Self {
+            status,
+            content_type: "application/json",
+            body: value.to_string().into_bytes(),
+        }
+    }
+
+    fn png(png: &Png) -> Self {
+        Self {
+            status: 200,
+            content_type: "image/png",
+            body: png.as_bytes(),
+        }
+    }
+
+    fn error<M: Display>(status: u16, message: M) -> Self {
+        Self::json(status, json!({ "error": message.to_string() }))
+    }
+}
+
+/// The query parameters of a request
+type Params = HashMap<String, String>;
+
+/// Answers a request to the API. `url` is the path with the query string,
+/// `body` the PNG that was posted.
+///
+/// - `/inspect` lists the chunks, anomalies and likely payloads as JSON, see
+///   [`Scanner`]
+/// - `/encode?chunk_type=ruSt&message=...` returns the PNG with the message
+///   sealed in an [`Envelope`] in a new chunk, like the encode command
+///   stores it by default
+/// - `/decode?chunk_type=ruSt` returns the message as JSON
+/// - `/strip?chunk_type=ruSt` returns the PNG without the chunk
+pub fn handle(method: &str, url: &str, body: &[u8]) -> Response {
+    let (path, query) = url.split_once('?').unwrap_or((url, ""));
+    let params: Params = form_urlencoded::parse(query.as_bytes())
+        .into_owned()
+        .collect();
+    let endpoint = match path {
+        "/inspect" => inspect,
+        "/encode" => encode,
+        "/decode" => decode,
+        "/strip" => strip,
+        _ => return Response::error(404, format!("unknown endpoint {}", path)),
+    };
+    if method != "POST" {
+        return Response::error(405, "the PNG must be sent with POST");
+    }
+    endpoint(body, &params).unwrap_or_else(|response| response)
+}
+
+fn param<'a>(params: &'a Params, name: &str) -> Result<&'a str, Response> {
+    params
+        .get(name)
+        .map(String::as_str)
+        .ok_or_else(|| Response::error(400, format!("the {} parameter is missing", name)))
+}
+
+fn chunk_type(params: &Params) -> Result<&str, Response> {
+    let chunk_type = param(params, "chunk_type")?;
+    ChunkType::from_str(chunk_type).map_err(|err| Response::error(400, err))?;
+    Ok(chunk_type)
+}
+
+fn parse(body: &[u8]) -> Result<Png, Response> {
+    Png::try_from(body).map_err(|err| Response::error(400, err))
+}
+
+fn inspect(body: &[u8], _params: &Params) -> Result<Response, Response> {
+    let report = Scanner::default()
+        .scan(body)
+        .map_err(|err| Response::error(500, err))?;
+    let chunks: Vec<Value> = report
+        .chunks
+        .iter()
+        .map(|chunk| {
+            json!({
+                "type": chunk.chunk_type.to_string(),
+                "offset": chunk.offset,
+                "length": chunk.length,
+                "complete": chunk.complete,
+                "after_end": chunk.after_end,
+            })
+        })
+        .collect();
+    let anomalies: Vec<String> = report.anomalies.iter().map(ToString::to_string).collect();
+    let payloads: Vec<Value> = report
+        .payloads
+        .iter()
+        .map(|candidate| {
+            json!({
+                "type": candidate.chunk_type,
+                "offset": candidate.offset,
+                "length": candidate.length,
+                "evidence": candidate.evidence.to_string(),
+            })
+        })
+        .collect();
+    Ok(Response::json(
+        200,
+        json!({
+            "size": report.bytes_read,
+            "chunks": chunks,
+            "anomalies": anomalies,
+            "payloads": payloads,
+        }),
+    ))
+}
+
+fn encode(body: &[u8], params: &Params) -> Result<Response, Response> {
+    let chunk_type = chunk_type(params)?;
+    let message = param(params, "message")?;
+    let mut png = parse(body)?;
+    let sealed = Envelope::seal(message.as_bytes().to_vec());
+    png.insert(chunk_type, sealed.as_bytes())
+        .map_err(|err| Response::error(400, err))?;
+    Ok(Response::png(&png))
+}
+
+fn decode(body: &[u8], params: &Params) -> Result<Response, Response> {
+    let chunk_type = chunk_type(params)?;
+    let png = parse(body)?;
+    let Some(data) = png
+        .get(chunk_type)
+        .map_err(|err| Response::error(400, err))?
+    else {
+        return Err(Response::error(404, "no chunk with that type found"));
+    };
+    if Part::try_from(data).is_ok() {
+        return Err(Response::error(
+            422,
+            "the chunk holds one part of a payload spread across several files",
+        ));
+    }
+
+    let mut response = json!({ "chunk_type": chunk_type });
+    let data = match Envelope::try_from(data) {
+        Ok(envelope) => {
+            if let Some(content_type) = envelope.content_type() {
+                response["content_type"] = content_type.as_str().into();
+            }
+            response["sealed"] = true.into();
+            envelope.open().map_err(|err| Response::error(422, err))?
+        }
+        Err(_) => {
+            response["sealed"] = false.into();
+            data.to_vec()
+        }
+    };
+    match String::from_utf8(data) {
+        Ok(text) => response["message"] = text.into(),
+        Err(err) => response["message_base64"] = BASE64_STANDARD.encode(err.as_bytes()).into(),
+    }
+    Ok(Response::json(200, response))
+}
+
+fn strip(body: &[u8], params: &Params) -> Result<Response, Response> {
+    let chunk_type = chunk_type(params)?;
+    let mut png = parse(body)?;
+    match png.remove(chunk_type) {
+        Ok(Some(_)) => Ok(Response::png(&png)),
+        Ok(None) => Err(Response::error(404, "no chunk with that type found")),
+        Err(err) => Err(Response::error(400, err)),
+    }
+}
+
+/// Serves the API of [`handle`] over HTTP on `address`, e.g.
+/// `127.0.0.1:8080`, until the process is stopped. Every request is logged
+/// to stderr.
+#[cfg(feature = "server")]
+pub fn serve(address: &str, options: &ServeOptions) -> Result<(), ServerError> {
+    let server = tiny_http::Server::http(address).map_err(|err| ServerError::Listen {
+        address: address.to_owned(),
+        message: err.to_string(),
+    })?;
+    crate::console::errln!("Listening on http://{}", address);
+    std::thread::scope(|scope| {
+        for _ in 0..options.workers.max(1) {
+            scope.spawn(|| {
+                for request in server.incoming_requests() {
+                    respond(request, options.max_body);
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+#[cfg(feature = "server")]
+fn respond(mut request: tiny_http::Request, max_body: u64) {
+    use std::io::Read;
+
+    let too_large = || Response::error(413, format!("the body is larger than {} bytes", max_body));
+    let response = if request
+        .body_length()
+        .is_some_and(|len| len as u64 > max_body)
+    {
+        too_large()
+    } else {
+        let mut body = Vec::new();
+        match request
+            .as_reader()
+            .take(max_body + 1)
+            .read_to_end(&mut body)
+        {
+            Err(err) => Response::error(400, format!("failed to read the body: {}", err)),
+            Ok(len) if len as u64 > max_body => too_large(),
+            Ok(_) => handle(request.method().as_str(), request.url(), &body),
+        }
+    };
+    crate::console::errln!(
+        "{} {} {}",
+        request.method(),
+        logged_path(request.url()),
+        response.status
+    );
+
+    let content_type = tiny_http::Header::from_bytes("Content-Type", response.content_type)
+        .expect("content types are valid header values");
+    let reply = tiny_http::Response::from_data(response.body)
+        .with_status_code(response.status)
+        .with_header(content_type);
+    // The client may be gone already, which only concerns this request
+    let _ = request.respond(reply);
+}
+
+/// The part of `url` which is logged: the path without the query string,
+/// which holds the messages being encoded
+#[cfg(any(feature = "server", test))]
+fn logged_path(url: &str) -> &str {
+    url.split_once('?').map_or(url, |(path, _)| path)
+}
+
+#[cfg(not(feature = "server"))]
+pub fn serve(_address: &str, _options: &ServeOptions) -> Result<(), ServerError> {
+    Err(ServerError::Unsupported)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::png::tests::PNG_FILE;
+
+    fn json(response: &Response) -> Value {
+        assert_eq!(response.content_type, "application/json");
+        serde_json::from_slice(&response.body).unwrap()
+    }
+
+    #[test]
+    fn test_inspect() {
+        let response = handle("POST", "/inspect", &PNG_FILE);
+        assert_eq!(response.status, 200);
+        let report = json(&response);
+        assert_eq!(report["size"], PNG_FILE.len());
+        assert_eq!(report["chunks"][0]["type"], "IHDR");
+        assert_eq!(report["chunks"].as_array().unwrap().len(), 7);
+        assert_eq!(report["anomalies"].as_array().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_encode_decode_strip() {
+        let encoded = handle(
+            "POST",
+            "/encode?chunk_type=ruSt&message=hello%20world",
+            &PNG_FILE,
+        );
+        assert_eq!(encoded.status, 200);
+        assert_eq!(encoded.content_type, "image/png");
+
+        let decoded = json(&handle("POST", "/decode?chunk_type=ruSt", &encoded.body));
+        assert_eq!(decoded["message"], "hello world");
+        assert_eq!(decoded["sealed"], true);
+
+        // Sealed like the encode command does, so decode can check it
+        let png = Png::try_from(&encoded.body[..]).unwrap();
+        let envelope = Envelope::try_from(png.get("ruSt").unwrap().unwrap()).unwrap();
+        assert_eq!(envelope.open().unwrap(), b"hello world");
+
+        let stripped = handle("POST", "/strip?chunk_type=ruSt", &encoded.body);
+        assert_eq!(stripped.status, 200);
+        assert_eq!(stripped.body, PNG_FILE);
+    }
+
+    #[test]
+    fn test_decode_envelope() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let sealed = Envelope::seal(vec![0xff, 0xfe]).as_bytes();
+        png.insert("ruSt", sealed).unwrap();
+
+        let decoded = json(&handle("POST", "/decode?chunk_type=ruSt", &png.as_bytes()));
+        assert_eq!(decoded["sealed"], true);
+        assert_eq!(decoded["message_base64"], "//4=");
+    }
+
+    #[test]
+    fn test_logged_path() {
+        assert_eq!(
+            logged_path("/encode?chunk_type=ruSt&message=secret"),
+            "/encode"
+        );
+        assert_eq!(logged_path("/inspect"), "/inspect");
+    }
+
+    #[test]
+    fn test_errors() {
+        let status = |method, url, body: &[u8]| handle(method, url, body).status;
+        assert_eq!(status("POST", "/nope", &PNG_FILE), 404);
+        assert_eq!(status("GET", "/inspect", b""), 405);
+        assert_eq!(status("POST", "/encode?message=hi", &PNG_FILE), 400);
+        assert_eq!(
+            status("POST", "/encode?chunk_type=r1St&message=hi", &PNG_FILE),
+            400
+        );
+        assert_eq!(status("POST", "/decode?chunk_type=ruSt", b"not a png"), 400);
+        assert_eq!(status("POST", "/decode?chunk_type=zzZz", &PNG_FILE), 404);
+        assert_eq!(status("POST", "/strip?chunk_type=zzZz", &PNG_FILE), 404);
+
+        let error = json(&handle("POST", "/decode?chunk_type=ruSt", b"not a png"));
+        assert!(error["error"].as_str().unwrap().contains("PNG"));
+    }
+}
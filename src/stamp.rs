@@ -0,0 +1,200 @@
+use crate::{chunk::Chunk, chunk_type::ChunkType, png::Png};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// The chunk type stamps are stored in (private, ancillary, safe to copy)
+pub const STAMP_CHUNK_TYPE: [u8; 4] = *b"stMp";
+
+/// Build metadata embedded into a PNG, stored as a JSON object in a single
+/// `stMp` chunk
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Stamp {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub commit: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<String>,
+
+    /// Any other key/value pairs
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub values: BTreeMap<String, String>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum StampError {
+    #[error("stamp is not valid JSON: {0}")]
+    Json(#[from] serde_json::Error),
+
+    #[error("expected a JSON object")]
+    NotAnObject,
+}
+
+impl Stamp {
+    fn chunk_type() -> ChunkType {
+        ChunkType::try_from(STAMP_CHUNK_TYPE).unwrap()
+    }
+
+    /// Reads a stamp from a flat JSON object. The keys `version`, `commit`
+    /// and `timestamp` fill the respective fields, all other keys end up in
+    /// [`Stamp::values`]. Values which aren't strings are kept as JSON.
+    pub fn from_json_object(json: &str) -> Result<Self, StampError> {
+        let serde_json::Value::Object(object) = serde_json::from_str(json)? else {
+            return Err(StampError::NotAnObject);
+        };
+
+        let mut stamp = Self::default();
+        for (key, value) in object {
+            let value = match value {
+                serde_json::Value::String(value) => value,
+                other => other.to_string(),
+            };
+            stamp.set(key, value);
+        }
+        Ok(stamp)
+    }
+
+    /// Sets a field, or an entry in [`Stamp::values`] for unknown keys
+    pub fn set(&mut self, key: String, value: String) {
+        match key.as_str() {
+            "version" => self.version = Some(value),
+            "commit" => self.commit = Some(value),
+            "timestamp" => self.timestamp = Some(value),
+            _ => {
+                self.values.insert(key, value);
+            }
+        }
+    }
+
+    /// Overwrites the fields of `self` with all fields set in `other`
+    pub fn merge(&mut self, other: Stamp) {
+        self.version = other.version.or(self.version.take());
+        self.commit = other.commit.or(self.commit.take());
+        self.timestamp = other.timestamp.or(self.timestamp.take());
+        self.values.extend(other.values);
+    }
+
+    pub fn to_chunk(&self) -> Chunk {
+        let data = serde_json::to_vec(self).expect("stamps are always serializable");
+        Chunk::new(Self::chunk_type(), data)
+    }
+
+    pub fn from_chunk(chunk: &Chunk) -> Result<Self, StampError> {
+        Ok(serde_json::from_slice(chunk.data())?)
+    }
+
+    /// Reads the stamp of a PNG, if it has one
+    pub fn read(png: &Png) -> Option<Result<Self, StampError>> {
+        png.chunk_by_type(&Self::chunk_type()).map(Self::from_chunk)
+    }
+
+    /// Stores the stamp in a PNG, replacing an existing stamp. New stamps are
+    /// inserted before the `IEND` chunk.
+    pub fn write(&self, png: &mut Png) {
+        let chunk_type = Self::chunk_type();
+        let position = png
+            .chunks()
+            .iter()
+            .position(|chunk| *chunk.chunk_type() == chunk_type);
+
+        if let Some(position) = position {
+            png.remove_chunk(&chunk_type).unwrap();
+            png.insert_chunk(position, self.to_chunk());
+        } else {
+            png.insert_before_end(self.to_chunk());
+        }
+    }
+
+    /// Lists all fields which are set as key/value pairs
+    pub fn entries(&self) -> Vec<(&str, &str)> {
+        let fields = [
+            ("version", &self.version),
+            ("commit", &self.commit),
+            ("timestamp", &self.timestamp),
+        ];
+        fields
+            .into_iter()
+            .filter_map(|(key, value)| value.as_deref().map(|value| (key, value)))
+            .chain(
+                self.values
+                    .iter()
+                    .map(|(key, value)| (key.as_str(), value.as_str())),
+            )
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::png::tests::PNG_FILE;
+
+    fn testing_stamp() -> Stamp {
+        let mut stamp = Stamp {
+            version: Some("1.2.3".to_owned()),
+            commit: Some("abc123".to_owned()),
+            ..Default::default()
+        };
+        stamp.set("channel".to_owned(), "beta".to_owned());
+        stamp
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        testing_stamp().write(&mut png);
+
+        let chunks = png.chunks();
+        assert_eq!(
+            chunks[chunks.len() - 2].chunk_type().bytes(),
+            STAMP_CHUNK_TYPE
+        );
+        assert_eq!(Stamp::read(&png).unwrap().unwrap(), testing_stamp());
+    }
+
+    #[test]
+    fn test_write_replaces_existing() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        testing_stamp().write(&mut png);
+        let count = png.chunks().len();
+
+        let stamp = Stamp {
+            version: Some("2.0.0".to_owned()),
+            ..Default::default()
+        };
+        stamp.write(&mut png);
+        assert_eq!(png.chunks().len(), count);
+        assert_eq!(Stamp::read(&png).unwrap().unwrap(), stamp);
+    }
+
+    #[test]
+    fn test_from_json_object() {
+        let stamp =
+            Stamp::from_json_object(r#"{"version": "1.2.3", "build": 42, "channel": "beta"}"#)
+                .unwrap();
+        assert_eq!(stamp.version.as_deref(), Some("1.2.3"));
+        assert_eq!(stamp.values["build"], "42");
+        assert_eq!(
+            stamp.entries(),
+            vec![("version", "1.2.3"), ("build", "42"), ("channel", "beta")]
+        );
+
+        assert!(matches!(
+            Stamp::from_json_object("[1, 2]"),
+            Err(StampError::NotAnObject)
+        ));
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut stamp = testing_stamp();
+        stamp.merge(Stamp {
+            commit: Some("def456".to_owned()),
+            ..Default::default()
+        });
+        assert_eq!(stamp.version.as_deref(), Some("1.2.3"));
+        assert_eq!(stamp.commit.as_deref(), Some("def456"));
+    }
+}
@@ -0,0 +1,328 @@
+use crate::{
+    cancel::CancellationToken,
+    chunk::{ChunkParseError, CrcHasher, MAX_CHUNK_LENGTH},
+    chunk_type::ChunkType,
+    png::{Png, PngParseError},
+};
+
+/// An event emitted by [`PngStreamParser`] while bytes are pushed into it
+#[derive(Debug, PartialEq, Eq)]
+pub enum StreamEvent<'a> {
+    /// The 8 byte PNG signature has been read and is valid
+    Signature,
+
+    /// The length and type of a new chunk have been read
+    ChunkStart { length: u32, chunk_type: ChunkType },
+
+    /// A piece of the current chunk's data. A chunk's data may be split across
+    /// any number of these events, depending on how the input arrives.
+    ChunkData(&'a [u8]),
+
+    /// The current chunk is complete and its checksum has been verified
+    ChunkEnd { crc: u32 },
+
+    /// Bytes found after the `IEND` chunk
+    TrailingData(&'a [u8]),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Signature,
+    Header,
+    Data { remaining: u32 },
+    Crc,
+    Trailer,
+}
+
+/// A push-based PNG parser.
+///
+/// Instead of reading from a source itself, the parser is fed byte slices of
+/// arbitrary size as they arrive (e.g. from a network socket) and reports
+/// what it found as [`StreamEvent`]s. Only the fixed-size parts of a chunk
+/// (length, type and crc) are buffered, chunk data is passed through as is.
+///
+/// After an error has been returned the parser should not be used anymore.
+pub struct PngStreamParser {
+    state: State,
+    buf: [u8; 8],
+    buf_len: usize,
+    chunk_type: Option<ChunkType>,
+    /// The CRC of the current chunk
+    crc: Option<CrcHasher>,
+    cancel: CancellationToken,
+}
+
+impl Default for PngStreamParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PngStreamParser {
+    pub fn new() -> Self {
+        Self {
+            state: State::Signature,
+            buf: [0; 8],
+            buf_len: 0,
+            chunk_type: None,
+            crc: None,
+            cancel: CancellationToken::new(),
+        }
+    }
+
+    /// Creates a parser which fails with [`PngParseError::Cancelled`] once
+    /// `cancel` is cancelled
+    pub fn with_cancellation(cancel: CancellationToken) -> Self {
+        Self {
+            cancel,
+            ..Self::new()
+        }
+    }
+
+    /// Feeds the next piece of input into the parser and returns the events
+    /// which could be produced from it.
+    pub fn push<'a>(&mut self, mut input: &'a [u8]) -> Result<Vec<StreamEvent<'a>>, PngParseError> {
+        let mut events = Vec::new();
+
+        while !input.is_empty() {
+            self.cancel.check()?;
+
+            match self.state {
+                State::Signature => {
+                    if !self.fill(&mut input, 8) {
+                        break;
+                    }
+                    if &self.buf != Png::SIGNATURE {
+                        return Err(PngParseError::from_signature(&self.buf));
+                    }
+                    self.buf_len = 0;
+                    self.state = State::Header;
+                    events.push(StreamEvent::Signature);
+                }
+                State::Header => {
+                    if !self.fill(&mut input, 8) {
+                        break;
+                    }
+                    let length = u32::from_be_bytes(self.buf[..4].try_into().unwrap());
+                    if length > MAX_CHUNK_LENGTH {
+                        return Err(ChunkParseError::LengthOutOfRange(length).into());
+                    }
+                    let type_bytes: [u8; 4] = self.buf[4..8].try_into().unwrap();
+                    let chunk_type =
+                        ChunkType::try_from(type_bytes).map_err(ChunkParseError::from)?;
+                    self.buf_len = 0;
+
+                    self.crc = Some(CrcHasher::new(&chunk_type));
+                    self.chunk_type = Some(chunk_type.clone());
+                    self.state = match length {
+                        0 => State::Crc,
+                        remaining => State::Data { remaining },
+                    };
+                    events.push(StreamEvent::ChunkStart { length, chunk_type });
+                }
+                State::Data { remaining } => {
+                    let n = input.len().min(remaining as usize);
+                    let (data, rest) = input.split_at(n);
+                    input = rest;
+
+                    if let Some(crc) = &mut self.crc {
+                        crc.update(data);
+                    }
+                    // n is at most remaining, so it fits into a u32
+                    self.state = match remaining - n as u32 {
+                        0 => State::Crc,
+                        remaining => State::Data { remaining },
+                    };
+                    events.push(StreamEvent::ChunkData(data));
+                }
+                State::Crc => {
+                    if !self.fill(&mut input, 4) {
+                        break;
+                    }
+                    let crc = u32::from_be_bytes(self.buf[..4].try_into().unwrap());
+                    self.buf_len = 0;
+
+                    if self.crc.take().map(CrcHasher::finalize) != Some(crc) {
+                        return Err(ChunkParseError::InvalidChecksum.into());
+                    }
+
+                    let is_end = self
+                        .chunk_type
+                        .take()
+                        .is_some_and(|chunk_type| &chunk_type.bytes() == b"IEND");
+                    self.state = if is_end {
+                        State::Trailer
+                    } else {
+                        State::Header
+                    };
+                    events.push(StreamEvent::ChunkEnd { crc });
+                }
+                State::Trailer => {
+                    events.push(StreamEvent::TrailingData(input));
+                    input = &[];
+                }
+            }
+        }
+
+        Ok(events)
+    }
+
+    /// Signals that no more input will arrive. Fails if the input stopped in
+    /// the middle of the signature or a chunk.
+    pub fn finish(&self) -> Result<(), PngParseError> {
+        match self.state {
+            State::Signature => Err(PngParseError::Format),
+            State::Header | State::Trailer if self.buf_len == 0 => Ok(()),
+            _ => Err(ChunkParseError::Incomplete.into()),
+        }
+    }
+
+    /// Moves bytes from `input` into the internal buffer until it holds `n`
+    /// bytes. Returns whether the buffer is full.
+    fn fill(&mut self, input: &mut &[u8], n: usize) -> bool {
+        let take = (n - self.buf_len).min(input.len());
+        self.buf[self.buf_len..self.buf_len + take].copy_from_slice(&input[..take]);
+        self.buf_len += take;
+        *input = &input[take..];
+        self.buf_len == n
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use crate::png::tests::PNG_FILE;
+
+    /// Rebuilds the chunks from the events the parser emitted
+    fn collect_chunks(parser: &mut PngStreamParser, pieces: &[&[u8]]) -> Vec<Chunk> {
+        let mut chunks = Vec::new();
+        let mut current: Option<(ChunkType, Vec<u8>)> = None;
+
+        for piece in pieces {
+            for event in parser.push(piece).unwrap() {
+                match event {
+                    StreamEvent::ChunkStart { chunk_type, .. } => {
+                        current = Some((chunk_type, Vec::new()))
+                    }
+                    StreamEvent::ChunkData(data) => {
+                        current.as_mut().unwrap().1.extend_from_slice(data)
+                    }
+                    StreamEvent::ChunkEnd { .. } => {
+                        let (chunk_type, data) = current.take().unwrap();
+                        chunks.push(Chunk::new(chunk_type, data));
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        chunks
+    }
+
+    #[test]
+    fn test_push_whole_file() {
+        let mut parser = PngStreamParser::new();
+        let chunks = collect_chunks(&mut parser, &[&PNG_FILE]);
+        assert!(parser.finish().is_ok());
+
+        let png = Png::try_from(&PNG_FILE[..]).unwrap();
+        assert_eq!(Png::from_chunks(chunks).as_bytes(), png.as_bytes(),);
+    }
+
+    #[test]
+    fn test_push_byte_by_byte() {
+        let mut parser = PngStreamParser::new();
+        let pieces: Vec<&[u8]> = PNG_FILE.chunks(1).collect();
+        let chunks = collect_chunks(&mut parser, &pieces);
+        assert!(parser.finish().is_ok());
+
+        assert_eq!(Png::from_chunks(chunks).as_bytes(), PNG_FILE.to_vec());
+    }
+
+    #[test]
+    fn test_invalid_signature() {
+        let mut parser = PngStreamParser::new();
+        assert!(parser.push(&[137, 80, 78]).unwrap().is_empty());
+        assert!(matches!(
+            parser.push(&[71, 13, 10, 26, 11]),
+            Err(PngParseError::Format)
+        ));
+    }
+
+    #[test]
+    fn test_invalid_checksum() {
+        let mut bytes = PNG_FILE.to_vec();
+        // Last byte of the IEND crc
+        *bytes.last_mut().unwrap() ^= 0xff;
+
+        let mut parser = PngStreamParser::new();
+        assert!(matches!(
+            parser.push(&bytes),
+            Err(PngParseError::InvalidChunk(
+                ChunkParseError::InvalidChecksum
+            ))
+        ));
+    }
+
+    #[test]
+    fn test_incomplete_input() {
+        let mut parser = PngStreamParser::new();
+        parser.push(&PNG_FILE[..PNG_FILE.len() - 2]).unwrap();
+        assert!(matches!(
+            parser.finish(),
+            Err(PngParseError::InvalidChunk(ChunkParseError::Incomplete))
+        ));
+    }
+
+    #[test]
+    fn test_length_out_of_range() {
+        let mut bytes = PNG_FILE[..Png::SIGNATURE.len()].to_vec();
+        bytes.extend_from_slice(&u32::MAX.to_be_bytes());
+        bytes.extend_from_slice(b"RuSt");
+
+        let mut parser = PngStreamParser::new();
+        assert!(matches!(
+            parser.push(&bytes),
+            Err(PngParseError::InvalidChunk(
+                ChunkParseError::LengthOutOfRange(u32::MAX)
+            ))
+        ));
+    }
+
+    #[test]
+    fn test_cancelled() {
+        let cancel = CancellationToken::new();
+        let mut parser = PngStreamParser::with_cancellation(cancel.clone());
+        parser.push(&PNG_FILE[..100]).unwrap();
+
+        cancel.cancel();
+        assert!(matches!(
+            parser.push(&PNG_FILE[100..]),
+            Err(PngParseError::Cancelled(_))
+        ));
+    }
+
+    #[test]
+    fn test_trailing_data() {
+        let mut bytes = PNG_FILE.to_vec();
+        bytes.extend_from_slice(b"hidden");
+
+        let mut parser = PngStreamParser::new();
+        let events = parser.push(&bytes).unwrap();
+        assert_eq!(events.last(), Some(&StreamEvent::TrailingData(b"hidden")));
+        assert!(parser.finish().is_ok());
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn prop_roundtrip(png in Png::arbitrary_valid(8), piece_size in 1..64usize) {
+            let bytes = png.as_bytes();
+            let pieces: Vec<_> = bytes.chunks(piece_size).collect();
+            let mut parser = PngStreamParser::new();
+            let chunks = collect_chunks(&mut parser, &pieces);
+            proptest::prop_assert!(parser.finish().is_ok());
+            proptest::prop_assert_eq!(chunks.as_slice(), png.chunks());
+        }
+    }
+}
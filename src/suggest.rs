@@ -0,0 +1,190 @@
+use crate::{
+    chunk_type::{ChunkType, REGISTERED_TYPES},
+    detect::CHUNK_SIGNATURES,
+    padding,
+    scatter::COVER_TYPES,
+};
+use sha2::{Digest, Sha256};
+
+/// Only this many letters of a project name are combined into types, which
+/// bounds the number of combinations
+const MAX_PROJECT_LETTERS: usize = 12;
+
+/// Give up after this many candidates, e.g. when asked for more types than
+/// there are
+const MAX_ATTEMPTS: usize = 10_000;
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum SuggestError {
+    #[error("the project name must contain at least one ASCII letter")]
+    NoLetters,
+}
+
+/// Chunk types of the specification, pngme and other software or
+/// conventions, which suggestions stay clear of
+fn taken() -> Vec<[u8; 4]> {
+    COVER_TYPES
+        .iter()
+        .chain(CHUNK_SIGNATURES.iter().map(|(chunk_type, _)| chunk_type))
+        .map(|chunk_type| **chunk_type)
+        .chain(REGISTERED_TYPES.iter().map(|info| info.chunk_type))
+        .collect()
+}
+
+/// Sets the case of `letters` so the type is ancillary, private, has the
+/// reserved bit unset and is safe to copy
+fn private_type(letters: [u8; 4]) -> [u8; 4] {
+    let [a, b, c, d] = letters;
+    [
+        a.to_ascii_lowercase(),
+        b.to_ascii_lowercase(),
+        c.to_ascii_uppercase(),
+        d.to_ascii_lowercase(),
+    ]
+}
+
+/// Letters derived from `seed`, which are the same on every run
+fn hashed_letters(seed: &[u8], counter: usize) -> impl Iterator<Item = u8> {
+    let mut hasher = Sha256::new();
+    hasher.update(seed);
+    hasher.update((counter as u64).to_le_bytes());
+    let digest: [u8; 32] = hasher.finalize().into();
+    digest.into_iter().map(|byte| b'a' + byte % 26)
+}
+
+/// The candidates for a project: the letters of its name in order, starting
+/// with the first one, and then the first letters filled up with letters
+/// derived from the name
+fn project_candidates(letters: &[u8]) -> impl Iterator<Item = [u8; 4]> + '_ {
+    let n = letters.len();
+    let combinations = (1..n).flat_map(move |i| {
+        (i + 1..n).flat_map(move |j| {
+            (j + 1..n).map(move |k| [letters[0], letters[i], letters[j], letters[k]])
+        })
+    });
+    let filled = (0..).map(move |counter| {
+        let keep = letters.len().min(3 - counter % 3);
+        let mut candidate = [0; 4];
+        for (slot, letter) in candidate.iter_mut().zip(
+            letters[..keep]
+                .iter()
+                .copied()
+                .chain(hashed_letters(letters, counter)),
+        ) {
+            *slot = letter;
+        }
+        candidate
+    });
+    combinations.chain(filled)
+}
+
+fn random_candidates() -> impl Iterator<Item = [u8; 4]> {
+    std::iter::repeat_with(|| {
+        let mut candidate = [0; 4];
+        for (slot, byte) in candidate.iter_mut().zip(padding::random_bytes(4)) {
+            *slot = b'a' + byte % 26;
+        }
+        candidate
+    })
+}
+
+/// Suggests `count` chunk types for private data which are valid, ancillary
+/// (viewers can skip them), private and safe to copy, derived from the
+/// letters of `project` if given, or else at random.
+///
+/// Types which only differ in case from those of the specification, pngme,
+/// well-known software or `avoid` are left out, as they are easily
+/// confused.
+pub fn suggest(
+    project: Option<&str>,
+    count: usize,
+    avoid: &[ChunkType],
+) -> Result<Vec<ChunkType>, SuggestError> {
+    let letters: Option<Vec<u8>> = project.map(|project| {
+        project
+            .bytes()
+            .filter(u8::is_ascii_alphabetic)
+            .map(|byte| byte.to_ascii_lowercase())
+            .take(MAX_PROJECT_LETTERS)
+            .collect()
+    });
+    let candidates: Box<dyn Iterator<Item = [u8; 4]>> = match &letters {
+        Some(letters) if letters.is_empty() => return Err(SuggestError::NoLetters),
+        Some(letters) => Box::new(project_candidates(letters)),
+        None => Box::new(random_candidates()),
+    };
+
+    let mut taken: Vec<[u8; 4]> = taken()
+        .into_iter()
+        .chain(avoid.iter().map(ChunkType::bytes))
+        .map(|chunk_type| chunk_type.map(|byte| byte.to_ascii_lowercase()))
+        .collect();
+    let mut suggestions = Vec::with_capacity(count);
+    for candidate in candidates.take(MAX_ATTEMPTS) {
+        if suggestions.len() == count {
+            break;
+        }
+        if taken.contains(&candidate) {
+            continue;
+        }
+        taken.push(candidate);
+        suggestions.push(ChunkType::try_from(private_type(candidate)).unwrap());
+    }
+    Ok(suggestions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn is_private_ancillary(chunk_type: &ChunkType) -> bool {
+        !chunk_type.is_critical()
+            && !chunk_type.is_public()
+            && chunk_type.is_reserved_bit_valid()
+            && chunk_type.is_safe_to_copy()
+    }
+
+    #[test]
+    fn test_project() {
+        let suggestions = suggest(Some("Rust Lang"), 3, &[]).unwrap();
+        let names: Vec<String> = suggestions.iter().map(ToString::to_string).collect();
+        // ruSt is taken by PNGme tutorial implementations
+        assert_eq!(names, ["ruSl", "ruSa", "ruSn"]);
+        assert!(suggestions.iter().all(is_private_ancillary));
+        assert_eq!(suggest(Some("Rust Lang"), 3, &[]).unwrap(), suggestions);
+
+        // Taken types are skipped, whatever their case
+        let avoid = [ChunkType::from_str("RUSL").unwrap()];
+        let names: Vec<String> = suggest(Some("rusl"), 2, &avoid)
+            .unwrap()
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(names.len(), 2);
+        assert!(names
+            .iter()
+            .all(|name| name.starts_with('r') && name != "ruSl"));
+
+        // Short names are filled up
+        let short = suggest(Some("x"), 5, &[]).unwrap();
+        assert_eq!(short.len(), 5);
+        assert!(short.iter().all(|chunk_type| chunk_type.bytes()[0] == b'x'));
+
+        assert_eq!(suggest(Some("42"), 1, &[]), Err(SuggestError::NoLetters));
+    }
+
+    #[test]
+    fn test_random() {
+        let suggestions = suggest(None, 20, &[]).unwrap();
+        assert_eq!(suggestions.len(), 20);
+        let taken = taken();
+        for suggestion in &suggestions {
+            assert!(is_private_ancillary(suggestion));
+            let lower = suggestion.bytes().map(|byte| byte.to_ascii_lowercase());
+            assert!(!taken
+                .iter()
+                .any(|chunk_type| chunk_type.map(|byte| byte.to_ascii_lowercase()) == lower));
+        }
+    }
+}
@@ -0,0 +1,272 @@
+use crate::{
+    chunk::Chunk,
+    display::{self, DisplayOptions},
+};
+use std::{fmt::Write, str::FromStr};
+
+/// A chunk property that can be referenced in a [`Template`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Field {
+    Index,
+    Offset,
+    Type,
+    Length,
+    Crc,
+    Data,
+    /// The name of a registered type, see
+    /// [`ChunkType::registered_info`](crate::chunk_type::ChunkType::registered_info)
+    Name,
+}
+
+/// How a numeric [`Field`] is rendered
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Spec {
+    Decimal,
+    LowerHex,
+    UpperHex,
+    /// A size like `1.5 KiB`
+    Size,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Literal(String),
+    Field(Field, Spec),
+}
+
+/// A line format for chunk listings, similar to `git log --pretty`.
+///
+/// Placeholders are written as `{field}` or `{field:spec}`. The fields are
+/// `index`, `offset`, `type`, `length`, `crc`, `data` and `name` (of
+/// registered types, empty for others), numeric fields
+/// accept `x` and `X` as spec to be rendered as hex and `h` to be rendered
+/// as a size like `1.5 KiB`. `{{` and `}}` produce
+/// literal braces and `\t`, `\n` and `\\` are unescaped so templates can be
+/// passed from a shell without quoting gymnastics.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template(Vec<Segment>);
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum TemplateParseError {
+    #[error("unknown field '{0}', expected one of index, offset, type, length, crc, data or name")]
+    UnknownField(String),
+
+    #[error("unknown format spec '{0}', expected 'x', 'X' or 'h'")]
+    UnknownSpec(String),
+
+    #[error("format spec is only allowed for numeric fields")]
+    SpecNotAllowed,
+
+    #[error("unclosed '{{' in template")]
+    Unclosed,
+
+    #[error("unmatched '}}' in template, use '}}}}' for a literal brace")]
+    Unmatched,
+}
+
+impl FromStr for Template {
+    type Err = TemplateParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut segments = Vec::new();
+        let mut literal = String::new();
+        let mut chars = s.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '}' => return Err(TemplateParseError::Unmatched),
+                '\\' => match chars.next() {
+                    Some('t') => literal.push('\t'),
+                    Some('n') => literal.push('\n'),
+                    Some('\\') => literal.push('\\'),
+                    Some(other) => {
+                        literal.push('\\');
+                        literal.push(other);
+                    }
+                    None => literal.push('\\'),
+                },
+                '{' => {
+                    let mut placeholder = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => placeholder.push(c),
+                            None => return Err(TemplateParseError::Unclosed),
+                        }
+                    }
+
+                    if !literal.is_empty() {
+                        segments.push(Segment::Literal(std::mem::take(&mut literal)));
+                    }
+                    segments.push(parse_placeholder(&placeholder)?);
+                }
+                c => literal.push(c),
+            }
+        }
+
+        if !literal.is_empty() {
+            segments.push(Segment::Literal(literal));
+        }
+        Ok(Self(segments))
+    }
+}
+
+fn parse_placeholder(placeholder: &str) -> Result<Segment, TemplateParseError> {
+    let (name, spec) = match placeholder.split_once(':') {
+        Some((name, spec)) => (name, Some(spec)),
+        None => (placeholder, None),
+    };
+
+    let field = match name.trim() {
+        "index" => Field::Index,
+        "offset" => Field::Offset,
+        "type" => Field::Type,
+        "length" => Field::Length,
+        "crc" => Field::Crc,
+        "data" => Field::Data,
+        "name" => Field::Name,
+        other => return Err(TemplateParseError::UnknownField(other.to_owned())),
+    };
+
+    let spec = match spec {
+        None => Spec::Decimal,
+        Some(_) if matches!(field, Field::Type | Field::Data | Field::Name) => {
+            return Err(TemplateParseError::SpecNotAllowed)
+        }
+        Some("x") => Spec::LowerHex,
+        Some("X") => Spec::UpperHex,
+        Some("h") => Spec::Size,
+        Some(other) => return Err(TemplateParseError::UnknownSpec(other.to_owned())),
+    };
+
+    Ok(Segment::Field(field, spec))
+}
+
+impl Template {
+    /// Renders the template for the chunk at position `index`, which starts
+    /// `offset` bytes into the file. `data` is rendered using `options`.
+    pub fn render(
+        &self,
+        index: usize,
+        offset: usize,
+        chunk: &Chunk,
+        options: &DisplayOptions,
+    ) -> String {
+        let mut out = String::new();
+        for segment in &self.0 {
+            let number = match segment {
+                Segment::Literal(literal) => {
+                    out.push_str(literal);
+                    continue;
+                }
+                Segment::Field(Field::Type, _) => {
+                    write!(out, "{}", chunk.chunk_type()).unwrap();
+                    continue;
+                }
+                Segment::Field(Field::Data, _) => {
+                    write!(out, "{}", options.data(chunk)).unwrap();
+                    continue;
+                }
+                Segment::Field(Field::Name, _) => {
+                    if let Some(info) = chunk.chunk_type().registered_info() {
+                        out.push_str(info.name);
+                    }
+                    continue;
+                }
+                Segment::Field(Field::Index, spec) => (index as u64, spec),
+                Segment::Field(Field::Offset, spec) => (offset as u64, spec),
+                Segment::Field(Field::Length, spec) => (chunk.length() as u64, spec),
+                Segment::Field(Field::Crc, spec) => (chunk.crc() as u64, spec),
+            };
+
+            match number {
+                (n, Spec::Decimal) => write!(out, "{}", n),
+                (n, Spec::LowerHex) => write!(out, "{:x}", n),
+                (n, Spec::UpperHex) => write!(out, "{:X}", n),
+                (n, Spec::Size) => out.write_str(&display::human_size(n)),
+            }
+            .unwrap();
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk_type::ChunkType;
+
+    fn testing_chunk() -> Chunk {
+        Chunk::new(
+            ChunkType::from_str("RuSt").unwrap(),
+            b"This is where your secret message will be!".to_vec(),
+        )
+    }
+
+    #[test]
+    fn test_render() {
+        let template = Template::from_str(r"{index}\t{type}\t{length}\t{crc:x}").unwrap();
+        let rendered = template.render(3, 42, &testing_chunk(), &DisplayOptions::default());
+        assert_eq!(rendered, "3\tRuSt\t42\tabd1d84e");
+
+        let template = Template::from_str("{length:h}").unwrap();
+        let rendered = template.render(0, 0, &testing_chunk(), &DisplayOptions::default());
+        assert_eq!(rendered, "42 B");
+    }
+
+    #[test]
+    fn test_render_data_and_offset() {
+        let template = Template::from_str("{offset:X} {{{data}}}").unwrap();
+        let options = DisplayOptions {
+            max_data: Some(4),
+            ..Default::default()
+        };
+        let rendered = template.render(0, 255, &testing_chunk(), &options);
+        assert_eq!(rendered, "FF {This... (38 more bytes)}");
+    }
+
+    #[test]
+    fn test_render_name() {
+        let template = Template::from_str("{type}: {name}").unwrap();
+        let gamma = Chunk::new(ChunkType::from_str("gAMA").unwrap(), vec![0, 0, 177, 143]);
+        let options = DisplayOptions::default();
+        assert_eq!(template.render(0, 0, &gamma, &options), "gAMA: Image gamma");
+        assert_eq!(template.render(0, 0, &testing_chunk(), &options), "RuSt: ");
+        assert_eq!(
+            Template::from_str("{name:x}"),
+            Err(TemplateParseError::SpecNotAllowed)
+        );
+    }
+
+    #[test]
+    fn test_invalid_templates() {
+        assert_eq!(
+            Template::from_str("{nope}"),
+            Err(TemplateParseError::UnknownField("nope".to_owned()))
+        );
+        assert_eq!(
+            Template::from_str("{crc:o}"),
+            Err(TemplateParseError::UnknownSpec("o".to_owned()))
+        );
+        assert_eq!(
+            Template::from_str("{type:x}"),
+            Err(TemplateParseError::SpecNotAllowed)
+        );
+        assert_eq!(
+            Template::from_str("{index"),
+            Err(TemplateParseError::Unclosed)
+        );
+        assert_eq!(
+            Template::from_str("index}"),
+            Err(TemplateParseError::Unmatched)
+        );
+    }
+}
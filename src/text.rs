@@ -0,0 +1,668 @@
+use crate::{chunk::Chunk, chunk_type::ChunkType, png::Png};
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
+use std::io::{Read, Write};
+
+/// The keywords predefined by the PNG specification, and the registered one
+/// for XMP packets. Others are allowed, but readers won't know what they mean.
+pub const REGISTERED_KEYWORDS: &[&str] = &[
+    "Title",
+    "Author",
+    "Description",
+    "Copyright",
+    "Creation Time",
+    "Software",
+    "Disclaimer",
+    "Warning",
+    "Source",
+    "Comment",
+    "Collection",
+    "XML:com.adobe.xmp",
+];
+
+/// The flavor of a text chunk
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TextKind {
+    /// `tEXt`: Latin-1 text
+    Text,
+
+    /// `zTXt`: zlib compressed Latin-1 text
+    Compressed,
+
+    /// `iTXt`: UTF-8 text with an optional language tag and translated
+    /// keyword, zlib compressed if `compressed` is set
+    International {
+        language: String,
+        translated_keyword: String,
+        compressed: bool,
+    },
+}
+
+impl TextKind {
+    /// The type of the chunks of this kind
+    pub fn chunk_type(&self) -> &'static str {
+        match self {
+            Self::Text => "tEXt",
+            Self::Compressed => "zTXt",
+            Self::International { .. } => "iTXt",
+        }
+    }
+}
+
+/// A decoded `tEXt`, `zTXt` or `iTXt` chunk
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextChunk {
+    pub keyword: String,
+    pub text: String,
+    pub kind: TextKind,
+}
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum TextError {
+    #[error("keywords must be 1 to 79 characters long, got {0}")]
+    KeywordLength(usize),
+
+    #[error("keywords must only contain printable Latin-1 characters")]
+    KeywordCharacters,
+
+    #[error("keywords can't start or end with a space or contain consecutive spaces")]
+    KeywordSpaces,
+
+    #[error("invalid language tag '{0}', expected e.g. en or de-DE")]
+    LanguageTag(String),
+
+    #[error("translated keywords can't contain null characters")]
+    TranslatedKeywordNull,
+
+    #[error("tEXt chunks can only hold Latin-1 text, use an iTXt chunk instead")]
+    NotLatin1,
+
+    #[error("the text chunk is missing a null separator")]
+    MissingSeparator,
+
+    #[error("the iTXt chunk contains invalid UTF-8")]
+    InvalidUtf8,
+
+    #[error("unknown compression flag {0}")]
+    UnknownCompressionFlag(u8),
+
+    #[error("unknown compression method {0}")]
+    UnknownCompressionMethod(u8),
+
+    #[error("the compressed text is corrupt")]
+    CorruptCompression,
+}
+
+fn decode_latin1(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| *b as char).collect()
+}
+
+fn encode_latin1(text: &str) -> Option<Vec<u8>> {
+    text.chars().map(|c| u8::try_from(c).ok()).collect()
+}
+
+/// Checks the keyword rules of the PNG spec: 1-79 printable Latin-1
+/// characters, with single spaces only between words
+fn encode_keyword(keyword: &str) -> Result<Vec<u8>, TextError> {
+    let bytes = encode_latin1(keyword).ok_or(TextError::KeywordCharacters)?;
+    if bytes.is_empty() || bytes.len() > 79 {
+        return Err(TextError::KeywordLength(bytes.len()));
+    }
+    if bytes.iter().any(|b| !matches!(b, 32..=126 | 161..=255)) {
+        return Err(TextError::KeywordCharacters);
+    }
+    if bytes.starts_with(b" ") || bytes.ends_with(b" ") || keyword.contains("  ") {
+        return Err(TextError::KeywordSpaces);
+    }
+    Ok(bytes)
+}
+
+/// Checks `keyword` against the rules of the PNG spec, which
+/// [`TextChunk::to_chunk`] enforces
+pub fn validate_keyword(keyword: &str) -> Result<(), TextError> {
+    encode_keyword(keyword).map(|_| ())
+}
+
+/// Whether `keyword` is one of the [`REGISTERED_KEYWORDS`]. Keywords are case
+/// sensitive.
+pub fn is_registered_keyword(keyword: &str) -> bool {
+    REGISTERED_KEYWORDS.contains(&keyword)
+}
+
+/// The registered keyword `keyword` differs from only in case, e.g. `Title`
+/// for `title`
+pub fn registered_spelling(keyword: &str) -> Option<&'static str> {
+    REGISTERED_KEYWORDS
+        .iter()
+        .find(|registered| registered.eq_ignore_ascii_case(keyword))
+        .copied()
+}
+
+/// Checks that `language` is empty or a language tag as the PNG spec wants
+/// it (RFC 3066): subtags of 1-8 ASCII letters or digits, joined by hyphens
+fn encode_language(language: &str) -> Result<&[u8], TextError> {
+    let valid = |subtag: &str| {
+        (1..=8).contains(&subtag.len()) && subtag.bytes().all(|b| b.is_ascii_alphanumeric())
+    };
+    if !language.is_empty() && !language.split('-').all(valid) {
+        return Err(TextError::LanguageTag(language.to_owned()));
+    }
+    Ok(language.as_bytes())
+}
+
+fn deflate(bytes: &[u8]) -> Vec<u8> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::best());
+    encoder
+        .write_all(bytes)
+        .expect("writing to a Vec never fails");
+    encoder.finish().expect("writing to a Vec never fails")
+}
+
+/// Decompresses the text of a `zTXt` or compressed `iTXt` chunk, which must
+/// use compression method 0
+fn inflate(method: u8, compressed: &[u8]) -> Result<Vec<u8>, TextError> {
+    if method != 0 {
+        return Err(TextError::UnknownCompressionMethod(method));
+    }
+    let mut text = Vec::new();
+    ZlibDecoder::new(compressed)
+        .read_to_end(&mut text)
+        .map_err(|_| TextError::CorruptCompression)?;
+    Ok(text)
+}
+
+/// Splits off everything up to the next null byte
+fn split_null(bytes: &[u8]) -> Result<(&[u8], &[u8]), TextError> {
+    let position = bytes
+        .iter()
+        .position(|b| *b == 0)
+        .ok_or(TextError::MissingSeparator)?;
+    Ok((&bytes[..position], &bytes[position + 1..]))
+}
+
+fn utf8(bytes: &[u8]) -> Result<String, TextError> {
+    String::from_utf8(bytes.to_vec()).map_err(|_| TextError::InvalidUtf8)
+}
+
+impl TextChunk {
+    /// Creates a `tEXt` chunk
+    pub fn text(keyword: &str, text: &str) -> Self {
+        Self {
+            keyword: keyword.to_owned(),
+            text: text.to_owned(),
+            kind: TextKind::Text,
+        }
+    }
+
+    /// Creates an `iTXt` chunk without language tag and translated keyword
+    pub fn international(keyword: &str, text: &str) -> Self {
+        Self {
+            keyword: keyword.to_owned(),
+            text: text.to_owned(),
+            kind: TextKind::International {
+                language: String::new(),
+                translated_keyword: String::new(),
+                compressed: false,
+            },
+        }
+    }
+
+    /// Creates an `iTXt` chunk with a language tag (e.g. `de-DE`) and the
+    /// keyword translated into that language
+    pub fn localized(keyword: &str, text: &str, language: &str, translated_keyword: &str) -> Self {
+        Self {
+            keyword: keyword.to_owned(),
+            text: text.to_owned(),
+            kind: TextKind::International {
+                language: language.to_owned(),
+                translated_keyword: translated_keyword.to_owned(),
+                compressed: false,
+            },
+        }
+    }
+
+    /// Turns a `tEXt` chunk into a `zTXt` chunk and sets the compression
+    /// flag of an `iTXt` chunk
+    pub fn compressed(mut self) -> Self {
+        match &mut self.kind {
+            TextKind::Text => self.kind = TextKind::Compressed,
+            TextKind::Compressed => {}
+            TextKind::International { compressed, .. } => *compressed = true,
+        }
+        self
+    }
+
+    /// Creates a `tEXt` chunk if the text is Latin-1 and an `iTXt` chunk
+    /// otherwise
+    pub fn best_fit(keyword: &str, text: &str) -> Self {
+        match encode_latin1(text) {
+            Some(_) => Self::text(keyword, text),
+            None => Self::international(keyword, text),
+        }
+    }
+
+    pub fn is_text_chunk(chunk: &Chunk) -> bool {
+        matches!(&chunk.chunk_type().bytes(), b"tEXt" | b"zTXt" | b"iTXt")
+    }
+
+    /// Decodes a text chunk. Returns `None` if the chunk isn't a text chunk.
+    pub fn from_chunk(chunk: &Chunk) -> Option<Result<Self, TextError>> {
+        match &chunk.chunk_type().bytes() {
+            b"tEXt" => Some(Self::from_text(chunk.data())),
+            b"zTXt" => Some(Self::from_compressed(chunk.data())),
+            b"iTXt" => Some(Self::from_international(chunk.data())),
+            _ => None,
+        }
+    }
+
+    fn from_text(data: &[u8]) -> Result<Self, TextError> {
+        let (keyword, text) = split_null(data)?;
+        Ok(Self::text(&decode_latin1(keyword), &decode_latin1(text)))
+    }
+
+    fn from_compressed(data: &[u8]) -> Result<Self, TextError> {
+        let (keyword, rest) = split_null(data)?;
+        let (method, compressed) = rest.split_first().ok_or(TextError::MissingSeparator)?;
+        let text = inflate(*method, compressed)?;
+        Ok(Self {
+            keyword: decode_latin1(keyword),
+            text: decode_latin1(&text),
+            kind: TextKind::Compressed,
+        })
+    }
+
+    fn from_international(data: &[u8]) -> Result<Self, TextError> {
+        let (keyword, rest) = split_null(data)?;
+        let (compression_flag, method, rest) = match rest {
+            [flag, method, rest @ ..] => (*flag, *method, rest),
+            _ => return Err(TextError::MissingSeparator),
+        };
+        let (language, rest) = split_null(rest)?;
+        let (translated_keyword, text) = split_null(rest)?;
+        let text = match compression_flag {
+            0 => utf8(text)?,
+            1 => utf8(&inflate(method, text)?)?,
+            flag => return Err(TextError::UnknownCompressionFlag(flag)),
+        };
+
+        Ok(Self {
+            keyword: decode_latin1(keyword),
+            text,
+            kind: TextKind::International {
+                language: decode_latin1(language),
+                translated_keyword: utf8(translated_keyword)?,
+                compressed: compression_flag == 1,
+            },
+        })
+    }
+
+    pub fn to_chunk(&self) -> Result<Chunk, TextError> {
+        let mut data = encode_keyword(&self.keyword)?;
+        data.push(0);
+
+        let chunk_type: &[u8; 4] = match &self.kind {
+            TextKind::Text => {
+                data.extend(encode_latin1(&self.text).ok_or(TextError::NotLatin1)?);
+                b"tEXt"
+            }
+            TextKind::Compressed => {
+                let text = encode_latin1(&self.text).ok_or(TextError::NotLatin1)?;
+                // Compression method 0, zlib
+                data.push(0);
+                data.extend(deflate(&text));
+                b"zTXt"
+            }
+            TextKind::International {
+                language,
+                translated_keyword,
+                compressed,
+            } => {
+                if translated_keyword.contains('\0') {
+                    return Err(TextError::TranslatedKeywordNull);
+                }
+                // The compression flag, and method 0 which is zlib
+                data.extend_from_slice(&[u8::from(*compressed), 0]);
+                data.extend_from_slice(encode_language(language)?);
+                data.push(0);
+                data.extend_from_slice(translated_keyword.as_bytes());
+                data.push(0);
+                match compressed {
+                    true => data.extend(deflate(self.text.as_bytes())),
+                    false => data.extend_from_slice(self.text.as_bytes()),
+                }
+                b"iTXt"
+            }
+        };
+
+        Ok(Chunk::new(ChunkType::try_from(*chunk_type).unwrap(), data))
+    }
+}
+
+/// Finds the first text chunk with the given keyword. Chunks which can't be
+/// decoded are ignored.
+pub fn find_text(png: &Png, keyword: &str) -> Option<TextChunk> {
+    png.chunks()
+        .iter()
+        .filter_map(TextChunk::from_chunk)
+        .filter_map(Result::ok)
+        .find(|text| text.keyword == keyword)
+}
+
+/// Stores `text` in the PNG, replacing all text chunks with the same
+/// keyword. The new chunk takes the place of the first replaced one, or is
+/// inserted before `IEND`.
+pub fn set_text(png: &mut Png, text: &TextChunk) -> Result<(), TextError> {
+    let chunk = text.to_chunk()?;
+    let has_keyword = |chunk: &Chunk| {
+        TextChunk::is_text_chunk(chunk) && keyword_of(chunk) == Some(text.keyword.as_str())
+    };
+
+    match png.chunks().iter().position(has_keyword) {
+        Some(position) => {
+            png.retain(|chunk| !has_keyword(chunk));
+            png.insert_chunk(position, chunk);
+        }
+        None => png.insert_before_end(chunk),
+    }
+    Ok(())
+}
+
+/// Removes all text chunks with the given keyword, returning how many were
+/// removed
+pub fn remove_text(png: &mut Png, keyword: &str) -> usize {
+    let before = png.len();
+    png.retain(|chunk| !(TextChunk::is_text_chunk(chunk) && keyword_of(chunk) == Some(keyword)));
+    before - png.len()
+}
+
+/// The outcome of [`compress_texts`] or [`decompress_texts`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Conversion {
+    /// The number of converted chunks
+    pub chunks: usize,
+    /// By how many bytes the converted chunks shrank or grew
+    pub bytes: usize,
+}
+
+/// Replaces the text chunks for which `convert` returns a new chunk, keeping
+/// their position
+fn convert_texts<F>(png: &mut Png, mut convert: F) -> Result<Conversion, TextError>
+where
+    F: FnMut(&Chunk) -> Result<Option<Chunk>, TextError>,
+{
+    let mut conversion = Conversion::default();
+    let mut chunks = Vec::with_capacity(png.chunks().len());
+    for chunk in png.chunks() {
+        match convert(chunk)? {
+            Some(converted) => {
+                conversion.chunks += 1;
+                conversion.bytes += chunk.length().abs_diff(converted.length()) as usize;
+                chunks.push(converted);
+            }
+            None => chunks.push(chunk.clone()),
+        }
+    }
+    *png = Png::from_chunks(chunks);
+    Ok(conversion)
+}
+
+/// Converts `tEXt` chunks with at least `min_size` bytes of data into `zTXt`
+/// chunks, if that makes them smaller. Returns how many bytes were saved.
+pub fn compress_texts(png: &mut Png, min_size: usize) -> Conversion {
+    let compress = |chunk: &Chunk| {
+        if &chunk.chunk_type().bytes() != b"tEXt" || chunk.data().len() < min_size {
+            return Ok(None);
+        }
+        let Ok(text) = TextChunk::from_text(chunk.data()) else {
+            return Ok(None);
+        };
+        let compressed = TextChunk {
+            kind: TextKind::Compressed,
+            ..text
+        }
+        .to_chunk();
+        Ok(compressed
+            .ok()
+            .filter(|compressed| compressed.length() < chunk.length()))
+    };
+    convert_texts(png, compress).expect("compressing never fails")
+}
+
+/// Converts all `zTXt` chunks into `tEXt` chunks, for readers which don't
+/// support compressed text. Returns how many bytes were added.
+pub fn decompress_texts(png: &mut Png) -> Result<Conversion, TextError> {
+    convert_texts(png, |chunk| {
+        if &chunk.chunk_type().bytes() != b"zTXt" {
+            return Ok(None);
+        }
+        let text = TextChunk::from_compressed(chunk.data())?;
+        Ok(Some(
+            TextChunk {
+                kind: TextKind::Text,
+                ..text
+            }
+            .to_chunk()?,
+        ))
+    })
+}
+
+/// Returns the keyword of a text chunk without decoding the rest. Keywords
+/// are Latin-1, this only matches keywords in the ASCII subset.
+fn keyword_of(chunk: &Chunk) -> Option<&str> {
+    let (keyword, _) = split_null(chunk.data()).ok()?;
+    std::str::from_utf8(keyword).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::png::tests::PNG_FILE;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_text_round_trip() {
+        let text = TextChunk::text("Comment", "Grüße");
+        let chunk = text.to_chunk().unwrap();
+        assert_eq!(chunk.chunk_type().to_string(), "tEXt");
+        assert_eq!(chunk.data(), b"Comment\0Gr\xfc\xdfe");
+        assert_eq!(TextChunk::from_chunk(&chunk), Some(Ok(text)));
+    }
+
+    #[test]
+    fn test_international_round_trip() {
+        let text = TextChunk {
+            keyword: "Title".to_owned(),
+            text: "日の出".to_owned(),
+            kind: TextKind::International {
+                language: "ja".to_owned(),
+                translated_keyword: "タイトル".to_owned(),
+                compressed: false,
+            },
+        };
+        let chunk = text.to_chunk().unwrap();
+        assert_eq!(chunk.chunk_type().to_string(), "iTXt");
+        assert_eq!(TextChunk::from_chunk(&chunk), Some(Ok(text)));
+    }
+
+    #[test]
+    fn test_localized_compressed_round_trip() {
+        let text = TextChunk::localized(
+            "Comment",
+            &"Schöne Würfel ".repeat(50),
+            "de-DE",
+            "Kommentar",
+        )
+        .compressed();
+        let chunk = text.to_chunk().unwrap();
+        assert_eq!(chunk.chunk_type().to_string(), "iTXt");
+        assert!(chunk
+            .data()
+            .starts_with(b"Comment\0\x01\x00de-DE\0Kommentar\0"));
+        assert!(chunk.data().len() < 100);
+        assert_eq!(TextChunk::from_chunk(&chunk), Some(Ok(text)));
+
+        assert_eq!(
+            TextChunk::text("Title", "Dice").compressed().kind,
+            TextKind::Compressed
+        );
+    }
+
+    #[test]
+    fn test_invalid_international_fields() {
+        let localized = |language, translated_keyword| {
+            TextChunk::localized("Title", "Dice", language, translated_keyword).to_chunk()
+        };
+        assert!(localized("", "").is_ok());
+        assert!(localized("x-klingon", "").is_ok());
+        for language in ["de_DE", "de--DE", "-de", "toolongsubtag", "dé"] {
+            assert_eq!(
+                localized(language, ""),
+                Err(TextError::LanguageTag(language.to_owned()))
+            );
+        }
+        assert_eq!(
+            localized("de", "Ti\0tel"),
+            Err(TextError::TranslatedKeywordNull)
+        );
+
+        let mut data = localized("de", "Titel").unwrap().data().to_vec();
+        data[6] = 2;
+        let chunk = Chunk::new(ChunkType::from_str("iTXt").unwrap(), data);
+        assert_eq!(
+            TextChunk::from_chunk(&chunk),
+            Some(Err(TextError::UnknownCompressionFlag(2)))
+        );
+    }
+
+    #[test]
+    fn test_compressed_round_trip() {
+        let text = TextChunk {
+            keyword: "Description".to_owned(),
+            text: "Grüße ".repeat(100),
+            kind: TextKind::Compressed,
+        };
+        let chunk = text.to_chunk().unwrap();
+        assert_eq!(chunk.chunk_type().to_string(), "zTXt");
+        assert!(chunk.data().len() < 100);
+        assert_eq!(TextChunk::from_chunk(&chunk), Some(Ok(text)));
+
+        let mut data = chunk.data().to_vec();
+        data[12] = 1;
+        let chunk = Chunk::new(chunk.chunk_type().clone(), data);
+        assert_eq!(
+            TextChunk::from_chunk(&chunk),
+            Some(Err(TextError::UnknownCompressionMethod(1)))
+        );
+    }
+
+    #[test]
+    fn test_compress_and_decompress_texts() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        set_text(&mut png, &TextChunk::text("Comment", &"abc".repeat(500))).unwrap();
+        set_text(&mut png, &TextChunk::text("Title", "Dice")).unwrap();
+        let original = png.as_bytes();
+        let position = |png: &Png, chunk_type: &str| {
+            png.chunks()
+                .iter()
+                .position(|chunk| chunk.chunk_type().to_string() == chunk_type)
+        };
+        let text_position = position(&png, "tEXt");
+
+        let compressed = compress_texts(&mut png, 100);
+        assert_eq!(compressed.chunks, 1);
+        assert_eq!(position(&png, "zTXt"), text_position);
+        assert_eq!(png.as_bytes().len(), original.len() - compressed.bytes);
+        assert_eq!(find_text(&png, "Comment").unwrap().text, "abc".repeat(500));
+        assert_eq!(find_text(&png, "Title").unwrap().kind, TextKind::Text);
+
+        let decompressed = decompress_texts(&mut png).unwrap();
+        assert_eq!(decompressed, compressed);
+        assert_eq!(png.as_bytes(), original);
+    }
+
+    #[test]
+    fn test_best_fit() {
+        assert_eq!(TextChunk::best_fit("Title", "Sunset").kind, TextKind::Text);
+        assert!(matches!(
+            TextChunk::best_fit("Title", "Sonnenuntergang 🌅").kind,
+            TextKind::International { .. }
+        ));
+        assert_eq!(
+            TextChunk::text("Title", "🌅").to_chunk(),
+            Err(TextError::NotLatin1)
+        );
+    }
+
+    #[test]
+    fn test_invalid_keywords() {
+        assert_eq!(
+            TextChunk::text("", "text").to_chunk(),
+            Err(TextError::KeywordLength(0))
+        );
+        assert_eq!(
+            TextChunk::text(&"k".repeat(80), "text").to_chunk(),
+            Err(TextError::KeywordLength(80))
+        );
+        assert_eq!(
+            TextChunk::text("Key\nword", "text").to_chunk(),
+            Err(TextError::KeywordCharacters)
+        );
+        for keyword in [" Title", "Title ", "Creation  Time"] {
+            assert_eq!(
+                TextChunk::text(keyword, "text").to_chunk(),
+                Err(TextError::KeywordSpaces)
+            );
+        }
+        assert!(TextChunk::text("Creation Time", "text").to_chunk().is_ok());
+    }
+
+    #[test]
+    fn test_registered_keywords() {
+        assert!(REGISTERED_KEYWORDS
+            .iter()
+            .all(|keyword| validate_keyword(keyword).is_ok()));
+        assert!(is_registered_keyword("Creation Time"));
+        assert!(!is_registered_keyword("creation time"));
+        assert!(!is_registered_keyword("License"));
+        assert_eq!(registered_spelling("creation time"), Some("Creation Time"));
+        assert_eq!(registered_spelling("Comment"), Some("Comment"));
+        assert_eq!(registered_spelling("Remark"), None);
+        assert_eq!(validate_keyword(" Title"), Err(TextError::KeywordSpaces));
+    }
+
+    #[test]
+    fn test_set_and_find_text() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        set_text(&mut png, &TextChunk::text("Author", "Alice")).unwrap();
+        set_text(&mut png, &TextChunk::text("Title", "Dice")).unwrap();
+        set_text(&mut png, &TextChunk::international("Author", "Bob")).unwrap();
+
+        assert_eq!(find_text(&png, "Author").unwrap().text, "Bob");
+        assert_eq!(find_text(&png, "Title").unwrap().text, "Dice");
+        // Replaced in place, before IEND
+        let types: Vec<String> = png
+            .chunks()
+            .iter()
+            .rev()
+            .take(3)
+            .map(|chunk| chunk.chunk_type().to_string())
+            .collect();
+        assert_eq!(types, vec!["IEND", "tEXt", "iTXt"]);
+
+        assert_eq!(remove_text(&mut png, "Author"), 1);
+        assert!(find_text(&png, "Author").is_none());
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn prop_roundtrip(keyword in "[A-Za-z]{1,10}( [A-Za-z]{1,10}){0,6}", text in "\\PC*") {
+            for text_chunk in [
+                TextChunk::best_fit(&keyword, &text),
+                TextChunk::international(&keyword, &text),
+                TextChunk::localized(&keyword, &text, "en-GB", &text).compressed(),
+            ] {
+                let chunk = text_chunk.to_chunk().unwrap();
+                proptest::prop_assert_eq!(TextChunk::from_chunk(&chunk), Some(Ok(text_chunk)));
+            }
+        }
+    }
+}
@@ -0,0 +1,83 @@
+use base64::prelude::{Engine, BASE64_STANDARD};
+use std::str::FromStr;
+
+/// How binary data is carried through stdin, stdout and arguments
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Transport {
+    /// Byte for byte
+    #[default]
+    Raw,
+    /// Standard base64 with padding, for systems which only carry text
+    Base64,
+}
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+#[error("unknown format '{0}', expected raw or base64")]
+pub struct UnknownTransportError(String);
+
+impl FromStr for Transport {
+    type Err = UnknownTransportError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "raw" => Ok(Self::Raw),
+            "base64" => Ok(Self::Base64),
+            other => Err(UnknownTransportError(other.to_owned())),
+        }
+    }
+}
+
+impl Transport {
+    /// Turns data received this way back into bytes. Base64 may be wrapped
+    /// over several lines.
+    pub fn decode(self, data: Vec<u8>) -> Result<Vec<u8>, base64::DecodeError> {
+        match self {
+            Self::Raw => Ok(data),
+            Self::Base64 => {
+                let text: Vec<u8> = data
+                    .into_iter()
+                    .filter(|byte| !byte.is_ascii_whitespace())
+                    .collect();
+                BASE64_STANDARD.decode(text)
+            }
+        }
+    }
+
+    /// Prepares `data` to be sent this way, base64 ending with a newline
+    pub fn encode(self, data: Vec<u8>) -> Vec<u8> {
+        match self {
+            Self::Raw => data,
+            Self::Base64 => {
+                let mut text = BASE64_STANDARD.encode(data).into_bytes();
+                text.push(b'\n');
+                text
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let data = vec![0x89, b'P', b'N', b'G', 0, 0xff];
+        for transport in [Transport::Raw, Transport::Base64] {
+            let sent = transport.encode(data.clone());
+            assert_eq!(transport.decode(sent).unwrap(), data);
+        }
+        assert_eq!(Transport::Base64.encode(data), b"iVBORwD/\n");
+        assert_eq!(
+            Transport::Base64
+                .decode(b"aGVs\r\nbG8=\n".to_vec())
+                .unwrap(),
+            b"hello"
+        );
+        assert!(Transport::Base64.decode(b"not base64!".to_vec()).is_err());
+        assert_eq!(
+            "hex".parse::<Transport>(),
+            Err(UnknownTransportError("hex".to_owned()))
+        );
+    }
+}
@@ -0,0 +1,266 @@
+use crate::{
+    audit::{Finding, Location, Rule},
+    cancel::{CancellationToken, Cancelled},
+    chunk::CrcHasher,
+    chunk_type::ChunkType,
+    color::{self, ColorChunk, COLOR_CHUNKS},
+    magic::Format,
+    png::Png,
+    provenance::{self, C2PA_CHUNK_TYPE},
+};
+
+/// Checks the structure of a PNG file: the signature, every chunk's type and
+/// crc, and that the file isn't truncated.
+///
+/// Unlike parsing, verification doesn't stop at the first broken chunk, so
+/// all problems (as far as the chunk boundaries can still be trusted) are
+/// reported. The color management chunks are checked for consistency, see
+/// [`color::check`].
+pub fn verify(bytes: &[u8], cancel: &CancellationToken) -> Result<Vec<Finding>, Cancelled> {
+    let mut findings = Vec::new();
+
+    if !Png::is_png(bytes) {
+        findings.push(Finding {
+            rule: Rule::InvalidSignature,
+            location: Some(Location {
+                chunk: None,
+                offset: 0,
+                length: bytes.len().min(8),
+            }),
+            message: match Format::detect(bytes) {
+                Some(format) => format!(
+                    "the file does not start with the PNG signature (looks like {})",
+                    format
+                ),
+                None => "the file does not start with the PNG signature".to_owned(),
+            },
+        });
+        return Ok(findings);
+    }
+
+    let mut cursor = 8;
+    let mut index = 0;
+    let mut found_end = false;
+    let mut truncated = false;
+    let mut manifest_store = None;
+    let mut color_chunks = Vec::new();
+
+    while cursor < bytes.len() {
+        cancel.check()?;
+
+        let remaining = bytes.len() - cursor;
+        let length = match bytes.get(cursor..cursor + 4) {
+            Some(length) => u32::from_be_bytes(length.try_into().unwrap()) as usize,
+            None => 0,
+        };
+        if remaining < 12 || remaining - 12 < length {
+            findings.push(Finding {
+                rule: Rule::TruncatedChunk,
+                location: Some(Location {
+                    chunk: Some(index),
+                    offset: cursor,
+                    length: remaining,
+                }),
+                message: format!(
+                    "chunk {} needs {} bytes but the file ends after {}",
+                    index,
+                    length as u64 + 12,
+                    remaining
+                ),
+            });
+            truncated = true;
+            break;
+        }
+
+        let location = Location {
+            chunk: Some(index),
+            offset: cursor,
+            length: length + 12,
+        };
+        let type_bytes: [u8; 4] = bytes[cursor + 4..cursor + 8].try_into().unwrap();
+        let data = &bytes[cursor + 8..cursor + 8 + length];
+        let stored_crc = u32::from_be_bytes(
+            bytes[cursor + 8 + length..cursor + 12 + length]
+                .try_into()
+                .unwrap(),
+        );
+
+        match ChunkType::try_from(type_bytes) {
+            Ok(chunk_type) => {
+                if !chunk_type.is_reserved_bit_valid() {
+                    findings.push(Finding {
+                        rule: Rule::ReservedBit,
+                        location: Some(location),
+                        message: format!(
+                            "chunk {} has the type {} whose reserved bit is set",
+                            index, chunk_type
+                        ),
+                    });
+                }
+            }
+            Err(err) => findings.push(Finding {
+                rule: Rule::InvalidChunkType,
+                location: Some(location),
+                message: format!("chunk {} has an invalid type: {}", index, err),
+            }),
+        }
+
+        let mut hasher = CrcHasher::from_type_bytes(&type_bytes);
+        hasher.update(data);
+        let crc = hasher.finalize();
+        if crc != stored_crc {
+            findings.push(Finding {
+                rule: Rule::InvalidChecksum,
+                location: Some(location),
+                message: format!(
+                    "chunk {} has the crc {:08x} but its contents hash to {:08x}",
+                    index, stored_crc, crc
+                ),
+            });
+        }
+
+        if COLOR_CHUNKS.contains(&&type_bytes) {
+            color_chunks.push(ColorChunk {
+                chunk_type: type_bytes,
+                location,
+                data,
+            });
+        }
+        if type_bytes == C2PA_CHUNK_TYPE && manifest_store.is_none() {
+            manifest_store = Some((location, data));
+        }
+
+        cursor += length + 12;
+        index += 1;
+
+        // Whatever follows is trailing data, which is up to the audit
+        if &type_bytes == b"IEND" {
+            found_end = true;
+            break;
+        }
+    }
+
+    if !found_end && !truncated {
+        findings.push(Finding {
+            rule: Rule::MissingEnd,
+            location: None,
+            message: "the file has no IEND chunk".to_owned(),
+        });
+    }
+
+    findings.extend(color::check(&color_chunks));
+    if let Some((location, data)) = manifest_store {
+        findings.extend(check_provenance(bytes, location, data));
+    }
+
+    Ok(findings)
+}
+
+/// Checks the file against the data hash of its active C2PA manifest
+fn check_provenance(bytes: &[u8], location: Location, data: &[u8]) -> Option<Finding> {
+    let message = match provenance::parse_manifest_store(data) {
+        Ok(manifests) => {
+            let manifest = manifests.last()?;
+            let data_hash = manifest.data_hash.as_ref()?;
+            if provenance::check_data_hash(bytes, data_hash)? {
+                return None;
+            }
+            format!(
+                "the file no longer matches the {} hash in C2PA manifest {}",
+                data_hash.alg, manifest.label
+            )
+        }
+        Err(err) => format!("the C2PA manifest store can't be read: {}", err),
+    };
+
+    Some(Finding {
+        rule: Rule::ProvenanceMismatch,
+        location: Some(location),
+        message,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::png::tests::PNG_FILE;
+
+    fn rules(bytes: &[u8]) -> Vec<Rule> {
+        verify(bytes, &CancellationToken::new())
+            .unwrap()
+            .into_iter()
+            .map(|finding| finding.rule)
+            .collect()
+    }
+
+    #[test]
+    fn test_valid_file() {
+        assert!(rules(&PNG_FILE).is_empty());
+    }
+
+    #[test]
+    fn test_invalid_signature() {
+        assert_eq!(rules(b"GIF89a"), vec![Rule::InvalidSignature]);
+    }
+
+    #[test]
+    fn test_all_checksums_reported() {
+        let mut bytes = PNG_FILE.to_vec();
+        // Flip a byte in the data of IHDR and of the last IDAT
+        bytes[20] ^= 0xff;
+        bytes[4770] ^= 0xff;
+        assert_eq!(
+            rules(&bytes),
+            vec![Rule::InvalidChecksum, Rule::InvalidChecksum]
+        );
+    }
+
+    #[test]
+    fn test_provenance() {
+        let mut bytes = provenance::tests::signed_png().as_bytes();
+        assert!(rules(&bytes).is_empty());
+
+        // Change a pixel in the IDAT data and fix up its crc
+        let mut png = Png::try_from(&bytes[..]).unwrap();
+        png[5]
+            .map_data(|data| {
+                let mut data = data.to_vec();
+                data[100] ^= 0xff;
+                data
+            })
+            .unwrap();
+        bytes = png.as_bytes();
+        assert_eq!(rules(&bytes), vec![Rule::ProvenanceMismatch]);
+    }
+
+    #[test]
+    fn test_truncated() {
+        let findings = verify(&PNG_FILE[..100], &CancellationToken::new()).unwrap();
+        assert_eq!(findings.len(), 1);
+        assert_eq!(findings[0].rule, Rule::TruncatedChunk);
+        assert_eq!(findings[0].location.unwrap().offset, 83);
+    }
+
+    #[test]
+    fn test_missing_end() {
+        assert_eq!(rules(&PNG_FILE[..4791]), vec![Rule::MissingEnd]);
+    }
+
+    #[test]
+    fn test_invalid_type_and_reserved_bit() {
+        let mut bytes = PNG_FILE.to_vec();
+        // RuSt -> R1St and IEND -> IEnD
+        bytes[4781] = b'1';
+        bytes[4797] = b'n';
+        assert_eq!(
+            rules(&bytes),
+            vec![
+                Rule::InvalidChunkType,
+                Rule::InvalidChecksum,
+                Rule::ReservedBit,
+                Rule::InvalidChecksum,
+                Rule::MissingEnd,
+            ]
+        );
+    }
+}
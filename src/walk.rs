@@ -0,0 +1,207 @@
+use ignore::{overrides::OverrideBuilder, WalkBuilder};
+use std::{
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+/// The file which lists paths to leave out of a scan, in `.gitignore` syntax.
+/// It applies to the directory it is in and everything below.
+pub const IGNORE_FILE: &str = ".pngmeignore";
+
+/// What to do with symbolic links found while scanning a directory
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SymlinkPolicy {
+    /// Leave links alone, so files outside the directory are never touched
+    #[default]
+    Skip,
+
+    /// Treat links like the file or directory they point to. Loops are
+    /// detected and reported as errors.
+    Follow,
+}
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+#[error("unknown symlink policy '{0}', expected skip or follow")]
+pub struct UnknownSymlinkPolicyError(String);
+
+impl FromStr for SymlinkPolicy {
+    type Err = UnknownSymlinkPolicyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "skip" => Ok(Self::Skip),
+            "follow" => Ok(Self::Follow),
+            other => Err(UnknownSymlinkPolicyError(other.to_owned())),
+        }
+    }
+}
+
+/// Which files in a directory [`scan`] finds
+#[derive(Debug, Clone, Default)]
+pub struct ScanOptions {
+    /// Also look into subdirectories
+    pub recursive: bool,
+    /// How deep to look when `recursive` is set, 1 being the files directly
+    /// in the directory. Unlimited if not set.
+    pub max_depth: Option<usize>,
+    /// Paths to leave out, in `.gitignore` syntax relative to the directory
+    pub exclude: Vec<String>,
+    pub symlinks: SymlinkPolicy,
+}
+
+/// The regular files in `dir` selected by `options`, in a stable order.
+/// Files listed in `.pngmeignore` files are left out, as are the
+/// `.pngmeignore` files themselves.
+pub fn scan(dir: &Path, options: &ScanOptions) -> Result<Vec<PathBuf>, ignore::Error> {
+    let mut overrides = OverrideBuilder::new(dir);
+    for pattern in &options.exclude {
+        // Overrides select files, negated ones leave them out
+        overrides.add(&format!("!{}", pattern))?;
+    }
+
+    let max_depth = if options.recursive {
+        options.max_depth
+    } else {
+        Some(1)
+    };
+    let walk = WalkBuilder::new(dir)
+        .standard_filters(false)
+        .add_custom_ignore_filename(IGNORE_FILE)
+        .overrides(overrides.build()?)
+        .max_depth(max_depth)
+        .follow_links(options.symlinks == SymlinkPolicy::Follow)
+        .build();
+
+    let mut files = Vec::new();
+    for entry in walk {
+        let entry = entry?;
+        // Links which aren't followed have the type of the link itself
+        if entry
+            .file_type()
+            .is_some_and(|file_type| file_type.is_file())
+            && entry.file_name() != IGNORE_FILE
+        {
+            files.push(entry.into_path());
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    /// A directory tree with files at several depths, removed when dropped
+    struct Tree(PathBuf);
+
+    impl Tree {
+        fn new(name: &str, files: &[&str]) -> Self {
+            let root = std::env::temp_dir().join(format!("pngme-{}-{}", name, std::process::id()));
+            for file in files {
+                let path = root.join(file);
+                fs::create_dir_all(path.parent().unwrap()).unwrap();
+                fs::write(path, file).unwrap();
+            }
+            Self(root)
+        }
+
+        fn scan(&self, options: &ScanOptions) -> Vec<String> {
+            scan(&self.0, options)
+                .unwrap()
+                .iter()
+                .map(|path| {
+                    let path = path.strip_prefix(&self.0).unwrap();
+                    path.to_string_lossy().replace('\\', "/")
+                })
+                .collect()
+        }
+    }
+
+    impl Drop for Tree {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    const FILES: &[&str] = &[
+        "a.png",
+        "icons/b.png",
+        "icons/small/c.png",
+        "node_modules/pkg/d.png",
+    ];
+
+    #[test]
+    fn test_depth() {
+        let tree = Tree::new("scan-depth", FILES);
+        assert_eq!(tree.scan(&ScanOptions::default()), ["a.png"]);
+
+        let mut options = ScanOptions {
+            recursive: true,
+            ..ScanOptions::default()
+        };
+        assert_eq!(tree.scan(&options), FILES);
+        options.max_depth = Some(2);
+        assert_eq!(tree.scan(&options), ["a.png", "icons/b.png"]);
+    }
+
+    #[test]
+    fn test_exclude() {
+        let tree = Tree::new("scan-exclude", FILES);
+        let options = ScanOptions {
+            recursive: true,
+            exclude: vec!["node_modules/**".to_owned()],
+            ..ScanOptions::default()
+        };
+        assert_eq!(
+            tree.scan(&options),
+            ["a.png", "icons/b.png", "icons/small/c.png"]
+        );
+
+        fs::write(tree.0.join("icons").join(IGNORE_FILE), "small/\n").unwrap();
+        assert_eq!(tree.scan(&options), ["a.png", "icons/b.png"]);
+    }
+
+    #[test]
+    fn test_unusual_names() {
+        let files = ["my art/ünïcode 画.png", "space name.png"];
+        let tree = Tree::new("scan names", &files);
+        let options = ScanOptions {
+            recursive: true,
+            ..ScanOptions::default()
+        };
+        assert_eq!(tree.scan(&options), files);
+        // On Windows this is a \\?\ path, which isn't limited to 260
+        // characters
+        let canonical = tree.0.canonicalize().unwrap();
+        assert_eq!(scan(&canonical, &options).unwrap().len(), files.len());
+    }
+
+    /// Linux file names are bytes, which needn't be UTF-8
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_non_utf8_names() {
+        use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+        let tree = Tree::new("scan-bytes", &["a.png"]);
+        let name = OsStr::from_bytes(b"caf\xe9.png");
+        fs::write(tree.0.join(name), b"").unwrap();
+        let files = scan(&tree.0, &ScanOptions::default()).unwrap();
+        assert_eq!(files, [tree.0.join("a.png"), tree.0.join(name)]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_symlinks() {
+        let tree = Tree::new("scan-symlinks", FILES);
+        std::os::unix::fs::symlink(tree.0.join("icons"), tree.0.join("linked")).unwrap();
+        let mut options = ScanOptions {
+            recursive: true,
+            ..ScanOptions::default()
+        };
+        assert_eq!(tree.scan(&options), FILES);
+
+        options.symlinks = SymlinkPolicy::Follow;
+        assert_eq!(tree.scan(&options).len(), FILES.len() + 2);
+    }
+}
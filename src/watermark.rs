@@ -0,0 +1,362 @@
+use crate::{
+    ihdr::{ColorType, ImageHeader},
+    png::Png,
+    raster::{Raster, RasterError},
+};
+use crc::{Crc, CRC_16_IBM_3740};
+use sha2::{Digest, Sha256};
+
+/// The longest identifier a watermark holds, in bytes
+pub const MAX_IDENTIFIER_LEN: usize = 8;
+
+/// The strength used unless told otherwise, in steps of an 8-bit sample
+pub const DEFAULT_STRENGTH: u8 = 3;
+
+/// The identifier followed by its checksum
+const BITS: usize = MAX_IDENTIFIER_LEN * 8 + 16;
+
+/// The pattern of a bit covers a block of `BLOCK` x `BLOCK` pixels, with
+/// the same sign for each `CELL` x `CELL` square. Patterns of squares rather
+/// than single pixels survive blurring and resampling better.
+const BLOCK: u32 = 16;
+const CELL: u32 = 2;
+
+/// Every bit is spread across at least this many blocks
+const MIN_BLOCKS_PER_BIT: usize = 2;
+
+/// A bit counts as clearly read if its correlation is this many standard
+/// deviations away from what an unmarked image gives
+const CLEAR_SCORE: f64 = 3.0;
+
+static CHECKSUM: Crc<u16> = Crc::<u16>::new(&CRC_16_IBM_3740);
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum WatermarkError {
+    #[error(transparent)]
+    Pixels(#[from] RasterError),
+
+    #[error("palette images can't be watermarked, convert them to RGB first")]
+    Indexed,
+
+    #[error(
+        "images with {0} bit(s) per sample can't be watermarked, convert them to 8 bits first"
+    )]
+    LowBitDepth(u8),
+
+    #[error("the image is too small for a watermark, it needs at least {} blocks of {BLOCK}x{BLOCK} pixels", BITS * MIN_BLOCKS_PER_BIT)]
+    TooSmall,
+
+    #[error("the identifier is longer than {MAX_IDENTIFIER_LEN} bytes")]
+    IdentifierTooLong,
+
+    #[error("the identifier must not be empty or contain NUL")]
+    InvalidIdentifier,
+
+    #[error("the strength must be between 1 and 32")]
+    InvalidStrength,
+}
+
+/// What [`detect`] read from an image
+#[derive(Debug, Clone, PartialEq)]
+pub struct Detection {
+    /// The identifier, if the checksum of the bits read matches
+    pub identifier: Option<String>,
+    /// How many of the bits were read clearly
+    pub clear_bits: usize,
+}
+
+impl Detection {
+    /// The share of bits read clearly, from 0 to 1. Unmarked images and
+    /// images marked with another key stay close to 0.
+    pub fn confidence(&self) -> f64 {
+        self.clear_bits as f64 / BITS as f64
+    }
+}
+
+/// The blocks of the image, each assigned to one bit
+struct Layout {
+    columns: u32,
+    rows: u32,
+}
+
+impl Layout {
+    fn new(header: &ImageHeader) -> Result<Self, WatermarkError> {
+        if header.color_type == ColorType::Indexed {
+            return Err(WatermarkError::Indexed);
+        }
+        if header.bit_depth < 8 {
+            return Err(WatermarkError::LowBitDepth(header.bit_depth));
+        }
+        let layout = Self {
+            columns: header.width / BLOCK,
+            rows: header.height / BLOCK,
+        };
+        if ((layout.columns * layout.rows) as usize) < BITS * MIN_BLOCKS_PER_BIT {
+            return Err(WatermarkError::TooSmall);
+        }
+        Ok(layout)
+    }
+
+    /// The blocks as (bit, x, y, signs of the cells), the pattern being
+    /// derived from `key`
+    fn blocks<'a>(&self, key: &'a [u8]) -> impl Iterator<Item = (usize, u32, u32, [u8; 32])> + 'a {
+        let columns = self.columns;
+        (0..self.columns * self.rows).map(move |block| {
+            let mut hasher = Sha256::new();
+            hasher.update(key);
+            hasher.update(block.to_le_bytes());
+            (
+                block as usize % BITS,
+                block % columns * BLOCK,
+                block / columns * BLOCK,
+                hasher.finalize().into(),
+            )
+        })
+    }
+}
+
+/// The cells along each side of a block
+const CELLS: u32 = BLOCK / CELL;
+
+/// The cell of pixel `x`, `y` within a block
+fn cell(x: u32, y: u32) -> usize {
+    (y / CELL * CELLS + x / CELL) as usize
+}
+
+/// Whether `cell` is raised (1) or lowered (-1) for a one bit
+fn sign(pattern: &[u8; 32], cell: usize) -> i32 {
+    if pattern[cell / 8] >> (cell % 8) & 1 == 1 {
+        1
+    } else {
+        -1
+    }
+}
+
+/// The color samples of a pixel, leaving out alpha
+fn color_channels(header: &ImageHeader) -> u32 {
+    header.color_type.channels() - header.color_type.has_alpha() as u32
+}
+
+/// Embeds `identifier` into the pixels of `png`, spread redundantly across
+/// the whole image: every bit raises and lowers the brightness of
+/// pseudo-random squares in many blocks by `strength`, in a pattern derived
+/// from `key`. Unlike data in the lowest bits, the watermark survives
+/// re-encoding, small brightness changes and mild noise, and it can only be
+/// read with the key.
+pub fn embed(
+    png: &mut Png,
+    identifier: &str,
+    key: &[u8],
+    strength: u8,
+) -> Result<(), WatermarkError> {
+    if identifier.is_empty() || identifier.contains('\0') {
+        return Err(WatermarkError::InvalidIdentifier);
+    }
+    if identifier.len() > MAX_IDENTIFIER_LEN {
+        return Err(WatermarkError::IdentifierTooLong);
+    }
+    if !(1..=32).contains(&strength) {
+        return Err(WatermarkError::InvalidStrength);
+    }
+    let mut raster = Raster::decode(png)?;
+    let header = raster.header;
+    let layout = Layout::new(&header)?;
+
+    let mut payload = [0; MAX_IDENTIFIER_LEN + 2];
+    payload[..identifier.len()].copy_from_slice(identifier.as_bytes());
+    let checksum = CHECKSUM.checksum(&payload[..MAX_IDENTIFIER_LEN]);
+    payload[MAX_IDENTIFIER_LEN..].copy_from_slice(&checksum.to_be_bytes());
+
+    // 16-bit samples are changed by the same share of their range
+    let (strength, max) = if header.bit_depth == 16 {
+        (strength as i32 * 257, u16::MAX as i32)
+    } else {
+        (strength as i32, u8::MAX as i32)
+    };
+    for (bit, x0, y0, pattern) in layout.blocks(key) {
+        let one = payload[bit / 8] >> (7 - bit % 8) & 1 == 1;
+        let direction = if one { strength } else { -strength };
+        for y in 0..BLOCK {
+            for x in 0..BLOCK {
+                let delta = direction * sign(&pattern, cell(x, y));
+                for channel in 0..color_channels(&header) {
+                    let sample = raster.sample(x0 + x, y0 + y, channel) as i32;
+                    let marked = (sample + delta).clamp(0, max);
+                    raster.set_sample(x0 + x, y0 + y, channel, marked as u16);
+                }
+            }
+        }
+    }
+    raster.store(png, header.interlaced)?;
+    Ok(())
+}
+
+/// Reads the watermark embedded with `key` from the pixels of `png`, see
+/// [`embed`]. The brightness of the cells of every block is correlated with
+/// the pattern of its bit, after subtracting the brightness of their
+/// neighbors, which leaves little of smooth image content.
+pub fn detect(png: &Png, key: &[u8]) -> Result<Detection, WatermarkError> {
+    let raster = Raster::decode(png)?;
+    let header = raster.header;
+    let layout = Layout::new(&header)?;
+    let channels = color_channels(&header);
+
+    // The correlation of every bit, and the energy of the residuals it was
+    // computed from. As the signs of the cells are random, the energy is the
+    // variance of the correlation for an unmarked image.
+    let mut correlation = [0.0f64; BITS];
+    let mut energy = [0.0f64; BITS];
+    for (bit, x0, y0, pattern) in layout.blocks(key) {
+        let mut brightness = [0.0f64; (CELLS * CELLS) as usize];
+        for y in 0..BLOCK {
+            for x in 0..BLOCK {
+                for channel in 0..channels {
+                    brightness[cell(x, y)] += raster.sample(x0 + x, y0 + y, channel) as f64;
+                }
+            }
+        }
+        for (i, &value) in brightness.iter().enumerate() {
+            let (x, y) = (i as u32 % CELLS, i as u32 / CELLS);
+            let neighbors: Vec<f64> = [(-1, 0), (1, 0), (0, -1), (0, 1)]
+                .iter()
+                .map(|(dx, dy)| (x as i32 + dx, y as i32 + dy))
+                .filter(|&(x, y)| (0..CELLS as i32).contains(&x) && (0..CELLS as i32).contains(&y))
+                .map(|(x, y)| brightness[(y * CELLS as i32 + x) as usize])
+                .collect();
+            let residual = value - neighbors.iter().sum::<f64>() / neighbors.len() as f64;
+            correlation[bit] += residual * sign(&pattern, i) as f64;
+            energy[bit] += residual * residual;
+        }
+    }
+
+    let mut payload = [0u8; MAX_IDENTIFIER_LEN + 2];
+    let mut clear_bits = 0;
+    for bit in 0..BITS {
+        if correlation[bit] > 0.0 {
+            payload[bit / 8] |= 1 << (7 - bit % 8);
+        }
+        if energy[bit] > 0.0 && correlation[bit].abs() / energy[bit].sqrt() >= CLEAR_SCORE {
+            clear_bits += 1;
+        }
+    }
+
+    let checksum =
+        u16::from_be_bytes([payload[MAX_IDENTIFIER_LEN], payload[MAX_IDENTIFIER_LEN + 1]]);
+    let identifier = &payload[..MAX_IDENTIFIER_LEN];
+    let len = identifier
+        .iter()
+        .position(|&byte| byte == 0)
+        .unwrap_or(MAX_IDENTIFIER_LEN);
+    let valid = CHECKSUM.checksum(identifier) == checksum
+        && len > 0
+        && identifier[len..].iter().all(|&byte| byte == 0);
+    Ok(Detection {
+        identifier: valid.then(|| String::from_utf8_lossy(&identifier[..len]).into_owned()),
+        clear_bits,
+    })
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::{chunk::Chunk, chunk_type::ChunkType, png::tests::PNG_FILE};
+
+    const SIZE: u32 = 224;
+
+    /// A textured RGB image, large enough for a watermark
+    pub(crate) fn image() -> Png {
+        let mut header = Vec::new();
+        header.extend(SIZE.to_be_bytes());
+        header.extend(SIZE.to_be_bytes());
+        header.extend([8, 2, 0, 0, 0]);
+        let mut raster = Raster::new(ImageHeader::try_from(&header[..]).unwrap());
+        for y in 0..SIZE {
+            for x in 0..SIZE {
+                for channel in 0..3 {
+                    let value = (x + 2 * y + 40 * channel) % 200 + (x * y * 7919 % 13);
+                    raster.set_sample(x, y, channel, value as u16);
+                }
+            }
+        }
+
+        let chunk = |chunk_type: &[u8; 4], data| {
+            Chunk::new(ChunkType::try_from(*chunk_type).unwrap(), data)
+        };
+        let mut png = Png::from_chunks(vec![
+            chunk(b"IHDR", header),
+            chunk(b"IDAT", Vec::new()),
+            chunk(b"IEND", Vec::new()),
+        ]);
+        raster.store(&mut png, false).unwrap();
+        png
+    }
+
+    /// Changes every sample of the image with `edit`
+    fn edit(png: &mut Png, edit: impl Fn(u32, u32, u16) -> u16) {
+        let mut raster = Raster::decode(png).unwrap();
+        for y in 0..SIZE {
+            for x in 0..SIZE {
+                for channel in 0..3 {
+                    let value = edit(x, y, raster.sample(x, y, channel));
+                    raster.set_sample(x, y, channel, value);
+                }
+            }
+        }
+        raster.store(png, false).unwrap();
+    }
+
+    #[test]
+    fn test_embed_detect() {
+        let mut png = image();
+        let unmarked = detect(&png, b"secret").unwrap();
+        assert_eq!(unmarked.identifier, None);
+        assert!(unmarked.confidence() < 0.1);
+
+        embed(&mut png, "alice", b"secret", DEFAULT_STRENGTH).unwrap();
+        let detection = detect(&png, b"secret").unwrap();
+        assert_eq!(detection.identifier.as_deref(), Some("alice"));
+        assert!(detection.confidence() > 0.9);
+
+        let other_key = detect(&png, b"other").unwrap();
+        assert_eq!(other_key.identifier, None);
+        assert!(other_key.confidence() < 0.1);
+
+        // Interlacing and refiltering don't touch the pixels
+        let raster = Raster::decode(&png).unwrap();
+        raster.store(&mut png, true).unwrap();
+        assert_eq!(
+            detect(&png, b"secret").unwrap().identifier.as_deref(),
+            Some("alice")
+        );
+    }
+
+    #[test]
+    fn test_mild_edits() {
+        let mut png = image();
+        embed(&mut png, "bob", b"secret", DEFAULT_STRENGTH).unwrap();
+        edit(&mut png, |_, _, value| (value + 12).min(255));
+        edit(&mut png, |x, y, value| {
+            let noise = (x * 31 + y * 17) % 5;
+            (value as u32 + noise).saturating_sub(2).min(255) as u16
+        });
+        let detection = detect(&png, b"secret").unwrap();
+        assert_eq!(detection.identifier.as_deref(), Some("bob"));
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut png = image();
+        let embed = |png: &mut Png, identifier, strength| {
+            embed(png, identifier, b"secret", strength).unwrap_err()
+        };
+        assert_eq!(
+            embed(&mut png, "too long!", 3),
+            WatermarkError::IdentifierTooLong
+        );
+        assert_eq!(embed(&mut png, "", 3), WatermarkError::InvalidIdentifier);
+        assert_eq!(embed(&mut png, "a", 0), WatermarkError::InvalidStrength);
+
+        let mut dice = Png::try_from(&PNG_FILE[..]).unwrap();
+        assert_eq!(embed(&mut dice, "alice", 3), WatermarkError::TooSmall);
+    }
+}
@@ -0,0 +1,182 @@
+use crate::{chunk::CrcHasher, chunk_type::ChunkType};
+use std::io::{self, Seek, SeekFrom, Write};
+
+pub use crate::chunk::MAX_CHUNK_LENGTH;
+
+enum Length<W> {
+    /// Given upfront, the data must have exactly this length
+    Known(u32),
+    /// Written as 0 at `start` and patched by `patch` when finishing
+    Patched {
+        start: u64,
+        patch: fn(&mut W, u64, u32) -> io::Result<()>,
+    },
+}
+
+/// Writes a single chunk to `W` while its data is still arriving, so the
+/// data never has to be held in memory as a whole. The data is written with
+/// the [`Write`] implementation, [`ChunkWriter::finish`] appends the crc.
+pub struct ChunkWriter<W: Write> {
+    sink: W,
+    crc: CrcHasher,
+    length: Length<W>,
+    written: u64,
+}
+
+fn invalid_input(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+impl<W: Write> ChunkWriter<W> {
+    /// Starts a chunk whose data will be `length` bytes long. The header is
+    /// written right away.
+    pub fn new(mut sink: W, chunk_type: &ChunkType, length: u32) -> io::Result<Self> {
+        if length > MAX_CHUNK_LENGTH {
+            return Err(invalid_input(format!(
+                "a chunk can hold at most {} bytes, got {}",
+                MAX_CHUNK_LENGTH, length
+            )));
+        }
+        sink.write_all(&length.to_be_bytes())?;
+        sink.write_all(&chunk_type.bytes())?;
+        Ok(Self {
+            sink,
+            crc: CrcHasher::new(chunk_type),
+            length: Length::Known(length),
+            written: 0,
+        })
+    }
+
+    /// Writes the crc and returns the sink. Fails if less data was written
+    /// than announced.
+    pub fn finish(mut self) -> io::Result<W> {
+        // `write` never goes past the limit, which fits into a u32
+        let written = self.written as u32;
+        match self.length {
+            Length::Known(length) if length != written => {
+                return Err(invalid_input(format!(
+                    "the chunk was announced with {} bytes, but only {} were written",
+                    length, written
+                )));
+            }
+            Length::Known(_) => {}
+            Length::Patched { start, patch } => patch(&mut self.sink, start, written)?,
+        }
+        self.sink.write_all(&self.crc.finalize().to_be_bytes())?;
+        Ok(self.sink)
+    }
+
+    /// The limit for the data, the announced length or the largest possible
+    fn limit(&self) -> u64 {
+        match self.length {
+            Length::Known(length) => length as u64,
+            Length::Patched { .. } => MAX_CHUNK_LENGTH as u64,
+        }
+    }
+}
+
+impl<W: Write + Seek> ChunkWriter<W> {
+    /// Starts a chunk whose length isn't known yet. A placeholder is written
+    /// and replaced with the actual length when finishing, so only the
+    /// header position is remembered.
+    pub fn with_unknown_length(mut sink: W, chunk_type: &ChunkType) -> io::Result<Self> {
+        let start = sink.stream_position()?;
+        sink.write_all(&0u32.to_be_bytes())?;
+        sink.write_all(&chunk_type.bytes())?;
+        Ok(Self {
+            sink,
+            crc: CrcHasher::new(chunk_type),
+            length: Length::Patched {
+                start,
+                patch: patch_length::<W>,
+            },
+            written: 0,
+        })
+    }
+}
+
+fn patch_length<W: Write + Seek>(sink: &mut W, start: u64, length: u32) -> io::Result<()> {
+    let end = sink.stream_position()?;
+    sink.seek(SeekFrom::Start(start))?;
+    sink.write_all(&length.to_be_bytes())?;
+    sink.seek(SeekFrom::Start(end))?;
+    Ok(())
+}
+
+impl<W: Write> Write for ChunkWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.written + buf.len() as u64 > self.limit() {
+            return Err(invalid_input(format!(
+                "the data doesn't fit into the chunk, which is limited to {} bytes",
+                self.limit()
+            )));
+        }
+        let n = self.sink.write(buf)?;
+        self.crc.update(&buf[..n]);
+        self.written += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.sink.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use std::{io::Cursor, str::FromStr};
+
+    const DATA: &[u8] = b"This is where your secret message will be!";
+
+    fn expected() -> Vec<u8> {
+        Chunk::new(ChunkType::from_str("RuSt").unwrap(), DATA.to_vec()).as_bytes()
+    }
+
+    #[test]
+    fn test_known_length() {
+        let chunk_type = ChunkType::from_str("RuSt").unwrap();
+        let mut writer = ChunkWriter::new(Vec::new(), &chunk_type, DATA.len() as u32).unwrap();
+        for piece in DATA.chunks(7) {
+            writer.write_all(piece).unwrap();
+        }
+        assert_eq!(writer.finish().unwrap(), expected());
+
+        let mut writer = ChunkWriter::new(Vec::new(), &chunk_type, 4).unwrap();
+        assert!(writer.write_all(DATA).is_err());
+        let writer = ChunkWriter::new(Vec::new(), &chunk_type, 4).unwrap();
+        assert!(writer.finish().is_err());
+    }
+
+    #[test]
+    fn test_unknown_length() {
+        let chunk_type = ChunkType::from_str("RuSt").unwrap();
+        let mut sink = Cursor::new(b"before".to_vec());
+        sink.set_position(6);
+
+        let mut writer = ChunkWriter::with_unknown_length(sink, &chunk_type).unwrap();
+        for piece in DATA.chunks(7) {
+            writer.write_all(piece).unwrap();
+        }
+        let mut sink = writer.finish().unwrap();
+        sink.write_all(b"after").unwrap();
+
+        let mut file = b"before".to_vec();
+        file.extend(expected());
+        file.extend(b"after");
+        assert_eq!(sink.into_inner(), file);
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn prop_matches_chunk(chunk in Chunk::arbitrary_valid(1024), piece_size in 1..64usize) {
+            let mut writer =
+                ChunkWriter::new(Vec::new(), chunk.chunk_type(), chunk.length()).unwrap();
+            for piece in chunk.data().chunks(piece_size) {
+                writer.write_all(piece).unwrap();
+            }
+            proptest::prop_assert_eq!(writer.finish().unwrap(), chunk.as_bytes());
+        }
+    }
+}
@@ -0,0 +1,229 @@
+use crate::{
+    png::Png,
+    text::{find_text, set_text, TextChunk, TextError, TextKind},
+};
+
+/// The iTXt keyword XMP packets are stored under
+pub const XMP_KEYWORD: &str = "XML:com.adobe.xmp";
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum XmpError {
+    #[error("the data is not an XMP packet")]
+    NotXmp,
+
+    #[error("'{0}' is not a valid key, expected something like dc:creator or dc:creator[1]")]
+    InvalidKey(String),
+
+    #[error(transparent)]
+    Text(#[from] TextError),
+}
+
+/// Returns the XMP packet of the PNG.
+///
+/// Following the XMP specification, only an uncompressed `iTXt` chunk
+/// without language tag and translated keyword is accepted.
+pub fn read(png: &Png) -> Option<String> {
+    let text = find_text(png, XMP_KEYWORD)?;
+    match text.kind {
+        TextKind::International {
+            language,
+            translated_keyword,
+            compressed: false,
+        } if language.is_empty() && translated_keyword.is_empty() => Some(text.text),
+        _ => None,
+    }
+}
+
+/// Stores an XMP packet in the PNG, replacing any existing one
+pub fn write(png: &mut Png, packet: &str) -> Result<(), XmpError> {
+    if !packet.contains("<x:xmpmeta") && !packet.contains("<rdf:RDF") {
+        return Err(XmpError::NotXmp);
+    }
+    set_text(png, &TextChunk::international(XMP_KEYWORD, packet))?;
+    Ok(())
+}
+
+/// Looks up a property in an XMP packet.
+///
+/// `key` is the qualified property name, optionally preceded by a path
+/// (`rdf:Description/dc:creator`), which is ignored, and followed by a
+/// 1-based index into a list (`dc:creator[2]`). Both the element and the
+/// attribute form of simple properties are understood. Returns all values of
+/// a list, or only the indexed one.
+///
+/// This is not a full XML parser, it is meant for the common properties
+/// written by image editors.
+pub fn get(packet: &str, key: &str) -> Result<Vec<String>, XmpError> {
+    let invalid = || XmpError::InvalidKey(key.to_owned());
+
+    let name = key.rsplit('/').next().unwrap_or(key);
+    let (name, index) = match name.strip_suffix(']') {
+        Some(rest) => {
+            let (name, index) = rest.split_once('[').ok_or_else(invalid)?;
+            let index: usize = index.parse().map_err(|_| invalid())?;
+            (name, Some(index.checked_sub(1).ok_or_else(invalid)?))
+        }
+        None => (name, None),
+    };
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_alphanumeric() || matches!(c, ':' | '-' | '_' | '.'))
+    {
+        return Err(invalid());
+    }
+
+    let mut values = element_values(packet, name);
+    if values.is_empty() {
+        values.extend(attribute_value(packet, name));
+    }
+
+    Ok(match index {
+        Some(index) => values.into_iter().nth(index).into_iter().collect(),
+        None => values,
+    })
+}
+
+/// Finds the first `<name ...>content</name>` and returns the `rdf:li` items
+/// of the content, or the content itself if there are none
+fn element_values(packet: &str, name: &str) -> Vec<String> {
+    let Some(content) = element_contents(packet, name).into_iter().next() else {
+        return Vec::new();
+    };
+
+    let items = element_contents(content, "rdf:li");
+    if items.is_empty() {
+        vec![unescape_xml(content.trim())]
+    } else {
+        items.into_iter().map(unescape_xml).collect()
+    }
+}
+
+/// Returns the contents of all `<name ...>content</name>` elements
+fn element_contents<'a>(packet: &'a str, name: &str) -> Vec<&'a str> {
+    let open = format!("<{}", name);
+    let close = format!("</{}>", name);
+
+    let mut contents = Vec::new();
+    let mut rest = packet;
+    while let Some(start) = rest.find(&open) {
+        rest = &rest[start + open.len()..];
+        // Skip elements which merely start with the name, e.g. dc:creatorTool
+        if !rest.starts_with(|c: char| c == '>' || c.is_whitespace()) {
+            continue;
+        }
+        let (Some(content_start), Some(end)) = (rest.find('>'), rest.find(&close)) else {
+            break;
+        };
+        if end < content_start {
+            continue;
+        }
+        contents.push(&rest[content_start + 1..end]);
+        rest = &rest[end + close.len()..];
+    }
+    contents
+}
+
+/// Finds `name="value"` and returns the value
+fn attribute_value(packet: &str, name: &str) -> Option<String> {
+    let (quote, start) = ['"', '\'']
+        .iter()
+        .filter_map(|quote| {
+            let pattern = format!("{}={}", name, quote);
+            packet
+                .match_indices(&pattern)
+                .find(|(position, _)| packet[..*position].ends_with(|c: char| c.is_whitespace()))
+                .map(|(position, _)| (*quote, position + pattern.len()))
+        })
+        .min_by_key(|(_, start)| *start)?;
+
+    let rest = &packet[start..];
+    let end = rest.find(quote)?;
+    Some(unescape_xml(&rest[..end]))
+}
+
+pub(crate) fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn unescape_xml(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::png::tests::PNG_FILE;
+
+    const PACKET: &str = r#"<x:xmpmeta xmlns:x="adobe:ns:meta/">
+ <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+  <rdf:Description rdf:about="" xmp:CreatorTool="GIMP 2.10"
+    xmlns:dc="http://purl.org/dc/elements/1.1/"
+    xmlns:xmp="http://ns.adobe.com/xap/1.0/">
+   <dc:creator><rdf:Seq><rdf:li>Jane Doe</rdf:li><rdf:li>Tom &amp; Jerry</rdf:li></rdf:Seq></dc:creator>
+   <dc:title><rdf:Alt><rdf:li xml:lang="x-default">Dice</rdf:li></rdf:Alt></dc:title>
+   <dc:format>image/png</dc:format>
+  </rdf:Description>
+ </rdf:RDF>
+</x:xmpmeta>"#;
+
+    #[test]
+    fn test_write_and_read() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        assert_eq!(read(&png), None);
+
+        write(&mut png, PACKET).unwrap();
+        assert_eq!(read(&png).as_deref(), Some(PACKET));
+        assert_eq!(&png.chunks()[6].chunk_type().bytes(), b"iTXt");
+    }
+
+    #[test]
+    fn test_write_rejects_non_xmp() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        assert_eq!(write(&mut png, "hello"), Err(XmpError::NotXmp));
+    }
+
+    #[test]
+    fn test_read_ignores_language_tag() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let mut text = TextChunk::international(XMP_KEYWORD, PACKET);
+        text.kind = TextKind::International {
+            language: "en".to_owned(),
+            translated_keyword: String::new(),
+            compressed: false,
+        };
+        set_text(&mut png, &text).unwrap();
+        assert_eq!(read(&png), None);
+    }
+
+    #[test]
+    fn test_get() {
+        assert_eq!(
+            get(PACKET, "dc:creator").unwrap(),
+            vec!["Jane Doe", "Tom & Jerry"]
+        );
+        assert_eq!(get(PACKET, "dc:creator[2]").unwrap(), vec!["Tom & Jerry"]);
+        assert_eq!(get(PACKET, "dc:creator[3]").unwrap(), Vec::<String>::new());
+        assert_eq!(get(PACKET, "dc:title").unwrap(), vec!["Dice"]);
+        assert_eq!(
+            get(PACKET, "rdf:Description/dc:format").unwrap(),
+            vec!["image/png"]
+        );
+        assert_eq!(get(PACKET, "xmp:CreatorTool").unwrap(), vec!["GIMP 2.10"]);
+        assert_eq!(get(PACKET, "dc:rights").unwrap(), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_get_invalid_key() {
+        assert!(get(PACKET, "dc:creator[0]").is_err());
+        assert!(get(PACKET, "dc:creator[x]").is_err());
+        assert!(get(PACKET, "<dc>").is_err());
+    }
+}
@@ -0,0 +1,204 @@
+//! Runs the pngme binary on temporary files, for what only shows on the
+//! command line: the arguments, stdin and stdout
+
+use pngme::{chunk_type::ChunkType, png::Png};
+use std::{
+    fs,
+    io::Write,
+    path::PathBuf,
+    process::{Command, Output, Stdio},
+    str::FromStr,
+};
+
+const PNG_FILE: &[u8] = include_bytes!("../fuzz/corpus/parse_png/dice.png");
+
+/// A directory with a copy of the test image as `in.png`, removed when
+/// dropped
+struct TempDir(PathBuf);
+
+impl TempDir {
+    fn new(name: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("pngme-cli-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("in.png"), PNG_FILE).unwrap();
+        Self(dir)
+    }
+
+    fn path(&self, name: &str) -> PathBuf {
+        self.0.join(name)
+    }
+
+    /// Runs pngme in the directory with `stdin` as its input
+    fn run(&self, args: &[&str], stdin: &[u8]) -> Output {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_pngme"))
+            .args(args)
+            .current_dir(&self.0)
+            .env("NO_COLOR", "1")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        child.stdin.take().unwrap().write_all(stdin).unwrap();
+        child.wait_with_output().unwrap()
+    }
+
+    /// Runs pngme and checks that it succeeded
+    fn ok(&self, args: &[&str]) -> Output {
+        let output = self.run(args, b"");
+        assert!(output.status.success(), "{}", text(&output.stderr));
+        output
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+fn text(bytes: &[u8]) -> &str {
+    std::str::from_utf8(bytes).unwrap()
+}
+
+#[test]
+fn test_confirmation() {
+    let dir = TempDir::new("confirm");
+    let output = dir.ok(&["in.png", "encode", "ruSt", "hello", "-o", "out.png"]);
+    assert!(text(&output.stdout).starts_with("Wrote out.png ("));
+    assert!(text(&output.stdout).ends_with("): added ruSt\n"));
+
+    let output = dir.ok(&[
+        "out.png",
+        "encode",
+        "ruSt",
+        "hello",
+        "--in-place",
+        "--if-changed",
+    ]);
+    assert_eq!(text(&output.stdout), "out.png: unchanged\n");
+    let output = dir.ok(&[
+        "out.png",
+        "encode",
+        "ruSt",
+        "hello",
+        "--if-changed",
+        "-o",
+        "copy.png",
+    ]);
+    assert!(text(&output.stdout).starts_with("Wrote copy.png ("));
+
+    let output = dir.ok(&[
+        "-q",
+        "out.png",
+        "encode",
+        "ruSt",
+        "hello",
+        "--in-place",
+        "--if-changed",
+    ]);
+    assert_eq!(output.stdout, b"");
+    let output = dir.ok(&["out.png", "remove", "ruSt", "-o", "other.png", "--quiet"]);
+    assert_eq!(output.stdout, b"");
+    assert!(dir.path("other.png").exists());
+}
+
+#[test]
+fn test_in_place() {
+    let dir = TempDir::new("in-place");
+    dir.ok(&["in.png", "encode", "ruSt", "hello", "--in-place"]);
+    let encoded = Png::try_from(&fs::read(dir.path("in.png")).unwrap()[..]).unwrap();
+    let chunk_type = ChunkType::from_str("ruSt").unwrap();
+    assert!(encoded.chunk_by_type(&chunk_type).is_some());
+
+    // The input is only modified when asked to
+    let output = dir.run(&["in.png", "remove", "ruSt"], b"");
+    assert!(!output.status.success());
+    assert!(text(&output.stderr).contains("--in-place"));
+    dir.ok(&["in.png", "remove", "ruSt", "--in-place"]);
+    assert_eq!(fs::read(dir.path("in.png")).unwrap(), PNG_FILE);
+}
+
+#[test]
+fn test_in_place_required() {
+    let dir = TempDir::new("in-place-required");
+    for args in [
+        &["in.png", "normalize"][..],
+        &["in.png", "text", "set", "Comment", "hello"],
+    ] {
+        let output = dir.run(args, b"");
+        assert!(!output.status.success(), "{:?}", args);
+        assert!(text(&output.stderr).contains("--in-place"));
+        assert_eq!(fs::read(dir.path("in.png")).unwrap(), PNG_FILE);
+    }
+
+    dir.ok(&["in.png", "text", "set", "Comment", "hello", "--in-place"]);
+    let output = dir.ok(&["in.png", "text", "get", "Comment"]);
+    assert_eq!(text(&output.stdout), "hello\n");
+}
+
+#[test]
+fn test_stdout() {
+    let dir = TempDir::new("stdout");
+    dir.ok(&["in.png", "encode", "ruSt", "hello", "-o", "encoded.png"]);
+    // What is written to stdout is exactly the file, the status goes to
+    // stderr where it can't corrupt it
+    for args in [
+        &["in.png", "encode", "ruSt", "hello", "-o"][..],
+        &["encoded.png", "remove", "ruSt", "-o"],
+        &["in.png", "normalize", "-o"],
+        &["in.png", "optimize", "-o"],
+        &["in.png", "provenance", "strip", "-o"],
+    ] {
+        dir.ok(&[args, &["out.png"]].concat());
+        let output = dir.ok(&[args, &["-"]].concat());
+        let written = fs::read(dir.path("out.png")).unwrap();
+        assert!(output.stdout == written, "{:?}", args);
+    }
+    // and the input isn't touched
+    assert_eq!(fs::read(dir.path("in.png")).unwrap(), PNG_FILE);
+}
+
+#[test]
+fn test_stdin_payload() {
+    let dir = TempDir::new("stdin");
+    // Not UTF-8 and with a trailing newline, which must be kept
+    let payload = b"\x00secret\xff\n";
+    for args in [
+        &["in.png", "encode", "ruSt", "-", "-o", "out.png"][..],
+        &["in.png", "encode", "ruSt", "--file", "-", "-o", "out.png"],
+    ] {
+        let output = dir.run(args, payload);
+        assert!(output.status.success(), "{}", text(&output.stderr));
+        let output = dir.ok(&["out.png", "decode", "ruSt", "--hex"]);
+        assert_eq!(text(&output.stdout), "00736563726574ff0a\n");
+    }
+
+    let output = dir.run(
+        &[
+            "in.png",
+            "--stdin-format",
+            "base64",
+            "encode",
+            "ruSt",
+            "-",
+            "-o",
+            "out.png",
+        ],
+        b"AHNlY3JldP8K\n",
+    );
+    assert!(output.status.success(), "{}", text(&output.stderr));
+    let output = dir.ok(&["out.png", "decode", "ruSt", "--base64"]);
+    assert_eq!(text(&output.stdout), "AHNlY3JldP8K\n");
+}
+
+#[test]
+fn test_decode_to_pipe() {
+    let dir = TempDir::new("decode-pipe");
+    // Passed on byte for byte, not escaped and without a newline added
+    let payload = b"line1\r\nline2\x1b[31m";
+    let output = dir.run(&["in.png", "encode", "ruSt", "-", "-o", "out.png"], payload);
+    assert!(output.status.success(), "{}", text(&output.stderr));
+    let output = dir.ok(&["out.png", "decode", "ruSt"]);
+    assert_eq!(output.stdout, payload);
+}